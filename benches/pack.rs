@@ -0,0 +1,92 @@
+// This file is part of rust-vpk.
+//
+// rust-vpk is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rust-vpk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rust-vpk.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Packs a generated directory and reports the time and, on Linux, how many
+//! bytes `rvpk pack` read compared to the size of the input files. Without
+//! `--dedup` every file should only be read once.
+//!
+//! Run with `cargo bench --bench pack`.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::Instant;
+
+const FILE_COUNT: usize = 64;
+const FILE_SIZE:  usize = 4 * 1024 * 1024;
+const RUNS:       usize = 3;
+
+/// Bytes read by this process and its waited-for children so far.
+#[cfg(target_os = "linux")]
+fn read_bytes() -> Option<u64> {
+    let io = fs::read_to_string("/proc/self/io").ok()?;
+    io.lines()
+        .find_map(|line| line.strip_prefix("rchar: "))
+        .and_then(|value| value.trim().parse().ok())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_bytes() -> Option<u64> {
+    None
+}
+
+fn pack(dir: &Path, args: &[&str]) {
+    let status = Command::new(env!("CARGO_BIN_EXE_rvpk"))
+        .args(["pack", "-q"])
+        .args(args)
+        .args(["bench_dir.vpk", "in"])
+        .current_dir(dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+fn main() {
+    let dir = std::env::temp_dir().join(format!("rvpk-bench-pack-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+
+    let mut data = vec![0u8; FILE_SIZE];
+    for index in 0..FILE_COUNT {
+        for (pos, byte) in data.iter_mut().enumerate() {
+            *byte = (pos * 31 + index * 17) as u8;
+        }
+        let path = dir.join(format!("in/d{}/f{}.bin", index % 8, index));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, &data).unwrap();
+    }
+    let input_size = (FILE_COUNT * FILE_SIZE) as f64;
+
+    for args in &[&[][..], &["--dedup"][..]] {
+        let mut best = f64::INFINITY;
+        let mut read = None;
+        for _ in 0..RUNS {
+            let before = read_bytes();
+            let start = Instant::now();
+            pack(&dir, args);
+            best = best.min(start.elapsed().as_secs_f64());
+            if let (Some(before), Some(after)) = (before, read_bytes()) {
+                read = Some(after - before);
+            }
+        }
+
+        print!("pack {:<8} {:>8.3} s {:>8.1} MiB/s", args.join(" "), best, input_size / best / (1024.0 * 1024.0));
+        if let Some(read) = read {
+            print!(", read {:.2}x the input size", read as f64 / input_size);
+        }
+        println!();
+    }
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -15,21 +15,44 @@
 
 use std::collections::HashMap;
 use std::fs;
-use std::io::{Read, Write, SeekFrom, Seek};
-use std::path::{PathBuf};
+use std::io::{Read, Write, SeekFrom, Seek, ErrorKind};
+use std::time::Duration;
+use std::path::{Path, PathBuf};
 
 use crate::consts::{BUFFER_SIZE, DIR_INDEX};
 use crate::result::{Result, Error};
 use crate::entry::File;
+use crate::package::Package;
 use crate::util::{archive_path};
-use crate::io::transfer;
+use crate::io::{transfer, transfer_with_progress};
 
+/// Keeps the archives of a package open, so reading the data of many files
+/// doesn't need to open the same archive again and again.
+///
+/// ```ignore
+/// let package = Package::from_path("pak01_dir.vpk", ReadOptions::new())?;
+/// let mut archs = ArchiveCache::for_package(&package);
+///
+/// if let Some(file) = package.get_file("materials/foo.vmt") {
+///     let data = archs.read_file_bytes(file)?;
+///     println!("{} bytes", data.len());
+/// }
+/// ```
 pub struct ArchiveCache {
     dirpath: PathBuf,
     prefix: String,
+    /// Directory of the _NNN.vpk archives if it differs from `dirpath`.
+    archive_dirpath: Option<PathBuf>,
     dir_open_options: fs::OpenOptions,
     open_options: fs::OpenOptions,
     archives: HashMap<u16, fs::File>,
+    buffer_size: usize,
+    buf: Vec<u8>,
+    retries: u32,
+    max_open_files: usize,
+    /// Open archives, least recently used first. Only maintained if
+    /// `max_open_files` is set.
+    lru: Vec<u16>,
 }
 
 impl ArchiveCache {
@@ -41,6 +64,18 @@ impl ArchiveCache {
         &self.prefix
     }
 
+    pub fn archive_dirpath(&self) -> &Path {
+        self.archive_dirpath.as_deref().unwrap_or(&self.dirpath)
+    }
+
+    /// Open the _NNN.vpk archives in this directory instead of `dirpath`.
+    /// The _dir.vpk file is always opened in `dirpath`.
+    pub fn set_archive_dirpath(&mut self, archive_dirpath: Option<PathBuf>) {
+        self.archive_dirpath = archive_dirpath;
+        self.archives.retain(|index, _| *index == DIR_INDEX);
+        self.lru.retain(|index| *index == DIR_INDEX);
+    }
+
     pub fn dir_open_options(&self) -> &fs::OpenOptions {
         &self.dir_open_options
     }
@@ -53,16 +88,74 @@ impl ArchiveCache {
         &self.archives
     }
 
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Size of the chunks in which file data is read and copied. 0 is
+    /// rejected, because reading in empty chunks would never finish.
+    pub fn set_buffer_size(&mut self, buffer_size: usize) -> Result<()> {
+        if buffer_size == 0 {
+            return Err(Error::illegal_argument("buffer_size", "0"));
+        }
+        self.buffer_size = buffer_size;
+        self.buf = Vec::new();
+        Ok(())
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// How often reading a chunk of file data is retried after a transient
+    /// I/O error (e.g. on a network share), waiting longer before each try.
+    /// Defaults to 0.
+    pub fn set_retries(&mut self, retries: u32) {
+        self.retries = retries;
+    }
+
+    pub fn max_open_files(&self) -> usize {
+        self.max_open_files
+    }
+
+    /// Keep at most this many archives open at once, closing the least
+    /// recently used archive when another one needs to be opened. 0 means
+    /// no limit, which is the default. Only use this with caches created by
+    /// [`ArchiveCache::for_reading`], archives opened for writing would be
+    /// truncated when they are opened again.
+    pub fn set_max_open_files(&mut self, max_open_files: usize) {
+        self.max_open_files = max_open_files;
+        self.lru.clear();
+        if max_open_files > 0 {
+            // the order of already open archives is unknown
+            self.lru.extend(self.archives.keys());
+            while self.archives.len() > max_open_files {
+                let index = self.lru.remove(0);
+                self.archives.remove(&index);
+            }
+        }
+    }
+
     pub fn new(dirpath: PathBuf, prefix: String, dir_open_options: fs::OpenOptions, open_options: fs::OpenOptions) -> ArchiveCache {
         ArchiveCache {
             dirpath,
             prefix,
+            archive_dirpath: None,
             dir_open_options,
             open_options,
             archives: HashMap::new(),
+            buffer_size: BUFFER_SIZE,
+            buf: Vec::new(),
+            retries: 0,
+            max_open_files: 0,
+            lru: Vec::new(),
         }
     }
 
+    /// Open archives `{dirpath}/{prefix}_{NNN}.vpk` for reading. Use
+    /// [`Package::dirpath`](crate::package::Package::dirpath) and
+    /// [`Package::prefix`](crate::package::Package::prefix) of the package
+    /// the files belong to.
     pub fn for_reading(dirpath: PathBuf, prefix: String) -> Self {
         let mut dir_opts = fs::OpenOptions::new();
         dir_opts.read(true);
@@ -73,7 +166,17 @@ impl ArchiveCache {
         ArchiveCache::new(dirpath, prefix, dir_opts, opts)
     }
 
+    /// Open the archives of `package` for reading, including a different
+    /// archive directory (see [`Package::archive_dirpath`](crate::package::Package::archive_dirpath)).
+    pub fn for_package(package: &Package) -> Self {
+        let mut archs = ArchiveCache::for_reading(package.dirpath().to_path_buf(), package.prefix().to_owned());
+        archs.archive_dirpath = package.archive_dirpath.clone();
+        archs
+    }
+
     /// Assumes that the index in *_dir.vpk is already written.
+    /// Not part of the stable API.
+    #[doc(hidden)]
     pub fn for_writing(dirpath: PathBuf, prefix: String) -> Self {
         let mut dir_opts = fs::OpenOptions::new();
         dir_opts.write(true).create_new(false).truncate(false);
@@ -84,9 +187,26 @@ impl ArchiveCache {
         ArchiveCache::new(dirpath, prefix, dir_opts, opts)
     }
 
+    /// Get the archive with the given index, opening it if it isn't open
+    /// yet. The position of the returned file is unspecified. If the archive
+    /// doesn't exist the error is [`ErrorType::MissingArchive`](crate::result::ErrorType::MissingArchive).
     pub fn get(&mut self, index: u16) -> Result<&mut fs::File> {
-        if !self.archives.contains_key(&index) {
-            let path = archive_path(&self.dirpath, &self.prefix, index);
+        if self.archives.contains_key(&index) {
+            if self.max_open_files > 0 {
+                if let Some(pos) = self.lru.iter().position(|&item| item == index) {
+                    self.lru.remove(pos);
+                }
+                self.lru.push(index);
+            }
+        } else {
+            if self.max_open_files > 0 {
+                while self.archives.len() >= self.max_open_files {
+                    let oldest = self.lru.remove(0);
+                    self.archives.remove(&oldest);
+                }
+            }
+
+            let path = self.archive_path(index);
             let reader = if index == DIR_INDEX {
                 self.dir_open_options.open(&path)
             } else {
@@ -95,6 +215,12 @@ impl ArchiveCache {
             match reader {
                 Ok(reader) => {
                     self.archives.insert(index, reader);
+                    if self.max_open_files > 0 {
+                        self.lru.push(index);
+                    }
+                },
+                Err(error) if error.kind() == ErrorKind::NotFound => {
+                    return Err(Error::missing_archive(index).with_path(path));
                 },
                 Err(error) => {
                     return Err(Error::io_with_path(error, path));
@@ -107,64 +233,461 @@ impl ArchiveCache {
 
     #[inline]
     pub fn archive_path(&self, index: u16) -> PathBuf {
-        archive_path(&self.dirpath, &self.prefix, index)
+        if index == DIR_INDEX {
+            archive_path(&self.dirpath, &self.prefix, index)
+        } else {
+            archive_path(self.archive_dirpath(), &self.prefix, index)
+        }
     }
 
+    /// Pass the data of `file` in chunks to `callback`. The archive is only
+    /// opened if the file isn't fully inlined.
     pub fn read_file_data(&mut self, file: &File, mut callback: impl FnMut(&[u8]) -> Result<()>) -> Result<()> {
         callback(&file.preload)?;
 
         if file.size > 0 {
+            // the buffer is taken out so it can be used alongside the reader
+            let mut buf = std::mem::take(&mut self.buf);
+            let buffer_size = self.buffer_size;
+            buf.resize(buffer_size, 0);
+
             let archive_index = file.archive_index;
+            let retries = self.retries;
             let reader = self.get(archive_index)?;
 
-            if let Err(error) = reader.seek(SeekFrom::Start(file.offset as u64)) {
+            let mut offset = file.offset as u64;
+            if let Err(error) = reader.seek(SeekFrom::Start(offset)) {
                 return Err(Error::io_with_path(error, self.archive_path(archive_index)));
             }
 
-            #[cfg(not(os_name = "windows"))]
-            let mut buf = [0u8; BUFFER_SIZE];
-
-            // Windows gets a stack overflow otherwise:
-            #[cfg(os_name = "windows")]
-            let mut buf = Vec::with_capacity(BUFFER_SIZE);
-            #[cfg(os_name = "windows")]
-            buf.resize(BUFFER_SIZE, 0);
-
             let mut remain = file.size as usize;
-            while remain >= BUFFER_SIZE {
-                if let Err(error) = reader.read_exact(&mut buf) {
+            while remain > 0 {
+                let buf = &mut buf[..remain.min(buffer_size)];
+                if let Err(error) = read_chunk(reader, buf, offset, retries) {
                     return Err(Error::io_with_path(error, self.archive_path(archive_index)));
                 }
-                callback(&buf)?;
-                remain -= BUFFER_SIZE;
+                callback(buf)?;
+                offset += buf.len() as u64;
+                remain -= buf.len();
             }
 
-            if remain > 0 {
-                let buf = &mut buf[..remain];
-                if let Err(error) = reader.read_exact(buf) {
-                    return Err(Error::io_with_path(error, self.archive_path(archive_index)));
-                }
-                callback(&buf)?;
-            }
+            self.buf = buf;
         }
 
         Ok(())
     }
 
+    /// Read the data of `file` chunk by chunk without copying it, e.g. to
+    /// feed it into a hasher or decoder. Unlike [`ArchiveCache::read_file_data`]
+    /// the caller drives the reading and can stop at any chunk. The archive is
+    /// only opened when the first chunk past the inlined data is requested.
+    /// Use [`Package::open_file`](crate::package::Package::open_file) if you
+    /// need a `Read` implementation instead.
+    ///
+    /// ```ignore
+    /// let mut chunks = archs.read_file_chunks(file);
+    /// while let Some(chunk) = chunks.next_chunk() {
+    ///     hasher.update(chunk?);
+    /// }
+    /// ```
+    pub fn read_file_chunks<'a>(&'a mut self, file: &'a File) -> FileChunks<'a> {
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.resize(self.buffer_size, 0);
+
+        FileChunks {
+            cache: self,
+            file,
+            buf,
+            preload_done: file.preload.is_empty(),
+            offset: file.offset as u64,
+            remain: file.size as usize,
+            seeked: false,
+        }
+    }
+
+    /// Read the whole data of `file` into memory.
+    pub fn read_file_bytes(&mut self, file: &File) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(file.inline_size as usize + file.size as usize);
+        self.read_file_data(file, |chunk| {
+            data.extend_from_slice(chunk);
+            Ok(())
+        })?;
+        Ok(data)
+    }
+
+    /// Write the data of `file` to `writer`. The archive is only opened if
+    /// the file isn't fully inlined.
     pub fn transfer(&mut self, file: &File, writer: &mut fs::File) -> Result<()> {
+        if self.retries > 0 {
+            // read in chunks, so only a failed chunk needs to be read again
+            return self.read_file_data(file, |data| {
+                writer.write_all(data)?;
+                Ok(())
+            });
+        }
+
         writer.write_all(&file.preload)?;
-        
+
         if file.size > 0 {
             let archive_index = file.archive_index;
+            let buffer_size = self.buffer_size;
             let reader = self.get(archive_index)?;
 
             if let Err(error) = reader.seek(SeekFrom::Start(file.offset as u64)) {
                 return Err(Error::io_with_path(error, self.archive_path(archive_index)));
             }
 
-            transfer(reader, writer, file.size as usize)?;
+            transfer(reader, writer, file.size as usize, buffer_size)?;
         }
 
         Ok(())
     }
+
+    /// Like [`ArchiveCache::transfer`], but `progress` is called with the
+    /// number of bytes of every written chunk, including the inlined data.
+    pub fn transfer_with_progress(&mut self, file: &File, writer: &mut fs::File, progress: &mut impl FnMut(usize)) -> Result<()> {
+        if self.retries > 0 {
+            // read in chunks, so only a failed chunk needs to be read again
+            return self.read_file_data(file, |data| {
+                writer.write_all(data)?;
+                progress(data.len());
+                Ok(())
+            });
+        }
+
+        if !file.preload.is_empty() {
+            writer.write_all(&file.preload)?;
+            progress(file.preload.len());
+        }
+
+        if file.size > 0 {
+            let archive_index = file.archive_index;
+            let buffer_size = self.buffer_size;
+            let reader = self.get(archive_index)?;
+
+            if let Err(error) = reader.seek(SeekFrom::Start(file.offset as u64)) {
+                return Err(Error::io_with_path(error, self.archive_path(archive_index)));
+            }
+
+            transfer_with_progress(reader, writer, file.size as usize, buffer_size, progress)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Chunks of the data of a file as returned by
+/// [`ArchiveCache::read_file_chunks`]. This isn't an `Iterator`, because the
+/// returned chunks borrow the internal buffer, which is reused for the next
+/// chunk.
+pub struct FileChunks<'a> {
+    cache: &'a mut ArchiveCache,
+    file: &'a File,
+    buf: Vec<u8>,
+    preload_done: bool,
+    offset: u64,
+    remain: usize,
+    seeked: bool,
+}
+
+impl FileChunks<'_> {
+    #[inline]
+    pub fn file(&self) -> &File {
+        self.file
+    }
+
+    /// Number of bytes that weren't returned yet.
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        let preload = if self.preload_done { 0 } else { self.file.preload.len() };
+        preload + self.remain
+    }
+
+    /// The next chunk of file data, starting with the inlined data, or `None`
+    /// if all data was returned. After an error no more chunks are returned.
+    pub fn next_chunk(&mut self) -> Option<Result<&[u8]>> {
+        if !self.preload_done {
+            self.preload_done = true;
+            return Some(Ok(&self.file.preload));
+        }
+
+        if self.remain == 0 {
+            return None;
+        }
+
+        let archive_index = self.file.archive_index;
+        let retries = self.cache.retries;
+        let len = self.remain.min(self.buf.len());
+        let reader = match self.cache.get(archive_index) {
+            Ok(reader) => reader,
+            Err(error) => {
+                self.remain = 0;
+                return Some(Err(error));
+            }
+        };
+
+        let buf = &mut self.buf[..len];
+        let offset = self.offset;
+        let mut result = Ok(());
+        if !self.seeked {
+            result = reader.seek(SeekFrom::Start(offset)).map(|_| ());
+            self.seeked = true;
+        }
+        if let Err(error) = result.and_then(|_| read_chunk(reader, buf, offset, retries)) {
+            self.remain = 0;
+            return Some(Err(Error::io_with_path(error, self.cache.archive_path(archive_index))));
+        }
+
+        self.offset += buf.len() as u64;
+        self.remain -= buf.len();
+
+        Some(Ok(buf))
+    }
+}
+
+impl Drop for FileChunks<'_> {
+    fn drop(&mut self) {
+        // give the buffer back for reuse
+        self.cache.buf = std::mem::take(&mut self.buf);
+    }
+}
+
+/// Read `buf.len()` bytes from the current position, which has to be
+/// `offset`. Retries up to `retries` times on transient errors.
+fn read_chunk<R: Read + Seek>(reader: &mut R, buf: &mut [u8], offset: u64, retries: u32) -> std::io::Result<()> {
+    let mut attempt = 0;
+    let mut result = reader.read_exact(buf);
+    loop {
+        match result {
+            Err(error) if attempt < retries && is_transient(&error) => {
+                // 100 ms, 200 ms, 400 ms, ... but at most 10 s
+                let delay = 100u64.saturating_mul(1 << attempt.min(7)).min(10_000);
+                std::thread::sleep(Duration::from_millis(delay));
+                attempt += 1;
+                // a failed read_exact leaves the position unspecified
+                result = reader.seek(SeekFrom::Start(offset)).and_then(|_| reader.read_exact(buf));
+            },
+            result => return result,
+        }
+    }
+}
+
+fn is_transient(error: &std::io::Error) -> bool {
+    matches!(error.kind(),
+        ErrorKind::Interrupted | ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::package::ReadOptions;
+    use crate::hash::CrcAlgorithm;
+    use crate::util::{TempDir, test_dir_vpk};
+
+    /// Data of "m/split.bin", the first 4 bytes are inlined.
+    fn split_data() -> Vec<u8> {
+        (0..1004u32).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    /// A package with a file that is split into inlined data and data in
+    /// archive 0 and a file stored in the _dir.vpk file.
+    fn split_package(tmp: &TempDir) -> Package {
+        let data = split_data();
+        tmp.write("pak_000.vpk", &data[4..]);
+        let path = tmp.write("pak_dir.vpk", &test_dir_vpk(&[
+            ("m/split.bin", 0, 0, 1000, &data[..4]),
+            ("m/dir.bin", DIR_INDEX, 0, 500, b""),
+        ], &data[..500]));
+        Package::from_path(path, ReadOptions::new()).unwrap()
+    }
+
+    #[test]
+    fn tiny_buffer_sizes() {
+        let tmp = TempDir::new("tiny-buffer-sizes");
+        let package = split_package(&tmp);
+        let data = split_data();
+        let mut archs = ArchiveCache::for_package(&package);
+
+        assert!(archs.set_buffer_size(0).is_err());
+        assert_eq!(archs.buffer_size(), BUFFER_SIZE);
+
+        for buffer_size in [1, 7, BUFFER_SIZE] {
+            archs.set_buffer_size(buffer_size).unwrap();
+            for (path, expected) in [("m/split.bin", &data[..]), ("m/dir.bin", &data[..500])] {
+                let file = package.get_file(path).unwrap();
+
+                let mut max_chunk = 0;
+                let mut read = Vec::new();
+                archs.read_file_data(file, |chunk| {
+                    if !std::ptr::eq(chunk, &file.preload[..]) {
+                        max_chunk = max_chunk.max(chunk.len());
+                    }
+                    read.extend_from_slice(chunk);
+                    Ok(())
+                }).unwrap();
+                assert_eq!(read, expected, "{} with buffer size {}", path, buffer_size);
+                assert!(max_chunk <= buffer_size);
+
+                let out = tmp.path.join("out.bin");
+                let mut writer = fs::File::create(&out).unwrap();
+                archs.transfer(file, &mut writer).unwrap();
+                drop(writer);
+                assert_eq!(fs::read(&out).unwrap(), expected, "{} with buffer size {}", path, buffer_size);
+            }
+        }
+    }
+
+    #[test]
+    fn file_chunk_lengths_sum_to_file_size() {
+        let tmp = TempDir::new("file-chunks");
+        let package = split_package(&tmp);
+        let mut archs = ArchiveCache::for_package(&package);
+
+        for buffer_size in [1, 3, 64, 1000, BUFFER_SIZE] {
+            archs.set_buffer_size(buffer_size).unwrap();
+            for path in ["m/split.bin", "m/dir.bin"] {
+                let file = package.get_file(path).unwrap();
+                let full_size = file.inline_size as usize + file.size as usize;
+
+                let mut lengths = Vec::new();
+                let mut chunks = archs.read_file_chunks(file);
+                assert_eq!(chunks.remaining(), full_size);
+                while let Some(chunk) = chunks.next_chunk() {
+                    lengths.push(chunk.unwrap().len());
+                    assert_eq!(chunks.remaining(), full_size - lengths.iter().sum::<usize>());
+                }
+                assert!(chunks.next_chunk().is_none());
+
+                assert_eq!(lengths.iter().sum::<usize>(), full_size, "{} with buffer size {}", path, buffer_size);
+                // the preload comes first, then chunks of at most the buffer size
+                let archive_chunks = match file.preload.len() {
+                    0 => &lengths[..],
+                    len => {
+                        assert_eq!(lengths[0], len);
+                        &lengths[1..]
+                    }
+                };
+                assert_eq!(archive_chunks.len(), (file.size as usize).div_ceil(buffer_size), "{} with buffer size {}", path, buffer_size);
+                assert!(archive_chunks.iter().all(|&len| len > 0 && len <= buffer_size));
+            }
+        }
+    }
+
+    #[test]
+    fn read_file_bytes_of_package() {
+        let tmp = TempDir::new("read-file-bytes");
+        let package = split_package(&tmp);
+        let data = split_data();
+
+        let mut archs = ArchiveCache::for_package(&package);
+        let split = package.get_file("m/split.bin").unwrap();
+        let dir = package.get_file("m/dir.bin").unwrap();
+        assert_eq!(archs.read_file_bytes(split).unwrap(), data);
+        assert_eq!(archs.read_file_bytes(dir).unwrap(), &data[..500]);
+        // archives stay open for further reads
+        assert_eq!(archs.archives().len(), 2);
+        assert_eq!(archs.read_file_bytes(split).unwrap(), data);
+
+        let mut archs = ArchiveCache::for_reading(package.dirpath().to_path_buf(), package.prefix().to_owned());
+        assert_eq!(archs.read_file_bytes(split).unwrap(), data);
+        assert!(archs.get(0).is_ok());
+
+        let error = archs.get(1).unwrap_err();
+        assert!(error.is_missing_archive());
+        let missing = File { archive_index: 1, ..split.clone() };
+        assert!(archs.read_file_bytes(&missing).unwrap_err().is_missing_archive());
+    }
+
+    #[test]
+    fn inline_only_without_archives() {
+        let tmp = TempDir::new("inline-only");
+        let path = tmp.write("pak_dir.vpk", &test_dir_vpk(&[
+            ("m/a.txt",     0, 0, 0, b"hello"),
+            ("m/b.txt",     3, 0, 0, b"world"),
+            ("m/empty.txt", 1, 0, 0, b""),
+        ], b""));
+        let package = Package::from_path(path, ReadOptions::new()).unwrap();
+        let mut archs = ArchiveCache::for_package(&package);
+
+        for (path, expected) in [("m/a.txt", &b"hello"[..]), ("m/b.txt", b"world"), ("m/empty.txt", b"")] {
+            let file = package.get_file(path).unwrap();
+            assert_eq!(archs.read_file_bytes(file).unwrap(), expected, "{}", path);
+
+            let mut read = Vec::new();
+            {
+                let mut chunks = archs.read_file_chunks(file);
+                while let Some(chunk) = chunks.next_chunk() {
+                    read.extend_from_slice(chunk.unwrap());
+                }
+            }
+            assert_eq!(read, expected, "{}", path);
+
+            for retries in [0, 1] {
+                archs.set_retries(retries);
+                let out = tmp.path.join("out.txt");
+                let mut writer = fs::File::create(&out).unwrap();
+                archs.transfer(file, &mut writer).unwrap();
+                drop(writer);
+                assert_eq!(fs::read(&out).unwrap(), expected, "{}", path);
+            }
+
+            let mut read = Vec::new();
+            package.open_file(path).unwrap().read_to_end(&mut read).unwrap();
+            assert_eq!(read, expected, "{}", path);
+
+            assert!(package.verify_file_crc(file, &mut archs, CrcAlgorithm::IEEE).unwrap());
+        }
+
+        assert!(archs.archives().is_empty());
+        assert!(package.validate_offsets().is_ok());
+    }
+
+    /// Returns the first `partial` bytes, then fails `failures` times with
+    /// a transient error, then reads normally.
+    struct FlakyReader {
+        inner: Cursor<Vec<u8>>,
+        partial: usize,
+        failures: u32,
+    }
+
+    impl Read for FlakyReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.partial > 0 {
+                let len = self.partial.min(buf.len());
+                self.partial -= len;
+                return self.inner.read(&mut buf[..len]);
+            }
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(std::io::Error::new(ErrorKind::TimedOut, "flaky"));
+            }
+            self.inner.read(buf)
+        }
+    }
+
+    impl Seek for FlakyReader {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    fn flaky_reader(failures: u32) -> FlakyReader {
+        let mut inner = Cursor::new((0..100).collect());
+        inner.set_position(10);
+        FlakyReader { inner, partial: 3, failures }
+    }
+
+    #[test]
+    fn read_chunk_retries_transient_errors() {
+        let mut buf = [0; 8];
+        read_chunk(&mut flaky_reader(1), &mut buf, 10, 1).unwrap();
+        assert_eq!(buf, [10, 11, 12, 13, 14, 15, 16, 17]);
+
+        let error = read_chunk(&mut flaky_reader(1), &mut buf, 10, 0).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+
+        let error = read_chunk(&mut flaky_reader(3), &mut buf, 10, 2).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+    }
 }
@@ -13,23 +13,64 @@
 // You should have received a copy of the GNU General Public License
 // along with rust-vpk.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::io::{Write, Read, Seek, SeekFrom};
+use std::io::Write;
+use std::ops::Range;
+use std::path::Path;
 
-use crc::{crc32, Hasher32};
+use crc::Hasher32;
 
 use crate::sort::PHYSICAL_ORDER;
 use crate::archive_cache::ArchiveCache;
 use crate::package::{Package, Md5};
+use crate::entry::File;
 use crate::result::{Result, Error};
-use crate::consts::{DIR_INDEX, BUFFER_SIZE, V2_HEADER_SIZE};
-use crate::util::format_size;
+use crate::consts::{DIR_INDEX, BUFFER_SIZE};
+use crate::util::{format_archive, format_size, retain_extensions, json_string, json_document};
+use crate::hash::{HashAlgorithm, CrcAlgorithm, Hasher, Sidecar, sidecar_path};
+use crate::color::{paint, Color};
+use crate::warnings::Warnings;
+use crate::io::md5_of_range;
 
 pub struct CheckOptions<'a> {
     pub verbose:        bool,
+    /// Don't print warnings. Errors are still reported.
+    pub quiet:          bool,
     pub stop_on_error:  bool,
     pub human_readable: bool,
     pub filter:    Option<&'a [&'a str]>,
+    pub extensions: Option<&'a [&'a str]>,
     pub alignment: Option<u32>,
+    pub check_overlaps: bool,
+    pub max_gap:   Option<u64>,
+    /// Must not be 0.
+    pub buffer_size:    usize,
+    pub retries:        u32,
+    pub max_open_files: usize,
+    /// Algorithm of the sidecar file to check the files against. If not
+    /// given it is derived from the extension of `sidecar`.
+    pub sidecar_hash:   Option<HashAlgorithm>,
+    /// Sidecar file to check the files against. If neither this nor
+    /// `sidecar_hash` is given the sidecar file next to the package is used,
+    /// if there is one. The sidecar checksum is calculated in the same pass
+    /// over the file data as the CRC32 sum.
+    pub sidecar:        Option<&'a Path>,
+    /// Polynomial of the CRC32 sums in the index.
+    pub crc:            CrcAlgorithm,
+    pub color:          bool,
+    /// Print one JSON object per checked file and a summary object
+    /// instead of human readable output.
+    pub json:           bool,
+    /// Only check the MD5 sums of the index, the archive MD5 list, and the
+    /// whole _dir.vpk file of a version 2 package. Neither file data nor
+    /// the data archives are read.
+    pub md5_only:       bool,
+    /// In verbose mode print a header for each archive and indent its
+    /// files below it instead of one flat list.
+    pub group_by_archive: bool,
+    /// Skip files and archive MD5 sections whose archive doesn't exist
+    /// instead of failing, e.g. for partially downloaded packages. The
+    /// number of skipped items is reported as a warning at the end.
+    pub ignore_missing: bool,
 }
 
 impl CheckOptions<'_> {
@@ -44,72 +85,241 @@ impl Default for CheckOptions<'_> {
     fn default() -> Self {
         Self {
             verbose:        false,
+            quiet:          false,
             stop_on_error:  false,
             human_readable: false,
             filter:    None,
+            extensions: None,
             alignment: None,
+            check_overlaps: false,
+            max_gap:   None,
+            buffer_size:    BUFFER_SIZE,
+            retries:        0,
+            max_open_files: 0,
+            sidecar_hash:   None,
+            sidecar:        None,
+            crc:            CrcAlgorithm::default(),
+            color:          false,
+            json:           false,
+            md5_only:       false,
+            group_by_archive: false,
+            ignore_missing: false,
         }
     }
 }
 
-fn check_range(arch: &mut std::fs::File, buf: &mut [u8], offset: u64, size: u64, expected: &Md5, what: &str, verbose: bool) -> std::io::Result<bool> {
-    if verbose {
-        print!("checking MD5 sum of {}... ", what);
-        let _ = std::io::stdout().flush();
-    }
+#[inline]
+fn paint_ok(color: bool) -> String {
+    paint("OK", Color::Green, color)
+}
 
-    if let Err(error) = arch.seek(SeekFrom::Start(offset)) {
-        if verbose {
-            println!("FAILED");
+#[inline]
+fn paint_failed(color: bool) -> String {
+    paint("FAILED", Color::Red, color)
+}
+
+#[inline]
+fn paint_skipped(color: bool) -> String {
+    paint("SKIPPED", Color::Bold, color)
+}
+
+/// Read the data of `file` once, calculating its CRC32 sum and feeding it
+/// into `hasher` if given.
+fn read_sums(file: &File, archs: &mut ArchiveCache, crc: CrcAlgorithm, mut hasher: Option<&mut Box<dyn Hasher>>) -> Result<u32> {
+    let mut digest = crc.digest();
+    archs.read_file_data(file, |data| {
+        digest.write(data);
+        if let Some(hasher) = &mut hasher {
+            hasher.update(data);
         }
-        return Err(error);
+        Ok(())
+    })?;
+
+    Ok(digest.sum32())
+}
+
+/// The sidecar file to check against, see [`CheckOptions::sidecar`].
+fn open_sidecar(package: &Package, options: &CheckOptions) -> Result<Option<Sidecar>> {
+    let (path, algorithm) = match (options.sidecar, options.sidecar_hash) {
+        (Some(path), Some(algorithm)) => (path.to_path_buf(), algorithm),
+        (Some(path), None) => match HashAlgorithm::from_path(path) {
+            Some(algorithm) => (path.to_path_buf(), algorithm),
+            None => return Err(Error::other(
+                "cannot derive the hash algorithm from the sidecar file name, use --sidecar-hash").
+                with_path(path)),
+        },
+        (None, Some(algorithm)) => (sidecar_path(package, algorithm), algorithm),
+        (None, None) => match Sidecar::find(package) {
+            Some(found) => found,
+            None => return Ok(None),
+        },
+    };
+
+    if options.verbose {
+        println!("checking files also against {:?}", path);
     }
 
-    let mut hasher = md5::Context::new();
-    let mut remaining = size;
-    while remaining >= buf.len() as u64 {
-        if let Err(error) = arch.read_exact(buf) {
-            if verbose {
-                println!("FAILED");
-            }
-            return Err(error);
-        }
-        remaining -= buf.len() as u64;
-        hasher.consume(&buf);
+    Ok(Some(Sidecar::read(path, algorithm)?))
+}
+
+/// Where the archive data of `file` is stored, e.g. for pinpointing
+/// corrupted data with a hex editor.
+fn data_location(file: &File) -> String {
+    format!("archive {} at offset {} with size {}",
+        format_archive(file.archive_index), file.offset, file.size)
+}
+
+pub(crate) fn check_range(arch: &mut std::fs::File, buf: &mut [u8], range: Range<u64>, expected: &Md5, what: &str, verbose: bool, color: bool) -> std::io::Result<bool> {
+    if verbose {
+        print!("checking MD5 sum of {}... ", what);
+        let _ = std::io::stdout().flush();
     }
 
-    if remaining > 0 {
-        let buf = &mut buf[..remaining as usize];
-        if let Err(error) = arch.read_exact(buf) {
+    let sum = match md5_of_range(arch, buf, range) {
+        Ok(sum) => sum,
+        Err(error) => {
             if verbose {
-                println!("FAILED");
+                println!("{}", paint_failed(color));
             }
             return Err(error);
         }
-        hasher.consume(buf);
-    }
+    };
 
-    let sum = *hasher.compute();
     if expected != &sum {
         if verbose {
-            println!("FAILED");
+            println!("{}", paint_failed(color));
         } else {
             eprintln!("checking MD5 of {} failed", what);
         }
         return Ok(false);
     } else if verbose {
-        println!("OK");
+        println!("{}", paint_ok(color));
     }
 
     Ok(true)
 }
 
+/// Expects files to be sorted in physical order.
+/// Returns the number of found problems.
+fn check_overlaps(files: &[(String, &File)], max_gap: Option<u64>, verbose: bool, color: bool) -> usize {
+    if verbose {
+        print!("checking for overlapping file data... ");
+        let _ = std::io::stdout().flush();
+    }
+
+    let mut problem_count = 0usize;
+    // (archive_index, offset, end offset, path) of the file reaching furthest into the current archive
+    let mut prev: Option<(u16, u64, u64, &str)> = None;
+
+    for (path, file) in files {
+        if file.size == 0 {
+            continue;
+        }
+
+        let offset = file.offset as u64;
+        let end    = offset + file.size as u64;
+
+        match prev {
+            Some((archive_index, prev_offset, prev_end, prev_path)) if archive_index == file.archive_index => {
+                if offset == prev_offset && end == prev_end {
+                    // deduplicated files share the same data
+                } else if offset < prev_end {
+                    if verbose && problem_count == 0 {
+                        println!("{}", paint_failed(color));
+                    }
+                    eprintln!("archive {}: {} overlaps with {} by {} bytes",
+                        format_archive(archive_index), path, prev_path,
+                        if end < prev_end { file.size as u64 } else { prev_end - offset });
+                    problem_count += 1;
+                } else if let Some(max_gap) = max_gap {
+                    let gap = offset - prev_end;
+                    if gap > max_gap {
+                        if verbose && problem_count == 0 {
+                            println!("{}", paint_failed(color));
+                        }
+                        eprintln!("archive {}: gap of {} bytes between {} and {}",
+                            format_archive(archive_index), gap, prev_path, path);
+                        problem_count += 1;
+                    }
+                }
+
+                if end > prev_end {
+                    prev = Some((archive_index, offset, end, path));
+                }
+            },
+            _ => {
+                prev = Some((file.archive_index, offset, end, path));
+            }
+        }
+    }
+
+    if verbose && problem_count == 0 {
+        println!("{}", paint_ok(color));
+    }
+
+    problem_count
+}
+
+/// Counts of checked items, reported as summary with `--json`.
+#[derive(Debug, Default)]
+struct CheckCounts {
+    files:            usize,
+    failed_files:     usize,
+    failed_md5s:      usize,
+    overlaps:         usize,
+    sidecar_failures: usize,
+    skipped_files:    usize,
+    skipped_md5s:     usize,
+}
+
 pub fn check(package: &Package, options: CheckOptions) -> Result<()> {
-    let mut digest = crc32::Digest::new(crc32::IEEE);
-    let mut archs  = ArchiveCache::for_reading(package.dirpath.to_path_buf(), package.prefix.to_string());
+    let mut warnings = Warnings::new(options.verbose, options.quiet);
+    warnings.warn_duplicates(package);
+    warnings.warn_truncated(package);
+
+    let mut counts = CheckCounts::default();
+    let result = check_package(package, &options, &mut counts);
+    if counts.skipped_files > 0 || counts.skipped_md5s > 0 {
+        warnings.warn(format!("skipped {} file(s) and {} MD5 section(s) in missing archives",
+            counts.skipped_files, counts.skipped_md5s));
+    }
+    warnings.print_summary();
+
+    if options.json {
+        // also printed if the check stopped early, so the output is always complete
+        println!("{}", json_document(&format!(
+            "\"type\":\"summary\",\"files\":{},\"failed_files\":{},\"failed_md5_sections\":{},\"overlaps\":{},\"sidecar_failures\":{},\"skipped_files\":{},\"skipped_md5_sections\":{},\"ok\":{},\"error\":{}",
+            counts.files,
+            counts.failed_files,
+            counts.failed_md5s,
+            counts.overlaps,
+            counts.sidecar_failures,
+            counts.skipped_files,
+            counts.skipped_md5s,
+            result.is_ok(),
+            match &result {
+                Ok(()) => "null".to_owned(),
+                Err(error) => json_string(&error.to_string()),
+            })));
+    }
+
+    result
+}
+
+fn check_package(package: &Package, options: &CheckOptions, counts: &mut CheckCounts) -> Result<()> {
+    if options.md5_only && package.version < 2 {
+        return Err(Error::other(format!(
+            "nothing to check with --md5-only: version {} packages have no MD5 sums",
+            package.version)));
+    }
+
+    let mut archs = ArchiveCache::for_package(package);
+    archs.set_buffer_size(options.buffer_size)?;
+    archs.set_retries(options.retries);
+    archs.set_max_open_files(options.max_open_files);
     let mut stdout = std::io::stdout();
-    let mut faild_files_count = 0usize;
     let alignment = options.alignment.unwrap_or(0);
+    let color = options.color;
 
     let fmt_size = if options.human_readable {
         |size: u64| format_size(size)
@@ -117,15 +327,7 @@ pub fn check(package: &Package, options: CheckOptions) -> Result<()> {
         |size: u64| format!("{}", size)
     };
 
-    if options.verbose {
-        if alignment > 0 {
-            println!("Archive      Offset   Unaligned  Inline-Size  Archive-Size       CRC32  Filename");
-        } else {
-            println!("Archive      Offset  Inline-Size  Archive-Size       CRC32  Filename");
-        }
-    }
-
-    let files = match options.filter {
+    let mut files = match options.filter {
         None => {
             package.recursive_file_list(&PHYSICAL_ORDER)
         },
@@ -134,10 +336,53 @@ pub fn check(package: &Package, options: CheckOptions) -> Result<()> {
         }
     };
 
+    if let Some(extensions) = options.extensions {
+        retain_extensions(&mut files, extensions);
+    }
+
+    if options.check_overlaps {
+        counts.overlaps = check_overlaps(&files, options.max_gap, options.verbose, color);
+
+        if counts.overlaps > 0 && options.stop_on_error {
+            return Err(Error::other("package check failed"));
+        }
+
+        if options.verbose {
+            println!();
+        }
+    }
+
+    let sidecar = if options.md5_only {
+        // no file data is read, the index is covered by its MD5 sum
+        files.clear();
+        None
+    } else {
+        open_sidecar(package, options)?
+    };
+    let indent = if options.group_by_archive { "  " } else { "" };
+
+    if options.verbose && !options.md5_only {
+        if alignment > 0 {
+            println!("{}Archive      Offset   Unaligned  Inline-Size  Archive-Size       CRC32  Filename", indent);
+        } else {
+            println!("{}Archive      Offset  Inline-Size  Archive-Size       CRC32  Filename", indent);
+        }
+    }
+
+    // files are in physical order, so each archive is one consecutive run
+    let mut prev_archive_index = None;
+
     for (path, file) in files {
         let mut ok = true;
+        let mut skipped = false;
         let reminder = if alignment > 0 { file.offset % alignment } else { 0 };
         if options.verbose {
+            if options.group_by_archive && prev_archive_index != Some(file.archive_index) {
+                prev_archive_index = Some(file.archive_index);
+                println!();
+                println!("{}", paint(format!("archive {}:", format_archive(file.archive_index)), Color::Bold, color));
+            }
+            print!("{}", indent);
             if file.archive_index == DIR_INDEX {
                 print!("    dir");
             } else {
@@ -152,40 +397,69 @@ pub fn check(package: &Package, options: CheckOptions) -> Result<()> {
                 path);
             let _ = stdout.flush();
         }
-        digest.reset();
-        if let Err(error) = archs.read_file_data(file, |data| {
-            digest.write(data);
-            Ok(())
-        }) {
-            ok = false;
-            if options.verbose {
-                print!("FAILED, {}", error);
-            } else {
-                eprint!("{}: {}", path, error);
-            }
-        } else {
-            let sum = digest.sum32();
+        let mut actual_crc32 = None;
+        let mut read_error = None;
+        let mut sidecar_problem = None;
+        // a fresh hasher per file, so nothing of a failed read carries over
+        let mut hasher = sidecar.as_ref().map(|sidecar| sidecar.algorithm.hasher());
+        match read_sums(file, &mut archs, options.crc, hasher.as_mut()) {
+            Err(error) => {
+                if options.ignore_missing && error.is_missing_archive() {
+                    skipped = true;
+                    counts.skipped_files += 1;
+                    if options.verbose {
+                        print!("{}", paint_skipped(color));
+                    }
+                } else {
+                    ok = false;
+                    if options.verbose {
+                        print!("{}, {}", paint_failed(color), error);
+                    } else if !options.json {
+                        eprint!("{}: {}", path, error);
+                    }
+                    read_error = Some(error.to_string());
+                }
+            },
+            Ok(sum) => {
+                actual_crc32 = Some(sum);
+
+                if let (Some(sidecar), Some(hasher)) = (&sidecar, &mut hasher) {
+                    let actual = hasher.finish();
+                    match sidecar.get(&path) {
+                        None => {
+                            sidecar_problem = Some(format!("not listed in {:?}", sidecar.path));
+                        },
+                        Some(expected) if !actual.eq_ignore_ascii_case(expected) => {
+                            sidecar_problem = Some(format!("{} sum missmatch, expected: {}, actual: {}",
+                                sidecar.algorithm.extension(), expected, actual));
+                        },
+                        Some(_) => {},
+                    }
+                }
 
-            if options.verbose {
-                if sum != file.crc32 {
+                if options.verbose {
+                    if sum != file.crc32 {
+                        ok = false;
+                        print!("{}, CRC32 sum missmatch in {}, expected: 0x{:08x}, actual: 0x{:08x}",
+                            paint_failed(color), data_location(file), file.crc32, sum);
+                    }
+                } else if sum != file.crc32 {
                     ok = false;
-                    print!("FAILED, CRC32 sum missmatch, expected: 0x{:08x}, actual: 0x{:08x}",
-                        file.crc32, sum);
+                    if !options.json {
+                        eprint!("{}: CRC32 sum missmatch in {}, expected: 0x{:08x}, actual: 0x{:08x}",
+                            path, data_location(file), file.crc32, sum);
+                    }
                 }
-            } else if sum != file.crc32 {
-                ok = false;
-                eprint!("{}: CRC32 sum missmatch, expected: 0x{:08x}, actual: 0x{:08x}",
-                    path, file.crc32, sum);
             }
         }
 
         if reminder != 0 {
             if options.verbose {
-                if ok {
-                    print!("FAILED");
+                if ok && !skipped {
+                    print!("{}", paint_failed(color));
                 }
                 print!(", not aligned");
-            } else {
+            } else if !options.json {
                 if ok {
                     eprint!("{}: ", path);
                 } else {
@@ -196,74 +470,105 @@ pub fn check(package: &Package, options: CheckOptions) -> Result<()> {
             ok = false;
         }
 
-        if ok {
+        if let Some(problem) = &sidecar_problem {
+            if options.verbose {
+                if ok {
+                    print!("{}", paint_failed(color));
+                }
+                print!(", {}", problem);
+            } else if !options.json {
+                if ok {
+                    eprint!("{}: ", path);
+                } else {
+                    eprint!(", ");
+                }
+                eprint!("{}", problem);
+            }
+            counts.sidecar_failures += 1;
+        }
+
+        counts.files += 1;
+
+        if options.json {
+            println!("{}", json_document(&format!(
+                "\"type\":\"file\",\"path\":{},\"archive\":{},\"offset\":{},\"size\":{},\"crc32_expected\":{},\"crc32_actual\":{},\"ok\":{},\"skipped\":{},\"aligned\":{},\"sidecar_ok\":{},\"error\":{}",
+                json_string(&path),
+                json_string(&format_archive(file.archive_index)),
+                file.offset,
+                file.size,
+                file.crc32,
+                match actual_crc32 {
+                    Some(sum) => sum.to_string(),
+                    None => "null".to_owned(),
+                },
+                ok,
+                skipped,
+                reminder == 0,
+                match (&sidecar, actual_crc32) {
+                    (Some(_), Some(_)) => sidecar_problem.is_none().to_string(),
+                    _ => "null".to_owned(),
+                },
+                match &read_error {
+                    Some(error) => json_string(error),
+                    None => "null".to_owned(),
+                })));
+        }
+
+        if ok && sidecar_problem.is_none() {
             if options.verbose {
-                println!("OK");
+                if skipped {
+                    println!();
+                } else {
+                    println!("{}", paint_ok(color));
+                }
             }
         } else {
             if options.verbose {
                 println!();
-            } else {
+            } else if !options.json {
                 eprintln!();
             }
+            if !ok {
+                counts.failed_files += 1;
+            }
+            if options.stop_on_error {
+                return Err(Error::other("package check failed"));
+            }
+        }
+    }
+
+    if let Some(sidecar) = &sidecar {
+        let mut missing: Vec<&str> = sidecar.paths().filter(|path| package.get_file(path).is_none()).collect();
+        missing.sort_unstable();
+        for path in missing {
+            if !options.json {
+                eprintln!("{}: listed in {:?}, but not in the package", path, sidecar.path);
+            }
+            counts.sidecar_failures += 1;
             if options.stop_on_error {
                 return Err(Error::other("package check failed"));
             }
-            faild_files_count += 1;
         }
     }
 
-    let mut failed_md5_count = 0usize;
 
     if package.version > 1 {
-        #[cfg(not(target_os = "windows"))]
-        let mut buf = [0; BUFFER_SIZE];
-
-        #[cfg(target_os = "windows")]
-        let mut buf = Vec::with_capacity(BUFFER_SIZE);
-        #[cfg(target_os = "windows")]
-        buf.resize(BUFFER_SIZE, 0);
+        let mut buf = vec![0; options.buffer_size];
 
         let arch = archs.get(DIR_INDEX)?;
 
-        if let Some(md5) = package.index_md5() {
-            if options.verbose {
-                println!();
-            }
-
-            match check_range(arch, &mut buf,
-                    V2_HEADER_SIZE as u64,
-                    package.index_size as u64,
-                    md5,
-                    "directory index",
-                    options.verbose) {
-                Ok(valid) => {
-                    if !valid {
-                        if options.stop_on_error {
-                            return Err(Error::other("package check failed"));
-                        }
-                        failed_md5_count += 1;
-                    }
-                },
-                Err(error) => {
-                    return Err(Error::io_with_path(error, archs.archive_path(DIR_INDEX)));
-                }
-            }
+        if options.verbose && package.index_md5().is_some() {
+            println!();
         }
 
-        if let Some(md5) = package.archive_md5s_md5() {
-            match check_range(arch, &mut buf,
-                    package.data_offset as u64 + package.data_size as u64,
-                    package.archive_md5_size as u64,
-                    md5,
-                    "MD5 sum list",
-                    options.verbose) {
+        for section in package.md5_sections() {
+            match check_range(arch, &mut buf, section.range, section.md5, section.what, options.verbose, color) {
                 Ok(valid) => {
                     if !valid {
+                        counts.failed_md5s += 1;
                         if options.stop_on_error {
                             return Err(Error::other("package check failed"));
                         }
-                        failed_md5_count += 1;
                     }
                 },
                 Err(error) => {
@@ -272,41 +577,20 @@ pub fn check(package: &Package, options: CheckOptions) -> Result<()> {
             }
         }
 
-        if let Some(md5) = package.everything_md5() {
-            // TODO: instead of reading everything twice do this check alongside?
-            match check_range(arch, &mut buf,
-                    0,
-                    package.data_offset as u64 +
-                    package.data_size as u64 +
-                    package.archive_md5_size as u64 +
-                    16 * 2,
-                    md5,
-                    "everything",
-                    options.verbose) {
-                Ok(valid) => {
-                    if !valid {
-                        if options.stop_on_error {
-                            return Err(Error::other("package check failed"));
-                        }
-                        failed_md5_count += 1;
-                    }
-                },
-                Err(error) => {
-                    return Err(Error::io_with_path(error, archs.archive_path(DIR_INDEX)));
-                }
-            }
-        }
-
-        if !package.archive_md5s.is_empty() {
+        if !options.md5_only && !package.archive_md5s.is_empty() {
             if options.verbose {
                 println!();
                 println!("Archive      Offset        Size  MD5 Sum");
             }
 
             for item in &package.archive_md5s {
-                let arch = archs.get(item.archive_index)?;
-                let mut remaining = item.size;
-                let mut hasher = md5::Context::new();
+                let arch = match archs.get(item.archive_index) {
+                    Err(error) if options.ignore_missing && error.is_missing_archive() => {
+                        counts.skipped_md5s += 1;
+                        continue;
+                    },
+                    result => result?,
+                };
 
                 if options.verbose {
                     if item.archive_index == DIR_INDEX {
@@ -323,39 +607,19 @@ pub fn check(package: &Package, options: CheckOptions) -> Result<()> {
                     let _ = stdout.flush();
                 }
 
-                if let Err(error) = arch.seek(SeekFrom::Start(item.offset as u64)) {
-                    if options.verbose {
-                        println!("FAILED");
-                    }
-                    return Err(Error::io_with_path(error, archs.archive_path(DIR_INDEX)));
-                }
-
-                while remaining >= BUFFER_SIZE as u32 {
-                    if let Err(error) = arch.read_exact(&mut buf) {
-                        if options.verbose {
-                            println!("FAILED");
-                        }
-                        return Err(Error::io_with_path(error, archs.archive_path(item.archive_index)));
-                    }
-                    remaining -= BUFFER_SIZE as u32;
-                    hasher.consume(&buf);
-                }
-
-                if remaining > 0 {
-                    let buf = &mut buf[..remaining as usize];
-                    if let Err(error) = arch.read_exact(buf) {
+                let range = item.offset as u64..item.offset as u64 + item.size as u64;
+                let sum = match md5_of_range(arch, &mut buf, range) {
+                    Ok(sum) => sum,
+                    Err(error) => {
                         if options.verbose {
-                            println!("FAILED");
+                            println!("{}", paint_failed(color));
                         }
                         return Err(Error::io_with_path(error, archs.archive_path(item.archive_index)));
                     }
-                    hasher.consume(buf);
-                }
-
-                let sum = *hasher.compute();
+                };
                 if sum != item.md5 {
                     if options.verbose {
-                        println!("FAILED");
+                        println!("{}", paint_failed(color));
                     } else if item.archive_index == DIR_INDEX {
                         eprintln!("archive dir at offset {} with size {}: MD5 sum missmatch",
                             item.offset, item.size);
@@ -364,21 +628,184 @@ pub fn check(package: &Package, options: CheckOptions) -> Result<()> {
                         item.archive_index, item.offset, item.size);
                     }
 
-                    failed_md5_count += 1;
+                    counts.failed_md5s += 1;
                     if options.stop_on_error {
                         return Err(Error::other("package check failed"));
                     }
                 } else if options.verbose {
-                    println!("OK");
+                    println!("{}", paint_ok(color));
                 }
             }
         }
     }
 
-    if faild_files_count == 0 && failed_md5_count == 0 {
-        Ok(())
+    if counts.failed_files == 0 && counts.failed_md5s == 0 && counts.overlaps == 0 && counts.sidecar_failures == 0 {
+        return Ok(());
+    }
+
+    let mut problems = vec![
+        format!("CRC32 check failed for {} file(s)", counts.failed_files),
+        format!("MD5 check failed for {} section(s)", counts.failed_md5s),
+    ];
+
+    if options.check_overlaps {
+        problems.push(format!("found {} overlap(s) or gap(s)", counts.overlaps));
+    }
+
+    if let Some(sidecar) = &sidecar {
+        problems.push(format!("{} sidecar check failed for {} file(s)", sidecar.algorithm.extension(), counts.sidecar_failures));
+    }
+
+    let last = problems.pop().unwrap();
+    if problems.len() > 1 {
+        Err(Error::other(format!("{}, and {}", problems.join(", "), last)))
     } else {
-        Err(Error::other(format!("CRC32 check failed for {} file(s) and MD5 check failed for {} section(s)",
-            faild_files_count, failed_md5_count)))
+        Err(Error::other(format!("{} and {}", problems.join(", "), last)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::ReadOptions;
+    use crate::pack::{pack, ArchiveStrategy, PackOptions};
+    use crate::util::{TempDir, TestFile, test_dir_vpk};
+
+    /// Problems found by [`check_overlaps`] for files with the given
+    /// (path, archive index, offset, size).
+    fn overlaps(files: &[(&str, u16, u32, u32)], max_gap: Option<u64>) -> usize {
+        let files: Vec<TestFile> = files.iter()
+            .map(|&(path, archive_index, offset, size)| (path, archive_index, offset, size, &b""[..]))
+            .collect();
+        let index = std::io::Cursor::new(test_dir_vpk(&files, b""));
+        let package = Package::from_reader(index, ".", "pak", ReadOptions::new()).unwrap();
+
+        check_overlaps(&package.recursive_file_list(&PHYSICAL_ORDER), max_gap, false, false)
     }
+
+    #[test]
+    fn overlapping_data() {
+        let adjacent = [("m/a.bin", 0, 0, 100), ("m/b.bin", 0, 100, 100)];
+        assert_eq!(overlaps(&adjacent, None), 0);
+        assert_eq!(overlaps(&adjacent, Some(0)), 0);
+
+        assert_eq!(overlaps(&[("m/a.bin", 0, 0, 100), ("m/b.bin", 0, 50, 100)], None), 1);
+
+        // b lies inside of a, c starts where a ends
+        assert_eq!(overlaps(&[
+            ("m/a.bin", 0, 0,   200),
+            ("m/b.bin", 0, 50,  50),
+            ("m/c.bin", 0, 200, 10),
+        ], Some(0)), 1);
+
+        // the same offsets in different archives don't overlap
+        assert_eq!(overlaps(&[("m/a.bin", 0, 0, 100), ("m/b.bin", 1, 50, 100)], None), 0);
+    }
+
+    #[test]
+    fn gaps_between_data() {
+        let files = [("m/a.bin", 0, 0, 100), ("m/b.bin", 0, 300, 100)];
+        assert_eq!(overlaps(&files, None), 0);
+        assert_eq!(overlaps(&files, Some(200)), 0);
+        assert_eq!(overlaps(&files, Some(199)), 1);
+
+        // the gap to the start of the next archive doesn't count
+        assert_eq!(overlaps(&[("m/a.bin", 0, 0, 100), ("m/b.bin", 1, 300, 100)], Some(0)), 0);
+    }
+
+    #[test]
+    fn deduplicated_data() {
+        let files = [("m/a.bin", 0, 0, 100), ("m/b.bin", 0, 0, 100), ("m/c.bin", 0, 100, 10)];
+        assert_eq!(overlaps(&files, Some(0)), 0);
+
+        // sharing only the start isn't deduplication
+        assert_eq!(overlaps(&[("m/a.bin", 0, 0, 100), ("m/b.bin", 0, 0, 50)], None), 1);
+    }
+
+    #[test]
+    fn data_locations() {
+        let file = File {
+            index: 0,
+            crc32: 0,
+            inline_size: 4,
+            archive_index: 12,
+            offset: 4096,
+            size: 100,
+            preload: vec![0; 4],
+        };
+        assert_eq!(data_location(&file), "archive 012 at offset 4096 with size 100");
+        assert_eq!(data_location(&File { archive_index: DIR_INDEX, ..file }), "archive dir at offset 4096 with size 100");
+    }
+
+    #[test]
+    fn md5_only_without_archives() {
+        let tmp = TempDir::new("check-md5-only");
+        tmp.write("in/m/a.bin", &[1; 300]);
+        tmp.write("in/m/b.bin", &[2; 300]);
+
+        let options = PackOptions::builder()
+            .version(2)
+            .strategy(ArchiveStrategy::MaxArchiveSize(400))
+            .max_inline_size(0)
+            .quiet(true)
+            .build().unwrap();
+        pack(tmp.path.join("pak_dir.vpk"), tmp.path.join("in"), options).unwrap();
+        let package = Package::from_path(tmp.path.join("pak_dir.vpk"), ReadOptions::new()).unwrap();
+        // the _dir.vpk is filled first
+        assert_eq!(package.archive_indices(), [0, DIR_INDEX]);
+        std::fs::remove_file(package.archive_path(0)).unwrap();
+
+        let md5_only = CheckOptions { quiet: true, md5_only: true, ..CheckOptions::new() };
+        check(&package, md5_only).unwrap();
+
+        let error = check(&package, CheckOptions { quiet: true, ..CheckOptions::new() }).unwrap_err();
+        assert!(error.is_missing_archive(), "{:?}", error);
+
+        // file data in the _dir.vpk is still covered by the MD5 sum of everything
+        let dir_file = package.recursive_file_list(&[]).into_iter()
+            .find(|(_, file)| file.archive_index == DIR_INDEX).unwrap().1;
+        let mut data = std::fs::read(tmp.path.join("pak_dir.vpk")).unwrap();
+        data[dir_file.offset as usize] ^= 0xff;
+        tmp.write("pak_dir.vpk", &data);
+        let md5_only = CheckOptions { quiet: true, md5_only: true, ..CheckOptions::new() };
+        assert!(check(&package, md5_only).is_err());
+
+        let package = Package::from_reader(std::io::Cursor::new(test_dir_vpk(&[], b"")), ".", "pak", ReadOptions::new()).unwrap();
+        let md5_only = CheckOptions { quiet: true, md5_only: true, ..CheckOptions::new() };
+        let error = check(&package, md5_only).unwrap_err();
+        assert_eq!(error.to_string(), "nothing to check with --md5-only: version 1 packages have no MD5 sums");
+    }
+
+    #[test]
+    fn read_sums_in_one_pass() {
+        let tmp = TempDir::new("read-sums");
+        let data: Vec<u8> = (0..5000u32).map(|index| (index * 13 % 251) as u8).collect();
+        tmp.write("pak_000.vpk", &data[10..]);
+        let dirpath = tmp.write("pak_dir.vpk", &test_dir_vpk(&[
+            ("m/split.bin", 0, 0, 4990, &data[..10]),
+        ], b""));
+        let package = Package::from_path(&dirpath, ReadOptions::new()).unwrap();
+        let file = package.get_file("m/split.bin").unwrap();
+
+        let mut archs = ArchiveCache::for_package(&package);
+        archs.set_buffer_size(100).unwrap();
+
+        assert_eq!(read_sums(file, &mut archs, CrcAlgorithm::IEEE, None).unwrap(), crc::crc32::checksum_ieee(&data));
+
+        // the hasher sees every byte exactly once, preload included
+        let mut hasher = HashAlgorithm::MD5.hasher();
+        let crc32 = read_sums(file, &mut archs, CrcAlgorithm::IEEE, Some(&mut hasher)).unwrap();
+        assert_eq!(crc32, crc::crc32::checksum_ieee(&data));
+        assert_eq!(hasher.finish(), format!("{:x}", md5::compute(&data)));
+
+        let mut hasher = HashAlgorithm::CRC32.hasher();
+        let crc32 = read_sums(file, &mut archs, CrcAlgorithm::Castagnoli, Some(&mut hasher)).unwrap();
+        assert_eq!(crc32, crc::crc32::checksum_castagnoli(&data));
+        assert_eq!(hasher.finish(), format!("{:08x}", crc::crc32::checksum_ieee(&data)));
+
+        std::fs::remove_file(tmp.path.join("pak_000.vpk")).unwrap();
+        let mut archs = ArchiveCache::for_package(&package);
+        assert!(read_sums(file, &mut archs, CrcAlgorithm::IEEE, None).unwrap_err().is_missing_archive());
+    }
+
 }
@@ -0,0 +1,85 @@
+// This file is part of rust-vpk.
+//
+// rust-vpk is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rust-vpk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rust-vpk.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::sort::{Order, DEFAULT_ORDER};
+use crate::archive_cache::ArchiveCache;
+use crate::package::Package;
+use crate::result::Result;
+use crate::util::{format_md5, retain_extensions};
+use crate::consts::BUFFER_SIZE;
+
+pub struct ChecksumOptions<'a> {
+    pub order:  &'a Order,
+    pub filter: Option<&'a [&'a str]>,
+    pub extensions: Option<&'a [&'a str]>,
+    pub md5: bool,
+    /// Must not be 0.
+    pub buffer_size: usize,
+}
+
+impl ChecksumOptions<'_> {
+    #[inline]
+    pub fn new() -> Self {
+        ChecksumOptions::default()
+    }
+}
+
+impl Default for ChecksumOptions<'_> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            order:  &DEFAULT_ORDER,
+            filter: None,
+            extensions: None,
+            md5: false,
+            buffer_size: BUFFER_SIZE,
+        }
+    }
+}
+
+/// Print the CRC32 sum of each file as stored in the index, and optionally
+/// the MD5 sum calculated from the file data, in the style of `md5sum`.
+pub fn checksum(package: &Package, options: ChecksumOptions) -> Result<()> {
+    let mut files = match options.filter {
+        None => package.recursive_file_list(options.order),
+        Some(paths) => package.recursive_file_list_from(paths, options.order)?,
+    };
+
+    if let Some(extensions) = options.extensions {
+        retain_extensions(&mut files, extensions);
+    }
+
+    if options.md5 {
+        let mut archs = ArchiveCache::for_package(package);
+        archs.set_buffer_size(options.buffer_size)?;
+
+        for (path, file) in &files {
+            let mut hasher = md5::Context::new();
+            archs.read_file_data(file, |data| {
+                hasher.consume(data);
+                Ok(())
+            })?;
+            let md5 = *hasher.compute();
+
+            println!("{:08x}  {}  {}", file.crc32, format_md5(&md5), path);
+        }
+    } else {
+        for (path, file) in &files {
+            println!("{:08x}  {}", file.crc32, path);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,109 @@
+// This file is part of rust-vpk.
+//
+// rust-vpk is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rust-vpk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rust-vpk.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::io::IsTerminal;
+
+use crate::result::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(value: &str) -> Result<Self> {
+        if value.eq_ignore_ascii_case("auto") {
+            Ok(ColorChoice::Auto)
+        } else if value.eq_ignore_ascii_case("always") {
+            Ok(ColorChoice::Always)
+        } else if value.eq_ignore_ascii_case("never") {
+            Ok(ColorChoice::Never)
+        } else {
+            Err(Error::illegal_argument("--color", value))
+        }
+    }
+
+    /// Whether output written to stdout shall be colorized. In `Auto` mode
+    /// this is only the case if stdout is a terminal and the `NO_COLOR`
+    /// environment variable is not set.
+    pub fn use_color(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never  => false,
+            ColorChoice::Auto   => {
+                let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+                !no_color && std::io::stdout().is_terminal()
+            }
+        }
+    }
+}
+
+impl Default for ColorChoice {
+    #[inline]
+    fn default() -> Self {
+        ColorChoice::Auto
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    Red,
+    Green,
+    Blue,
+    Bold,
+}
+
+impl Color {
+    #[inline]
+    fn code(self) -> &'static str {
+        match self {
+            Color::Red   => "31",
+            Color::Green => "32",
+            Color::Blue  => "34",
+            Color::Bold  => "1",
+        }
+    }
+}
+
+/// Wrap `text` in ANSI escape sequences for the given color if `enabled`.
+pub fn paint(text: impl AsRef<str>, color: Color, enabled: bool) -> String {
+    let text = text.as_ref();
+    if enabled && !text.is_empty() {
+        format!("\x1b[{}m{}\x1b[0m", color.code(), text)
+    } else {
+        text.to_owned()
+    }
+}
+
+/// Number of characters of `text` as displayed on a terminal, i.e. without
+/// any ANSI escape sequences.
+pub fn display_len(text: &str) -> usize {
+    let mut len = 0;
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\x1b' {
+            for ch in &mut chars {
+                if ch.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            len += 1;
+        }
+    }
+    len
+}
@@ -16,12 +16,19 @@
 use crate::package::{Magic};
 
 pub const VPK_MAGIC: Magic = [0x34, 0x12, 0xAA, 0x55];
+pub const VPK_MAGIC_BIG_ENDIAN: Magic = [0x55, 0xAA, 0x12, 0x34];
 
 pub const DIR_INDEX:  u16 = 0x7FFF;
 pub const TERMINATOR: u16 = 0xFFFF;
 pub const BUFFER_SIZE: usize = 1024 * 1024;
 pub const DEFAULT_MAX_INLINE_SIZE: u16 = 8 * 1024;
+pub const DEFAULT_INDEX_WARNING_RATIO: f64 = 0.5;
+/// Warn when the index of a package to be packed exceeds this fraction of
+/// the maximum index size (`i32::MAX`).
+pub const INDEX_SIZE_WARNING_RATIO: f64 = 0.75;
 pub const DEFAULT_MD5_CHUNK_SIZE: u32 = 1024 * 1024;
+/// Version of the format of all JSON output, increased on incompatible changes.
+pub const JSON_VERSION: u32 = 1;
 
 pub const V1_HEADER_SIZE: usize = 4 * 3;
 pub const V2_HEADER_SIZE: usize = 4 * 3 + 4 * 4;
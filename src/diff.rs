@@ -0,0 +1,189 @@
+// This file is part of rust-vpk.
+//
+// rust-vpk is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rust-vpk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rust-vpk.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::BTreeMap;
+
+use crate::package::Package;
+use crate::entry::File;
+use crate::result::Result;
+use crate::util::format_archive;
+
+pub struct DiffOptions {
+    /// Also report files with the same content that are stored at a
+    /// different place, i.e. in a different archive, at a different offset,
+    /// or with a different amount of inline data.
+    pub by_offset: bool,
+}
+
+impl DiffOptions {
+    #[inline]
+    pub fn new() -> Self {
+        DiffOptions::default()
+    }
+}
+
+impl Default for DiffOptions {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            by_offset: false,
+        }
+    }
+}
+
+pub enum Change<'a> {
+    Added(&'a File),
+    Deleted(&'a File),
+    /// CRC32 or size differ.
+    Modified(&'a File, &'a File),
+    /// Same content, but stored at a different place.
+    Moved(&'a File, &'a File),
+}
+
+fn file_map(package: &Package) -> BTreeMap<String, &File> {
+    package.recursive_file_list(&[]).into_iter().collect()
+}
+
+/// Compare two packages by file content identity (CRC32 and size), not by
+/// index or offset, so that repacking a package with a different order of
+/// the file data doesn't report every file as modified. The returned
+/// changes are sorted by path.
+pub fn diff_files<'a>(old: &'a Package, new: &'a Package, options: &DiffOptions) -> Vec<(String, Change<'a>)> {
+    let mut old_files = file_map(old);
+    let mut changes = Vec::new();
+
+    for (path, new_file) in file_map(new) {
+        match old_files.remove(&path) {
+            None => changes.push((path, Change::Added(new_file))),
+            Some(old_file) => {
+                if old_file.crc32 != new_file.crc32 || full_size(old_file) != full_size(new_file) {
+                    changes.push((path, Change::Modified(old_file, new_file)));
+                } else if options.by_offset && (
+                        old_file.archive_index != new_file.archive_index ||
+                        old_file.inline_size   != new_file.inline_size ||
+                        // stored offsets, so that a change of the index size alone
+                        // doesn't move data in the _dir.vpk
+                        old.stored_offset(old_file) != new.stored_offset(new_file)) {
+                    changes.push((path, Change::Moved(old_file, new_file)));
+                }
+            }
+        }
+    }
+
+    for (path, old_file) in old_files {
+        changes.push((path, Change::Deleted(old_file)));
+    }
+
+    changes.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    changes
+}
+
+/// Size of the inlined and the archive data, which can exceed `u32` in a
+/// corrupt index.
+#[inline]
+fn full_size(file: &File) -> u64 {
+    file.inline_size as u64 + file.size as u64
+}
+
+fn location(package: &Package, file: &File) -> String {
+    if file.size == 0 {
+        "inline".to_owned()
+    } else {
+        format!("{}:{}", format_archive(file.archive_index), package.stored_offset(file))
+    }
+}
+
+/// Print the differences between two packages, one file per line prefixed
+/// with `A` (added), `D` (deleted), `M` (modified), or `L` (layout changed,
+/// only with `by_offset`).
+pub fn diff(old: &Package, new: &Package, options: DiffOptions) -> Result<()> {
+    for (path, change) in diff_files(old, new, &options) {
+        match change {
+            Change::Added(_)   => println!("A {}", path),
+            Change::Deleted(_) => println!("D {}", path),
+            Change::Modified(old_file, new_file) => {
+                println!("M {} (crc32 {:08x} -> {:08x}, size {} -> {})", path,
+                    old_file.crc32, new_file.crc32,
+                    full_size(old_file),
+                    full_size(new_file));
+            },
+            Change::Moved(old_file, new_file) => {
+                println!("L {} ({} -> {})", path, location(old, old_file), location(new, new_file));
+            },
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::pack::{pack, PackOptions};
+    use crate::package::ReadOptions;
+    use crate::util::TempDir;
+
+    fn kinds(changes: &[(String, Change)]) -> Vec<(&'static str, String)> {
+        changes.iter().map(|(path, change)| (match change {
+            Change::Added(_)       => "A",
+            Change::Deleted(_)     => "D",
+            Change::Modified(_, _) => "M",
+            Change::Moved(_, _)    => "L",
+        }, path.clone())).collect()
+    }
+
+    #[test]
+    fn repacked_in_different_order() {
+        let tmp = TempDir::new("diff-repacked");
+        tmp.write("in/m/a.bin", &[1; 1000]);
+        tmp.write("in/m/b.bin", &[2; 2000]);
+        tmp.write("in/s/c.bin", &[3; 1000]);
+
+        let packed = |name: &str, order: &[&str]| {
+            let options = PackOptions::builder()
+                .max_inline_size(0)
+                .data_order(Some(order.iter().map(|path| path.to_string()).collect()))
+                .quiet(true)
+                .build().unwrap();
+            pack(tmp.path.join(name), tmp.path.join("in"), options).unwrap();
+            Package::from_path(tmp.path.join(name), ReadOptions::new()).unwrap()
+        };
+
+        let old = packed("old_dir.vpk", &["m/a.bin", "m/b.bin", "s/c.bin"]);
+        let new = packed("new_dir.vpk", &["s/c.bin", "m/b.bin", "m/a.bin"]);
+        assert_ne!(old.get_file("m/a.bin").unwrap().offset, new.get_file("m/a.bin").unwrap().offset);
+
+        assert!(diff_files(&old, &new, &DiffOptions::new()).is_empty());
+        // m/a.bin and s/c.bin swap places, m/b.bin stays where it is
+        assert_eq!(kinds(&diff_files(&old, &new, &DiffOptions { by_offset: true })), [
+            ("L", "m/a.bin".to_owned()),
+            ("L", "s/c.bin".to_owned()),
+        ]);
+
+        tmp.write("in/m/b.bin", &[4; 2000]);
+        tmp.write("in/m/d.bin", &[5; 10]);
+        fs::remove_file(tmp.path.join("in/s/c.bin")).unwrap();
+        let changed = packed("changed_dir.vpk", &["m/d.bin", "m/b.bin", "m/a.bin"]);
+        assert_eq!(kinds(&diff_files(&old, &changed, &DiffOptions::new())), [
+            ("M", "m/b.bin".to_owned()),
+            ("A", "m/d.bin".to_owned()),
+            ("D", "s/c.bin".to_owned()),
+        ]);
+    }
+
+}
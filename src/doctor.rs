@@ -0,0 +1,190 @@
+// This file is part of rust-vpk.
+//
+// rust-vpk is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rust-vpk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rust-vpk.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+
+use crate::package::Package;
+use crate::validate::{ValidateOptions, ProblemKind};
+use crate::result::{Result, Error};
+use crate::consts::{DIR_INDEX, ARCHIVE_MD5_SIZE};
+use crate::util::{print_table, Align::*};
+
+struct Diagnosis {
+    name: &'static str,
+    problems: Vec<String>,
+    /// Shown in the details if there are no problems.
+    note: Option<String>,
+}
+
+impl Diagnosis {
+    #[inline]
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            problems: Vec::new(),
+            note: None,
+        }
+    }
+}
+
+fn diagnose(package: &Package) -> Result<Vec<Diagnosis>> {
+    let mut diagnoses = Vec::new();
+
+    // header
+    let mut header = Diagnosis::new("Header sizes");
+    let expected_data_offset = package.header_size() as u64 + package.index_size as u64;
+    if package.version > 0 && package.data_offset as u64 != expected_data_offset {
+        header.problems.push(format!(
+            "data offset {} != header size {} + index size {}",
+            package.data_offset, package.header_size(), package.index_size));
+    }
+    diagnoses.push(header);
+
+    let problems = package.validate(&ValidateOptions::new())?;
+    let messages = |kind: ProblemKind, with_path: bool| -> Vec<String> {
+        problems.iter()
+            .filter(|problem| problem.kind == kind && problem.path.is_some() == with_path)
+            .map(|problem| problem.message.clone())
+            .collect()
+    };
+
+    let mut archives = Diagnosis::new("Archives exist");
+    archives.problems = messages(ProblemKind::MissingArchive, false);
+    diagnoses.push(archives);
+
+    let mut ranges = Diagnosis::new("File data in bounds");
+    ranges.problems = messages(ProblemKind::OutOfBounds, true);
+    diagnoses.push(ranges);
+
+    let mut paths = Diagnosis::new("Unique paths");
+    paths.problems = messages(ProblemKind::DuplicatePath, true);
+    diagnoses.push(paths);
+
+    // Terminators are validated while parsing, a package with a wrong
+    // terminator can only be loaded in lenient mode.
+    let mut terminators = Diagnosis::new("Entry terminators");
+    if let Some(offset) = package.truncated_at() {
+        terminators.problems.push(format!(
+            "illegal terminator at offset {}, the rest of the index is missing",
+            offset));
+    }
+    diagnoses.push(terminators);
+
+    if package.version > 1 {
+        let mut sections = Diagnosis::new("VPK v2 sections");
+        let end =
+            package.data_offset      as u64 +
+            package.data_size        as u64 +
+            package.archive_md5_size as u64 +
+            package.other_md5_size   as u64 +
+            package.signature_size   as u64;
+
+        if let Ok(meta) = fs::metadata(package.archive_path(DIR_INDEX)) {
+            let dir_size = meta.len();
+            if end > dir_size {
+                sections.problems.push(format!(
+                    "sections end at {}, but _dir.vpk is only {} bytes long",
+                    end, dir_size));
+            } else if end < dir_size {
+                // e.g. padding or metadata appended by other tools
+                sections.note = Some(format!(
+                    "{} trailing bytes after the signature section",
+                    dir_size - end));
+            }
+        }
+
+        let remainder = package.archive_md5_size as usize % ARCHIVE_MD5_SIZE;
+        if remainder != 0 {
+            sections.problems.push(format!(
+                "archive MD5 section size {} is not a multiple of {}",
+                package.archive_md5_size, ARCHIVE_MD5_SIZE));
+        }
+
+        // MD5 chunks beyond the end of their archive
+        sections.problems.extend(messages(ProblemKind::OutOfBounds, false));
+        diagnoses.push(sections);
+    }
+
+    Ok(diagnoses)
+}
+
+/// Run fast structural checks on a package without hashing any file data.
+pub fn doctor(package: &Package) -> Result<()> {
+    let diagnoses = diagnose(package)?;
+
+    let mut failed_count = 0usize;
+    let mut body = Vec::new();
+    for diagnosis in &diagnoses {
+        if diagnosis.problems.is_empty() {
+            body.push(vec![diagnosis.name.to_owned(), "OK".to_owned(), diagnosis.note.clone().unwrap_or_default()]);
+        } else {
+            failed_count += 1;
+            let mut first = true;
+            for problem in &diagnosis.problems {
+                if first {
+                    body.push(vec![diagnosis.name.to_owned(), "FAILED".to_owned(), problem.to_owned()]);
+                    first = false;
+                } else {
+                    body.push(vec!["".to_owned(), "".to_owned(), problem.to_owned()]);
+                }
+            }
+        }
+    }
+
+    print_table(
+        &["Check", "Result", "Details"],
+        &[Left,    Left,     Left],
+        &body);
+
+    if failed_count > 0 {
+        return Err(Error::sanity_check_failed(format!(
+            "{} of {} checks failed", failed_count, diagnoses.len())));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::ReadOptions;
+    use crate::util::{TempDir, test_dir_vpk};
+
+    fn problems<'a>(diagnoses: &'a [Diagnosis], name: &str) -> &'a [String] {
+        &diagnoses.iter().find(|diagnosis| diagnosis.name == name).unwrap().problems
+    }
+
+    #[test]
+    fn truncated_index_fails_terminators() {
+        let files = [
+            ("m/a.txt", DIR_INDEX, 0, 0, &b""[..]),
+            ("m/b.txt", DIR_INDEX, 0, 0, b""),
+        ];
+        let mut data = test_dir_vpk(&files, b"");
+        let tmp = TempDir::new("doctor-terminators");
+        let path = tmp.write("ok_dir.vpk", &data);
+        let package = Package::from_path(path, ReadOptions::new()).unwrap();
+        assert!(problems(&diagnose(&package).unwrap(), "Entry terminators").is_empty());
+
+        let last = data.len() - 5;
+        assert_eq!(data[last..last + 2], [0xFF, 0xFF]);
+        data[last] = 0;
+        let path = tmp.write("bad_dir.vpk", &data);
+        let package = Package::from_path(path, ReadOptions { lenient: true, ..ReadOptions::new() }).unwrap();
+        let diagnoses = diagnose(&package).unwrap();
+        assert_eq!(problems(&diagnoses, "Entry terminators").len(), 1);
+        assert!(doctor(&package).is_err());
+    }
+}
@@ -15,6 +15,8 @@
 
 use std::collections::HashMap;
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct File {
     pub(crate) index: usize,
     pub(crate) crc32: u32,
@@ -22,13 +24,21 @@ pub struct File {
     pub(crate) archive_index: u16,
     pub(crate) offset: u32,
     pub(crate) size: u32,
+
+    // the preload data can be read via Package::open_file()
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub(crate) preload: Vec<u8>,
 }
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Dir {
     pub(crate) children: HashMap<String, Entry>,
 }
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "lowercase"))]
 pub enum Entry {
     File(File),
     Dir(Dir),
@@ -92,5 +102,35 @@ impl Entry {
             Entry::Dir(_)  => true,
         }
     }
+
+    /// Call `f` for this entry and, if it is a directory, recursively for
+    /// all entries inside of it. `path` has to be the path of this entry
+    /// and is used as buffer for the paths of the children, so no new
+    /// string is allocated per entry. Its content is restored before this
+    /// function returns. Children are visited in no particular order.
+    ///
+    /// ```ignore
+    /// let mut count = 0;
+    /// let mut path = String::from("materials");
+    /// entry.walk(&mut path, &mut |_path, entry| {
+    ///     if entry.is_file() {
+    ///         count += 1;
+    ///     }
+    /// });
+    /// ```
+    pub fn walk(&self, path: &mut String, f: &mut impl FnMut(&str, &Entry)) {
+        f(path, self);
+        if let Entry::Dir(dir) = self {
+            for (name, child) in &dir.children {
+                let len = path.len();
+                if len > 0 {
+                    path.push('/');
+                }
+                path.push_str(name);
+                child.walk(path, f);
+                path.truncate(len);
+            }
+        }
+    }
 }
 
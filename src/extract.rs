@@ -0,0 +1,116 @@
+// This file is part of rust-vpk.
+//
+// rust-vpk is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rust-vpk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rust-vpk.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::path::Path;
+use std::io::Write;
+use std::fs;
+
+use crc::Hasher32;
+
+use crate::archive_cache::ArchiveCache;
+use crate::package::Package;
+use crate::entry::Entry;
+use crate::result::{Result, Error};
+use crate::consts::BUFFER_SIZE;
+use crate::hash::CrcAlgorithm;
+
+pub struct ExtractOptions {
+    pub verbose:     bool,
+    /// Don't warn if `path` occurs more than once in the index.
+    pub quiet:       bool,
+    pub check:       bool,
+    /// Must not be 0.
+    pub buffer_size: usize,
+    pub retries:     u32,
+    /// Polynomial of the CRC32 sums in the index, for `check`.
+    pub crc:         CrcAlgorithm,
+}
+
+impl ExtractOptions {
+    #[inline]
+    pub fn new() -> Self {
+        ExtractOptions::default()
+    }
+}
+
+impl Default for ExtractOptions {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            verbose:     false,
+            quiet:       false,
+            check:       false,
+            buffer_size: BUFFER_SIZE,
+            retries:     0,
+            crc:         CrcAlgorithm::default(),
+        }
+    }
+}
+
+/// Write the single file at `path` inside of the package to exactly `dest`,
+/// without creating the directory structure of the package.
+pub fn extract(package: &Package, path: &str, dest: impl AsRef<Path>, options: ExtractOptions) -> Result<()> {
+    let path = path.trim_matches('/');
+    let dest = dest.as_ref();
+
+    let file = match package.get(path) {
+        None => return Err(Error::no_such_entry(path)),
+        Some(Entry::Dir(_)) => return Err(Error::other(format!("entry is a directory: {:?}", path))),
+        Some(Entry::File(file)) => file,
+    };
+
+    if !options.quiet && package.duplicate_warnings().iter().any(|duplicate| duplicate == path) {
+        eprintln!("WARNING: file occured more than once, extracting the last entry: {:?}", path);
+    }
+
+    let mut archs = ArchiveCache::for_package(package);
+    archs.set_buffer_size(options.buffer_size)?;
+    archs.set_retries(options.retries);
+
+    if options.verbose {
+        println!("writing {:?}", dest);
+    }
+
+    let mut writer = match fs::File::create(dest) {
+        Ok(writer) => writer,
+        Err(error) => return Err(Error::io_with_path(error, dest)),
+    };
+
+    if options.check {
+        let mut digest = options.crc.digest();
+        archs.read_file_data(file, |data| {
+            if let Err(error) = writer.write_all(data) {
+                return Err(Error::io_with_path(error, dest));
+            }
+            digest.write(data);
+            Ok(())
+        })?;
+
+        let sum = digest.sum32();
+        if sum != file.crc32 {
+            return Err(Error::other(format!(
+                "{}: CRC32 sum missmatch, expected: 0x{:08x}, actual: 0x{:08x}",
+                path, file.crc32, sum)));
+        }
+    } else if let Err(error) = archs.transfer(file, &mut writer) {
+        return if error.path.is_none() {
+            Err(error.with_path(dest))
+        } else {
+            Err(error)
+        };
+    }
+
+    Ok(())
+}
@@ -0,0 +1,158 @@
+// This file is part of rust-vpk.
+//
+// rust-vpk is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rust-vpk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rust-vpk.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::entry::File;
+
+/// Reads the logical content of a file inside of a package, which is the
+/// preload data embedded in the index followed by the data in the archive.
+pub struct VpkFileReader<'a> {
+    file: &'a File,
+    archive: Option<fs::File>,
+    pos: u64,
+}
+
+impl<'a> VpkFileReader<'a> {
+    /// `archive` is only needed if `file.size() > 0`.
+    pub fn new(file: &'a File, archive: Option<fs::File>) -> Self {
+        Self {
+            file,
+            archive,
+            pos: 0,
+        }
+    }
+
+    #[inline]
+    pub fn file(&self) -> &'a File {
+        self.file
+    }
+
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.file.inline_size as u64 + self.file.size as u64
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Read for VpkFileReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let inline_size = self.file.inline_size as u64;
+
+        if self.pos < inline_size {
+            let preload = &self.file.preload[self.pos as usize..];
+            let count = preload.len().min(buf.len());
+            buf[..count].copy_from_slice(&preload[..count]);
+            self.pos += count as u64;
+            return Ok(count);
+        }
+
+        let offset = self.pos - inline_size;
+        if offset >= self.file.size as u64 || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let remaining = self.file.size as u64 - offset;
+        let count = if remaining < buf.len() as u64 { remaining as usize } else { buf.len() };
+
+        let archive = match &mut self.archive {
+            Some(archive) => archive,
+            None => return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "archive of file is not open")),
+        };
+
+        archive.seek(SeekFrom::Start(self.file.offset as u64 + offset))?;
+        let count = archive.read(&mut buf[..count])?;
+        self.pos += count as u64;
+
+        Ok(count)
+    }
+}
+
+impl Seek for VpkFileReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset)   => Some(offset),
+            SeekFrom::End(offset)     => add_offset(self.len(), offset),
+            SeekFrom::Current(offset) => add_offset(self.pos, offset),
+        };
+
+        if let Some(new_pos) = new_pos {
+            self.pos = new_pos;
+            Ok(new_pos)
+        } else {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position"))
+        }
+    }
+}
+
+#[inline]
+fn add_offset(base: u64, offset: i64) -> Option<u64> {
+    if offset < 0 {
+        base.checked_sub(offset.unsigned_abs())
+    } else {
+        base.checked_add(offset as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek, SeekFrom};
+
+    use crate::package::{Package, ReadOptions};
+    use crate::util::{TempDir, test_dir_vpk};
+
+    #[test]
+    fn read_across_preload_boundary() {
+        let tmp = TempDir::new("file-reader");
+        tmp.write("pak_000.vpk", b"0123456789efghij");
+        let dirpath = tmp.write("pak_dir.vpk", &test_dir_vpk(&[
+            ("m/a.txt", 0, 10, 6, b"abcd"),
+        ], b""));
+        let package = Package::from_path(&dirpath, ReadOptions::new()).unwrap();
+        let mut reader = package.open_file("m/a.txt").unwrap();
+        assert_eq!(reader.len(), 10);
+
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"abcdefghij");
+
+        let mut buf = [0; 4];
+        assert_eq!(reader.seek(SeekFrom::Start(2)).unwrap(), 2);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"cdef");
+
+        assert_eq!(reader.seek(SeekFrom::End(-3)).unwrap(), 7);
+        reader.read_exact(&mut buf[..3]).unwrap();
+        assert_eq!(&buf[..3], b"hij");
+
+        assert_eq!(reader.seek(SeekFrom::Current(-7)).unwrap(), 3);
+        reader.read_exact(&mut buf[..2]).unwrap();
+        assert_eq!(&buf[..2], b"de");
+
+        assert_eq!(reader.seek(SeekFrom::Start(20)).unwrap(), 20);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+        assert!(reader.seek(SeekFrom::Current(-21)).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+// This file is part of rust-vpk.
+//
+// rust-vpk is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rust-vpk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rust-vpk.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::path::Path;
+
+use crate::entry::File;
+use crate::result::Result;
+use crate::util::read_lines;
+
+struct Rule {
+    pattern: Vec<char>,
+    /// Re-include matching paths (leading `!`).
+    negate: bool,
+    /// Only match directories (trailing `/`).
+    dir_only: bool,
+    /// Match the whole path instead of any single name (pattern contains a `/`).
+    anchored: bool,
+}
+
+/// Patterns in the style of a `.gitignore` file that exclude paths inside
+/// of a package.
+///
+/// Unlike git, a file can be re-included with `!` even if one of its parent
+/// directories is excluded. For every path the last matching pattern wins:
+///
+/// ```text
+/// # exclude all materials except for one file
+/// materials/
+/// !materials/foo.vmt
+/// ```
+///
+/// Supported are `*` and `?` (not matching `/`), `**` (matching across
+/// directories), `[...]` character classes, a leading `/` or any other `/`
+/// to match the whole path instead of a single name, and a trailing `/` to
+/// only match directories. Empty lines and lines starting with `#` are
+/// ignored, use `\#` and `\!` for patterns starting with these characters.
+pub struct FilterFile {
+    rules: Vec<Rule>,
+}
+
+impl FilterFile {
+    pub fn parse<'a>(lines: impl IntoIterator<Item=&'a str>) -> Self {
+        let mut rules = Vec::new();
+        for line in lines {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (negate, line) = match line.strip_prefix('!') {
+                Some(line) => (true, line),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+
+            let anchored = line.contains('/');
+            let line = line.trim_start_matches('/');
+            if line.is_empty() {
+                continue;
+            }
+
+            rules.push(Rule {
+                pattern: line.chars().collect(),
+                negate,
+                dir_only,
+                anchored,
+            });
+        }
+
+        FilterFile { rules }
+    }
+
+    /// Read the patterns from a file, `-` reads from stdin.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let lines = read_lines(path.as_ref(), false)?;
+        Ok(Self::parse(lines.iter().map(String::as_str)))
+    }
+
+    /// Whether the file at `path` (without leading `/`) is not excluded.
+    pub fn is_included(&self, path: &str) -> bool {
+        let mut included = true;
+        for rule in &self.rules {
+            if rule.negate == included {
+                // wouldn't change anything
+                continue;
+            }
+
+            if rule.matches(path) {
+                included = rule.negate;
+            }
+        }
+        included
+    }
+
+    /// Only keep the files that are not excluded.
+    pub fn retain(&self, files: &mut Vec<(String, &File)>) {
+        files.retain(|(path, _)| self.is_included(path));
+    }
+}
+
+impl Rule {
+    /// Whether the rule matches the file itself or any of its parent directories.
+    fn matches(&self, path: &str) -> bool {
+        let mut end = 0;
+        for (index, ch) in path.char_indices() {
+            if ch == '/' {
+                if self.matches_candidate(&path[..index], end) {
+                    return true;
+                }
+                end = index + 1;
+            }
+        }
+
+        !self.dir_only && self.matches_candidate(path, end)
+    }
+
+    /// `name_start` is the index of the last name in `candidate`.
+    fn matches_candidate(&self, candidate: &str, name_start: usize) -> bool {
+        let text = if self.anchored { candidate } else { &candidate[name_start..] };
+        let text: Vec<char> = text.chars().collect();
+        glob_match(&self.pattern, &text)
+    }
+}
+
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            if pattern.get(2) == Some(&'/') {
+                // "**/" matches zero or more whole directories
+                let rest = &pattern[3..];
+                glob_match(rest, text) || (0..text.len()).any(|index|
+                    text[index] == '/' && glob_match(rest, &text[index + 1..]))
+            } else {
+                let rest = &pattern[2..];
+                (0..=text.len()).any(|index| glob_match(rest, &text[index..]))
+            }
+        },
+        Some('*') => {
+            let rest = &pattern[1..];
+            for index in 0..=text.len() {
+                if glob_match(rest, &text[index..]) {
+                    return true;
+                }
+                if index < text.len() && text[index] == '/' {
+                    break;
+                }
+            }
+            false
+        },
+        Some('?') => {
+            !text.is_empty() && text[0] != '/' && glob_match(&pattern[1..], &text[1..])
+        },
+        Some('[') => {
+            match (text.first(), class_end(pattern)) {
+                (Some(&ch), Some(end)) if ch != '/' => {
+                    class_matches(&pattern[1..end], ch) && glob_match(&pattern[end + 1..], &text[1..])
+                },
+                (Some(&ch), None) => ch == '[' && glob_match(&pattern[1..], &text[1..]),
+                _ => false,
+            }
+        },
+        Some('\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && glob_match(&pattern[2..], &text[1..])
+        },
+        Some(&ch) => {
+            text.first() == Some(&ch) && glob_match(&pattern[1..], &text[1..])
+        },
+    }
+}
+
+/// Index of the `]` closing the character class starting at `pattern[0]`.
+fn class_end(pattern: &[char]) -> Option<usize> {
+    let mut index = 1;
+    if matches!(pattern.get(index), Some('!') | Some('^')) {
+        index += 1;
+    }
+    if pattern.get(index) == Some(&']') {
+        index += 1;
+    }
+    pattern[index.min(pattern.len())..].iter().position(|&ch| ch == ']').map(|pos| index + pos)
+}
+
+/// `class` is the content between `[` and `]`.
+fn class_matches(class: &[char], ch: char) -> bool {
+    let (negate, class) = match class.first() {
+        Some('!') | Some('^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+
+    let mut found = false;
+    let mut index = 0;
+    while index < class.len() {
+        if index + 2 < class.len() && class[index + 1] == '-' {
+            if class[index] <= ch && ch <= class[index + 2] {
+                found = true;
+            }
+            index += 3;
+        } else {
+            if class[index] == ch {
+                found = true;
+            }
+            index += 1;
+        }
+    }
+
+    found != negate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str, text: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let text: Vec<char> = text.chars().collect();
+        glob_match(&pattern, &text)
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob("foo.vmt", "foo.vmt"));
+        assert!(!glob("foo.vmt", "foo.vtf"));
+
+        assert!(glob("*.vmt", "foo.vmt"));
+        assert!(glob("*", ""));
+        assert!(!glob("*.vmt", "a/foo.vmt"));
+        assert!(glob("f?o.*", "foo.vmt"));
+        assert!(!glob("?", "/"));
+
+        assert!(glob("**/foo.vmt", "foo.vmt"));
+        assert!(glob("**/foo.vmt", "a/b/foo.vmt"));
+        assert!(glob("a/**/foo.vmt", "a/foo.vmt"));
+        assert!(glob("a/**/foo.vmt", "a/b/c/foo.vmt"));
+        assert!(!glob("a/**/foo.vmt", "b/foo.vmt"));
+        assert!(glob("a/**", "a/b/c"));
+
+        assert!(glob("[abc].txt", "b.txt"));
+        assert!(!glob("[abc].txt", "d.txt"));
+        assert!(glob("[a-c].txt", "c.txt"));
+        assert!(glob("[!a-c].txt", "d.txt"));
+        assert!(!glob("[^a-c].txt", "a.txt"));
+        assert!(glob("[]].txt", "].txt"));
+        assert!(glob("[.txt", "[.txt"));
+
+        assert!(glob("\\*.txt", "*.txt"));
+        assert!(!glob("\\*.txt", "a.txt"));
+    }
+
+    #[test]
+    fn filter_file_rules() {
+        let filter = FilterFile::parse([
+            "# comment",
+            "",
+            "*.bak",
+            "materials/",
+            "!materials/keep.vmt",
+            "/sound/*.wav",
+            "\\#hash.txt",
+            "models/tmp/",
+        ].iter().copied());
+
+        assert!(filter.is_included("models/foo.mdl"));
+        assert!(!filter.is_included("models/foo.bak"));
+        assert!(!filter.is_included("a/b/c.bak"));
+
+        assert!(!filter.is_included("materials/foo.vmt"));
+        assert!(!filter.is_included("materials/sub/foo.vmt"));
+        assert!(filter.is_included("materials/keep.vmt"));
+
+        assert!(!filter.is_included("sound/a.wav"));
+        assert!(filter.is_included("sound/sub/a.wav"));
+        assert!(filter.is_included("other/sound/a.wav"));
+
+        assert!(!filter.is_included("x/#hash.txt"));
+        assert!(!filter.is_included("models/tmp/a.mdl"));
+        // dir-only pattern doesn't match a file of that name
+        assert!(filter.is_included("a/materials"));
+    }
+}
@@ -0,0 +1,335 @@
+// This file is part of rust-vpk.
+//
+// rust-vpk is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rust-vpk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rust-vpk.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+
+use crc::{crc32, Hasher32};
+
+use crate::archive_cache::ArchiveCache;
+use crate::package::Package;
+use crate::sort::DEFAULT_ORDER;
+use crate::consts::DIR_INDEX;
+use crate::result::{Result, Error};
+
+/// Incrementally calculates a checksum.
+pub trait Hasher {
+    fn update(&mut self, data: &[u8]);
+
+    /// Return the checksum of all the data passed so far as lower case hex
+    /// string and reset the hasher, so it can be used for the next file.
+    fn finish(&mut self) -> String;
+}
+
+impl Hasher for crc32::Digest {
+    fn update(&mut self, data: &[u8]) {
+        self.write(data);
+    }
+
+    fn finish(&mut self) -> String {
+        let sum = self.sum32();
+        self.reset();
+        format!("{:08x}", sum)
+    }
+}
+
+impl Hasher for md5::Context {
+    fn update(&mut self, data: &[u8]) {
+        self.consume(data);
+    }
+
+    fn finish(&mut self) -> String {
+        let context = std::mem::replace(self, md5::Context::new());
+        format!("{:x}", context.compute())
+    }
+}
+
+#[cfg(feature = "sha2")]
+impl Hasher for sha2::Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        sha2::Digest::update(self, data);
+    }
+
+    fn finish(&mut self) -> String {
+        let mut hex = String::with_capacity(64);
+        for byte in sha2::Digest::finalize_reset(self) {
+            hex.push_str(&format!("{:02x}", byte));
+        }
+        hex
+    }
+}
+
+/// Polynomial of the CRC32 sums stored in the index. Standard VPK packages
+/// always use IEEE, Castagnoli is only for some VPK-like formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrcAlgorithm {
+    IEEE,
+    Castagnoli,
+}
+
+impl Default for CrcAlgorithm {
+    #[inline]
+    fn default() -> Self {
+        CrcAlgorithm::IEEE
+    }
+}
+
+impl CrcAlgorithm {
+    pub fn parse(value: &str) -> Result<Self> {
+        if value.eq_ignore_ascii_case("ieee") {
+            Ok(CrcAlgorithm::IEEE)
+        } else if value.eq_ignore_ascii_case("castagnoli") {
+            Ok(CrcAlgorithm::Castagnoli)
+        } else {
+            Err(Error::illegal_argument("--crc", value))
+        }
+    }
+
+    #[inline]
+    pub fn digest(self) -> crc32::Digest {
+        match self {
+            CrcAlgorithm::IEEE       => crc32::Digest::new(crc32::IEEE),
+            CrcAlgorithm::Castagnoli => crc32::Digest::new(crc32::CASTAGNOLI),
+        }
+    }
+}
+
+/// Hash algorithms usable for the sidecar checksum file written by `pack`
+/// and checked by `check`. This is independent of the checksums defined by
+/// the VPK format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    CRC32,
+    MD5,
+    #[cfg(feature = "sha2")]
+    SHA256,
+}
+
+impl HashAlgorithm {
+    pub fn parse(value: &str) -> Result<Self> {
+        if value.eq_ignore_ascii_case("crc32") {
+            return Ok(HashAlgorithm::CRC32);
+        }
+
+        if value.eq_ignore_ascii_case("md5") {
+            return Ok(HashAlgorithm::MD5);
+        }
+
+        #[cfg(feature = "sha2")]
+        if value.eq_ignore_ascii_case("sha256") {
+            return Ok(HashAlgorithm::SHA256);
+        }
+
+        Err(Error::illegal_argument("--sidecar-hash", value))
+    }
+
+    /// All supported algorithms.
+    pub fn all() -> &'static [HashAlgorithm] {
+        &[
+            #[cfg(feature = "sha2")]
+            HashAlgorithm::SHA256,
+            HashAlgorithm::MD5,
+            HashAlgorithm::CRC32,
+        ]
+    }
+
+    /// The algorithm of a sidecar file by its file name extension.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let ext = path.as_ref().extension()?.to_str()?;
+        HashAlgorithm::all().iter().copied().find(|algorithm| ext.eq_ignore_ascii_case(algorithm.extension()))
+    }
+
+    /// File name extension of the sidecar file, which is also the name of the algorithm.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            HashAlgorithm::CRC32  => "crc32",
+            HashAlgorithm::MD5    => "md5",
+            #[cfg(feature = "sha2")]
+            HashAlgorithm::SHA256 => "sha256",
+        }
+    }
+
+    pub fn hasher(&self) -> Box<dyn Hasher> {
+        match self {
+            HashAlgorithm::CRC32  => Box::new(crc32::Digest::new(crc32::IEEE)),
+            HashAlgorithm::MD5    => Box::new(md5::Context::new()),
+            #[cfg(feature = "sha2")]
+            HashAlgorithm::SHA256 => Box::new(<sha2::Sha256 as sha2::Digest>::new()),
+        }
+    }
+}
+
+/// Path of the sidecar file of a package, e.g. `pak01_dir.vpk.sha256`.
+pub fn sidecar_path(package: &Package, algorithm: HashAlgorithm) -> PathBuf {
+    let mut path = package.archive_path(DIR_INDEX).into_os_string();
+    path.push(".");
+    path.push(algorithm.extension());
+    PathBuf::from(path)
+}
+
+/// Write the checksums of all files of a package in the format used by
+/// `sha256sum` and similar tools.
+pub fn write_sidecar(package: &Package, algorithm: HashAlgorithm, buffer_size: usize) -> Result<()> {
+    let path = sidecar_path(package, algorithm);
+    let mut archs = ArchiveCache::for_package(package);
+    archs.set_buffer_size(buffer_size)?;
+
+    let mut writer = match fs::File::create(&path) {
+        Ok(file) => std::io::BufWriter::new(file),
+        Err(error) => return Err(Error::io_with_path(error, path)),
+    };
+
+    let mut hasher = algorithm.hasher();
+    for (vpk_path, file) in package.recursive_file_list(&DEFAULT_ORDER) {
+        archs.read_file_data(file, |data| {
+            hasher.update(data);
+            Ok(())
+        })?;
+
+        if let Err(error) = writeln!(writer, "{}  {}", hasher.finish(), vpk_path) {
+            return Err(Error::io_with_path(error, path));
+        }
+    }
+
+    if let Err(error) = writer.flush() {
+        return Err(Error::io_with_path(error, path));
+    }
+
+    Ok(())
+}
+
+/// Checksums of the files of a package read from a sidecar file.
+pub struct Sidecar {
+    pub path: PathBuf,
+    pub algorithm: HashAlgorithm,
+    sums: HashMap<String, String>,
+}
+
+impl Sidecar {
+    /// Read a sidecar file in the format written by [`write_sidecar`].
+    pub fn read(path: impl AsRef<Path>, algorithm: HashAlgorithm) -> Result<Self> {
+        let path = path.as_ref();
+        let reader = match fs::File::open(path) {
+            Ok(file) => BufReader::new(file),
+            Err(error) => return Err(Error::io_with_path(error, path)),
+        };
+
+        let mut sums = HashMap::new();
+        for line in reader.lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(error) => return Err(Error::io_with_path(error, path)),
+            };
+
+            if line.is_empty() {
+                continue;
+            }
+
+            match line.find("  ") {
+                Some(index) => {
+                    sums.insert(line[index + 2..].to_owned(), line[..index].to_owned());
+                },
+                None => return Err(Error::other(format!("illegal line in sidecar file: {:?}", line)).with_path(path)),
+            }
+        }
+
+        Ok(Sidecar {
+            path: path.to_path_buf(),
+            algorithm,
+            sums,
+        })
+    }
+
+    /// Find the sidecar file next to a package, trying all supported
+    /// algorithms in the order of [`HashAlgorithm::all`].
+    pub fn find(package: &Package) -> Option<(PathBuf, HashAlgorithm)> {
+        for &algorithm in HashAlgorithm::all() {
+            let path = sidecar_path(package, algorithm);
+            if path.is_file() {
+                return Some((path, algorithm));
+            }
+        }
+        None
+    }
+
+    /// The expected checksum of a file as lower or upper case hex string.
+    #[inline]
+    pub fn get(&self, vpk_path: &str) -> Option<&str> {
+        self.sums.get(vpk_path).map(String::as_str)
+    }
+
+    /// Paths of all files listed in the sidecar file, in no particular order.
+    #[inline]
+    pub fn paths(&self) -> impl Iterator<Item=&str> {
+        self.sums.keys().map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check::{check, CheckOptions};
+    use crate::pack::{pack, PackOptions};
+    use crate::util::TempDir;
+
+    #[test]
+    fn crc32_hasher_resets_on_finish() {
+        let mut hasher = HashAlgorithm::CRC32.hasher();
+        hasher.update(b"first file");
+        hasher.finish();
+        hasher.update(b"second file");
+        assert_eq!(hasher.finish(), format!("{:08x}", crc32::checksum_ieee(b"second file")));
+    }
+
+    #[test]
+    fn sidecar_round_trip() {
+        let tmp = TempDir::new("sidecar-round-trip");
+        tmp.write("in/m/a.bin", b"some data");
+        tmp.write("in/m/b.bin", &[0x5a; 20000]);
+        tmp.write("in/n/c.txt", b"more data");
+
+        for &algorithm in HashAlgorithm::all() {
+            let options = PackOptions::builder()
+                .max_inline_size(0)
+                .sidecar_hash(Some(algorithm))
+                .quiet(true)
+                .build().unwrap();
+            let package = pack(tmp.path.join("out_dir.vpk"), tmp.path.join("in"), options).unwrap();
+
+            let sidecar = Sidecar::read(sidecar_path(&package, algorithm), algorithm).unwrap();
+            if algorithm == HashAlgorithm::CRC32 {
+                assert_eq!(sidecar.get("m/b.bin"), Some(format!("{:08x}", crc32::checksum_ieee(&[0x5a; 20000])).as_str()));
+            }
+
+            check(&package, CheckOptions {
+                quiet: true,
+                sidecar_hash: Some(algorithm),
+                ..CheckOptions::new()
+            }).unwrap();
+
+            for path in ["m/a.bin", "m/b.bin", "n/c.txt"] {
+                let filter = [path];
+                check(&package, CheckOptions {
+                    quiet: true,
+                    filter: Some(&filter),
+                    sidecar_hash: Some(algorithm),
+                    ..CheckOptions::new()
+                }).unwrap();
+            }
+        }
+    }
+}
@@ -14,10 +14,31 @@
 // along with rust-vpk.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::io::{Read, BufRead, Write, SeekFrom, Seek};
+use std::borrow::Cow;
+use std::ops::Range;
 
 use crate::result::{Result, Error};
 use crate::consts::{DIR_INDEX, TERMINATOR};
 use crate::entry;
+use crate::package::{Encoding, Md5};
+
+/// Calculate the MD5 sum of the bytes in `range` of `reader`, using `buf`
+/// for reading.
+pub(crate) fn md5_of_range(reader: &mut (impl Read + Seek), buf: &mut [u8], range: Range<u64>) -> std::io::Result<Md5> {
+    reader.seek(SeekFrom::Start(range.start))?;
+
+    let mut hasher = md5::Context::new();
+    let mut remaining = range.end - range.start;
+    while remaining > 0 {
+        let len = remaining.min(buf.len() as u64) as usize;
+        let buf = &mut buf[..len];
+        reader.read_exact(buf)?;
+        hasher.consume(&buf);
+        remaining -= len as u64;
+    }
+
+    Ok(*hasher.compute())
+}
 
 #[inline]
 pub(crate) fn read_u16(file: &mut impl Read) -> std::io::Result<u16> {
@@ -35,7 +56,7 @@ pub(crate) fn read_u32(file: &mut impl Read) -> std::io::Result<u32> {
     Ok((buffer[3] as u32) << 24 | (buffer[2] as u32) << 16 | (buffer[1] as u32) << 8 | buffer[0] as u32)
 }
 
-pub(crate) fn read_str<'a>(file: &mut impl BufRead, mut buffer: &'a mut Vec<u8>) -> Result<&'a str> {
+pub(crate) fn read_str<'a>(file: &mut impl BufRead, mut buffer: &'a mut Vec<u8>, encoding: Encoding) -> Result<Cow<'a, str>> {
     buffer.clear();
     file.read_until(0, &mut buffer)?;
 
@@ -44,7 +65,14 @@ pub(crate) fn read_str<'a>(file: &mut impl BufRead, mut buffer: &'a mut Vec<u8>)
         _ => { return Err(Error::unexpected_eof()); }
     }
 
-    Ok(std::str::from_utf8(buffer)?)
+    match std::str::from_utf8(buffer) {
+        Ok(value) => Ok(Cow::Borrowed(value)),
+        Err(error) => match encoding {
+            Encoding::Utf8 => Err(error.into()),
+            // every byte is the code point of the same value
+            Encoding::Latin1 => Ok(Cow::Owned(buffer.iter().map(|&byte| byte as char).collect())),
+        },
+    }
 }
 
 pub(crate) fn read_file<R>(file: &mut R, index: usize, data_offset: u32) -> Result<entry::File>
@@ -117,8 +145,17 @@ pub(crate) fn write_file(file: &mut impl Write, entry: &entry::File, dir_size: u
     Ok(())
 }
 
+/// Copy `count` bytes from `in_file` to `out_file`, in chunks of at most
+/// `buffer_size` bytes.
+#[inline]
+pub fn transfer(in_file: &mut std::fs::File, out_file: &mut std::fs::File, count: usize, buffer_size: usize) -> std::io::Result<()> {
+    transfer_with_progress(in_file, out_file, count, buffer_size, &mut |_| {})
+}
+
+/// Like [`transfer`], but `progress` is called with the number of bytes
+/// of every copied chunk. The reported numbers add up to `count`.
 #[cfg(target_os = "linux")]
-pub fn transfer(in_file: &mut std::fs::File, out_file: &mut std::fs::File, count: usize) -> std::io::Result<()> {
+pub fn transfer_with_progress(in_file: &mut std::fs::File, out_file: &mut std::fs::File, count: usize, buffer_size: usize, progress: &mut impl FnMut(usize)) -> std::io::Result<()> {
     use std::os::unix::io::AsRawFd;
 
     let in_fd  = in_file.as_raw_fd();
@@ -126,38 +163,136 @@ pub fn transfer(in_file: &mut std::fs::File, out_file: &mut std::fs::File, count
 
     let mut remaining = count;
     while remaining > 0 {
-        unsafe {
-            let result = libc::sendfile(out_fd, in_fd, std::ptr::null_mut(), remaining as libc::size_t);
+        let chunk_size = if remaining > buffer_size { buffer_size } else { remaining };
+        let result = unsafe {
+            libc::sendfile(out_fd, in_fd, std::ptr::null_mut(), chunk_size as libc::size_t)
+        };
 
-            if result < 0 {
-                return Err(std::io::Error::last_os_error());
-            }
+        if result < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
 
-            remaining -= result as usize;
+        if result == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof,
+                format!("{} bytes missing at end of file", remaining)));
         }
+
+        remaining -= result as usize;
+        progress(result as usize);
     }
 
     Ok(())
 }
 
+/// Like [`transfer`], but `progress` is called with the number of bytes
+/// of every copied chunk. The reported numbers add up to `count`.
 #[cfg(not(target_os = "linux"))]
-pub fn transfer(in_file: &mut std::fs::File, out_file: &mut std::fs::File, count: usize) -> std::io::Result<()> {
-    use crate::consts::BUFFER_SIZE;
-
-    let mut buf = [0u8; BUFFER_SIZE];
+pub fn transfer_with_progress(in_file: &mut std::fs::File, out_file: &mut std::fs::File, count: usize, buffer_size: usize, progress: &mut impl FnMut(usize)) -> std::io::Result<()> {
+    let mut buf = vec![0u8; if count < buffer_size { count } else { buffer_size }];
 
     let mut remaining = count;
-    while remaining >= BUFFER_SIZE {
+    while remaining >= buf.len() && !buf.is_empty() {
         in_file.read_exact(&mut buf)?;
         out_file.write_all(&buf)?;
-        remaining -= BUFFER_SIZE;
+        remaining -= buf.len();
+        progress(buf.len());
     }
 
     if remaining > 0 {
         let buf = &mut buf[..remaining];
         in_file.read_exact(buf)?;
         out_file.write_all(buf)?;
+        progress(remaining);
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::util::TempDir;
+
+    #[test]
+    fn md5_of_range_in_chunks() {
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let mut reader = Cursor::new(&data);
+
+        for buf_size in [1, 7, 64, 4096] {
+            let mut buf = vec![0; buf_size];
+            assert_eq!(md5_of_range(&mut reader, &mut buf, 100..900).unwrap(), *md5::compute(&data[100..900]));
+            assert_eq!(md5_of_range(&mut reader, &mut buf, 0..0).unwrap(), *md5::compute(b""));
+        }
+
+        let mut buf = [0; 16];
+        assert!(md5_of_range(&mut reader, &mut buf, 900..1001).is_err());
+    }
+
+    #[test]
+    fn transfer_progress_sums_to_count() {
+        let tmp = TempDir::new("transfer-progress");
+        let data: Vec<u8> = (0..=255).cycle().take(10000).collect();
+        let in_path = tmp.write("in.bin", &data);
+        let out_path = tmp.path.join("out.bin");
+
+        let mut in_file = std::fs::File::open(&in_path).unwrap();
+        in_file.seek(SeekFrom::Start(100)).unwrap();
+        let mut out_file = std::fs::File::create(&out_path).unwrap();
+
+        let mut chunks = Vec::new();
+        transfer_with_progress(&mut in_file, &mut out_file, 5000, 1024, &mut |size| chunks.push(size)).unwrap();
+        drop(out_file);
+
+        assert_eq!(chunks.iter().sum::<usize>(), 5000);
+        assert!(chunks.len() >= 5);
+        assert!(chunks.iter().all(|&size| size > 0 && size <= 1024));
+        assert_eq!(std::fs::read(&out_path).unwrap(), &data[100..5100]);
+
+        // past the end of the input file
+        let mut out_file = std::fs::File::create(&out_path).unwrap();
+        let mut total = 0;
+        assert!(transfer_with_progress(&mut in_file, &mut out_file, 5000, 1024, &mut |size| total += size).is_err());
+        assert!(total <= 4900);
+    }
+
+    #[test]
+    fn read_str_latin1() {
+        let mut buffer = Vec::new();
+
+        // "café" with the Latin-1 byte for "é", which isn't valid UTF-8
+        let mut reader = Cursor::new(b"caf\xe9\0rest\0");
+        assert!(read_str(&mut reader, &mut buffer, Encoding::Utf8).is_err());
+
+        let mut reader = Cursor::new(b"caf\xe9\0rest\0");
+        assert_eq!(read_str(&mut reader, &mut buffer, Encoding::Latin1).unwrap(), "caf\u{e9}");
+        // the terminator is consumed
+        assert_eq!(read_str(&mut reader, &mut buffer, Encoding::Latin1).unwrap(), "rest");
+
+        // valid UTF-8 is kept as it is, even with Latin-1
+        let mut reader = Cursor::new("caf\u{e9}\0".as_bytes());
+        let value = read_str(&mut reader, &mut buffer, Encoding::Latin1).unwrap();
+        assert!(matches!(value, Cow::Borrowed(_)));
+        assert_eq!(value, "caf\u{e9}");
+
+        // missing terminator
+        let mut reader = Cursor::new(b"caf\xe9");
+        assert!(read_str(&mut reader, &mut buffer, Encoding::Latin1).is_err());
+    }
+
+    #[test]
+    fn latin1_names_in_index() {
+        use crate::package::{Package, ReadOptions};
+        use crate::util::test_dir_vpk;
+
+        let mut index = test_dir_vpk(&[("m/caf#.txt", DIR_INDEX, 0, 0, b"x")], &[]);
+        let pos = index.iter().position(|&byte| byte == b'#').unwrap();
+        index[pos] = 0xe9;
+
+        assert!(Package::from_reader(Cursor::new(&index), ".", "pak", ReadOptions::new()).is_err());
+
+        let options = ReadOptions { encoding: Encoding::Latin1, ..ReadOptions::new() };
+        let package = Package::from_reader(Cursor::new(&index), ".", "pak", options).unwrap();
+        assert_eq!(package.get_file("m/caf\u{e9}.txt").unwrap().preload, b"x");
+    }
+}
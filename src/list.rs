@@ -14,23 +14,63 @@
 // along with rust-vpk.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::io::Write;
+use std::fs;
+use std::collections::HashMap;
 
-use crate::sort::{Order, DEFAULT_ORDER};
-use crate::util::{format_size, print_table, Align::*};
+use crate::sort::{Order, DEFAULT_ORDER, sort_raw};
+use crate::util::{format_size, print_table, retain_extensions, Align::*};
 use crate::result::Result;
 use crate::package::Package;
+use crate::entry::File;
 use crate::consts::DIR_INDEX;
+use crate::filter_file::FilterFile;
+use crate::color::{paint, Color};
+use crate::stats::{Stats, print_archive_stats};
 
 #[derive(Debug, PartialEq)]
 pub enum ListStyle {
-    Table { human_readable: bool },
+    /// With `wide` an additional column shows how the data of each file is
+    /// stored, see [`Storage`].
+    Table { human_readable: bool, color: bool, wide: bool },
     OnlyNames { null_separated: bool },
 }
 
+/// Which offset of a file is listed. This only makes a difference for data
+/// stored in the _dir.vpk file, in all other archives the data section
+/// starts at the beginning of the file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OffsetBase {
+    /// The offset as stored in the index, i.e. relative to the start of the
+    /// data section of the _dir.vpk file.
+    Stored,
+    /// The position in the archive file, e.g. for use with a hex editor.
+    File,
+}
+
+impl Default for OffsetBase {
+    #[inline]
+    fn default() -> Self {
+        OffsetBase::File
+    }
+}
+
 pub struct ListOptions<'a> {
     pub order: &'a Order,
     pub style: ListStyle,
     pub filter: Option<&'a [&'a str]>,
+    pub extensions: Option<&'a [&'a str]>,
+    /// Exclude files matching these patterns, additionally to `filter`.
+    pub filter_file: Option<&'a FilterFile>,
+    pub check_archives: bool,
+    /// Only list files that can actually be read, i.e. whose archive
+    /// exists and is long enough to contain the file data.
+    pub only_existing: bool,
+    /// Sort files that are equal in respect to `order` by name.
+    pub stable_sort: bool,
+    /// Print the size of each archive file on disk compared to the size
+    /// used by the index after the table, like `stats` does.
+    pub verbose: bool,
+    pub offset_base: OffsetBase,
 }
 
 impl ListOptions<'_> {
@@ -43,7 +83,7 @@ impl ListOptions<'_> {
 impl Default for ListStyle {
     #[inline]
     fn default() -> Self {
-        ListStyle::Table { human_readable: false }
+        ListStyle::Table { human_readable: false, color: false, wide: false }
     }
 }
 
@@ -54,22 +94,149 @@ impl Default for ListOptions<'_> {
             order: &DEFAULT_ORDER,
             style: ListStyle::default(),
             filter: None,
+            extensions: None,
+            filter_file: None,
+            check_archives: false,
+            only_existing: false,
+            stable_sort: true,
+            verbose: false,
+            offset_base: OffsetBase::default(),
         }
     }
 }
 
-pub fn list(package: &Package, options: ListOptions) -> Result<()> {
-    let files = match options.filter {
+/// Where the data of a file is stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Storage {
+    /// All data is inlined into the index (this includes empty files).
+    Inline,
+    /// All data is in an archive.
+    Archive,
+    /// The first part of the data is inlined into the index (preload data)
+    /// and the rest is in an archive.
+    Split,
+}
+
+impl Storage {
+    pub fn of(file: &File) -> Self {
+        match (file.inline_size > 0, file.size > 0) {
+            (true, true)  => Storage::Split,
+            (false, true) => Storage::Archive,
+            _             => Storage::Inline,
+        }
+    }
+
+    #[inline]
+    pub fn name(self) -> &'static str {
+        match self {
+            Storage::Inline  => "inline",
+            Storage::Archive => "archive",
+            Storage::Split   => "split",
+        }
+    }
+}
+
+/// A single file of a package as listed by [`list`].
+pub struct ListRow<'a> {
+    pub path: String,
+    pub file: &'a File,
+    /// Offset of the file data as selected by [`ListOptions::offset_base`].
+    pub offset: u32,
+    /// Whether the archive containing the file data is missing on disk.
+    /// `None` if archives weren't checked or the file is fully inlined.
+    pub archive_missing: Option<bool>,
+}
+
+impl ListRow<'_> {
+    #[inline]
+    pub fn full_size(&self) -> u64 {
+        self.file.inline_size as u64 + self.file.size as u64
+    }
+
+    #[inline]
+    pub fn storage(&self) -> Storage {
+        Storage::of(self.file)
+    }
+}
+
+/// Collect the files of a package in the order and with the filter given
+/// in `options`, without printing anything.
+///
+/// ```ignore
+/// let package = Package::from_path("pak01_dir.vpk", ReadOptions::new())?;
+/// for row in list_rows(&package, &ListOptions::new())? {
+///     println!("{} {}", row.path, row.full_size());
+/// }
+/// ```
+pub fn list_rows<'a>(package: &'a Package, options: &ListOptions) -> Result<Vec<ListRow<'a>>> {
+    let order: &Order = if options.stable_sort { options.order } else { &[] };
+    let mut files = match options.filter {
         None => {
-            package.recursive_file_list(options.order)
+            package.recursive_file_list(order)
         },
         Some(paths) => {
-            package.recursive_file_list_from(&paths, options.order)?
+            package.recursive_file_list_from(paths, order)?
         }
     };
 
-    match options.style {
-        ListStyle::Table { human_readable } => {
+    if !options.stable_sort {
+        sort_raw(&mut files, options.order);
+    }
+
+    if let Some(extensions) = options.extensions {
+        retain_extensions(&mut files, extensions);
+    }
+
+    if let Some(filter_file) = options.filter_file {
+        filter_file.retain(&mut files);
+    }
+
+    // archive lengths, None if the archive is missing
+    let mut archives: HashMap<u16, Option<u64>> = HashMap::new();
+    let mut archive_len = |archive_index: u16| -> Option<u64> {
+        *archives.entry(archive_index).or_insert_with(||
+            fs::metadata(package.archive_path(archive_index)).ok().map(|meta| meta.len()))
+    };
+
+    let mut rows = Vec::with_capacity(files.len());
+    for (path, file) in files {
+        if options.only_existing && file.size > 0 {
+            match archive_len(file.archive_index) {
+                Some(len) if file.offset as u64 + file.size as u64 <= len => {},
+                _ => continue,
+            }
+        }
+
+        let archive_missing = if options.check_archives && file.size > 0 {
+            Some(archive_len(file.archive_index).is_none())
+        } else {
+            None
+        };
+        let offset = match options.offset_base {
+            OffsetBase::Stored => package.stored_offset(file),
+            OffsetBase::File   => file.offset,
+        };
+        rows.push(ListRow { path, file, offset, archive_missing });
+    }
+
+    Ok(rows)
+}
+
+/// Print the directory part of a path in a different color than the file name.
+fn paint_path(path: &str, color: bool) -> String {
+    match path.rfind('/') {
+        Some(index) if color => {
+            let (dirname, filename) = path.split_at(index + 1);
+            format!("{}{}", paint(dirname, Color::Blue, true), filename)
+        },
+        _ => path.to_owned(),
+    }
+}
+
+/// Print rows as returned by [`list_rows`] in the given style.
+pub fn print_list(rows: &[ListRow], style: &ListStyle) -> Result<()> {
+    match *style {
+        ListStyle::Table { human_readable, color, wide } => {
             let mut table: Vec<Vec<String>> = Vec::new();
 
             let fmt_size = if human_readable {
@@ -78,34 +245,57 @@ pub fn list(package: &Package, options: ListOptions) -> Result<()> {
                 |size: u64| format!("{}", size)
             };
 
-            for (path, file) in files {
-                let size = file.inline_size as u32 + file.size;
-                table.push(vec![
+            let check_archives = rows.iter().any(|row| row.archive_missing.is_some());
+
+            for row in rows {
+                let file = row.file;
+                let mut cols = vec![
                     format!("{}", file.index),
                     if file.archive_index == DIR_INDEX {
                         "dir".to_owned()
                     } else {
                         format!("{}", file.archive_index)
                     },
-                    format!("{}", file.offset),
+                    format!("{}", row.offset),
                     fmt_size(file.inline_size as u64),
                     fmt_size(file.size as u64),
-                    fmt_size(size as u64),
-                    format!("0x{:08x}", file.crc32),
-                    path.to_owned(),
-                ]);
+                    paint(fmt_size(row.full_size()), Color::Bold, color),
+                ];
+                if wide {
+                    cols.push(row.storage().name().to_owned());
+                }
+                cols.push(format!("0x{:08x}", file.crc32));
+                cols.push(paint_path(&row.path, color));
+                if check_archives {
+                    cols.push(if row.archive_missing == Some(true) {
+                        paint("MISSING", Color::Red, color)
+                    } else {
+                        String::new()
+                    });
+                }
+                table.push(cols);
+            }
+
+            let mut header = vec!["Index", "Archive", "Offset", "Inline-Size", "Archive-Size", "Full-Size"];
+            let mut align  = vec![Right,   Right,     Right,    Right,         Right,          Right];
+            if wide {
+                header.push("Storage");
+                align.push(Left);
+            }
+            header.extend(["CRC32", "Filename"]);
+            align.extend([Right,   Left]);
+            if check_archives {
+                header.push("Archive-Status");
+                align.push(Left);
             }
 
-            print_table(
-                &["Index", "Archive", "Offset", "Inline-Size", "Archive-Size", "Full-Size", "CRC32", "Filename"],
-                &[Right,   Right,     Right,    Right,         Right,          Right,       Right,   Left],
-                &table);
+            print_table(&header, &align, &table);
         }
         ListStyle::OnlyNames { null_separated } => {
             let sep = [if null_separated { 0 } else { '\n' as u8 }];
             let mut stdout = std::io::stdout();
-            for (path, _) in files {
-                stdout.write_all(path.as_bytes())?;
+            for row in rows {
+                stdout.write_all(row.path.as_bytes())?;
                 stdout.write_all(&sep)?;
             }
         }
@@ -113,3 +303,188 @@ pub fn list(package: &Package, options: ListOptions) -> Result<()> {
 
     Ok(())
 }
+
+pub fn list(package: &Package, options: ListOptions) -> Result<()> {
+    let rows = list_rows(package, &options)?;
+    print_list(&rows, &options.style)?;
+
+    if options.verbose {
+        if let ListStyle::Table { human_readable, color, .. } = options.style {
+            println!();
+            print_archive_stats(&Stats::scan(package, None), human_readable, color);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::ReadOptions;
+    use crate::util::{TempDir, TestFile, test_dir_vpk};
+
+    fn paths<'a>(rows: &'a [ListRow]) -> Vec<&'a str> {
+        rows.iter().map(|row| row.path.as_str()).collect()
+    }
+
+    #[test]
+    fn rows_without_printing() {
+        let tmp = TempDir::new("list-rows");
+        tmp.write("pak_000.vpk", &[0; 300]);
+        let dirpath = tmp.write("pak_dir.vpk", &test_dir_vpk(&[
+            ("sound/b.wav",     0,         100, 200, b""),
+            ("materials/a.vmt", DIR_INDEX, 0,   0,   b"abc"),
+            ("materials/c.vtf", 0,         0,   100, b"xy"),
+        ], &[]));
+        let package = Package::from_path(&dirpath, ReadOptions::new()).unwrap();
+
+        let rows = list_rows(&package, &ListOptions::new()).unwrap();
+        assert_eq!(paths(&rows), ["materials/a.vmt", "materials/c.vtf", "sound/b.wav"]);
+        assert_eq!(rows.iter().map(ListRow::full_size).collect::<Vec<_>>(), [3, 102, 200]);
+        assert_eq!(rows.iter().map(ListRow::storage).collect::<Vec<_>>(),
+            [Storage::Inline, Storage::Split, Storage::Archive]);
+        assert!(rows.iter().all(|row| row.archive_missing.is_none()));
+
+        let filter = ["materials"];
+        let rows = list_rows(&package, &ListOptions { filter: Some(&filter), ..ListOptions::new() }).unwrap();
+        assert_eq!(paths(&rows), ["materials/a.vmt", "materials/c.vtf"]);
+
+        let extensions = ["VTF", "wav"];
+        let rows = list_rows(&package, &ListOptions { extensions: Some(&extensions), ..ListOptions::new() }).unwrap();
+        assert_eq!(paths(&rows), ["materials/c.vtf", "sound/b.wav"]);
+
+        let filter = ["nothing"];
+        assert!(list_rows(&package, &ListOptions { filter: Some(&filter), ..ListOptions::new() }).is_err());
+    }
+
+    #[test]
+    fn storage_classification() {
+        let file = File {
+            index: 0,
+            crc32: 0,
+            inline_size: 0,
+            archive_index: DIR_INDEX,
+            offset: 0,
+            size: 0,
+            preload: Vec::new(),
+        };
+        // empty files count as inline
+        assert_eq!(Storage::of(&file), Storage::Inline);
+        assert_eq!(Storage::of(&File { inline_size: 3, preload: b"abc".to_vec(), ..file.clone() }), Storage::Inline);
+        assert_eq!(Storage::of(&File { archive_index: 0, size: 100, ..file.clone() }), Storage::Archive);
+        assert_eq!(Storage::of(&File { size: 100, ..file.clone() }), Storage::Archive);
+        assert_eq!(Storage::of(&File { inline_size: 3, preload: b"abc".to_vec(), archive_index: 0, size: 100, ..file }), Storage::Split);
+
+        assert_eq!(Storage::Inline.name(),  "inline");
+        assert_eq!(Storage::Archive.name(), "archive");
+        assert_eq!(Storage::Split.name(),   "split");
+    }
+
+    #[test]
+    fn offset_base_of_dir_stored_file() {
+        let index = test_dir_vpk(&[
+            ("m/dir.bin",  DIR_INDEX, 5,   3,  b""),
+            ("m/arch.bin", 0,         100, 10, b""),
+        ], b"01234567");
+        let package = Package::from_reader(std::io::Cursor::new(&index), ".", "pak", ReadOptions::new()).unwrap();
+        let data_offset = package.data_offset();
+        assert_eq!(data_offset as usize, index.len() - 8);
+
+        let dir = package.get_file("m/dir.bin").unwrap();
+        assert_eq!(dir.offset, data_offset + 5);
+        assert_eq!(package.stored_offset(dir), 5);
+        // archives start at 0, so there is no difference
+        let arch = package.get_file("m/arch.bin").unwrap();
+        assert_eq!(arch.offset, 100);
+        assert_eq!(package.stored_offset(arch), 100);
+
+        let offsets = |offset_base: OffsetBase| -> Vec<(String, u32)> {
+            list_rows(&package, &ListOptions { offset_base, ..ListOptions::new() }).unwrap()
+                .into_iter().map(|row| (row.path, row.offset)).collect()
+        };
+        assert_eq!(offsets(OffsetBase::default()), offsets(OffsetBase::File));
+        assert_eq!(offsets(OffsetBase::File), [("m/arch.bin".to_owned(), 100), ("m/dir.bin".to_owned(), data_offset + 5)]);
+        assert_eq!(offsets(OffsetBase::Stored), [("m/arch.bin".to_owned(), 100), ("m/dir.bin".to_owned(), 5)]);
+    }
+
+    #[test]
+    fn missing_archive() {
+        let tmp = TempDir::new("list-missing-archive");
+        tmp.write("pak_000.vpk", &[0; 100]);
+        let dirpath = tmp.write("pak_dir.vpk", &test_dir_vpk(&[
+            ("m/a.bin", 0,         0, 100, b""),
+            ("m/b.bin", 1,         0, 100, b""),
+            ("m/c.txt", 1,         0, 0,   b"inline"),
+            ("m/d.bin", DIR_INDEX, 0, 3,   b""),
+        ], b"abc"));
+        let package = Package::from_path(&dirpath, ReadOptions::new()).unwrap();
+
+        let rows = list_rows(&package, &ListOptions::new()).unwrap();
+        assert!(rows.iter().all(|row| row.archive_missing.is_none()));
+
+        let rows = list_rows(&package, &ListOptions { check_archives: true, ..ListOptions::new() }).unwrap();
+        let missing: Vec<_> = rows.iter().map(|row| (row.path.as_str(), row.archive_missing)).collect();
+        assert_eq!(missing, [
+            ("m/a.bin", Some(false)),
+            ("m/b.bin", Some(true)),
+            ("m/c.txt", None),
+            ("m/d.bin", Some(false)),
+        ]);
+
+        let stats = Stats::scan(&package, None);
+        assert_eq!(stats.error_count(), 1);
+        assert!(stats.archives()[&1].io_error().is_some());
+        assert_eq!(stats.archives()[&1].file_size(), None);
+        assert_eq!(stats.archives()[&0].file_size(), Some(100));
+    }
+
+    #[test]
+    fn only_existing_with_truncated_archive() {
+        let tmp = TempDir::new("list-only-existing");
+        // truncated, the package expects 200 bytes
+        tmp.write("pak_000.vpk", &[0; 150]);
+        let dirpath = tmp.write("pak_dir.vpk", &test_dir_vpk(&[
+            ("m/a.bin", 0,         0,   100, b""),
+            ("m/b.bin", 0,         100, 100, b""),
+            ("m/c.bin", 0,         50,  100, b""),
+            ("m/d.bin", 1,         0,   10,  b""),
+            ("m/e.txt", 1,         0,   0,   b"inline"),
+            ("m/f.bin", DIR_INDEX, 0,   3,   b""),
+        ], b"abc"));
+        let package = Package::from_path(&dirpath, ReadOptions::new()).unwrap();
+
+        let rows = list_rows(&package, &ListOptions::new()).unwrap();
+        assert_eq!(rows.len(), 6);
+
+        let rows = list_rows(&package, &ListOptions { only_existing: true, ..ListOptions::new() }).unwrap();
+        // b.bin ends past the end of pak_000.vpk, c.bin ends exactly at it,
+        // pak_001.vpk is missing and inline files can always be read
+        assert_eq!(paths(&rows), ["m/a.bin", "m/c.bin", "m/e.txt", "m/f.bin"]);
+    }
+
+    #[test]
+    fn stable_rows_across_reads() {
+        use crate::sort::SortKey;
+
+        let names: Vec<String> = (0..50).map(|index| format!("d{}/f{:02}.bin", index % 5, index)).collect();
+        let files: Vec<TestFile> = names.iter()
+            .map(|name| (name.as_str(), DIR_INDEX, 0, 100, &b""[..]))
+            .collect();
+        let index = test_dir_vpk(&files, &[0; 100]);
+        let order = [SortKey::FullSize];
+
+        let read_rows = || -> Vec<String> {
+            let package = Package::from_reader(std::io::Cursor::new(&index), ".", "pak", ReadOptions::new()).unwrap();
+            list_rows(&package, &ListOptions { order: &order, ..ListOptions::new() }).unwrap()
+                .into_iter().map(|row| row.path).collect()
+        };
+
+        let mut sorted = names.clone();
+        sorted.sort();
+        for _ in 0..5 {
+            assert_eq!(read_rows(), sorted);
+        }
+    }
+
+}
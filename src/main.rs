@@ -18,12 +18,24 @@ pub(crate) mod util;
 
 pub mod list;
 pub mod stats;
+pub mod checksum;
+pub mod tree;
+pub mod color;
 pub mod sort;
+pub mod hash;
 pub mod check;
+pub mod doctor;
+pub mod validate;
+pub mod warnings;
+pub mod filter_file;
+pub mod diff;
+pub mod needed_archives;
 pub mod unpack;
+pub mod extract;
 pub mod pack;
 pub mod package;
 pub mod entry;
+pub mod file_reader;
 pub mod archive_cache;
 pub mod result;
 pub mod consts;
@@ -31,20 +43,32 @@ pub mod consts;
 #[cfg(feature = "fuse")]
 pub mod mount;
 
+use std::time::Instant;
+
 use clap::{Arg, App, SubCommand};
 
-use crate::list::{list, ListOptions, ListStyle};
-use crate::stats::stats;
+use crate::list::{list, ListOptions, ListStyle, OffsetBase};
+use crate::stats::{stats, StatsOptions};
+use crate::checksum::{checksum, ChecksumOptions};
+use crate::tree::{tree, TreeOptions};
 use crate::check::{check, CheckOptions};
-use crate::unpack::{unpack, UnpackOptions};
-use crate::pack::{pack, PackOptions};
-use crate::package::Package;
+use crate::doctor::doctor;
+use crate::diff::{diff, DiffOptions};
+use crate::needed_archives::{needed_archives, NeededArchivesOptions};
+use crate::unpack::{unpack, UnpackOptions, Flatten};
+use crate::extract::{extract, ExtractOptions};
+use crate::pack::{pack, read_file_list, read_order_list, PackOptions};
+use crate::package::{Package, Encoding, ReadOptions};
+use crate::warnings::Warnings;
 
-use crate::sort::{parse_order, DEFAULT_ORDER};
-use crate::consts::{DEFAULT_MAX_INLINE_SIZE, DEFAULT_MD5_CHUNK_SIZE};
+use crate::sort::{parse_order, SortKey, DEFAULT_ORDER};
+use crate::consts::{DEFAULT_MAX_INLINE_SIZE, DEFAULT_INDEX_WARNING_RATIO, BUFFER_SIZE};
 use crate::result::{Error, Result};
-use crate::pack::ArchiveStrategy;
-use crate::util::parse_size;
+use crate::pack::{ArchiveStrategy, AlignMode, Symlinks};
+use crate::hash::{HashAlgorithm, CrcAlgorithm};
+use crate::color::ColorChoice;
+use crate::filter_file::FilterFile;
+use crate::util::{parse_size, parse_time, read_lines, expand_response_files, format_size};
 
 #[cfg(feature = "fuse")]
 use crate::mount::{mount, MountOptions};
@@ -55,8 +79,12 @@ pub enum Filter<'a> {
 }
 
 impl<'a> Filter<'a> {
-    pub fn new(args: &'a clap::ArgMatches) -> Self {
-        if let Some(paths) = args.values_of("paths") {
+    /// `stdin_paths` are the paths read because of `--paths0`, if any.
+    pub fn new(args: &'a clap::ArgMatches, stdin_paths: Option<&'a [String]>) -> Self {
+        if let Some(paths) = stdin_paths {
+            // empty input selects nothing instead of everything
+            Filter::Paths(paths.iter().map(|path| path.as_str()).collect())
+        } else if let Some(paths) = args.values_of("paths") {
             if paths.len() == 0 {
                 Filter::None
             } else {
@@ -75,6 +103,25 @@ impl<'a> Filter<'a> {
     }
 }
 
+fn arg_paths0<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("paths0")
+        .long("paths0")
+        .takes_value(false)
+        .conflicts_with("paths")
+        .help(
+            "Read the paths from stdin instead of the command line, separated by NULL bytes \
+             like for xargs -0. Use this for paths that contain new lines.")
+}
+
+/// Read the paths given via `--paths0` from stdin.
+fn read_paths0(args: &clap::ArgMatches) -> Result<Option<Vec<String>>> {
+    if args.is_present("paths0") {
+        Ok(Some(read_lines(std::path::Path::new("-"), true)?))
+    } else {
+        Ok(None)
+    }
+}
+
 fn arg_human_readable<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("human-readable")
         .long("human-readable")
@@ -99,6 +146,204 @@ fn arg_paths<'a, 'b>() -> Arg<'a, 'b> {
         .help("If given, only consider these files from the package.")
 }
 
+fn arg_ext<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("ext")
+        .long("ext")
+        .short("e")
+        .takes_value(true)
+        .value_name("EXT,...")
+        .help(
+            "Only consider files with one of these comma separated extensions (e.g. vmt,vtf). \
+             If PATHs are also given only files matching both are considered.")
+}
+
+fn parse_extensions<'a>(args: &'a clap::ArgMatches) -> Option<Vec<&'a str>> {
+    args.value_of("ext").map(|exts| exts
+        .split(',')
+        .map(|ext| ext.trim().trim_start_matches('.'))
+        .filter(|ext| !ext.is_empty())
+        .collect())
+}
+
+fn arg_filter_file<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("filter-file")
+        .long("filter-file")
+        .takes_value(true)
+        .value_name("FILE")
+        .help(
+            "Exclude files matching the patterns in FILE, which uses the syntax of .gitignore files. \
+             Patterns starting with ! re-include files, even inside of excluded directories. The last \
+             matching pattern wins. If PATHs are also given only files matching both are considered.")
+}
+
+fn parse_filter_file(args: &clap::ArgMatches) -> Result<Option<FilterFile>> {
+    if let Some(path) = args.value_of_os("filter-file") {
+        Ok(Some(FilterFile::read(path)?))
+    } else {
+        Ok(None)
+    }
+}
+
+fn arg_sort<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("sort")
+        .long("sort")
+        .short("s")
+        .takes_value(true)
+        .value_name("ORDER")
+        .help(
+            "Sort order of list as comma separated keys:\n\
+             \n\
+             * path         - path of the file inside the package\n\
+             * inline-size  - size of the data embedded in the index\n\
+             * archive-size - size of the data in the actual archive\n\
+             * full-size    - sum of the other two sizes\n\
+             * offset       - offset inside of the archive\n\
+             * archive      - archive where the file is stored\n\
+             * index        - index of the file in the package index\n\
+             \n\
+             If you prepend the order with - you invert the sort order for that key. E.g.:\n\
+             \n\
+             vpk list --sort=-full-size,name")
+}
+
+fn parse_sort(args: &clap::ArgMatches) -> Result<Option<Vec<SortKey>>> {
+    if let Some(order) = args.value_of("sort") {
+        Ok(Some(parse_order(order)?))
+    } else {
+        Ok(None)
+    }
+}
+
+fn arg_sidecar_hash<'a, 'b>(help: &'static str) -> Arg<'a, 'b> {
+    #[cfg(feature = "sha2")]
+    const ALGORITHMS: &[&str] = &["crc32", "md5", "sha256"];
+
+    #[cfg(not(feature = "sha2"))]
+    const ALGORITHMS: &[&str] = &["crc32", "md5"];
+
+    Arg::with_name("sidecar-hash")
+        .long("sidecar-hash")
+        .takes_value(true)
+        .value_name("ALGORITHM")
+        .possible_values(ALGORITHMS)
+        .help(help)
+}
+
+fn parse_sidecar_hash(args: &clap::ArgMatches) -> Result<Option<HashAlgorithm>> {
+    if let Some(algorithm) = args.value_of("sidecar-hash") {
+        Ok(Some(HashAlgorithm::parse(algorithm)?))
+    } else {
+        Ok(None)
+    }
+}
+
+fn arg_color<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("color")
+        .long("color")
+        .takes_value(true)
+        .value_name("WHEN")
+        .possible_values(&["auto", "always", "never"])
+        .default_value("auto")
+        .help(
+            "Colorize the output. \"auto\" only colorizes the output if stdout is a terminal \
+             and the NO_COLOR environment variable is not set.")
+}
+
+fn parse_color(args: &clap::ArgMatches) -> Result<bool> {
+    if let Some(color) = args.value_of("color") {
+        Ok(ColorChoice::parse(color)?.use_color())
+    } else {
+        Ok(ColorChoice::default().use_color())
+    }
+}
+
+fn arg_retries<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("retries")
+        .long("retries")
+        .takes_value(true)
+        .value_name("N")
+        .help(
+            "Retry reading file data up to N times after transient I/O errors, \
+             waiting longer before each try. Useful for packages on network shares. [default: 0]")
+}
+
+fn arg_crc<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("crc")
+        .long("crc")
+        .takes_value(true)
+        .value_name("POLYNOMIAL")
+        .possible_values(&["ieee", "castagnoli"])
+        .default_value("ieee")
+        .help(
+            "Polynomial of the CRC32 sums in the index. Standard VPK packages always use \"ieee\", \
+             \"castagnoli\" is only for some VPK-like formats.")
+}
+
+fn parse_crc(args: &clap::ArgMatches) -> Result<CrcAlgorithm> {
+    if let Some(value) = args.value_of("crc") {
+        CrcAlgorithm::parse(value)
+    } else {
+        Ok(CrcAlgorithm::default())
+    }
+}
+
+fn arg_timing<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("timing")
+        .long("timing")
+        .takes_value(false)
+        .help("When done print the elapsed time and the throughput to stderr, based on the size of all files in the package.")
+}
+
+/// Print the elapsed time since `start` and the throughput for processing `size` bytes.
+fn print_timing(start: Instant, size: u64) {
+    let secs = start.elapsed().as_secs_f64();
+    let throughput = if secs > 0.0 { (size as f64 / secs) as u64 } else { 0 };
+    eprintln!("processed {} in {:.3} s ({}/s)", format_size(size), secs, format_size(throughput));
+}
+
+fn arg_ignore_missing<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("ignore-missing")
+        .long("ignore-missing")
+        .takes_value(false)
+        .help(
+            "Skip files whose archive doesn't exist instead of failing, e.g. for partially downloaded \
+             packages. The number of skipped files is reported at the end.")
+}
+
+fn parse_retries(args: &clap::ArgMatches) -> Result<u32> {
+    if let Some(value) = args.value_of("retries") {
+        if let Ok(retries) = value.parse::<u32>() {
+            Ok(retries)
+        } else {
+            Err(Error::illegal_argument("--retries", value))
+        }
+    } else {
+        Ok(0)
+    }
+}
+
+fn arg_max_open_files<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("max-open-files")
+        .long("max-open-files")
+        .takes_value(true)
+        .value_name("N")
+        .help(
+            "Keep at most N archives open at once, closing the least recently used one when needed. \
+             Useful on systems with a low limit of open files (ulimit -n). 0 means no limit. [default: 0]")
+}
+
+fn parse_max_open_files(args: &clap::ArgMatches) -> Result<usize> {
+    if let Some(value) = args.value_of("max-open-files") {
+        if let Ok(max_open_files) = value.parse::<usize>() {
+            Ok(max_open_files)
+        } else {
+            Err(Error::illegal_argument("--max-open-files", value))
+        }
+    } else {
+        Ok(0)
+    }
+}
+
 fn arg_verbose<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("verbose")
         .long("verbose")
@@ -107,6 +352,22 @@ fn arg_verbose<'a, 'b>() -> Arg<'a, 'b> {
         .help("Print verbose output.")
 }
 
+fn arg_quiet<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("quiet")
+        .long("quiet")
+        .short("q")
+        .takes_value(false)
+        .conflicts_with("verbose")
+        .help("Don't print informational output and warnings. Errors are still printed to stderr.")
+}
+
+fn arg_validate_offsets<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("validate-offsets")
+        .long("validate-offsets")
+        .takes_value(false)
+        .help("Before doing anything else make sure the data of every file lies within its archive.")
+}
+
 fn arg_allow_v0<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("allow-v0")
         .long("allow-v0")
@@ -115,12 +376,115 @@ fn arg_allow_v0<'a, 'b>() -> Arg<'a, 'b> {
         .help("Allow version 0 packages. (Packages without a header.)")
 }
 
+fn arg_encoding<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("encoding")
+        .long("encoding")
+        .takes_value(true)
+        .value_name("ENCODING")
+        .possible_values(&["utf8", "latin1"])
+        .default_value("utf8")
+        .help(
+            "Encoding of the names in the index. With \"latin1\" names that aren't valid UTF-8 \
+             are decoded as Latin-1 instead of failing, as needed for some legacy packages.")
+}
+
+fn parse_encoding(args: &clap::ArgMatches) -> Encoding {
+    match args.value_of("encoding") {
+        Some("latin1") => Encoding::Latin1,
+        _ => Encoding::Utf8,
+    }
+}
+
+fn parse_read_options(args: &clap::ArgMatches) -> ReadOptions {
+    ReadOptions {
+        allow_v0: args.is_present("allow-v0"),
+        lenient:  args.is_present("lenient"),
+        encoding: parse_encoding(args),
+    }
+}
+
+/// Read a package and warn right away if its index was truncated, which
+/// is only possible with --lenient. check and unpack use
+/// [`Package::from_path`] instead and report it in their warning summary.
+fn read_package(path: &str, options: ReadOptions, quiet: bool) -> Result<Package> {
+    let package = Package::from_path(path, options)?;
+    Warnings::new(true, quiet).warn_truncated(&package);
+    Ok(package)
+}
+
+fn arg_lenient<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("lenient")
+        .long("lenient")
+        .takes_value(false)
+        .help(
+            "Don't abort reading the index on an entry with an illegal terminator. \
+             Instead print a warning and use all the entries read up to that point.")
+}
+
+fn arg_buffer_size<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("buffer-size")
+        .long("buffer-size")
+        .takes_value(true)
+        .value_name("SIZE")
+        .help(
+            "Size of the buffer used for reading and copying file data. \
+             Values between 64 K and 16 M are sensible. [default: 1 M]")
+}
+
+fn parse_buffer_size(args: &clap::ArgMatches) -> Result<usize> {
+    if let Some(buffer_size) = args.value_of("buffer-size") {
+        if let Ok(size) = parse_size(buffer_size) {
+            if size == 0 {
+                return Err(Error::illegal_argument(
+                    "--buffer-size",
+                    buffer_size
+                ));
+            }
+            Ok(size)
+        } else {
+            Err(Error::illegal_argument(
+                "--buffer-size",
+                buffer_size
+            ))
+        }
+    } else {
+        Ok(BUFFER_SIZE)
+    }
+}
+
+fn parse_alignment(args: &clap::ArgMatches) -> Result<Option<u32>> {
+    if let Some(alignment) = args.value_of("alignment") {
+        if let Ok(align) = parse_size(alignment) {
+            if align == 0 || align > std::u32::MAX as usize {
+                return Err(Error::illegal_argument(
+                    "--alignment",
+                    alignment
+                ));
+            }
+            Ok(Some(align as u32))
+        } else {
+            Err(Error::illegal_argument(
+                "--alignment",
+                alignment
+            ))
+        }
+    } else {
+        Ok(None)
+    }
+}
+
 fn run() -> Result<()> {
     let default_max_inline_size_str = format!("{}", DEFAULT_MAX_INLINE_SIZE);
+    let default_index_warning_ratio_str = format!("{}", DEFAULT_INDEX_WARNING_RATIO);
 
     let app = App::new("VPK - Valve Packages")
         .version("1.0.0")
-        .author("Mathias Panzenböck <grosser.meister.morti@gmx.net>");
+        .author("Mathias Panzenböck <grosser.meister.morti@gmx.net>")
+        .after_help(
+            "Any argument of the form @FILE, where FILE exists, is replaced by the whitespace separated \
+             arguments read from FILE, e.g. for lists of paths that are too long for the command line. \
+             Other arguments starting with @ are kept as they are, e.g. --since @SECONDS. Use @@ to \
+             pass an argument starting with @ literally.");
 
     #[cfg(feature = "fuse")]
     let app = app
@@ -134,26 +498,13 @@ fn run() -> Result<()> {
         .subcommand(SubCommand::with_name("list")
             .alias("l")
             .about("List content of a VPK package.")
-            .arg(Arg::with_name("sort")
-                .long("sort")
-                .short("s")
-                .takes_value(true)
-                .value_name("ORDER")
-                .help(
-                    "Sort order of list as comma separated keys:\n\
-                     \n\
-                     * path         - path of the file inside the package\n\
-                     * inline-size  - size of the data embedded in the index\n\
-                     * archive-size - size of the data in the actual archive\n\
-                     * full-size    - sum of the other two sizes\n\
-                     * offset       - offset inside of the archive\n\
-                     * archive      - archive where the file is stored\n\
-                     * index        - index of the file in the package index\n\
-                     \n\
-                     If you prepend the order with - you invert the sort order for that key. E.g.:\n\
-                     \n\
-                     vpk list --sort=-full-size,name")
-            )
+            .arg(arg_sort())
+            .arg(Arg::with_name("no-stable-sort")
+                .long("no-stable-sort")
+                .takes_value(false)
+                .help(
+                    "Don't sort files that are equal in respect to --sort by name. \
+                     Their order might then change between runs."))
             .arg(Arg::with_name("only-names")
                 .long("only-names")
                 .short("n")
@@ -171,18 +522,95 @@ fn run() -> Result<()> {
                      This is useful for use with xargs --null, to be sure that \
                      possible new lines in file names aren't interpreted as \
                      file name separators."))
+            .arg(Arg::with_name("check-archives")
+                .long("check-archives")
+                .conflicts_with("only-names")
+                .takes_value(false)
+                .help(
+                    "Mark files whose data archive (the _NNN.vpk file) is missing on disk. \
+                     This helps to diagnose partially downloaded game content."))
+            .arg(Arg::with_name("wide")
+                .long("wide")
+                .short("w")
+                .conflicts_with("only-names")
+                .takes_value(false)
+                .help(
+                    "Add a column showing whether the data of each file is inlined into the index (inline), \
+                     in an archive (archive), or split between both (split)."))
+            .arg(Arg::with_name("offset-base")
+                .long("offset-base")
+                .takes_value(true)
+                .value_name("BASE")
+                .possible_values(&["stored", "file"])
+                .conflicts_with("only-names")
+                .help(
+                    "Which offset to list. \"file\" (the default) is the position in the archive file, e.g. for use with a hex editor. \
+                     \"stored\" is the offset as stored in the index, which is relative to the start of the data section. \
+                     This only makes a difference for data stored in the _dir.vpk file."))
+            .arg(Arg::with_name("paths-only-existing")
+                .long("paths-only-existing")
+                .takes_value(false)
+                .help(
+                    "Only list files that can actually be read, i.e. whose archive exists \
+                     and is long enough to contain the file data."))
+            .arg(arg_verbose()
+                .conflicts_with("only-names")
+                .help(
+                    "After the file list print the size of each archive file on disk \
+                     compared to the size used by the index, like stats does."))
+            .arg(arg_color())
             .arg(arg_allow_v0())
+            .arg(arg_lenient())
+            .arg(arg_encoding())
             .arg(arg_human_readable())
             .arg(arg_package())
-            .arg(arg_paths()))
+            .arg(arg_ext())
+            .arg(arg_filter_file())
+            .arg(arg_paths())
+            .arg(arg_paths0()))
 
         .subcommand(SubCommand::with_name("stats")
             .alias("s")
             .about("Print some statistics of a VPK package.")
+            .arg(Arg::with_name("alignment")
+                .long("alignment")
+                .short("a")
+                .takes_value(true)
+                .value_name("ALIGNMENT")
+                .help("Count files whose archive data is not aligned at given number of bytes and the padding needed to fix that."))
+            .arg(Arg::with_name("archives-only")
+                .long("archives-only")
+                .takes_value(false)
+                .conflicts_with("alignment")
+                .help(
+                    "Only print the table of archives with their file counts, file sizes, used sizes, \
+                     and wasted sizes. This is a quick overview of the archive layout."))
+            .arg(arg_color())
             .arg(arg_allow_v0())
+            .arg(arg_lenient())
+            .arg(arg_encoding())
             .arg(arg_human_readable())
             .arg(arg_package()))
 
+        .subcommand(SubCommand::with_name("tree")
+            .alias("t")
+            .about("Print the directory hierarchy of a VPK package.")
+            .arg(Arg::with_name("depth")
+                .long("depth")
+                .short("d")
+                .takes_value(true)
+                .value_name("N")
+                .help("Only descend N directory levels deep."))
+            .arg(arg_allow_v0())
+            .arg(arg_lenient())
+            .arg(arg_encoding())
+            .arg(arg_human_readable())
+            .arg(arg_package())
+            .arg(Arg::with_name("path")
+                .index(2)
+                .value_name("PATH")
+                .help("Only print the hierarchy below this directory of the package.")))
+
         .subcommand(SubCommand::with_name("check")
             .alias("c")
             .about("Check CRC32 and MD5 sums of files in a VPK package.")
@@ -192,20 +620,140 @@ fn run() -> Result<()> {
                 .takes_value(true)
                 .value_name("ALIGNMENT")
                 .help("Assume alignment of file data in bytes and print the differentce to the real alignment."))
+            .arg(Arg::with_name("check-overlaps")
+                .long("check-overlaps")
+                .takes_value(false)
+                .help("Check if the data of any two files overlaps inside of an archive."))
+            .arg(Arg::with_name("max-gap")
+                .long("max-gap")
+                .takes_value(true)
+                .value_name("SIZE")
+                .requires("check-overlaps")
+                .help("Also report gaps bigger than SIZE between the data of consecutive files in an archive. Requires: --check-overlaps"))
+            .arg(Arg::with_name("group-by-archive")
+                .long("group-by-archive")
+                .takes_value(false)
+                .requires("verbose")
+                .help("Print a header for each archive and indent its files below it. Requires: --verbose"))
+            .arg(Arg::with_name("md5-only")
+                .long("md5-only")
+                .takes_value(false)
+                .conflicts_with_all(&["alignment", "sidecar-hash", "sidecar"])
+                .help(
+                    "Only check the MD5 sums of the index, the archive MD5 list, and the whole _dir.vpk file \
+                     of a version 2 package, without reading any file data. This is a fast smoke test for big packages."))
+            .arg(arg_sidecar_hash(
+                "Also check the files against the checksums in the sidecar file written by pack --sidecar-hash \
+                 (e.g. pak01_dir.vpk.sha256). Without this option and --sidecar an existing sidecar file next \
+                 to the package is used automatically. The checksums are calculated in the same pass over \
+                 the file data as the CRC32 sums."))
+            .arg(Arg::with_name("sidecar")
+                .long("sidecar")
+                .takes_value(true)
+                .value_name("PATH")
+                .help(
+                    "Check the files against the checksums in the sidecar file at PATH. The algorithm is \
+                     derived from the file name extension unless --sidecar-hash is given."))
+            .arg(arg_buffer_size())
+            .arg(arg_retries())
+            .arg(arg_max_open_files())
+            .arg(arg_ignore_missing())
+            .arg(arg_crc())
+            .arg(arg_timing())
             .arg(arg_verbose())
+            .arg(Arg::with_name("json")
+                .long("json")
+                .takes_value(false)
+                .conflicts_with("verbose")
+                .help(
+                    "Print one JSON object per line for every checked file and a final summary object. \
+                     The summary is also printed if the check stops early. Every object has the fields \
+                     vpk_json_version and tool_version."))
+            .arg(arg_quiet())
+            .arg(arg_color())
             .arg(arg_allow_v0())
+            .arg(arg_lenient())
+            .arg(arg_encoding())
+            .arg(arg_validate_offsets())
             .arg(arg_human_readable())
             .arg(Arg::with_name("stop-on-error")
                 .long("stop-on-error")
                 .takes_value(false)
                 .help("Stop on first error."))
             .arg(arg_package())
+            .arg(arg_ext())
+            .arg(arg_paths())
+            .arg(arg_paths0()))
+
+        .subcommand(SubCommand::with_name("checksum")
+            .about("Print the CRC32 sum and optionally the MD5 sum of each file in a VPK package.")
+            .arg(arg_sort())
+            .arg(Arg::with_name("md5")
+                .long("md5")
+                .takes_value(false)
+                .help("Also calculate the MD5 sum of the data of each file."))
+            .arg(arg_buffer_size())
+            .arg(arg_allow_v0())
+            .arg(arg_lenient())
+            .arg(arg_encoding())
+            .arg(arg_package())
+            .arg(arg_ext())
             .arg(arg_paths()))
 
+        .subcommand(SubCommand::with_name("needed-archives")
+            .about(
+                "Print the archive files (the _NNN.vpk files) that contain data of the selected files, \
+                 e.g. to only download these before unpacking.")
+            .arg(Arg::with_name("json")
+                .long("json")
+                .takes_value(false)
+                .help(
+                    "Print a JSON object with the fields vpk_json_version, tool_version, and archives, \
+                     an array of objects with the archive index and path."))
+            .arg(arg_allow_v0())
+            .arg(arg_lenient())
+            .arg(arg_encoding())
+            .arg(arg_package())
+            .arg(arg_ext())
+            .arg(arg_paths())
+            .arg(arg_paths0()))
+
+        .subcommand(SubCommand::with_name("diff")
+            .about(
+                "Compare the files of two VPK packages by content (CRC32 and size). \
+                 Files whose data was only moved, e.g. by repacking, are not reported as modified.")
+            .arg(Arg::with_name("by-offset")
+                .long("by-offset")
+                .takes_value(false)
+                .help(
+                    "Also report files with unchanged content that are stored at a different place, \
+                     i.e. in a different archive, at a different offset, or with different inline data."))
+            .arg(arg_allow_v0())
+            .arg(arg_lenient())
+            .arg(arg_encoding())
+            .arg(Arg::with_name("old")
+                .index(1)
+                .required(true)
+                .value_name("OLD_PACKAGE")
+                .help("The package to compare against (e.g. pak01_dir.vpk)"))
+            .arg(Arg::with_name("new")
+                .index(2)
+                .required(true)
+                .value_name("NEW_PACKAGE")
+                .help("The package to compare (e.g. pak01_dir.vpk)")))
+
+        .subcommand(SubCommand::with_name("doctor")
+            .about("Run fast structural checks on a VPK package without checking any checksums.")
+            .arg(arg_allow_v0())
+            .arg(arg_lenient())
+            .arg(arg_encoding())
+            .arg(arg_package()))
+
         .subcommand(SubCommand::with_name("unpack")
             .alias("x")
             .about("Extract files from a VPK package.")
             .arg(arg_verbose())
+            .arg(arg_quiet())
             .arg(Arg::with_name("outdir")
                 .long("outdir")
                 .short("o")
@@ -217,15 +765,105 @@ fn run() -> Result<()> {
                 .short("a")
                 .help(
                     "Take directory names from the archives of the files.\n\
-                     Meaning the first level of generated directory names will be named \"000\", \"001\", \"002\", \"003\", ... and \"dir\"."))
+                     Meaning the first level of generated directory names will be named \"000\", \"001\", \"002\", \"003\", ... \"dir\", \n\
+                     and \"inline\" for files that are fully embedded in the index. pack --archive-from-dirname reads this layout back."))
+            .arg(Arg::with_name("root")
+                .long("root")
+                .takes_value(true)
+                .value_name("DIR")
+                .help(
+                    "Only extract the files below DIR inside of the package and strip DIR from their paths, \
+                     e.g. with --root materials the file materials/foo.vtf is written as foo.vtf. \
+                     PATHs are then relative to DIR."))
+            .arg(Arg::with_name("strip-components")
+                .long("strip-components")
+                .takes_value(true)
+                .value_name("N")
+                .help(
+                    "Strip N leading directory names from the paths of the extracted files. \
+                     Files with not more than N path components are skipped."))
+            .arg(Arg::with_name("prefix")
+                .long("prefix")
+                .takes_value(true)
+                .value_name("DIR")
+                .help(
+                    "Prepend DIR to the paths of the extracted files (after --strip-components). \
+                     This is useful to extract several packages into the same directory without collisions."))
+            .arg(Arg::with_name("flatten")
+                .long("flatten")
+                .takes_value(true)
+                .min_values(0)
+                .require_equals(true)
+                .value_name("MODE")
+                .possible_values(&["error", "rename"])
+                .conflicts_with("strip-components")
+                .help(
+                    "Write all files directly into the output directory using only their file names. \
+                     If two files have the same name this is an error (MODE \"error\", the default) \
+                     or further files are renamed to NAME-N.EXT (MODE \"rename\")."))
             .arg(Arg::with_name("check")
                 .long("check")
                 .short("c")
                 .takes_value(false)
                 .help("Check CRC32 sums while unpacking."))
+            .arg(Arg::with_name("verify")
+                .long("verify")
+                .takes_value(false)
+                .help(
+                    "Check CRC32 sums while unpacking and afterwards also the MD5 sums of the archive chunks \
+                     that contain the extracted data (VPK v2 only). Reports the number of failures at the end \
+                     instead of stopping at the first mismatch."))
+            .arg(Arg::with_name("preserve-times")
+                .long("preserve-times")
+                .takes_value(false)
+                .help(
+                    "Set the modification time of every extracted file to the one of the archive its data \
+                     is read from. VPK packages don't store times for the files themselves."))
+            .arg(arg_buffer_size())
+            .arg(arg_retries())
+            .arg(arg_max_open_files())
+            .arg(arg_ignore_missing())
+            .arg(arg_crc())
+            .arg(arg_timing())
+            .arg(arg_color())
             .arg(arg_allow_v0())
+            .arg(arg_lenient())
+            .arg(arg_encoding())
+            .arg(arg_validate_offsets())
             .arg(arg_package())
-            .arg(arg_paths()))
+            .arg(arg_ext())
+            .arg(arg_filter_file())
+            .arg(arg_paths())
+            .arg(arg_paths0()))
+
+        .subcommand(SubCommand::with_name("extract")
+            .about("Extract a single file from a VPK package to a given destination.")
+            .arg(arg_verbose())
+            .arg(arg_quiet())
+            .arg(Arg::with_name("to")
+                .long("to")
+                .short("o")
+                .value_name("DEST")
+                .takes_value(true)
+                .help("Write the file to DEST. [default: the file name of PATH in the current directory]"))
+            .arg(Arg::with_name("check")
+                .long("check")
+                .short("c")
+                .takes_value(false)
+                .help("Check the CRC32 sum while extracting."))
+            .arg(arg_buffer_size())
+            .arg(arg_retries())
+            .arg(arg_crc())
+            .arg(arg_allow_v0())
+            .arg(arg_lenient())
+            .arg(arg_encoding())
+            .arg(arg_validate_offsets())
+            .arg(arg_package())
+            .arg(Arg::with_name("path")
+                .index(2)
+                .required(true)
+                .value_name("PATH")
+                .help("The file inside of the package.")))
 
         .subcommand(SubCommand::with_name("pack")
             .alias("p")
@@ -248,16 +886,29 @@ fn run() -> Result<()> {
                 .short("a")
                 .takes_value(true)
                 .value_name("ALIGNMENT")
-                .help("Ensure that data in archives is aligned at given number of bytes."))
+                .help("Ensure that data in archives is aligned at given number of bytes. \
+                       Should be a power of two, other values print a warning."))
+            .arg(Arg::with_name("align-mode")
+                .long("align-mode")
+                .takes_value(true)
+                .value_name("MODE")
+                .possible_values(&["archive", "data"])
+                .default_value("archive")
+                .help(
+                    "What --alignment is measured from. \"archive\" aligns the position in the archive file, \
+                     \"data\" aligns the offset relative to the start of the file data, as stored in the index. \
+                     This only makes a difference for data stored in the _dir.vpk file."))
             .arg(Arg::with_name("archive-from-dirname")
                 .long("archive-from-dirname")
                 .short("d")
                 .takes_value(false)
-                .conflicts_with("max-archive-size")
+                .conflicts_with_all(&["max-archive-size", "single-archive", "num-archives", "balance-count"])
                 .help(
                     "Take archive distribution from directory names.\n\
-                     Meaning the first level of directory names have to be named \"000\", \"001\", \"002\", \"003\", ... and \"dir\".\n\
-                     Conficts with: --max-archive-size"))
+                     Meaning the first level of directory names have to be named \"000\", \"001\", \"002\", \"003\", ... \"dir\", and \"inline\".\n\
+                     Files in \"inline\" are embedded in the index, all other non-empty files are stored in the named archive \
+                     regardless of --max-inline-size. This is the layout written by unpack --dirname-from-archive.\n\
+                     Conficts with: --max-archive-size, --single-archive, --num-archives, --balance-count"))
             .arg(Arg::with_name("max-archive-size")
                 .long("max-archive-size")
                 .short("s")
@@ -265,8 +916,88 @@ fn run() -> Result<()> {
                 .value_name("SIZE")
                 .help(
                     "Distribute files to archives by ensuring no archive is bigger than the given size.\n\
-                     Conflicts with: --archive-from-dirname"
+                     Conflicts with: --archive-from-dirname, --single-archive, --num-archives, --balance-count"
                 ))
+            .arg(Arg::with_name("single-archive")
+                .long("single-archive")
+                .takes_value(false)
+                .conflicts_with_all(&["max-archive-size", "num-archives", "balance-count"])
+                .help(
+                    "Store all file data in the _dir.vpk file right after the index, regardless of its size. \
+                     No _NNN.vpk files are written.\n\
+                     Conflicts with: --archive-from-dirname, --max-archive-size, --num-archives, --balance-count"))
+            .arg(Arg::with_name("num-archives")
+                .long("num-archives")
+                .takes_value(true)
+                .value_name("N")
+                .conflicts_with_all(&["max-archive-size", "balance-count"])
+                .help(
+                    "Distribute files to at most N archives of about the same size. \
+                     The _dir.vpk file then only holds the index.\n\
+                     Conflicts with: --archive-from-dirname, --max-archive-size, --single-archive, --balance-count"))
+            .arg(Arg::with_name("balance-count")
+                .long("balance-count")
+                .takes_value(true)
+                .value_name("N")
+                .conflicts_with("max-archive-size")
+                .help(
+                    "Distribute files round-robin to N archives, so every archive has about the same number of files. \
+                     Unlike --num-archives the archive sizes can differ a lot, but reading many files is spread \
+                     evenly over the archives. The _dir.vpk file then only holds the index.\n\
+                     Conflicts with: --archive-from-dirname, --max-archive-size, --single-archive, --num-archives"))
+            .arg(Arg::with_name("follow-symlinks")
+                .long("follow-symlinks")
+                .takes_value(false)
+                .conflicts_with_all(&["skip-symlinks", "error-symlinks"])
+                .help("Pack the files and directories symbolic links in the input directory point to."))
+            .arg(Arg::with_name("skip-symlinks")
+                .long("skip-symlinks")
+                .takes_value(false)
+                .conflicts_with("error-symlinks")
+                .help("Ignore symbolic links in the input directory with a warning. This is the default."))
+            .arg(Arg::with_name("error-symlinks")
+                .long("error-symlinks")
+                .takes_value(false)
+                .help("Fail if there are symbolic links in the input directory."))
+            .arg(Arg::with_name("strict")
+                .long("strict")
+                .takes_value(false)
+                .help(
+                    "Fail on special files (FIFOs, sockets, devices) in the input directory \
+                     instead of skipping them with a warning."))
+            .arg(Arg::with_name("normalize-ext")
+                .long("normalize-ext")
+                .takes_value(false)
+                .help(
+                    "Write all file extensions in lower case into the index, so e.g. foo.VMT and bar.vmt \
+                     end up in the same extension group. Some engines treat extensions that differ only \
+                     in case as the same."))
+            .arg(arg_crc())
+            .arg(arg_timing())
+            .arg(Arg::with_name("lowercase-paths")
+                .long("lowercase-paths")
+                .takes_value(false)
+                .help(
+                    "Write all directory and file names in lower case into the index. Extensions are \
+                     only lowercased together with --normalize-ext. Fails if two files would end up \
+                     with the same path."))
+            .arg(Arg::with_name("replace")
+                .long("replace")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("OLD=NEW")
+                .help(
+                    "Replace the prefix OLD of paths inside of the package by NEW, e.g. \
+                     --replace models/old/=models/new/. Can be given multiple times, the rules are \
+                     applied in order and after --lowercase-paths and --normalize-ext. Fails if two \
+                     files would end up with the same path."))
+            .arg(Arg::with_name("keep-going")
+                .long("keep-going")
+                .takes_value(false)
+                .help(
+                    "Leave out source files that can't be read (e.g. permission denied) with a warning \
+                     instead of failing, and report the number of skipped files at the end."))
             .arg(Arg::with_name("max-inline-size")
                 .long("max-inline-size")
                 .short("i")
@@ -274,7 +1005,88 @@ fn run() -> Result<()> {
                 .value_name("SIZE")
                 .default_value(&default_max_inline_size_str)
                 .help("Maximum size of files that will be embedded in the index."))
+            .arg(Arg::with_name("index-warning-ratio")
+                .long("index-warning-ratio")
+                .takes_value(true)
+                .value_name("RATIO")
+                .default_value(&default_index_warning_ratio_str)
+                .help(
+                    "Warn if a --max-inline-size bigger than the default makes the _dir.vpk file \
+                     bigger than this fraction of the whole package. 0 disables the warning."))
+            .arg(Arg::with_name("verify")
+                .long("verify")
+                .takes_value(false)
+                .help("Re-read the written header and MD5 sections and verify them (VPK v2 only)."))
+            .arg(Arg::with_name("verify-preload")
+                .long("verify-preload")
+                .takes_value(false)
+                .help(
+                    "Before writing the package read the source of every file that is inlined into the index \
+                     again and make sure it still matches the inlined data."))
+            .arg(Arg::with_name("check-after")
+                .long("check-after")
+                .takes_value(false)
+                .help(
+                    "Check the CRC32 sums of all files and the MD5 sums (VPK v2) of the written package, \
+                     like the check command does. Fails if the package doesn't verify."))
+            .arg(Arg::with_name("dedup")
+                .long("dedup")
+                .takes_value(false)
+                .help("Store the data of files with identical content only once."))
+            .arg(Arg::with_name("files-from")
+                .long("files-from")
+                .short("T")
+                .takes_value(true)
+                .value_name("FILE")
+                .help(
+                    "Only pack the files listed in FILE (one path per line, - for stdin) instead of \
+                     scanning INDIR. Relative paths are relative to INDIR, other paths need to be inside of INDIR. \
+                     This works well with find and git ls-files."))
+            .arg(Arg::with_name("null")
+                .long("null")
+                .short("z")
+                .requires("files-from")
+                .takes_value(false)
+                .help("Paths in the --files-from list are separated by NULL bytes (e.g. find -print0)."))
+            .arg(Arg::with_name("order-from")
+                .long("order-from")
+                .takes_value(true)
+                .value_name("FILE")
+                .help(
+                    "Write the data of the files listed in FILE (paths inside of the package, one per line) \
+                     first and in the listed order, e.g. in the order a game loads them. \
+                     All other files follow sorted by path."))
+            .arg(Arg::with_name("relative-to")
+                .long("relative-to")
+                .takes_value(true)
+                .value_name("DIR")
+                .conflicts_with_all(&["files-from", "archive-from-dirname"])
+                .help(
+                    "Make paths inside of the package relative to DIR instead of INDIR. \
+                     INDIR needs to be inside of DIR, e.g. pack /game/materials with --relative-to /game \
+                     to store its files as materials/..."))
+            .arg(Arg::with_name("temp-dir")
+                .long("temp-dir")
+                .takes_value(true)
+                .value_name("DIR")
+                .help(
+                    "Write the package to a temporary directory inside of DIR and only move it into place \
+                     once it is complete, so a failed pack never leaves a broken package behind. \
+                     [default: the directory of PACKAGE]"))
+            .arg(Arg::with_name("since")
+                .long("since")
+                .takes_value(true)
+                .value_name("TIME")
+                .help(
+                    "Only pack files modified at or after TIME, given as RFC 3339 date-time \
+                     (e.g. 2021-02-03T04:05:06Z) or as @SECONDS since the UNIX epoch."))
+            .arg(arg_sidecar_hash(
+                "Write the checksums of all files using ALGORITHM into a sidecar file next to the package \
+                 (e.g. pak01_dir.vpk.sha256). This file is not part of the VPK format. \
+                 sha256 is only available if compiled with the sha2 feature."))
+            .arg(arg_buffer_size())
             .arg(arg_verbose())
+            .arg(arg_quiet())
             .arg(arg_package())
             .arg(Arg::with_name("indir")
                 .index(2)
@@ -290,6 +1102,8 @@ fn run() -> Result<()> {
             "Mount a VPK package as read-only filesystem.\n\
              Use `fusermount -u <MOUNT-POINT>` to unmount again.")
         .arg(arg_allow_v0())
+        .arg(arg_lenient())
+        .arg(arg_encoding())
         .arg(Arg::with_name("foreground")
             .long("foreground")
             .short("f")
@@ -300,6 +1114,14 @@ fn run() -> Result<()> {
             .short("d")
             .takes_value(false)
             .help("Add \"debug\" to FUSE options. Implies: --foreground"))
+        .arg(arg_max_open_files())
+        .arg(Arg::with_name("meta-files")
+            .long("meta-files")
+            .takes_value(false)
+            .help(
+                "Add a virtual file <name>.vpkmeta next to every file, containing the CRC32 sum, \
+                 archive and offset of the file as text. This is the same information as in the \
+                 extended attributes, but for tools that don't support those."))
         .arg(arg_package())
         .arg(Arg::with_name("mount-point")
             .index(2)
@@ -307,142 +1129,373 @@ fn run() -> Result<()> {
             .value_name("MOUNT-POINT")
             .help("Directory where filesystem will be mounted.")));
     
-    let matches = app.get_matches();
+    let matches = app.get_matches_from(expand_response_files(std::env::args_os())?);
 
     match matches.subcommand() {
         ("list", Some(args)) => {
-            let order = if let Some(order) = args.value_of("sort") {
-                Some(parse_order(order)?)
-            } else {
-                None
-            };
+            let order = parse_sort(args)?;
             let order = match &order {
                 Some(order) => &order[..],
                 None => &DEFAULT_ORDER[..],
             };
 
-            let allow_v0       = args.is_present("allow-v0");
+            let read_options   = parse_read_options(args);
             let human_readable = args.is_present("human-readable");
             let null_separated = args.is_present("null");
             let only_names     = args.is_present("only-names");
+            let check_archives = args.is_present("check-archives");
+            let only_existing  = args.is_present("paths-only-existing");
+            let stable_sort    = !args.is_present("no-stable-sort");
+            let verbose        = args.is_present("verbose");
+            let wide           = args.is_present("wide");
+            let offset_base    = if args.value_of("offset-base") == Some("stored") {
+                OffsetBase::Stored
+            } else {
+                OffsetBase::File
+            };
+            let color          = parse_color(args)?;
             let path           = args.value_of("package").unwrap();
-            let filter         = Filter::new(args);
+            let paths0         = read_paths0(args)?;
+            let filter         = Filter::new(args, paths0.as_deref());
+            let extensions     = parse_extensions(args);
+            let filter_file    = parse_filter_file(args)?;
 
-            let package = Package::from_path(path, allow_v0)?;
+            let package = read_package(path, read_options, args.is_present("quiet"))?;
 
             list(&package, ListOptions {
                 order,
                 style: if only_names {
                     ListStyle::OnlyNames { null_separated }
                 } else {
-                    ListStyle::Table { human_readable }
+                    ListStyle::Table { human_readable, color, wide }
                 },
                 filter: filter.as_ref(),
+                extensions: extensions.as_deref(),
+                filter_file: filter_file.as_ref(),
+                check_archives,
+                only_existing,
+                stable_sort,
+                verbose,
+                offset_base,
             })?;
         },
         ("check", Some(args)) => {
-            let allow_v0       = args.is_present("allow-v0");
+            let start = Instant::now();
+            let read_options   = parse_read_options(args);
             let human_readable = args.is_present("human-readable");
             let verbose        = args.is_present("verbose");
+            let quiet          = args.is_present("quiet");
             let stop_on_error  = args.is_present("stop-on-error");
             let path           = args.value_of("package").unwrap();
-            let filter         = Filter::new(args);
-            let alignment = if let Some(alignment) = args.value_of("alignment") {
-                if let Ok(align) = parse_size(alignment) {
-                    if align == 0 || align > std::u32::MAX as usize {
-                        return Err(Error::illegal_argument(
-                            "--alignment",
-                            alignment
-                        ));
-                    }
-                    Some(align as u32)
+            let paths0         = read_paths0(args)?;
+            let filter         = Filter::new(args, paths0.as_deref());
+            let extensions     = parse_extensions(args);
+            let alignment      = parse_alignment(args)?;
+            let check_overlaps = args.is_present("check-overlaps");
+            let buffer_size    = parse_buffer_size(args)?;
+            let retries        = parse_retries(args)?;
+            let max_open_files = parse_max_open_files(args)?;
+            let json           = args.is_present("json");
+            let md5_only       = args.is_present("md5-only");
+            let group_by_archive = args.is_present("group-by-archive");
+            let ignore_missing = args.is_present("ignore-missing");
+            let sidecar_hash   = parse_sidecar_hash(args)?;
+            let sidecar        = args.value_of_os("sidecar").map(std::path::Path::new);
+            let crc            = parse_crc(args)?;
+            let color          = parse_color(args)?;
+            let max_gap = if let Some(max_gap) = args.value_of("max-gap") {
+                if let Ok(size) = parse_size(max_gap) {
+                    Some(size as u64)
                 } else {
                     return Err(Error::illegal_argument(
-                        "--alignment",
-                        alignment
+                        "--max-gap",
+                        max_gap
                     ));
                 }
             } else {
                 None
             };
 
-            let package = Package::from_path(path, allow_v0)?;
+            let package = Package::from_path(path, read_options)?;
+
+            if args.is_present("validate-offsets") {
+                package.validate_offsets()?;
+            }
 
             check(&package, CheckOptions {
                 verbose,
+                quiet,
                 stop_on_error,
                 human_readable,
                 filter: filter.as_ref(),
+                extensions: extensions.as_deref(),
                 alignment,
+                check_overlaps,
+                max_gap,
+                buffer_size,
+                retries,
+                max_open_files,
+                sidecar_hash,
+                sidecar,
+                crc,
+                color,
+                json,
+                md5_only,
+                group_by_archive,
+                ignore_missing,
             })?;
 
             if verbose {
                 println!("everything is ok");
             }
+
+            if args.is_present("timing") {
+                print_timing(start, package.total_size());
+            }
+        },
+        ("checksum", Some(args)) => {
+            let order = parse_sort(args)?;
+            let order = match &order {
+                Some(order) => &order[..],
+                None => &DEFAULT_ORDER[..],
+            };
+
+            let read_options = parse_read_options(args);
+            let md5          = args.is_present("md5");
+            let buffer_size  = parse_buffer_size(args)?;
+            let path         = args.value_of("package").unwrap();
+            let filter       = Filter::new(args, None);
+            let extensions   = parse_extensions(args);
+
+            let package = read_package(path, read_options, args.is_present("quiet"))?;
+
+            checksum(&package, ChecksumOptions {
+                order,
+                filter: filter.as_ref(),
+                extensions: extensions.as_deref(),
+                md5,
+                buffer_size,
+            })?;
+        },
+        ("needed-archives", Some(args)) => {
+            let read_options = parse_read_options(args);
+            let json         = args.is_present("json");
+            let path         = args.value_of("package").unwrap();
+            let paths0       = read_paths0(args)?;
+            let filter       = Filter::new(args, paths0.as_deref());
+            let extensions   = parse_extensions(args);
+
+            let package = read_package(path, read_options, args.is_present("quiet"))?;
+
+            needed_archives(&package, NeededArchivesOptions {
+                filter: filter.as_ref(),
+                extensions: extensions.as_deref(),
+                json,
+            })?;
+        },
+        ("diff", Some(args)) => {
+            let read_options = parse_read_options(args);
+            let by_offset    = args.is_present("by-offset");
+            let old_path     = args.value_of("old").unwrap();
+            let new_path     = args.value_of("new").unwrap();
+
+            let old = read_package(old_path, read_options, false)?;
+            let new = read_package(new_path, read_options, false)?;
+
+            diff(&old, &new, DiffOptions { by_offset })?;
+        },
+        ("doctor", Some(args)) => {
+            let read_options = parse_read_options(args);
+            let path         = args.value_of("package").unwrap();
+
+            // a truncated index is reported as failed check
+            let package = read_package(path, read_options, true)?;
+
+            doctor(&package)?;
+        },
+        ("extract", Some(args)) => {
+            let read_options = parse_read_options(args);
+            let verbose      = args.is_present("verbose");
+            let quiet        = args.is_present("quiet");
+            let check        = args.is_present("check");
+            let crc          = parse_crc(args)?;
+            let buffer_size  = parse_buffer_size(args)?;
+            let retries      = parse_retries(args)?;
+            let path         = args.value_of("package").unwrap();
+            let vpk_path     = args.value_of("path").unwrap();
+            let dest = if let Some(dest) = args.value_of("to") {
+                dest
+            } else {
+                let name = vpk_path.trim_end_matches('/');
+                &name[name.rfind('/').map_or(0, |index| index + 1)..]
+            };
+
+            let package = read_package(path, read_options, quiet)?;
+
+            if args.is_present("validate-offsets") {
+                package.validate_offsets()?;
+            }
+
+            extract(&package, vpk_path, dest, ExtractOptions {
+                verbose,
+                quiet,
+                check,
+                buffer_size,
+                retries,
+                crc,
+            })?;
         },
         ("unpack", Some(args)) => {
-            let allow_v0             = args.is_present("allow-v0");
+            let start = Instant::now();
+            let read_options         = parse_read_options(args);
             let outdir               = args.value_of("outdir").unwrap_or(".");
             let verbose              = args.is_present("verbose");
+            let quiet                = args.is_present("quiet");
             let check                = args.is_present("check");
+            let verify               = args.is_present("verify");
             let dirname_from_archive = args.is_present("dirname-from-archive");
+            let strip_components = if let Some(value) = args.value_of("strip-components") {
+                if let Ok(value) = value.parse::<usize>() {
+                    value
+                } else {
+                    return Err(Error::illegal_argument("--strip-components", value));
+                }
+            } else {
+                0
+            };
+            let root                 = args.value_of("root");
+            let prefix               = args.value_of("prefix");
+            let flatten = if args.is_present("flatten") {
+                match args.value_of("flatten") {
+                    Some("rename") => Some(Flatten::Rename),
+                    _ => Some(Flatten::Error),
+                }
+            } else {
+                None
+            };
+            let buffer_size          = parse_buffer_size(args)?;
+            let retries              = parse_retries(args)?;
+            let max_open_files       = parse_max_open_files(args)?;
+            let ignore_missing       = args.is_present("ignore-missing");
+            let preserve_times       = args.is_present("preserve-times");
+            let crc                  = parse_crc(args)?;
+            let color                = parse_color(args)?;
             let path                 = args.value_of("package").unwrap();
-            let filter               = Filter::new(args);
+            let paths0               = read_paths0(args)?;
+            let filter               = Filter::new(args, paths0.as_deref());
+            let extensions           = parse_extensions(args);
+            let filter_file          = parse_filter_file(args)?;
+
+            if let Some(prefix) = prefix {
+                if prefix.split(['/', '\\']).any(|item| item == "..") || std::path::Path::new(prefix).is_absolute() {
+                    return Err(Error::illegal_argument("--prefix", prefix));
+                }
+            }
+
+            if let Some(root) = root {
+                if root.trim_matches('/').is_empty() {
+                    return Err(Error::illegal_argument("--root", root));
+                }
+            }
+
+            let package = Package::from_path(path, read_options)?;
 
-            let package = Package::from_path(path, allow_v0)?;
+            if args.is_present("validate-offsets") {
+                package.validate_offsets()?;
+            }
 
             unpack(&package, outdir, UnpackOptions {
                 filter: filter.as_ref(),
+                extensions: extensions.as_deref(),
+                filter_file: filter_file.as_ref(),
                 verbose,
+                quiet,
                 check,
+                verify,
                 dirname_from_archive,
+                root,
+                strip_components,
+                prefix,
+                flatten,
+                buffer_size,
+                retries,
+                max_open_files,
+                color,
+                ignore_missing,
+                preserve_times,
+                crc,
             })?;
+
+            if args.is_present("timing") {
+                print_timing(start, package.total_size());
+            }
         },
         ("pack", Some(args)) => {
+            let start = Instant::now();
             let indir   = args.value_of("indir").unwrap_or(".");
             let path    = args.value_of("package").unwrap();
-            let version = if let Some(version) = args.value_of("version") {
-                if let Ok(value) = version.parse::<u32>() {
-                    if value > 2 {
-                        return Err(Error::illegal_argument(
-                            "--version",
-                            version
-                        ));
-                    }
+            let mut builder = PackOptions::builder()
+                .verbose(args.is_present("verbose"))
+                .quiet(args.is_present("quiet"))
+                .verify(args.is_present("verify"))
+                .verify_preload(args.is_present("verify-preload"))
+                .dedup(args.is_present("dedup"))
+                .sidecar_hash(parse_sidecar_hash(args)?)
+                .buffer_size(parse_buffer_size(args)?);
 
-                    value
+            if let Some(version) = args.value_of("version") {
+                if let Ok(value) = version.parse::<u32>() {
+                    builder = builder.version(value);
                 } else {
                     return Err(Error::illegal_argument(
                         "--version",
                         version
                     ));
                 }
-            } else {
-                1u32
-            };
-            let md5_chunk_size = if let Some(md5_chunk_size) = args.value_of("md5-chunk-size") {
-                if version < 2 {
-                    return Err(Error::other("--md5-chunk-size requires --version to be 2"));
-                }
+            }
+
+            if let Some(md5_chunk_size) = args.value_of("md5-chunk-size") {
                 if let Ok(size) = parse_size(md5_chunk_size) {
-                    if size == 0 || size > std::u32::MAX as usize {
+                    if size > std::u32::MAX as usize {
                         return Err(Error::illegal_argument(
                             "--md5-chunk-size",
                             md5_chunk_size
                         ));
                     }
-                    size as u32
+                    builder = builder.md5_chunk_size(size as u32);
                 } else {
                     return Err(Error::illegal_argument(
                         "--md5-chunk-size",
                         md5_chunk_size
                     ));
                 }
-            } else {
-                DEFAULT_MD5_CHUNK_SIZE
-            };
-            let verbose = args.is_present("verbose");
-            let max_inline_size = if let Some(inline_size) = args.value_of("max-inline-size") {
+            }
+
+            if let Some(files_from) = args.value_of("files-from") {
+                builder = builder.files(Some(read_file_list(files_from, args.is_present("null"))?));
+            }
+
+            if let Some(order_from) = args.value_of("order-from") {
+                builder = builder.data_order(Some(read_order_list(order_from)?));
+            }
+
+            if let Some(relative_to) = args.value_of("relative-to") {
+                builder = builder.relative_to(Some(relative_to.into()));
+            }
+
+            if let Some(temp_dir) = args.value_of("temp-dir") {
+                builder = builder.temp_dir(Some(temp_dir.into()));
+            }
+
+            if let Some(since) = args.value_of("since") {
+                if let Some(time) = parse_time(since) {
+                    builder = builder.since(Some(time));
+                } else {
+                    return Err(Error::illegal_argument("--since", since));
+                }
+            }
+
+            if let Some(inline_size) = args.value_of("max-inline-size") {
                 if let Ok(size) = parse_size(inline_size) {
                     if size > std::u16::MAX as usize {
                         return Err(Error::illegal_argument(
@@ -450,78 +1503,200 @@ fn run() -> Result<()> {
                             inline_size
                         ));
                     }
-                    size as u16
+                    builder = builder.max_inline_size(size as u16);
                 } else {
                     return Err(Error::illegal_argument(
                         "--max-inline-size",
                         inline_size
                     ));
                 }
-            } else {
-                DEFAULT_MAX_INLINE_SIZE
-            };
-            let alignment = if let Some(alignment) = args.value_of("alignment") {
-                if let Ok(alignment) = parse_size(alignment) {
-                    alignment
+            }
+
+            if let Some(ratio) = args.value_of("index-warning-ratio") {
+                match ratio.parse::<f64>() {
+                    Ok(value) if (0.0..=1.0).contains(&value) => {
+                        builder = builder.index_warning_ratio(value);
+                    },
+                    _ => {
+                        return Err(Error::illegal_argument(
+                            "--index-warning-ratio",
+                            ratio
+                        ));
+                    }
+                }
+            }
+
+            if let Some(alignment) = args.value_of("alignment") {
+                if let Ok(align) = parse_size(alignment) {
+                    builder = builder.alignment(align);
                 } else {
                     return Err(Error::illegal_argument(
                         "--alignment",
                         alignment
                     ));
                 }
-            } else {
-                1
-            };
-            let strategy = if args.is_present("archive-from-dirname") {
-                ArchiveStrategy::ArchiveFromDirName
+            }
+
+            if args.is_present("strict") {
+                builder = builder.strict(true);
+            }
+
+            if args.is_present("keep-going") {
+                builder = builder.keep_going(true);
+            }
+
+            builder = builder.crc(parse_crc(args)?);
+
+            if args.is_present("normalize-ext") {
+                builder = builder.normalize_ext(true);
+            }
+
+            if args.is_present("lowercase-paths") {
+                builder = builder.lowercase_paths(true);
+            }
+
+            if let Some(values) = args.values_of("replace") {
+                let mut replace = Vec::new();
+                for value in values {
+                    match value.split_once('=') {
+                        Some((from, to)) if !from.is_empty() => {
+                            replace.push((from.to_owned(), to.to_owned()));
+                        },
+                        _ => {
+                            return Err(Error::illegal_argument(
+                                "--replace",
+                                value
+                            ));
+                        }
+                    }
+                }
+                builder = builder.replace(replace);
+            }
+
+            if args.is_present("follow-symlinks") {
+                builder = builder.symlinks(Symlinks::Follow);
+            } else if args.is_present("error-symlinks") {
+                builder = builder.symlinks(Symlinks::Error);
+            }
+
+            if args.value_of("align-mode") == Some("data") {
+                builder = builder.align_mode(AlignMode::Data);
+            }
+
+            if args.is_present("archive-from-dirname") {
+                builder = builder.strategy(ArchiveStrategy::ArchiveFromDirName);
+            } else if args.is_present("single-archive") {
+                builder = builder.strategy(ArchiveStrategy::SingleArchive);
+            } else if let Some(num_archives) = args.value_of("num-archives") {
+                match num_archives.parse::<u16>() {
+                    Ok(count) if count > 0 && count <= 1000 => {
+                        builder = builder.strategy(ArchiveStrategy::NumArchives(count));
+                    },
+                    _ => {
+                        return Err(Error::illegal_argument(
+                            "--num-archives",
+                            num_archives
+                        ));
+                    }
+                }
+            } else if let Some(balance_count) = args.value_of("balance-count") {
+                match balance_count.parse::<u16>() {
+                    Ok(count) if count > 0 && count <= 1000 => {
+                        builder = builder.strategy(ArchiveStrategy::BalancedCount(count));
+                    },
+                    _ => {
+                        return Err(Error::illegal_argument(
+                            "--balance-count",
+                            balance_count
+                        ));
+                    }
+                }
             } else if let Some(max_arch_size) = args.value_of("max-archive-size") {
                 if let Ok(size) = parse_size(max_arch_size) {
-                    if size == 0 || size > std::u32::MAX as usize {
+                    if size > std::u32::MAX as usize {
                         return Err(Error::illegal_argument(
                             "--max-archive-size",
                             max_arch_size
                         ));
                     }
-                    ArchiveStrategy::MaxArchiveSize(size as u32)
+                    builder = builder.strategy(ArchiveStrategy::MaxArchiveSize(size as u32));
                 } else {
                     return Err(Error::illegal_argument(
                         "--max-archive-size",
                         max_arch_size
                     ));
                 }
-            } else {
-                ArchiveStrategy::default()
-            };
+            }
+
+            let package = pack(path, indir, builder.build()?)?;
+
+            if args.is_present("check-after") {
+                check(&package, CheckOptions {
+                    verbose: args.is_present("verbose"),
+                    quiet:   args.is_present("quiet"),
+                    buffer_size: parse_buffer_size(args)?,
+                    crc:     parse_crc(args)?,
+                    ..CheckOptions::new()
+                })?;
+            }
 
-            pack(path, indir, PackOptions {
-                version,
-                md5_chunk_size,
-                strategy,
-                max_inline_size,
+            if args.is_present("timing") {
+                print_timing(start, package.total_size());
+            }
+        },
+        ("stats", Some(args)) => {
+            let read_options   = parse_read_options(args);
+            let human_readable = args.is_present("human-readable");
+            let alignment      = parse_alignment(args)?;
+            let archives_only  = args.is_present("archives-only");
+            let color          = parse_color(args)?;
+            let path           = args.value_of("package").unwrap();
+
+            let package = read_package(path, read_options, args.is_present("quiet"))?;
+
+            stats(&package, StatsOptions {
+                human_readable,
                 alignment,
-                verbose
+                color,
+                archives_only,
             })?;
         },
-        ("stats", Some(args)) => {
-            let allow_v0       = args.is_present("allow-v0");
+        ("tree", Some(args)) => {
+            let read_options   = parse_read_options(args);
             let human_readable = args.is_present("human-readable");
             let path           = args.value_of("package").unwrap();
+            let subpath        = args.value_of("path");
+            let depth = if let Some(depth) = args.value_of("depth") {
+                if let Ok(depth) = depth.parse() {
+                    Some(depth)
+                } else {
+                    return Err(Error::illegal_argument("--depth", depth));
+                }
+            } else {
+                None
+            };
 
-            let package = Package::from_path(path, allow_v0)?;
+            let package = read_package(path, read_options, args.is_present("quiet"))?;
 
-            stats(&package, human_readable)?;
+            tree(&package, TreeOptions {
+                human_readable,
+                depth,
+                path: subpath,
+            })?;
         },
         #[cfg(feature = "fuse")]
         ("mount", Some(args)) => {
-            let allow_v0    = args.is_present("allow-v0");
-            let debug       = args.is_present("debug");
-            let foreground  = args.is_present("foreground");
-            let path        = args.value_of("package").unwrap();
-            let mount_point = args.value_of("mount-point").unwrap();
+            let read_options = parse_read_options(args);
+            let debug        = args.is_present("debug");
+            let foreground   = args.is_present("foreground");
+            let meta_files   = args.is_present("meta-files");
+            let max_open_files = parse_max_open_files(args)?;
+            let path         = args.value_of("package").unwrap();
+            let mount_point  = args.value_of("mount-point").unwrap();
 
-            let package = Package::from_path(path, allow_v0)?;
+            let package = read_package(path, read_options, args.is_present("quiet"))?;
 
-            mount(package, &mount_point, MountOptions { foreground, debug })?;
+            mount(package, &mount_point, MountOptions { foreground, debug, meta_files, max_open_files })?;
         },
         ("", _) => {
             return Err(Error::other(
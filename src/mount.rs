@@ -31,6 +31,7 @@ use crate::consts::DIR_INDEX;
 use crate::package::Package;
 use crate::result::{Result, Error};
 use crate::util::archive_path;
+use crate::archive_cache::ArchiveCache;
 
 struct Dir {
     children: HashMap<String, u64>,
@@ -39,6 +40,8 @@ struct Dir {
 enum INodeData {
     File(File),
     Dir(Dir),
+    /// Synthetic `.vpkmeta` file describing a file of the package.
+    Meta(Vec<u8>),
 }
 
 struct INode {
@@ -53,6 +56,7 @@ impl INode {
         match self.data {
             INodeData::Dir(_)  => true,
             INodeData::File(_) => false,
+            INodeData::Meta(_) => false,
         }
     }
 
@@ -61,6 +65,7 @@ impl INode {
         match self.data {
             INodeData::Dir(_)  => false,
             INodeData::File(_) => true,
+            INodeData::Meta(_) => true,
         }
     }
 }
@@ -69,7 +74,9 @@ pub struct VPKFS {
     dirpath: PathBuf,
     prefix: String,
 
-    archives: HashMap<u16, fs::File>,
+    archives: ArchiveCache,
+    /// Sizes of all archives containing file data.
+    archive_sizes: HashMap<u16, u64>,
     inodes: HashMap<u64, INode>,
     next_inode: u64,
 
@@ -83,6 +90,8 @@ pub struct VPKFS {
 
     blksize: u64,
     blocks:  u64,
+
+    meta_files: bool,
 }
 
 fn make_time(mut time: i64, mut nsec: i64) -> SystemTime {
@@ -105,8 +114,18 @@ fn make_time(mut time: i64, mut nsec: i64) -> SystemTime {
     }
 }
 
+/// Number of blocks of `blksize` bytes needed to store `size` bytes.
+#[inline]
+fn block_count(size: u64, blksize: u64) -> u64 {
+    if size != 0 { 1 + ((size - 1) / blksize) } else { 0 }
+}
+
 impl VPKFS {
-    pub fn new(package: Package) -> Result<Self> {
+    /// With `meta_files` every file gets a sibling `<name>.vpkmeta` that
+    /// contains the CRC32 sum, archive and offset of the file as text.
+    /// `max_open_files` limits the number of archives kept open, 0 means
+    /// no limit.
+    pub fn new(package: Package, meta_files: bool, max_open_files: usize) -> Result<Self> {
         let path = package.archive_path(DIR_INDEX);
         let meta = match fs::metadata(&path) {
             Err(error) => return Err(Error::io_with_path(error, path)),
@@ -118,10 +137,20 @@ impl VPKFS {
             Ok(dirpath) => dirpath,
         };
 
+        let mut archives = ArchiveCache::for_reading(dirpath.clone(), package.prefix.to_owned());
+        if let Some(archive_dirpath) = &package.archive_dirpath {
+            match archive_dirpath.canonicalize() {
+                Err(error) => return Err(Error::io_with_path(error, archive_dirpath)),
+                Ok(archive_dirpath) => archives.set_archive_dirpath(Some(archive_dirpath)),
+            }
+        }
+        archives.set_max_open_files(max_open_files);
+
         let mut vpkfs = Self {
             dirpath,
             prefix:   package.prefix.to_owned(),
-            archives: HashMap::new(),
+            archives,
+            archive_sizes: HashMap::new(),
             inodes:   HashMap::new(),
             next_inode: FUSE_ROOT_ID + 1,
 
@@ -135,6 +164,8 @@ impl VPKFS {
 
             blksize: meta.st_blksize(),
             blocks:  0,
+
+            meta_files,
         };
 
         let mut fsdir = Dir {
@@ -143,19 +174,12 @@ impl VPKFS {
 
         vpkfs.init(package.entries, FUSE_ROOT_ID, &mut fsdir.children)?;
 
-        let mut sum_size = 0u64;
-        for (archive_index, file) in &vpkfs.archives {
-            let meta = match file.metadata() {
-                Err(error) => return Err(Error::io_with_path(
-                    error,
-                    archive_path(&vpkfs.dirpath, &vpkfs.prefix, *archive_index))),
-                Ok(meta) => meta,
-            };
-            sum_size += meta.len();
-        }
-        if sum_size != 0 {
-            vpkfs.blocks = 1 + ((sum_size - 1) / vpkfs.blksize);
+        // the _dir.vpk file is only in archives if it contains file data
+        let mut sum_size = if vpkfs.archive_sizes.contains_key(&DIR_INDEX) { 0 } else { meta.len() };
+        for size in vpkfs.archive_sizes.values() {
+            sum_size += size;
         }
+        vpkfs.blocks = block_count(sum_size, vpkfs.blksize);
 
         let mut stat = FileAttr {
             ino:    FUSE_ROOT_ID,
@@ -182,7 +206,7 @@ impl VPKFS {
             }
         }
 
-        stat.blocks = if stat.size != 0 { 1 + ((stat.size - 1) / vpkfs.blksize) } else { 0 };
+        stat.blocks = block_count(stat.size, vpkfs.blksize);
 
         vpkfs.inodes.insert(FUSE_ROOT_ID, INode {
             inode:  FUSE_ROOT_ID,
@@ -195,6 +219,8 @@ impl VPKFS {
     }
 
     fn init(&mut self, entries: HashMap<String, Entry>, parent_inode: u64, parent_entries: &mut HashMap<String, u64>) -> Result<()> {
+        let mut metas = Vec::new();
+
         for (name, entry) in entries {
             let inode = self.next_inode;
             self.next_inode += 1;
@@ -232,7 +258,7 @@ impl VPKFS {
                         }
                     }
 
-                    stat.blocks = if stat.size != 0 { 1 + ((stat.size - 1) / self.blksize) } else { 0 };
+                    stat.blocks = block_count(stat.size, self.blksize);
 
                     self.inodes.insert(inode, INode {
                         inode,
@@ -259,24 +285,88 @@ impl VPKFS {
                         flags:  0,
                     };
 
-                    stat.blocks = if stat.size != 0 { 1 + ((stat.size - 1) / self.blksize) } else { 0 };
+                    stat.blocks = block_count(stat.size, self.blksize);
+
+                    if self.meta_files {
+                        metas.push((format!("{}.vpkmeta", name), self.format_meta(&file)));
+                    }
 
                     let archive_index = file.archive_index;
+                    let has_archive_data = file.size > 0;
                     self.inodes.insert(inode, INode {
                         inode,
                         parent: parent_inode,
                         data: INodeData::File(file),
                         stat,
                     });
-                    if !self.archives.contains_key(&archive_index) {
-                        let archive = fs::File::open(archive_path(&self.dirpath, &self.prefix, archive_index))?;
-                        self.archives.insert(archive_index, archive);
+                    // Fully inlined files don't need their archive, which might not even exist.
+                    if has_archive_data && !self.archive_sizes.contains_key(&archive_index) {
+                        let archive = self.archives.get(archive_index)?;
+                        let size = match archive.metadata() {
+                            Err(error) => return Err(Error::io_with_path(
+                                error,
+                                self.archives.archive_path(archive_index))),
+                            Ok(meta) => meta.len(),
+                        };
+                        self.archive_sizes.insert(archive_index, size);
                     }
                 },
             }
         }
+
+        for (name, data) in metas {
+            // a real file of the same name takes precedence
+            if parent_entries.contains_key(&name) {
+                continue;
+            }
+
+            let inode = self.next_inode;
+            self.next_inode += 1;
+            parent_entries.insert(name, inode);
+
+            let size = data.len() as u64;
+            self.inodes.insert(inode, INode {
+                inode,
+                parent: parent_inode,
+                data: INodeData::Meta(data),
+                stat: FileAttr {
+                    ino:    inode,
+                    size,
+                    blocks: block_count(size, self.blksize),
+                    atime:  self.atime,
+                    mtime:  self.mtime,
+                    ctime:  self.ctime,
+                    crtime: self.crtime,
+                    kind:   FileType::RegularFile,
+                    perm:   0o444,
+                    nlink:  1,
+                    uid:    self.uid,
+                    gid:    self.gid,
+                    rdev:   0,
+                    flags:  0,
+                },
+            });
+        }
+
         Ok(())
     }
+
+    fn format_meta(&self, file: &File) -> Vec<u8> {
+        format!(
+            "crc32: 0x{:08x}\n\
+             archive_index: {}\n\
+             archive_path: {}\n\
+             offset: {}\n\
+             inline_size: {}\n\
+             size: {}\n",
+            file.crc32,
+            file.archive_index,
+            self.archives.archive_path(file.archive_index).to_string_lossy(),
+            file.offset,
+            file.inline_size,
+            file.size,
+        ).into_bytes()
+    }
 }
 
 const TTL: Duration = Duration::from_secs(std::u64::MAX);
@@ -344,7 +434,7 @@ impl Filesystem for VPKFS {
                 if name == OsStr::new("user.vpkfs.crc32") {
                     format!("0x{:08x}\0", file.crc32)
                 } else if name == OsStr::new("user.vpkfs.archive_path") {
-                    let mut path: String = archive_path(&self.dirpath, &self.prefix, file.archive_index)
+                    let mut path: String = self.archives.archive_path(file.archive_index)
                         .to_string_lossy().as_ref().to_owned();
                     path.push('\0');
                     path
@@ -377,6 +467,7 @@ impl Filesystem for VPKFS {
             let data = match inode_data.data {
                 INodeData::Dir(_)  => DIR_XATTRS,
                 INodeData::File(_) => FILE_XATTRS,
+                INodeData::Meta(_) => DIR_XATTRS,
             };
             if size == 0 {
                 return reply.size(data.len() as u32);
@@ -459,7 +550,7 @@ impl Filesystem for VPKFS {
             /* ffree   */ 0,
             /* bsize   */ self.blksize as u32,
             /* namelen */ std::u32::MAX,
-            /* frsize  */ 0);
+            /* frsize  */ self.blksize as u32);
     }
 
     fn open(&mut self, _req: &Request, ino: u64, flags: u32, reply: ReplyOpen) {
@@ -477,6 +568,15 @@ impl Filesystem for VPKFS {
 
     fn read(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64, size: u32, reply: ReplyRead) {
         if let Some(inode_data) = self.inodes.get(&ino) {
+            if let INodeData::Meta(data) = &inode_data.data {
+                if offset < 0 {
+                    return reply.error(EINVAL);
+                }
+                let start = (offset as u64).min(data.len() as u64) as usize;
+                let end   = (start + size as usize).min(data.len());
+                return reply.data(&data[start..end]);
+            }
+
             if let INodeData::File(file) = &inode_data.data {
                 if offset < 0 {
                     return reply.error(EINVAL);
@@ -504,7 +604,10 @@ impl Filesystem for VPKFS {
                         let index = buffer.len();
                         buffer.resize(actual_size as usize, 0);
 
-                        let archive = self.archives.get_mut(&file.archive_index).unwrap();
+                        let archive = match self.archives.get(file.archive_index) {
+                            Ok(archive) => archive,
+                            Err(_) => return reply.error(EIO),
+                        };
                         if let Err(error) = archive.read_exact_at(&mut buffer[index..], file.offset as u64 + offset - inline_size) {
                             return reply.error(error.raw_os_error().unwrap_or(EIO));
                         }
@@ -526,7 +629,10 @@ impl Filesystem for VPKFS {
                     let mut buffer = Vec::with_capacity(actual_size as usize);
                     buffer.resize(actual_size as usize, 0);
 
-                    let archive = self.archives.get_mut(&file.archive_index).unwrap();
+                    let archive = match self.archives.get(file.archive_index) {
+                        Ok(archive) => archive,
+                        Err(_) => return reply.error(EIO),
+                    };
                     if let Err(error) = archive.read_exact_at(&mut buffer, file.offset as u64 + offset) {
                         return reply.error(error.raw_os_error().unwrap_or(EIO));
                     }
@@ -552,6 +658,8 @@ impl std::convert::From<DaemonizeError> for Error {
 pub struct MountOptions {
     pub foreground: bool,
     pub debug: bool,
+    pub meta_files: bool,
+    pub max_open_files: usize,
 }
 
 impl MountOptions {
@@ -567,6 +675,8 @@ impl Default for MountOptions {
         Self {
             foreground: false,
             debug: false,
+            meta_files: false,
+            max_open_files: 0,
         }
     }
 }
@@ -591,7 +701,7 @@ pub fn mount(package: Package, mount_point: impl AsRef<Path>, options: MountOpti
         foreground = options.foreground;
     }
 
-    let fs = VPKFS::new(package)?;
+    let fs = VPKFS::new(package, options.meta_files, options.max_open_files)?;
 
     if !foreground {
         let daemonize = Daemonize::new()
@@ -605,3 +715,49 @@ pub fn mount(package: Package, mount_point: impl AsRef<Path>, options: MountOpti
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::ReadOptions;
+    use crate::util::{TempDir, test_dir_vpk};
+
+    #[test]
+    fn block_counts() {
+        assert_eq!(block_count(0, 4096), 0);
+        assert_eq!(block_count(1, 4096), 1);
+        assert_eq!(block_count(4096, 4096), 1);
+        assert_eq!(block_count(4097, 4096), 2);
+        assert_eq!(block_count(10, 1), 10);
+    }
+
+    #[test]
+    fn statfs_totals() {
+        let tmp = TempDir::new("mount-statfs");
+        tmp.write("pak_000.vpk", &[0; 5000]);
+        tmp.write("pak_001.vpk", &[0; 3000]);
+        let dirpath = tmp.write("pak_dir.vpk", &test_dir_vpk(&[
+            ("m/a.bin", 0,         0, 5000, b""),
+            ("m/b.bin", 1,         0, 3000, b""),
+            ("m/c.txt", DIR_INDEX, 0, 0,    b"inline"),
+        ], b""));
+        let dir_size = fs::metadata(&dirpath).unwrap().len();
+
+        let package = Package::from_path(&dirpath, ReadOptions::new()).unwrap();
+        let vpkfs = VPKFS::new(package, false, 0).unwrap();
+        assert!(vpkfs.blksize > 0);
+        assert_eq!(vpkfs.blocks, block_count(dir_size + 5000 + 3000, vpkfs.blksize));
+        assert!(vpkfs.blocks > 0);
+        // the root, "m" and three files
+        assert_eq!(vpkfs.inodes.len(), 5);
+
+        // the size of the _dir.vpk file is only counted once if it contains file data
+        let dirpath = tmp.write("dir_dir.vpk", &test_dir_vpk(&[
+            ("m/a.bin", DIR_INDEX, 0, 100, b""),
+        ], &[0; 100]));
+        let dir_size = fs::metadata(&dirpath).unwrap().len();
+        let package = Package::from_path(&dirpath, ReadOptions::new()).unwrap();
+        let vpkfs = VPKFS::new(package, false, 0).unwrap();
+        assert_eq!(vpkfs.blocks, block_count(dir_size, vpkfs.blksize));
+    }
+}
@@ -0,0 +1,81 @@
+// This file is part of rust-vpk.
+//
+// rust-vpk is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rust-vpk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rust-vpk.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::package::Package;
+use crate::result::Result;
+use crate::util::{format_archive, retain_extensions, json_string, json_document};
+
+pub struct NeededArchivesOptions<'a> {
+    pub filter:     Option<&'a [&'a str]>,
+    pub extensions: Option<&'a [&'a str]>,
+    /// Print a JSON object with an array of the archive indices and paths
+    /// instead of one path per line.
+    pub json:       bool,
+}
+
+impl NeededArchivesOptions<'_> {
+    #[inline]
+    pub fn new() -> Self {
+        NeededArchivesOptions::default()
+    }
+}
+
+impl Default for NeededArchivesOptions<'_> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            filter:     None,
+            extensions: None,
+            json:       false,
+        }
+    }
+}
+
+/// Print the archive files that contain data of the selected files, e.g.
+/// to only download these before unpacking the files. The _dir.vpk file is
+/// listed too if it contains file data.
+pub fn needed_archives(package: &Package, options: NeededArchivesOptions) -> Result<()> {
+    let mut files = match options.filter {
+        None => package.recursive_file_list(&[]),
+        Some(paths) => package.recursive_file_list_from(paths, &[])?,
+    };
+
+    if let Some(extensions) = options.extensions {
+        retain_extensions(&mut files, extensions);
+    }
+
+    // like Package::archive_indices, but only of the selected files
+    let mut indices: Vec<u16> = files.iter()
+        .filter(|(_, file)| file.size > 0)
+        .map(|(_, file)| file.archive_index)
+        .collect();
+    indices.sort_unstable();
+    indices.dedup();
+
+    if options.json {
+        let items: Vec<String> = indices.iter().map(|&archive_index| {
+            format!("{{\"archive\":{},\"path\":{}}}",
+                json_string(&format_archive(archive_index)),
+                json_string(&package.archive_path(archive_index).to_string_lossy()))
+        }).collect();
+        println!("{}", json_document(&format!("\"archives\":[{}]", items.join(","))));
+    } else {
+        for archive_index in indices {
+            println!("{}", package.archive_path(archive_index).to_string_lossy());
+        }
+    }
+
+    Ok(())
+}
@@ -16,39 +16,144 @@
 // TODO: make nicer
 
 use std::collections::{HashMap, HashSet};
-use std::path::{Path};
+use std::collections::hash_map::Entry as MapEntry;
+use std::path::{Path, PathBuf, Component};
+use std::ffi::{OsStr, OsString};
 use std::fs::{self, read_dir};
 use std::io::{Read, Write, Seek, SeekFrom, BufWriter};
+use std::time::SystemTime;
 //use std::fmt::Write;
 
 use crc::{crc32, Hasher32};
 
-use crate::result::{Result, Error};
-use crate::consts::{DIR_INDEX, BUFFER_SIZE, VPK_MAGIC, DEFAULT_MAX_INLINE_SIZE, V1_HEADER_SIZE, V2_HEADER_SIZE, DEFAULT_MD5_CHUNK_SIZE, ARCHIVE_MD5_SIZE};
-use crate::package::{Package, ArchiveMd5, Md5, parse_path};
+use crate::result::{Result, Error, ErrorType};
+use crate::consts::{DIR_INDEX, BUFFER_SIZE, VPK_MAGIC, DEFAULT_MAX_INLINE_SIZE, DEFAULT_INDEX_WARNING_RATIO, INDEX_SIZE_WARNING_RATIO, V1_HEADER_SIZE, V2_HEADER_SIZE, DEFAULT_MD5_CHUNK_SIZE, ARCHIVE_MD5_SIZE};
+use crate::package::{Package, ReadOptions, ArchiveMd5, Md5, parse_path, sum_files};
 use crate::entry::{Entry, File, Dir};
-use crate::io::{write_u32, write_str, write_file, transfer};
-use crate::util::{split_path, archive_path};
+use crate::io::{md5_of_range, write_u32, write_str, write_file};
+use crate::util::{read_lines, split_path, archive_path, format_archive, print_table, Align::*};
+use crate::hash::{HashAlgorithm, CrcAlgorithm, sidecar_path, write_sidecar};
 
 pub enum ArchiveStrategy {
     ArchiveFromDirName,
     MaxArchiveSize(u32),
+    /// Store all file data in the _dir.vpk file right after the index,
+    /// regardless of its size. No _NNN.vpk files are written.
+    SingleArchive,
+    /// Distribute file data to at most this many archives of about the same
+    /// size. Each file goes to the archive that is the smallest so far.
+    NumArchives(u16),
+    /// Distribute files round-robin to this many archives, so every archive
+    /// has about the same number of files. Unlike [`NumArchives`](ArchiveStrategy::NumArchives)
+    /// the archive sizes can differ a lot, but reading many files is spread
+    /// evenly over the archives.
+    BalancedCount(u16),
 }
 
 impl Default for ArchiveStrategy {
     #[inline]
     fn default() -> Self {
-        ArchiveStrategy::MaxArchiveSize(std::i32::MAX as u32)
+        ArchiveStrategy::MaxArchiveSize(i32::MAX as u32)
+    }
+}
+
+/// What the alignment of file data is measured from. This only makes a
+/// difference for data stored in the _dir.vpk file, in all other archives
+/// the data starts at the beginning of the file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlignMode {
+    /// Align the position in the archive file.
+    Archive,
+    /// Align the offset relative to the start of the data section, i.e.
+    /// the offset as stored in the index.
+    Data,
+}
+
+impl Default for AlignMode {
+    #[inline]
+    fn default() -> Self {
+        AlignMode::Archive
+    }
+}
+
+/// What to do with symbolic links found while scanning the input directory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Symlinks {
+    /// Pack the file or directory the link points to.
+    Follow,
+    /// Ignore the link and print a warning.
+    Skip,
+    /// Fail on the first link.
+    Error,
+}
+
+impl Default for Symlinks {
+    #[inline]
+    fn default() -> Self {
+        Symlinks::Skip
     }
 }
 
 pub struct PackOptions {
     pub version: u32,
-    pub md5_chunk_size: u32,
+    /// Size of the chunks of the archives covered by one MD5 sum. Only
+    /// supported by version 2 packages, `None` means the default of 1 MB.
+    pub md5_chunk_size: Option<u32>,
     pub strategy: ArchiveStrategy,
     pub max_inline_size: u16,
+    /// Warn if a `max_inline_size` bigger than the default makes the
+    /// _dir.vpk file bigger than this fraction of the whole package.
+    /// 0 disables the warning.
+    pub index_warning_ratio: f64,
+    /// Alignment of file data in archives in bytes. Should be a power of
+    /// two, other values work but are slower and a warning is printed.
     pub alignment: usize,
+    pub align_mode: AlignMode,
     pub verbose: bool,
+    /// Don't print warnings. Errors are still reported.
+    pub quiet: bool,
+    pub verify: bool,
+    /// Read the source of every inlined file again before the package is
+    /// written and make sure it still matches the inlined data.
+    pub verify_preload: bool,
+    pub dedup: bool,
+    pub buffer_size: usize,
+    pub sidecar_hash: Option<HashAlgorithm>,
+    /// Pack exactly these files instead of scanning the input directory.
+    pub files: Option<Vec<PathBuf>>,
+    /// Write the data of these files (paths inside of the package) first
+    /// and in this order. All other files follow sorted by path.
+    pub data_order: Option<Vec<String>>,
+    /// Only pack files modified at or after this point in time.
+    pub since: Option<SystemTime>,
+    /// Paths inside of the package are relative to this directory instead
+    /// of the input directory, which needs to be inside of it.
+    pub relative_to: Option<PathBuf>,
+    /// Write all file extensions in lower case into the index, so e.g.
+    /// `foo.VMT` and `bar.vmt` end up in the same extension group.
+    pub normalize_ext: bool,
+    /// Polynomial of the CRC32 sums written to the index. Only IEEE (the
+    /// default) is used by standard VPK packages.
+    pub crc: CrcAlgorithm,
+    /// Write all directory and file names in lower case into the index.
+    /// Extensions are only lowercased together with `normalize_ext`.
+    pub lowercase_paths: bool,
+    /// Rewrite paths inside of the package: a path starting with the first
+    /// string of a pair gets that prefix replaced by the second string.
+    /// The pairs are applied in order, each to the result of the previous
+    /// one, after names are normalized. Fails if two files would end up with
+    /// the same path.
+    pub replace: Vec<(String, String)>,
+    pub symlinks: Symlinks,
+    /// Fail on special files (FIFOs, sockets, devices) in the input
+    /// directory instead of skipping them with a warning.
+    pub strict: bool,
+    /// Leave out source files that can't be read (e.g. permission denied)
+    /// with a warning instead of failing.
+    pub keep_going: bool,
+    /// Directory in which the package is written before it is moved into
+    /// place. Defaults to the directory of the package.
+    pub temp_dir: Option<PathBuf>,
 }
 
 impl PackOptions {
@@ -56,6 +161,263 @@ impl PackOptions {
     pub fn new() -> Self {
         PackOptions::default()
     }
+
+    /// Build options with validation of the combination of values.
+    ///
+    /// ```ignore
+    /// let options = PackOptions::builder()
+    ///     .version(2)
+    ///     .max_inline_size(4096)
+    ///     .alignment(16)
+    ///     .build()?;
+    ///
+    /// pack("pak01_dir.vpk", "pak01", options)?;
+    /// ```
+    #[inline]
+    pub fn builder() -> PackOptionsBuilder {
+        PackOptionsBuilder::new()
+    }
+
+    /// Reject combinations of options that would produce an invalid
+    /// package. Called by [`pack`] before anything is written. The errors
+    /// name the corresponding command line options of `pack`.
+    pub fn validate(&self) -> Result<()> {
+        if self.version > 2 {
+            return Err(Error::illegal_argument("--version", self.version.to_string()));
+        }
+
+        if self.md5_chunk_size.is_some() && self.version < 2 {
+            return Err(Error::other("--md5-chunk-size requires --version to be 2"));
+        }
+
+        if self.md5_chunk_size == Some(0) {
+            return Err(Error::illegal_argument("--md5-chunk-size", "0"));
+        }
+
+        if self.verify && self.version < 2 {
+            return Err(Error::other("--verify requires --version to be 2"));
+        }
+
+        if self.alignment == 0 || self.alignment > u32::MAX as usize {
+            return Err(Error::illegal_argument("--alignment", self.alignment.to_string()));
+        }
+
+        if !(0.0..=1.0).contains(&self.index_warning_ratio) {
+            return Err(Error::illegal_argument("--index-warning-ratio", self.index_warning_ratio.to_string()));
+        }
+
+        match self.strategy {
+            ArchiveStrategy::MaxArchiveSize(0) => {
+                return Err(Error::illegal_argument("--max-archive-size", "0"));
+            },
+            ArchiveStrategy::NumArchives(count) if count == 0 || count > 1000 => {
+                return Err(Error::illegal_argument("--num-archives", count.to_string()));
+            },
+            ArchiveStrategy::BalancedCount(count) if count == 0 || count > 1000 => {
+                return Err(Error::illegal_argument("--balance-count", count.to_string()));
+            },
+            _ => {},
+        }
+
+        if self.buffer_size == 0 {
+            return Err(Error::illegal_argument("--buffer-size", "0"));
+        }
+
+        if self.relative_to.is_some() {
+            if self.files.is_some() {
+                return Err(Error::other("--files-from can't be combined with --relative-to"));
+            }
+            if let ArchiveStrategy::ArchiveFromDirName = self.strategy {
+                return Err(Error::other("--archive-from-dirname can't be combined with --relative-to"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct PackOptionsBuilder {
+    options: PackOptions,
+}
+
+impl PackOptionsBuilder {
+    #[inline]
+    pub fn new() -> Self {
+        PackOptionsBuilder {
+            options: PackOptions::default(),
+        }
+    }
+
+    #[inline]
+    pub fn version(mut self, version: u32) -> Self {
+        self.options.version = version;
+        self
+    }
+
+    /// Only supported by version 2 packages.
+    #[inline]
+    pub fn md5_chunk_size(mut self, md5_chunk_size: u32) -> Self {
+        self.options.md5_chunk_size = Some(md5_chunk_size);
+        self
+    }
+
+    #[inline]
+    pub fn strategy(mut self, strategy: ArchiveStrategy) -> Self {
+        self.options.strategy = strategy;
+        self
+    }
+
+    #[inline]
+    pub fn max_inline_size(mut self, max_inline_size: u16) -> Self {
+        self.options.max_inline_size = max_inline_size;
+        self
+    }
+
+    #[inline]
+    pub fn index_warning_ratio(mut self, index_warning_ratio: f64) -> Self {
+        self.options.index_warning_ratio = index_warning_ratio;
+        self
+    }
+
+    #[inline]
+    pub fn alignment(mut self, alignment: usize) -> Self {
+        self.options.alignment = alignment;
+        self
+    }
+
+    #[inline]
+    pub fn align_mode(mut self, align_mode: AlignMode) -> Self {
+        self.options.align_mode = align_mode;
+        self
+    }
+
+    #[inline]
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.options.verbose = verbose;
+        self
+    }
+
+    #[inline]
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.options.quiet = quiet;
+        self
+    }
+
+    /// Only supported by version 2 packages.
+    #[inline]
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.options.verify = verify;
+        self
+    }
+
+    #[inline]
+    pub fn verify_preload(mut self, verify_preload: bool) -> Self {
+        self.options.verify_preload = verify_preload;
+        self
+    }
+
+    #[inline]
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.options.dedup = dedup;
+        self
+    }
+
+    #[inline]
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.options.buffer_size = buffer_size;
+        self
+    }
+
+    #[inline]
+    pub fn sidecar_hash(mut self, sidecar_hash: Option<HashAlgorithm>) -> Self {
+        self.options.sidecar_hash = sidecar_hash;
+        self
+    }
+
+    #[inline]
+    pub fn files(mut self, files: Option<Vec<PathBuf>>) -> Self {
+        self.options.files = files;
+        self
+    }
+
+    #[inline]
+    pub fn data_order(mut self, data_order: Option<Vec<String>>) -> Self {
+        self.options.data_order = data_order;
+        self
+    }
+
+    #[inline]
+    pub fn since(mut self, since: Option<SystemTime>) -> Self {
+        self.options.since = since;
+        self
+    }
+
+    #[inline]
+    pub fn relative_to(mut self, relative_to: Option<PathBuf>) -> Self {
+        self.options.relative_to = relative_to;
+        self
+    }
+
+    #[inline]
+    pub fn normalize_ext(mut self, normalize_ext: bool) -> Self {
+        self.options.normalize_ext = normalize_ext;
+        self
+    }
+
+    #[inline]
+    pub fn crc(mut self, crc: CrcAlgorithm) -> Self {
+        self.options.crc = crc;
+        self
+    }
+
+    #[inline]
+    pub fn lowercase_paths(mut self, lowercase_paths: bool) -> Self {
+        self.options.lowercase_paths = lowercase_paths;
+        self
+    }
+
+    #[inline]
+    pub fn replace(mut self, replace: Vec<(String, String)>) -> Self {
+        self.options.replace = replace;
+        self
+    }
+
+    #[inline]
+    pub fn symlinks(mut self, symlinks: Symlinks) -> Self {
+        self.options.symlinks = symlinks;
+        self
+    }
+
+    #[inline]
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.options.strict = strict;
+        self
+    }
+
+    #[inline]
+    pub fn keep_going(mut self, keep_going: bool) -> Self {
+        self.options.keep_going = keep_going;
+        self
+    }
+
+    #[inline]
+    pub fn temp_dir(mut self, temp_dir: Option<PathBuf>) -> Self {
+        self.options.temp_dir = temp_dir;
+        self
+    }
+
+    /// Returns the options if they pass [`PackOptions::validate`].
+    pub fn build(self) -> Result<PackOptions> {
+        self.options.validate()?;
+        Ok(self.options)
+    }
+}
+
+impl Default for PackOptionsBuilder {
+    #[inline]
+    fn default() -> Self {
+        PackOptionsBuilder::new()
+    }
 }
 
 impl Default for PackOptions {
@@ -63,72 +425,139 @@ impl Default for PackOptions {
     fn default() -> Self {
         Self {
             version: 1,
-            md5_chunk_size: DEFAULT_MD5_CHUNK_SIZE,
+            md5_chunk_size: None,
             strategy: ArchiveStrategy::default(),
             max_inline_size: DEFAULT_MAX_INLINE_SIZE,
+            index_warning_ratio: DEFAULT_INDEX_WARNING_RATIO,
             alignment: 1,
+            align_mode: AlignMode::default(),
             verbose: false,
+            quiet: false,
+            verify: false,
+            verify_preload: false,
+            dedup: false,
+            buffer_size: BUFFER_SIZE,
+            sidecar_hash: None,
+            files: None,
+            data_order: None,
+            since: None,
+            relative_to: None,
+            normalize_ext: false,
+            crc: CrcAlgorithm::default(),
+            lowercase_paths: false,
+            replace: Vec::new(),
+            symlinks: Symlinks::default(),
+            strict: false,
+            keep_going: false,
+            temp_dir: None,
         }
     }
 }
 
+/// Read a list of file paths as used by `pack --files-from`. Paths are
+/// separated by new lines or, if `null_separated`, by NULL bytes. Empty
+/// lines are ignored. `-` reads the list from stdin.
+pub fn read_file_list(path: impl AsRef<Path>, null_separated: bool) -> Result<Vec<PathBuf>> {
+    Ok(read_lines(path.as_ref(), null_separated)?
+        .into_iter()
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Read a manifest of paths inside of the package as used by
+/// `pack --order-from`, one path per line. Empty lines are ignored.
+pub fn read_order_list(path: impl AsRef<Path>) -> Result<Vec<String>> {
+    Ok(read_lines(path.as_ref(), false)?
+        .into_iter()
+        .map(|line| line.trim_matches('/').to_owned())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
+
 struct Gather {
     digest: crc32::Digest,
     max_inline_size: u16,
 
-    #[cfg(target_os = "windows")]
     buf: Vec<u8>,
 
-    #[cfg(not(target_os = "windows"))]
-    buf: [u8; BUFFER_SIZE],
-
     exts: HashSet<String>,
     verbose: bool,
     inline: bool,
+    /// Files from "dir" and "NNN" directories stay in the archive even if
+    /// they are small enough to be inlined, so unpacked packages round-trip.
+    keep_archive: bool,
+    dedup: bool,
+    since: Option<SystemTime>,
+    symlinks: Symlinks,
+    strict: bool,
+    quiet: bool,
+    keep_going: bool,
+    skipped_count: usize,
+}
+
+/// Following a link to `dirpath` or one of its parents would never end.
+fn check_symlink_loop(dirpath: &Path, link: &Path) -> Result<()> {
+    let target = match fs::canonicalize(link) {
+        Ok(target) => target,
+        Err(error) => return Err(Error::io_with_path(error, link)),
+    };
+    let dirpath = match fs::canonicalize(dirpath) {
+        Ok(dirpath) => dirpath,
+        Err(error) => return Err(Error::io_with_path(error, dirpath)),
+    };
+    if dirpath.starts_with(&target) {
+        return Err(Error::other(format!("symbolic link loop, link points to {:?}", target)).with_path(link));
+    }
+    Ok(())
 }
 
 struct Item<'a> {
+    /// Path of the file relative to the input directory.
     path: String,
+    /// Path of the file as written to the index. Differs from `path` only
+    /// if names are normalized.
+    index_path: String,
     dot_index:   usize,
     slash_index: usize,
     file: &'a mut File,
+    duplicate: bool,
 }
 
 impl Item<'_> {
     #[inline]
     fn ext(&self) -> &str {
-        &self.path[self.dot_index + 1..]
+        &self.index_path[self.dot_index + 1..]
     }
 
     #[inline]
     fn name(&self) -> &str {
-        &self.path[self.slash_index + 1..self.dot_index]
+        &self.index_path[self.slash_index + 1..self.dot_index]
     }
 
     #[inline]
     fn dir(&self) -> &str {
-        &self.path[..self.slash_index]
+        &self.index_path[..self.slash_index]
     }
 }
 
 impl Gather {
     #[inline]
-    fn new(max_inline_size: u16, verbose: bool) -> Self {
-        #[cfg(not(target_os = "windows"))]
-        let buf = [0; BUFFER_SIZE];
-
-        #[cfg(target_os = "windows")]
-        let mut buf = Vec::with_capacity(BUFFER_SIZE);
-        #[cfg(target_os = "windows")]
-        buf.resize(BUFFER_SIZE, 0);
-
+    fn new(max_inline_size: u16, buffer_size: usize, verbose: bool) -> Self {
         Gather {
             digest: crc32::Digest::new(crc32::IEEE),
             max_inline_size,
-            buf,
+            buf: vec![0; buffer_size],
             exts: HashSet::new(),
             verbose,
             inline: false,
+            keep_archive: false,
+            dedup: false,
+            since: None,
+            symlinks: Symlinks::default(),
+            strict: false,
+            quiet: false,
+            keep_going: false,
+            skipped_count: 0,
         }
     }
 
@@ -147,10 +576,37 @@ impl Gather {
             }
             let os_name = dirent.file_name();
             if let Some(name) = os_name.to_str() {
-                let file_type = match dirent.file_type() {
+                // doesn't follow symbolic links, like fs::symlink_metadata()
+                let mut file_type = match dirent.file_type() {
                     Ok(file_type) => file_type,
                     Err(error) => return Err(Error::io_with_path(error, dirent.path())),
                 };
+                if file_type.is_symlink() {
+                    match self.symlinks {
+                        Symlinks::Skip => {
+                            if !self.quiet {
+                                eprintln!("WARNING: skipping symbolic link: {:?}", dirent.path());
+                            }
+                            continue;
+                        },
+                        Symlinks::Error => {
+                            return Err(Error::other("symbolic links are not allowed").with_path(dirent.path()));
+                        },
+                        Symlinks::Follow => {
+                            file_type = match fs::metadata(dirent.path()) {
+                                Ok(meta) => meta.file_type(),
+                                Err(error) => {
+                                    // e.g. a dangling link
+                                    self.skip_unreadable(Error::io_with_path(error, dirent.path()))?;
+                                    continue;
+                                }
+                            };
+                            if file_type.is_dir() {
+                                check_symlink_loop(dirpath, &dirent.path())?;
+                            }
+                        },
+                    }
+                }
                 if file_type.is_dir() {
                     if let Some(entry) = entries.get_mut(name) {
                         match entry {
@@ -169,116 +625,244 @@ impl Gather {
                         self.gather_files(&mut dir.children, archive_index, &dirent.path(), false)?;
                         entries.insert(name.to_owned(), Entry::Dir(dir));
                     }
+                } else if !file_type.is_file() {
+                    // opening a FIFO would block and devices have no sensible size
+                    if self.strict {
+                        return Err(Error::other("not a regular file").with_path(dirent.path()));
+                    }
+                    if !self.quiet {
+                        eprintln!("WARNING: skipping special file: {:?}", dirent.path());
+                    }
                 } else if root {
                     return Err(Error::other("all files must be in sub-directories").with_path(dirent.path()));
-                } else if let Some(dot_index) = name.rfind('.') {
-                    if dot_index == 0 || dot_index + 1 == name.len() {
-                        return Err(Error::other("filenames must be of format \"NAME.EXT\"").with_path(dirent.path()));
-                    }
+                } else if let Err(error) = self.gather_file(entries, archive_index, name, &dirent.path()) {
+                    self.skip_unreadable(error)?;
+                }
+            } else {
+                return Err(Error::other("cannot handle filename").with_path(dirent.path()));
+            }
+        }
 
-                    let ext = &name[dot_index + 1..];
-                    if !self.exts.contains(ext) {
-                        self.exts.insert(ext.to_owned());
-                    }
+        Ok(())
+    }
 
-                    let mut reader = match fs::File::open(dirent.path()) {
-                        Ok(reader) => reader,
-                        Err(error) => return Err(Error::io_with_path(error, dirent.path())),
-                    };
-                    let meta = match reader.metadata() {
-                        Ok(meta) => meta,
-                        Err(error) => return Err(Error::io_with_path(error, dirent.path())),
-                    };
-                    let size = meta.len();
+    fn gather_file(&mut self, entries: &mut HashMap<String, Entry>, archive_index: u16, name: &str, fs_path: &Path) -> Result<()> {
+        if let Some(since) = self.since {
+            let modified = match fs::metadata(fs_path).and_then(|meta| meta.modified()) {
+                Ok(modified) => modified,
+                Err(error) => return Err(Error::io_with_path(error, fs_path)),
+            };
+            if modified < since {
+                if self.verbose {
+                    println!("skipping unmodified {:?}", fs_path);
+                }
+                return Ok(());
+            }
+        }
 
-                    if size > std::i32::MAX as u64 {
-                        return Err(Error::other(format!("file too big {} > {}", size, std::i32::MAX))
-                            .with_path(dirent.path()));
-                    }
+        let dot_index = match name.rfind('.') {
+            Some(dot_index) if dot_index > 0 && dot_index + 1 < name.len() => dot_index,
+            _ => return Err(Error::other("filenames must be of format \"NAME.EXT\"").with_path(fs_path)),
+        };
 
-                    let mut size = size as u32;
-                    let mut preload = Vec::new();
-                    let inline_size: u16;
+        let mut reader = match fs::File::open(fs_path) {
+            Ok(reader) => reader,
+            Err(error) => return Err(Error::io_with_path(error, fs_path)),
+        };
+        let meta = match reader.metadata() {
+            Ok(meta) => meta,
+            Err(error) => return Err(Error::io_with_path(error, fs_path)),
+        };
+        let size = meta.len();
 
-                    self.digest.reset();
-                    if self.inline || size <= self.max_inline_size as u32 {
-                        if size > std::u16::MAX as u32 {
-                            return Err(Error::other(format!(
-                                "file is meant to be inlined into the index, but is too big: {} > {}",
-                                size, std::u16::MAX))
-                                .with_path(dirent.path()));
-                        }
-                        inline_size = size as u16;
-                        size = 0;
-                        preload.resize(inline_size as usize, 0);
-                        if let Err(error) = reader.read_exact(&mut preload) {
-                            return Err(Error::io_with_path(error, dirent.path()));
-                        }
-                        self.digest.write(&preload);
+        if size > i32::MAX as u64 {
+            return Err(Error::other(format!("file too big {} > {}", size, i32::MAX))
+                .with_path(fs_path));
+        }
+
+        let mut size = size as u32;
+        let mut preload = Vec::new();
+        let inline_size: u16;
+        let crc32;
+
+        if self.inline || size == 0 || (!self.keep_archive && size <= self.max_inline_size as u32) {
+            if size > std::u16::MAX as u32 {
+                return Err(Error::other(format!(
+                    "file is meant to be inlined into the index, but is too big: {} > {}",
+                    size, std::u16::MAX))
+                    .with_path(fs_path));
+            }
+            inline_size = size as u16;
+            size = 0;
+            preload.resize(inline_size as usize, 0);
+            if let Err(error) = reader.read_exact(&mut preload) {
+                return Err(Error::io_with_path(error, fs_path));
+            }
+            self.digest.reset();
+            self.digest.write(&preload);
+            crc32 = self.digest.sum32();
+        } else if self.dedup {
+            // Deduplication needs the CRC32 sums before the files
+            // are distributed to the archives.
+            let mut sink = std::io::sink();
+            inline_size = 0;
+            crc32 = match copy_with_crc32(&mut reader, &mut sink, size as usize, &mut self.buf, &mut self.digest) {
+                Ok(crc32) => crc32,
+                Err(error) => return Err(Error::io_with_path(error, fs_path)),
+            };
+        } else {
+            // The CRC32 sum is calculated while copying the data
+            // into the archive, so the file is only read once.
+            inline_size = 0;
+            crc32 = 0;
+        }
+
+        // only after the file could be read, so skipped files add no extensions
+        let ext = &name[dot_index + 1..];
+        if !self.exts.contains(ext) {
+            self.exts.insert(ext.to_owned());
+        }
+
+        let file = File {
+            index: 0, // not used when writing
+            crc32,
+            inline_size,
+            archive_index,
+            offset: 0, // to be determined
+            size,
+            preload,
+        };
+        if let Some(old) = entries.insert(name.to_owned(), Entry::File(file)) {
+            use std::fmt::Write;
+
+            let mut msg = String::new();
+            write!(&mut msg, "file \"{}\" occured twice, once ", name).unwrap();
+            if inline_size > 0 && size == 0 {
+                msg.push_str("inlined in index");
+            } else if archive_index == DIR_INDEX {
+                msg.push_str("from \"dir\"");
+            } else {
+                write!(&mut msg, "from archive \"{:03}\"", archive_index).unwrap();
+            }
+            msg.push_str(", and once ");
+            match old {
+                Entry::Dir(_) => {
+                    msg.push_str("it's a directory");
+                },
+                Entry::File(file) => {
+                    if file.inline_size > 0 && file.size == 0 {
+                        msg.push_str("inlined in index");
+                    } else if file.archive_index == DIR_INDEX {
+                        msg.push_str("from \"dir\"");
                     } else {
-                        let mut remain = size as usize;
-                        inline_size = 0;
-                        while remain >= BUFFER_SIZE {
-                            if let Err(error) = reader.read_exact(&mut self.buf) {
-                                return Err(Error::io_with_path(error, dirent.path()));
-                            }
-                            self.digest.write(&self.buf);
-                            remain -= BUFFER_SIZE;
-                        }
-                        if remain > 0 {
-                            let buf = &mut self.buf[..remain];
-                            if let Err(error) = reader.read_exact(buf) {
-                                return Err(Error::io_with_path(error, dirent.path()));
-                            }
-                            self.digest.write(buf);
-                        }
+                        write!(&mut msg, "from archive \"{:03}\"", file.archive_index).unwrap();
                     }
-                    let crc32 = self.digest.sum32();
-
-                    let file = File {
-                        index: 0, // not used when writing
-                        crc32,
-                        inline_size,
-                        archive_index,
-                        offset: 0, // to be determined
-                        size,
-                        preload,
-                    };
-                    if let Some(old) = entries.insert(name.to_owned(), Entry::File(file)) {
-                        use std::fmt::Write;
+                }
+            }
 
-                        let mut msg = String::new();
-                        write!(&mut msg, "file \"{}\" occured twice, once ", name).unwrap();
-                        if inline_size > 0 && size == 0 {
-                            msg.push_str("inlined in index");
-                        } else if archive_index == DIR_INDEX {
-                            msg.push_str("from \"dir\"");
+            return Err(Error::other(msg).with_path(fs_path));
+        }
+
+        Ok(())
+    }
+
+    /// With `keep_going` a file that can't be read is only reported and
+    /// left out, any other error is passed on.
+    fn skip_unreadable(&mut self, error: Error) -> Result<()> {
+        match error.error_type() {
+            ErrorType::IO(_) if self.keep_going => {
+                if !self.quiet {
+                    eprintln!("WARNING: skipping unreadable file: {}", error);
+                }
+                self.skipped_count += 1;
+                Ok(())
+            },
+            _ => Err(error),
+        }
+    }
+
+    /// Gather exactly the given files instead of scanning `indir`. Relative
+    /// paths are relative to `indir`, other paths need to be inside of it.
+    /// In archive-from-dirname mode the first directory of each path names
+    /// the archive, like when scanning `indir`.
+    fn gather_file_list(&mut self, entries: &mut HashMap<String, Entry>, indir: &Path, paths: &[PathBuf], archive_from_dirname: bool) -> Result<()> {
+        // so that e.g. "in/foo/bar.txt" is found in "./in" and vice versa
+        let indir = &without_cur_dir(indir);
+        for fs_path in paths {
+            let fs_path = &without_cur_dir(fs_path);
+            let rel_path = if fs_path.is_relative() && !fs_path.starts_with(indir) {
+                fs_path.as_path()
+            } else if let Ok(rel_path) = fs_path.strip_prefix(indir) {
+                rel_path
+            } else {
+                return Err(Error::other("file is not inside of the input directory").with_path(fs_path));
+            };
+
+            let mut names = Vec::new();
+            for component in rel_path.components() {
+                match component {
+                    Component::CurDir => {},
+                    Component::Normal(name) => {
+                        if let Some(name) = name.to_str() {
+                            names.push(name);
                         } else {
-                            write!(&mut msg, "from archive \"{:03}\"", archive_index).unwrap();
-                        }
-                        msg.push_str(", and once ");
-                        match old {
-                            Entry::Dir(_) => {
-                                msg.push_str("it's a directory");
-                            },
-                            Entry::File(file) => {
-                                if file.inline_size > 0 && file.size == 0 {
-                                    msg.push_str("inlined in index");
-                                } else if file.archive_index == DIR_INDEX {
-                                    msg.push_str("from \"dir\"");
-                                } else {
-                                    write!(&mut msg, "from archive \"{:03}\"", file.archive_index).unwrap();
-                                }
-                            }
+                            return Err(Error::other("cannot handle filename").with_path(fs_path));
                         }
+                    },
+                    _ => return Err(Error::other("file is not inside of the input directory").with_path(fs_path)),
+                }
+            }
+
+            let fs_path = indir.join(rel_path);
+
+            if self.verbose {
+                println!("adding {:?}", fs_path);
+            }
+
+            let mut names = &names[..];
+            let mut archive_index = DIR_INDEX;
+            self.inline = false;
+            self.keep_archive = false;
 
-                        return Err(Error::other(msg).with_path(dirent.path()));
+            if archive_from_dirname && names.len() > 1 {
+                match names[0] {
+                    "dir" => {
+                        self.keep_archive = true;
+                    },
+                    "inline" => {
+                        self.inline = true;
+                    },
+                    name => match name.parse::<u16>() {
+                        Ok(index) if name.len() == 3 => {
+                            archive_index = index;
+                            self.keep_archive = true;
+                        },
+                        _ => return Err(Error::other(
+                            "first directory name is neither a 3 digit number, \"dir\", nor \"inline\"")
+                            .with_path(fs_path)),
                     }
-                } else {
-                    return Err(Error::other("filenames must be of format \"NAME.EXT\"").with_path(dirent.path()));
                 }
-            } else {
-                return Err(Error::other("cannot handle filename").with_path(dirent.path()));
+                names = &names[1..];
+            }
+
+            let (name, dirnames) = match names.split_last() {
+                Some((name, dirnames)) if !dirnames.is_empty() => (*name, dirnames),
+                _ => return Err(Error::other("all files must be in sub-directories").with_path(fs_path)),
+            };
+
+            let mut children = &mut *entries;
+            for dirname in dirnames {
+                let entry = children.entry((*dirname).to_owned()).or_insert_with(|| Entry::Dir(Dir {
+                    children: HashMap::new()
+                }));
+                children = match entry {
+                    Entry::Dir(dir) => &mut dir.children,
+                    Entry::File(_) => return Err(Error::entry_not_a_dir(*dirname).with_path(fs_path)),
+                };
+            }
+
+            if let Err(error) = self.gather_file(children, archive_index, name, &fs_path) {
+                self.skip_unreadable(error)?;
             }
         }
 
@@ -286,14 +870,58 @@ impl Gather {
     }
 }
 
-fn recursive_file_list<'a>(entries: &'a mut HashMap<String, Entry>, pathbuf: &mut String, list: &mut Vec<Item<'a>>) {
+/// How names are normalized when they are written to the index.
+#[derive(Debug, Clone, Copy)]
+struct NameCase {
+    lowercase_paths: bool,
+    normalize_ext: bool,
+}
+
+impl NameCase {
+    #[inline]
+    fn is_identity(self) -> bool {
+        !self.lowercase_paths && !self.normalize_ext
+    }
+
+    fn normalize(self, path: &str, dot_index: usize) -> String {
+        let (stem, ext) = path.split_at(dot_index);
+        let mut normalized = if self.lowercase_paths {
+            stem.to_lowercase()
+        } else {
+            stem.to_owned()
+        };
+        if self.normalize_ext {
+            normalized.push_str(&ext.to_lowercase());
+        } else {
+            normalized.push_str(ext);
+        }
+        normalized
+    }
+
+    fn normalize_dir_name(self, name: &str) -> String {
+        if self.lowercase_paths {
+            name.to_lowercase()
+        } else {
+            name.to_owned()
+        }
+    }
+
+    fn normalize_file_name(self, name: &str) -> String {
+        match name.rfind('.') {
+            Some(dot_index) => self.normalize(name, dot_index),
+            None => self.normalize_dir_name(name),
+        }
+    }
+}
+
+fn recursive_file_list<'a>(entries: &'a mut HashMap<String, Entry>, name_case: NameCase, pathbuf: &mut String, list: &mut Vec<Item<'a>>) {
     for (name, entry) in entries.iter_mut() {
         let len = pathbuf.len();
         pathbuf.push_str(name);
         match entry {
             Entry::Dir(dir) => {
                 pathbuf.push('/');
-                recursive_file_list(&mut dir.children, pathbuf, list);
+                recursive_file_list(&mut dir.children, name_case, pathbuf, list);
             },
             Entry::File(file) => {
                 let path = pathbuf.to_string();
@@ -301,76 +929,358 @@ fn recursive_file_list<'a>(entries: &'a mut HashMap<String, Entry>, pathbuf: &mu
                 // I know that there is a '.' in the file name, I checked above.
                 let dot_index = path.rfind('.').unwrap();
 
+                let (index_path, dot_index) = if name_case.is_identity() {
+                    (path.clone(), dot_index)
+                } else {
+                    // lowercasing might change the length of the string
+                    let index_path = name_case.normalize(&path, dot_index);
+                    let dot_index = index_path.rfind('.').unwrap();
+                    (index_path, dot_index)
+                };
+
                 // I know that there is a '/' in the path, because I checked above.
-                let slash_index = path[..dot_index].rfind('/').unwrap();
+                let slash_index = index_path[..dot_index].rfind('/').unwrap();
 
                 list.push(Item {
                     path,
+                    index_path,
                     dot_index,
                     slash_index,
-                    file
+                    file,
+                    duplicate: false,
                 });
             }
         }
-        pathbuf.truncate(len);
-    }
-}
-
-fn write_dir(
-        extmap: &HashMap<&str, HashMap<&str, Vec<&Item>>>,
-        dirvpk_path: impl AsRef<Path>,
-        version:    u32,
-        dir_size:   u32,
-        index_size: u32) -> std::io::Result<fs::File> {
-    let mut dirfile = fs::File::create(dirvpk_path)?;
-    let mut dirwriter = BufWriter::new(&mut dirfile);
+        pathbuf.truncate(len);
+    }
+}
+
+fn write_dir(
+        extmap: &HashMap<&str, HashMap<&str, Vec<&Item>>>,
+        dirfile:    &mut fs::File,
+        version:    u32,
+        dir_size:   u32,
+        index_size: u32) -> std::io::Result<()> {
+    dirfile.seek(SeekFrom::Start(0))?;
+    let mut dirwriter = BufWriter::new(dirfile);
+
+    let mut exts: Vec<&str> = extmap.keys().map(|s| s.as_ref()).collect();
+    exts.sort();
+
+    if version > 0 {
+        dirwriter.write_all(&VPK_MAGIC)?;
+
+        write_u32(&mut dirwriter, version)?;
+        write_u32(&mut dirwriter, index_size)?;
+
+        if version > 1 {
+            // write placeholder
+            dirwriter.write_all(&[
+                0, 0, 0, 0, // data size
+                0, 0, 0, 0, // archive MD5 size
+                0, 0, 0, 0, // other MD5 size
+                0, 0, 0, 0, // signature size
+            ])?;
+        }
+    }
+
+    for ext in &exts {
+        write_str(&mut dirwriter, ext)?;
+
+        let dirmap = extmap.get(ext).unwrap();
+        let mut dirs: Vec<&str> = dirmap.keys().map(|s| s.as_ref()).collect();
+        dirs.sort();
+
+        for dir in &dirs {
+            write_str(&mut dirwriter, dir)?;
+
+            let filelist = dirmap.get(dir).unwrap();
+            for item in filelist {
+                let name = item.name();
+
+                write_str(&mut dirwriter, name)?;
+                write_file(&mut dirwriter, item.file, dir_size)?;
+            }
+            dirwriter.write_all(&[0])?;
+        }
+        dirwriter.write_all(&[0])?;
+    }
+    dirwriter.write_all(&[0])?;
+
+    dirwriter.flush()?;
+
+    Ok(())
+}
+
+/// Apply the rules of [`PackOptions::replace`] to the path of an item as
+/// written to the index.
+fn replace_path(item: &mut Item, replace: &[(String, String)]) -> Result<()> {
+    let mut index_path = std::mem::take(&mut item.index_path);
+    for (from, to) in replace {
+        if let Some(rest) = index_path.strip_prefix(from.as_str()) {
+            index_path = format!("{}{}", to, rest);
+        }
+    }
+
+    // same rules as for the files in the input directory
+    let indices = match (index_path.rfind('/'), index_path.rfind('.')) {
+        (Some(slash_index), Some(dot_index))
+            if slash_index > 0 && dot_index > slash_index + 1 && dot_index + 1 < index_path.len() &&
+               !index_path.starts_with('/') && !index_path.contains("//") => (slash_index, dot_index),
+        _ => return Err(Error::other(format!(
+                "{:?} would be rewritten to an invalid path: {:?}",
+                item.path, index_path))),
+    };
+
+    item.index_path  = index_path;
+    item.slash_index = indices.0;
+    item.dot_index   = indices.1;
+
+    Ok(())
+}
+
+/// Move all files to the paths they have in the index. Colliding paths
+/// need to be checked beforehand.
+fn move_files(entries: HashMap<String, Entry>, index_paths: &HashMap<String, String>, pathbuf: &mut String, moved: &mut HashMap<String, Entry>) {
+    for (name, entry) in entries {
+        let len = pathbuf.len();
+        pathbuf.push_str(&name);
+        match entry {
+            Entry::Dir(dir) => {
+                pathbuf.push('/');
+                move_files(dir.children, index_paths, pathbuf, moved);
+            },
+            Entry::File(file) => {
+                let index_path = &index_paths[pathbuf.as_str()];
+                let mut names: Vec<&str> = index_path.split('/').collect();
+                let name = names.pop().unwrap();
+
+                let mut children = &mut *moved;
+                for dirname in names {
+                    let entry = children.entry(dirname.to_owned()).or_insert_with(|| Entry::Dir(Dir {
+                        children: HashMap::new()
+                    }));
+                    children = match entry {
+                        Entry::Dir(dir) => &mut dir.children,
+                        Entry::File(_) => unreachable!(),
+                    };
+                }
+                children.insert(name.to_owned(), Entry::File(file));
+            },
+        }
+        pathbuf.truncate(len);
+    }
+}
+
+/// Rename all entries like they are written to the index. Directories that
+/// end up with the same name are merged. Colliding file names need to be
+/// checked beforehand.
+fn normalize_names(entries: HashMap<String, Entry>, name_case: NameCase) -> HashMap<String, Entry> {
+    let mut normalized: HashMap<String, Entry> = HashMap::with_capacity(entries.len());
+    for (name, entry) in entries {
+        match entry {
+            Entry::Dir(mut dir) => {
+                let children = normalize_names(std::mem::take(&mut dir.children), name_case);
+                match normalized.entry(name_case.normalize_dir_name(&name)) {
+                    MapEntry::Occupied(mut other) => {
+                        if let Entry::Dir(other) = other.get_mut() {
+                            other.children.extend(children);
+                        }
+                    },
+                    MapEntry::Vacant(vacant) => {
+                        dir.children = children;
+                        vacant.insert(Entry::Dir(dir));
+                    },
+                }
+            },
+            Entry::File(file) => {
+                normalized.insert(name_case.normalize_file_name(&name), Entry::File(file));
+            },
+        }
+    }
+    normalized
+}
+
+/// Names of the directories leading from `base` to `indir`.
+fn relative_dirnames(base: &Path, indir: &Path) -> Result<Vec<String>> {
+    let base = match base.canonicalize() {
+        Ok(base) => base,
+        Err(error) => return Err(Error::io_with_path(error, base)),
+    };
+    let canon_indir = match indir.canonicalize() {
+        Ok(indir) => indir,
+        Err(error) => return Err(Error::io_with_path(error, indir)),
+    };
+    let rel_path = match canon_indir.strip_prefix(&base) {
+        Ok(rel_path) => rel_path,
+        Err(_) => return Err(Error::other(format!(
+            "input directory is not inside of {:?}", base)).with_path(indir)),
+    };
+
+    let mut names = Vec::new();
+    for component in rel_path.components() {
+        match component.as_os_str().to_str() {
+            Some(name) => names.push(name.to_owned()),
+            None => return Err(Error::other("cannot handle filename").with_path(indir)),
+        }
+    }
+
+    Ok(names)
+}
+
+/// Path of the file in the input directory that holds the data of `vpk_path`.
+fn source_path(indir: &Path, strategy: &ArchiveStrategy, archive_index: u16, vpk_path: &str) -> PathBuf {
+    let mut fs_path = indir.to_path_buf();
+
+    if let ArchiveStrategy::ArchiveFromDirName = strategy {
+        if archive_index == DIR_INDEX {
+            fs_path.push("dir");
+        } else {
+            fs_path.push(format!("{:03}", archive_index));
+        }
+    }
+
+    for (_, item, _) in split_path(vpk_path) {
+        fs_path.push(item);
+    }
+
+    fs_path
+}
+
+/// Make sure the inlined data of all files still matches their source files.
+fn verify_preloads(indir: &Path, strategy: &ArchiveStrategy, list: &[Item], buf: &mut [u8]) -> Result<()> {
+    for item in list {
+        let file = &*item.file;
+        if file.inline_size == 0 {
+            continue;
+        }
+
+        // in archive-from-dirname mode only files from "inline" are inlined
+        let mut fs_path = indir.to_path_buf();
+        if let ArchiveStrategy::ArchiveFromDirName = strategy {
+            fs_path.push("inline");
+        }
+        for (_, name, _) in split_path(&item.path) {
+            fs_path.push(name);
+        }
+
+        let mut reader = match fs::File::open(&fs_path) {
+            Ok(reader) => reader,
+            Err(error) => return Err(Error::io_with_path(error, fs_path)),
+        };
+
+        let mut offset = 0usize;
+        while offset < file.preload.len() {
+            let len = (file.preload.len() - offset).min(buf.len());
+            let buf = &mut buf[..len];
+            if let Err(error) = reader.read_exact(buf) {
+                if error.kind() == std::io::ErrorKind::UnexpectedEof {
+                    break;
+                }
+                return Err(Error::io_with_path(error, fs_path));
+            }
+            if buf[..] != file.preload[offset..offset + buf.len()] {
+                return Err(Error::other(format!(
+                    "inlined data of {:?} doesn't match its source file at offset {}",
+                    item.path, offset)).with_path(fs_path));
+            }
+            offset += buf.len();
+        }
 
-    let mut exts: Vec<&str> = extmap.keys().map(|s| s.as_ref()).collect();
-    exts.sort();
+        let source_size = match reader.metadata() {
+            Ok(meta) => meta.len(),
+            Err(error) => return Err(Error::io_with_path(error, fs_path)),
+        };
+        if source_size != file.preload.len() as u64 {
+            return Err(Error::other(format!(
+                "inlined data of {:?} has {} bytes, but its source file has {} bytes",
+                item.path, file.preload.len(), source_size)).with_path(fs_path));
+        }
+    }
 
-    if version > 0 {
-        dirwriter.write_all(&VPK_MAGIC)?;
+    Ok(())
+}
 
-        write_u32(&mut dirwriter, version)?;
-        write_u32(&mut dirwriter, index_size)?;
+/// Remembers where the data of already distributed files was placed, so
+/// files with identical content can point to the same data.
+struct Dedup {
+    files: HashMap<(u32, u32), (u16, u32, PathBuf)>,
+    buf1: Vec<u8>,
+    buf2: Vec<u8>,
+    saved_size: u64,
+}
 
-        if version > 1 {
-            // write placeholder
-            dirwriter.write_all(&[
-                0, 0, 0, 0, // data size
-                0, 0, 0, 0, // archive MD5 size
-                0, 0, 0, 0, // other MD5 size
-                0, 0, 0, 0, // signature size
-            ])?;
+impl Dedup {
+    fn new(buffer_size: usize) -> Self {
+        Self {
+            files: HashMap::new(),
+            buf1: vec![0; buffer_size],
+            buf2: vec![0; buffer_size],
+            saved_size: 0,
         }
     }
 
-    for ext in &exts {
-        write_str(&mut dirwriter, ext)?;
+    /// If a file with the same content was already placed into an archive
+    /// `item` is changed to point to that data and `true` is returned.
+    fn find(&mut self, item: &mut Item, fs_path: &Path) -> Result<bool> {
+        if let Some((archive_index, offset, other_path)) = self.files.get(&(item.file.size, item.file.crc32)) {
+            let same = match same_content(fs_path, other_path, item.file.size as usize, &mut self.buf1, &mut self.buf2) {
+                Ok(same) => same,
+                Err(error) => return Err(Error::io_with_path(error, fs_path)),
+            };
 
-        let dirmap = extmap.get(ext).unwrap();
-        let mut dirs: Vec<&str> = dirmap.keys().map(|s| s.as_ref()).collect();
-        dirs.sort();
+            if same {
+                item.file.archive_index = *archive_index;
+                item.file.offset = *offset;
+                item.duplicate = true;
+                self.saved_size += item.file.size as u64;
+                return Ok(true);
+            }
+        }
 
-        for dir in &dirs {
-            write_str(&mut dirwriter, dir)?;
+        Ok(false)
+    }
 
-            let filelist = dirmap.get(dir).unwrap();
-            for item in filelist {
-                let name = item.name();
+    fn insert(&mut self, file: &File, fs_path: PathBuf) {
+        self.files.entry((file.size, file.crc32)).or_insert((file.archive_index, file.offset, fs_path));
+    }
+}
 
-                write_str(&mut dirwriter, name)?;
-                write_file(&mut dirwriter, item.file, dir_size)?;
-            }
-            dirwriter.write_all(&[0])?;
+fn same_content(path1: &Path, path2: &Path, size: usize, buf1: &mut [u8], buf2: &mut [u8]) -> std::io::Result<bool> {
+    let mut reader1 = fs::File::open(path1)?;
+    let mut reader2 = fs::File::open(path2)?;
+
+    let mut remain = size;
+    while remain > 0 {
+        let count = remain.min(buf1.len());
+        let buf1 = &mut buf1[..count];
+        let buf2 = &mut buf2[..count];
+        reader1.read_exact(buf1)?;
+        reader2.read_exact(buf2)?;
+        if buf1 != buf2 {
+            return Ok(false);
         }
-        dirwriter.write_all(&[0])?;
+        remain -= count;
     }
-    dirwriter.write_all(&[0])?;
 
-    drop(dirwriter);
+    Ok(true)
+}
+
+/// Copy `size` bytes from `reader` to `writer` and return the CRC32 sum of the copied data.
+fn copy_with_crc32(reader: &mut impl Read, writer: &mut impl Write, size: usize, buf: &mut [u8], digest: &mut crc32::Digest) -> std::io::Result<u32> {
+    digest.reset();
+
+    let mut remain = size;
+    while remain > 0 {
+        let count = remain.min(buf.len());
+        let buf = &mut buf[..count];
+        reader.read_exact(buf)?;
+        digest.write(buf);
+        writer.write_all(buf)?;
+        remain -= count;
+    }
 
-    Ok(dirfile)
+    Ok(digest.sum32())
 }
 
 fn write_sizes<W>(dirwriter: &mut W, data_size: u32, archive_md5_size: u32, other_md5_size: u32, signature_size: u32) -> std::io::Result<()>
@@ -395,31 +1305,325 @@ fn write_archive_md5s(dirwriter: &mut impl Write, archive_md5s: &Vec<ArchiveMd5>
     dirwriter.flush()
 }
 
-fn calculate_md5<R>(reader: &mut R, buf: &mut[u8], offset: u64, size: u64) -> std::io::Result<Md5>
-where R: Read, R: Seek {
+struct WrittenV2 {
+    index_size:       u32,
+    data_size:        u32,
+    archive_md5_size: u32,
+    other_md5_size:   u32,
+    signature_size:   u32,
+    index_md5:        Md5,
+    archive_md5s_md5: Md5,
+    everything_md5:   Md5,
+}
 
-    reader.seek(SeekFrom::Start(offset))?;
+/// Reads the just written VPK v2 _dir.vpk file again like any other package
+/// and compares its header sizes and MD5 sums with what was meant to be
+/// written. The MD5 sums are re-calculated over the sections as found by
+/// [`Package::md5_sections`], not over the ranges they were written for.
+fn verify_v2(dirvpk_path: &Path, buf: &mut [u8], expected: &WrittenV2) -> Result<()> {
+    let package = Package::from_path(dirvpk_path, ReadOptions::new())?;
+
+    let sizes = [
+        ("index size",       package.index_size(),       expected.index_size),
+        ("data size",        package.data_size(),        expected.data_size),
+        ("archive MD5 size", package.archive_md5_size(), expected.archive_md5_size),
+        ("other MD5 size",   package.other_md5_size(),   expected.other_md5_size),
+        ("signature size",   package.signature_size(),   expected.signature_size),
+    ];
+    for (name, actual, expected) in &sizes {
+        if actual != expected {
+            return Err(Error::sanity_check_failed(format!(
+                "{} in written header is {}, but should be {}",
+                name, actual, expected)).with_path(dirvpk_path));
+        }
+    }
 
-    let mut remaining = size as usize;
-    let mut hasher = md5::Context::new();
+    let md5s = [
+        ("index",        package.index_md5(),        &expected.index_md5),
+        ("archive list", package.archive_md5s_md5(), &expected.archive_md5s_md5),
+        ("everything",   package.everything_md5(),   &expected.everything_md5),
+    ];
+    for (name, actual, expected) in &md5s {
+        if *actual != Some(*expected) {
+            return Err(Error::sanity_check_failed(format!(
+                "written {} MD5 sum missmatch", name)).with_path(dirvpk_path));
+        }
+    }
 
-    while remaining >= buf.len() {
-        reader.read_exact(buf)?;
-        hasher.consume(&buf);
-        remaining -= buf.len();
+    let mut reader = match fs::File::open(dirvpk_path) {
+        Ok(file) => file,
+        Err(error) => return Err(Error::io_with_path(error, dirvpk_path)),
+    };
+
+    for section in package.md5_sections() {
+        match md5_of_range(&mut reader, buf, section.range) {
+            Ok(md5) if &md5 == section.md5 => {},
+            Ok(_) => return Err(Error::sanity_check_failed(format!(
+                "{} MD5 sum missmatch after writing", section.what)).with_path(dirvpk_path)),
+            Err(error) => return Err(Error::io_with_path(error, dirvpk_path)),
+        }
     }
 
-    if remaining > 0 {
-        let buf = &mut buf[..remaining];
-        reader.read_exact(buf)?;
-        hasher.consume(&buf);
+    Ok(())
+}
+
+/// Round `size` up to the next multiple of `alignment`. Uses a bit mask if
+/// `alignment` is a power of two and falls back to a modulo otherwise.
+#[inline]
+/// Warning message if the index is close to the maximum index size of
+/// `i32::MAX` bytes.
+fn index_size_warning(index_size: usize, inline_size: u64) -> Option<String> {
+    if index_size as f64 <= i32::MAX as f64 * INDEX_SIZE_WARNING_RATIO {
+        return None;
+    }
+
+    Some(format!(
+        "the index is {} bytes, {:.1} % of the maximum index size of {} bytes \
+         ({} bytes of it are inlined file data). Consider a lower --max-inline-size \
+         or splitting the files into several packages.",
+        index_size, index_size as f64 * 100.0 / i32::MAX as f64, i32::MAX, inline_size))
+}
+
+fn align_up(size: usize, alignment: usize) -> usize {
+    if alignment.is_power_of_two() {
+        let mask = alignment - 1;
+        (size + mask) & !mask
+    } else {
+        let remainder = size % alignment;
+        if remainder != 0 {
+            size + alignment - remainder
+        } else {
+            size
+        }
+    }
+}
+
+/// One row per written archive with the number of files whose data it
+/// holds, the size of that data, and the size of the archive file. The
+/// _dir.vpk file is always listed.
+fn archive_summary(dirpath: &Path, prefix: &str, archmap: &HashMap<u16, Vec<usize>>, list: &[Item]) -> Result<Vec<Vec<String>>> {
+    let mut arch_indices: Vec<u16> = archmap.keys().copied().collect();
+    if !archmap.contains_key(&DIR_INDEX) {
+        arch_indices.push(DIR_INDEX);
+    }
+    arch_indices.sort_unstable();
+
+    let mut rows = Vec::with_capacity(arch_indices.len());
+    for archive_index in arch_indices {
+        let mut count = 0usize;
+        let mut data_size = 0u64;
+        if let Some(indices) = archmap.get(&archive_index) {
+            for index in indices {
+                let item = &list[*index];
+                if !item.duplicate {
+                    count += 1;
+                    data_size += item.file.size as u64;
+                }
+            }
+        }
+
+        let archpath = archive_path(dirpath, prefix, archive_index);
+        let file_size = match fs::metadata(&archpath) {
+            Ok(meta) => meta.len(),
+            Err(error) => return Err(Error::io_with_path(error, archpath)),
+        };
+
+        rows.push(vec![
+            format_archive(archive_index),
+            format!("{}", count),
+            format!("{}", data_size),
+            format!("{}", file_size),
+        ]);
+    }
+
+    Ok(rows)
+}
+
+/// Create a fresh directory for writing a package with the given prefix.
+/// Errors name `parent`, because the temporary directory itself is an
+/// implementation detail.
+fn create_temp_dir(parent: &Path, prefix: &str) -> Result<PathBuf> {
+    let tempdir = parent.join(format!(".{}.rvpk-tmp-{}", prefix, std::process::id()));
+    if let Err(error) = fs::create_dir(&tempdir) {
+        return Err(Error::io_with_path(error, parent));
+    }
+    Ok(tempdir)
+}
+
+/// Move a file, replacing `dest`. If renaming fails, e.g. because the
+/// file is on a different file system, it is copied next to `dest` first,
+/// so `dest` is still replaced in one step.
+fn move_file(source: &Path, dest: &Path) -> Result<()> {
+    if fs::rename(source, dest).is_ok() {
+        return Ok(());
+    }
+
+    let mut name = OsString::from(".");
+    name.push(dest.file_name().unwrap_or_default());
+    name.push(format!(".rvpk-tmp-{}", std::process::id()));
+    let temp = dest.with_file_name(name);
+
+    if let Err(error) = fs::copy(source, &temp) {
+        let _ = fs::remove_file(&temp);
+        return Err(Error::io_with_path(error, dest));
+    }
+
+    if let Err(error) = fs::rename(&temp, dest) {
+        let _ = fs::remove_file(&temp);
+        return Err(Error::io_with_path(error, dest));
+    }
+
+    if let Err(error) = fs::remove_file(source) {
+        return Err(Error::io_with_path(error, source));
+    }
+
+    Ok(())
+}
+
+/// `path` without any `.` components. Only a leading `.` can be left by
+/// [`Path::components`].
+fn without_cur_dir(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+/// Names of all entries of the directory `path`.
+fn dir_names(path: &Path) -> Result<Vec<OsString>> {
+    let dirents = match read_dir(path) {
+        Ok(dirents) => dirents,
+        Err(error) => return Err(Error::io_with_path(error, path)),
+    };
+
+    let mut names = Vec::new();
+    for dirent in dirents {
+        match dirent {
+            Ok(dirent) => names.push(dirent.file_name()),
+            Err(error) => return Err(Error::io_with_path(error, path)),
+        }
+    }
+
+    Ok(names)
+}
+
+/// Whether `name` is the _dir.vpk file or an archive of the package `prefix`.
+fn is_package_file(name: &OsStr, prefix: &str) -> bool {
+    let name = match name.to_str().and_then(|name| name.strip_prefix(prefix)) {
+        Some(name) => name,
+        None => return false,
+    };
+
+    if name == "_dir.vpk" {
+        return true;
+    }
+
+    match name.strip_prefix('_').and_then(|name| name.strip_suffix(".vpk")) {
+        Some(digits) => digits.len() >= 3 && digits.bytes().all(|byte| byte.is_ascii_digit()),
+        None => false,
+    }
+}
+
+/// Undo a failed [`move_package`]: remove the files that were already
+/// moved and move the files of the old package back.
+fn restore_package(dirpath: &Path, backup: &Path, backed_up: &[OsString], moved: &[OsString]) {
+    for name in moved {
+        let _ = fs::remove_file(dirpath.join(name));
+    }
+    for name in backed_up {
+        let _ = fs::rename(backup.join(name), dirpath.join(name));
+    }
+    let _ = fs::remove_dir(backup);
+}
+
+/// Move all files of a package written to `tempdir` into `dirpath`. The
+/// files of an existing package with the same prefix are moved into a
+/// backup directory first and restored if moving any file fails. Archives
+/// of the old package that the new one doesn't have are removed. The
+/// _dir.vpk file is moved last, so the package only appears after all of
+/// its archives are in place. Files are moved using `move_file`.
+fn move_package(tempdir: &Path, dirpath: &Path, prefix: &str, dirvpk_name: &OsStr, mut move_file: impl FnMut(&Path, &Path) -> Result<()>) -> Result<()> {
+    let mut names = dir_names(tempdir)?;
+    names.sort_by_key(|name| name == dirvpk_name);
+
+    let old_names: Vec<OsString> = dir_names(dirpath)?
+        .into_iter()
+        .filter(|name| is_package_file(name, prefix))
+        .collect();
+
+    let backup = dirpath.join(format!(".{}.rvpk-backup-{}", prefix, std::process::id()));
+    if !old_names.is_empty() {
+        if let Err(error) = fs::create_dir(&backup) {
+            return Err(Error::io_with_path(error, dirpath));
+        }
+    }
+
+    let mut backed_up = Vec::new();
+    for name in &old_names {
+        if let Err(error) = fs::rename(dirpath.join(name), backup.join(name)) {
+            restore_package(dirpath, &backup, &backed_up, &[]);
+            return Err(Error::io_with_path(error, dirpath.join(name)));
+        }
+        backed_up.push(name.clone());
+    }
+
+    let mut moved = Vec::new();
+    for name in &names {
+        if let Err(error) = move_file(&tempdir.join(name), &dirpath.join(name)) {
+            restore_package(dirpath, &backup, &backed_up, &moved);
+            return Err(error);
+        }
+        moved.push(name.clone());
+    }
+
+    if !old_names.is_empty() {
+        // the new package is in place, failing to clean up doesn't change that
+        let _ = fs::remove_dir_all(&backup);
+    }
+
+    if let Err(error) = fs::remove_dir(tempdir) {
+        return Err(Error::io_with_path(error, tempdir));
     }
 
-    Ok(*hasher.compute())
+    Ok(())
 }
 
 // TODO: more grouping/file order options?
+/// Pack the files of `indir` into a new package. The package is written to
+/// a temporary directory first (see [`PackOptions::temp_dir`]) and only moved
+/// into place once it is complete. An existing package with the same name is
+/// replaced as a whole, including removing archives the new package doesn't
+/// need. If moving the new files fails the old files are restored, so a
+/// failed pack doesn't leave a mix of old and new files behind. Only if the
+/// process is killed while moving, the old files are left in a
+/// `.PREFIX.rvpk-backup-PID` directory next to the package.
 pub fn pack(dirvpk_path: impl AsRef<Path>, indir: impl AsRef<Path>, options: PackOptions) -> Result<Package> {
+    options.validate()?;
+
+    let dirvpk_path = dirvpk_path.as_ref();
+    let (dirpath, prefix) = parse_path(dirvpk_path)?;
+    let dirvpk_name = dirvpk_path.file_name().unwrap().to_owned();
+
+    let tempdir = create_temp_dir(options.temp_dir.as_deref().unwrap_or(&dirpath), &prefix)?;
+
+    let mut package = match pack_files(tempdir.join(&dirvpk_name), indir, options) {
+        Ok(package) => package,
+        Err(error) => {
+            let _ = fs::remove_dir_all(&tempdir);
+            return Err(error);
+        }
+    };
+
+    if let Err(error) = move_package(&tempdir, &dirpath, &prefix, &dirvpk_name, move_file) {
+        let _ = fs::remove_dir_all(&tempdir);
+        return Err(error);
+    }
+
+    package.dirpath = dirpath;
+
+    Ok(package)
+}
+
+fn pack_files(dirvpk_path: impl AsRef<Path>, indir: impl AsRef<Path>, options: PackOptions) -> Result<Package> {
     let header_size = match options.version {
         0 => 0,
         1 => V1_HEADER_SIZE,
@@ -427,84 +1631,210 @@ pub fn pack(dirvpk_path: impl AsRef<Path>, indir: impl AsRef<Path>, options: Pac
         _ => return Err(Error::unsupported_version(options.version)),
     };
 
+    if !options.alignment.is_power_of_two() && !options.quiet {
+        eprintln!("WARNING: alignment is not a power of two: {}", options.alignment);
+    }
+
     let (dirpath, prefix) = parse_path(dirvpk_path.as_ref())?;
 
     let mut entries = HashMap::new();
-    let mut gather = Gather::new(options.max_inline_size, options.verbose);
-
-    if options.verbose {
-        println!("scanning {:?}", indir.as_ref());
-    }
+    let mut gather = Gather::new(options.max_inline_size, options.buffer_size, options.verbose);
+    gather.dedup = options.dedup;
+    gather.since = options.since;
+    gather.symlinks = options.symlinks;
+    gather.strict = options.strict;
+    gather.quiet = options.quiet;
+    gather.keep_going = options.keep_going;
+    gather.digest = options.crc.digest();
+
+    if let Some(files) = &options.files {
+        let archive_from_dirname = matches!(options.strategy, ArchiveStrategy::ArchiveFromDirName);
+        gather.gather_file_list(&mut entries, indir.as_ref(), files, archive_from_dirname)?;
+    } else {
+        if options.verbose {
+            println!("scanning {:?}", indir.as_ref());
+        }
 
-    match options.strategy {
-        ArchiveStrategy::ArchiveFromDirName => {
-            let dirents = match read_dir(indir.as_ref()) {
-                Ok(dirents) => dirents,
-                Err(error) => return Err(Error::io_with_path(error, dirpath)),
-            };
-            for dirent in dirents {
-                let dirent = match dirent {
-                    Ok(dirent) => dirent,
+        match options.strategy {
+            ArchiveStrategy::ArchiveFromDirName => {
+                let dirents = match read_dir(indir.as_ref()) {
+                    Ok(dirents) => dirents,
                     Err(error) => return Err(Error::io_with_path(error, dirpath)),
                 };
-                let file_type = match dirent.file_type() {
-                    Ok(file_type) => file_type,
-                    Err(error) => return Err(Error::io_with_path(error, dirent.path())),
-                };
-                if file_type.is_dir() {
-                    if let Some(name) = dirent.file_name().to_str() {
-                        if name == "dir" {
-                            gather.inline = false;
-                            gather.gather_files(&mut entries, DIR_INDEX, &dirent.path(), true)?;
-                        } else if name == "inline" {
-                            gather.inline = true;
-                            gather.gather_files(&mut entries, DIR_INDEX, &dirent.path(), true)?;
-                        } else if name.len() != 3 {
-                            eprintln!("WARNING: directory name is neither a 3 digit number, \"dir\", nor \"inline\": {:?}", dirent.path());
-                        } else if let Ok(archive_index) = name.parse::<u16>() {
-                            if archive_index <= 999 {
+                for dirent in dirents {
+                    let dirent = match dirent {
+                        Ok(dirent) => dirent,
+                        Err(error) => return Err(Error::io_with_path(error, dirpath)),
+                    };
+                    let file_type = match dirent.file_type() {
+                        Ok(file_type) => file_type,
+                        Err(error) => return Err(Error::io_with_path(error, dirent.path())),
+                    };
+                    if file_type.is_dir() {
+                        if let Some(name) = dirent.file_name().to_str() {
+                            if name == "dir" {
                                 gather.inline = false;
-                                gather.gather_files(&mut entries, archive_index, &dirent.path(), true)?;
-                            } else {
-                                eprintln!("WARNING: directory name represents a too large number for an archive index: {:?}", dirent.path());
+                                gather.keep_archive = true;
+                                gather.gather_files(&mut entries, DIR_INDEX, &dirent.path(), true)?;
+                            } else if name == "inline" {
+                                gather.inline = true;
+                                gather.keep_archive = false;
+                                gather.gather_files(&mut entries, DIR_INDEX, &dirent.path(), true)?;
+                            } else if name.len() != 3 {
+                                if !options.quiet {
+                                    eprintln!("WARNING: directory name is neither a 3 digit number, \"dir\", nor \"inline\": {:?}", dirent.path());
+                                }
+                            } else if let Ok(archive_index) = name.parse::<u16>() {
+                                if archive_index <= 999 {
+                                    gather.inline = false;
+                                    gather.keep_archive = true;
+                                    gather.gather_files(&mut entries, archive_index, &dirent.path(), true)?;
+                                } else if !options.quiet {
+                                    eprintln!("WARNING: directory name represents a too large number for an archive index: {:?}", dirent.path());
+                                }
+                            } else if !options.quiet {
+                                eprintln!("WARNING: directory name is neither a 3 digit number, \"dir\", nor \"inline\": {:?}", dirent.path());
                             }
-                        } else {
-                            eprintln!("WARNING: directory name is neither a 3 digit number, \"dir\", nor \"inline\": {:?}", dirent.path());
                         }
                     }
                 }
+            },
+            ArchiveStrategy::MaxArchiveSize(_) | ArchiveStrategy::SingleArchive |
+            ArchiveStrategy::NumArchives(_) | ArchiveStrategy::BalancedCount(_) => {
+                if let Some(base) = &options.relative_to {
+                    let names = relative_dirnames(base, indir.as_ref())?;
+                    if names.is_empty() {
+                        gather.gather_files(&mut entries, DIR_INDEX, indir.as_ref(), true)?;
+                    } else {
+                        let mut children = &mut entries;
+                        for name in names {
+                            let entry = children.entry(name).or_insert_with(|| Entry::Dir(Dir {
+                                children: HashMap::new()
+                            }));
+                            children = match entry {
+                                Entry::Dir(dir) => &mut dir.children,
+                                Entry::File(_) => unreachable!(),
+                            };
+                        }
+                        gather.gather_files(children, DIR_INDEX, indir.as_ref(), false)?;
+                    }
+                } else {
+                    gather.gather_files(&mut entries, DIR_INDEX, indir.as_ref(), true)?;
+                }
             }
-        },
-        ArchiveStrategy::MaxArchiveSize(_) => {
-            gather.gather_files(&mut entries, DIR_INDEX, indir.as_ref(), true)?;
         }
     }
 
+    // the data of the files is read relative to the same directory as
+    // their paths inside of the package are
+    let indir = match &options.relative_to {
+        Some(base) => base.to_path_buf(),
+        None => indir.as_ref().to_path_buf(),
+    };
+
     if options.verbose {
         println!("calculating index size... ");
         let _ = std::io::stdout().flush();
     }
 
+    let name_case = NameCase {
+        lowercase_paths: options.lowercase_paths,
+        normalize_ext:   options.normalize_ext,
+    };
+
+    let mut pathbuf = String::new();
+    let mut list = Vec::new();
+    recursive_file_list(&mut entries, name_case, &mut pathbuf, &mut list);
+    list.sort_by(|a, b| a.path.cmp(&b.path));
+
+    if !options.replace.is_empty() {
+        for item in &mut list {
+            replace_path(item, &options.replace)?;
+        }
+    }
+
+    if !name_case.is_identity() || !options.replace.is_empty() {
+        // normalized or rewritten paths of different files might be the same
+        let mut index_paths: HashMap<&str, &str> = HashMap::with_capacity(list.len());
+        for item in &list {
+            if let Some(other) = index_paths.insert(&item.index_path, &item.path) {
+                return Err(Error::other(format!(
+                    "{:?} and {:?} would have the same path in the index: {:?}",
+                    other, item.path, item.index_path)));
+            }
+        }
+
+        for item in &list {
+            for (slash_index, _) in item.index_path.match_indices('/') {
+                if let Some(other) = index_paths.get(&item.index_path[..slash_index]) {
+                    return Err(Error::other(format!(
+                        "{:?} would be a file and a directory of {:?} in the index: {:?}",
+                        other, item.path, &item.index_path[..slash_index])));
+                }
+            }
+        }
+    }
+
     let mut index_size = 0usize;
 
+    // Some engines don't distinguish extensions by case, so e.g. files in
+    // a "VMT" and a "vmt" group might shadow each other.
+    let exts: HashSet<String> = if options.normalize_ext {
+        gather.exts.iter().map(|ext| ext.to_lowercase()).collect()
+    } else {
+        if !options.quiet {
+            let mut groups: HashMap<String, Vec<&str>> = HashMap::new();
+            for ext in &gather.exts {
+                groups.entry(ext.to_lowercase()).or_default().push(ext);
+            }
+            let mut groups: Vec<_> = groups.into_values().filter(|group| group.len() > 1).collect();
+            groups.sort();
+            for mut group in groups {
+                group.sort_unstable();
+                eprintln!("WARNING: file extensions differ only in case: {}. \
+                           Some engines treat them as the same, consider --normalize-ext.",
+                    group.join(", "));
+            }
+        }
+        gather.exts.clone()
+    };
+
+    // rewritten paths might have different extensions
+    let exts = if options.replace.is_empty() {
+        exts
+    } else {
+        list.iter().map(|item| item.ext().to_owned()).collect()
+    };
+
     // group files by extension and dir, for writing the index
     let mut extmap: HashMap<&str, HashMap<&str, Vec<&Item>>> =
-        HashMap::with_capacity(gather.exts.len());
+        HashMap::with_capacity(exts.len());
 
     let mut sizemap: HashMap<&str, HashSet<&str>> =
-        HashMap::with_capacity(gather.exts.len());
+        HashMap::with_capacity(exts.len());
 
-    for ext in &gather.exts {
+    for ext in &exts {
         extmap.insert(ext, HashMap::new());
         sizemap.insert(ext, HashSet::new());
         index_size += ext.len() + 1 + 1;
     }
     index_size += 1;
 
-    let mut pathbuf = String::new();
-    let mut list = Vec::new();
-    recursive_file_list(&mut entries, &mut pathbuf, &mut list);
-    list.sort_by(|a, b| a.path.cmp(&b.path));
+    if let Some(data_order) = &options.data_order {
+        let mut ranks = HashMap::with_capacity(data_order.len());
+        for (rank, path) in data_order.iter().enumerate() {
+            ranks.entry(path.as_str()).or_insert(rank);
+        }
+
+        for path in data_order {
+            if !options.quiet && list.binary_search_by(|item| item.path.as_str().cmp(path)).is_err() {
+                eprintln!("WARNING: file listed in data order is not in the package: {:?}", path);
+            }
+        }
+
+        // stable sort, so files not listed stay sorted by path
+        list.sort_by_key(|item| ranks.get(item.path.as_str()).copied().unwrap_or(usize::MAX));
+    }
 
     for item in &list {
         let extname  = item.ext();
@@ -525,37 +1855,90 @@ pub fn pack(dirvpk_path: impl AsRef<Path>, indir: impl AsRef<Path>, options: Pac
 
     let index_size = index_size;
 
-    if index_size > std::i32::MAX as usize {
+    if index_size > i32::MAX as usize {
         return Err(Error::other(format!(
                 "index too large: {} > {}",
-                index_size, std::i32::MAX)).
+                index_size, i32::MAX)).
             with_path(dirvpk_path));
     }
 
+    if !options.quiet {
+        let inline_size: u64 = list.iter().map(|item| item.file.inline_size as u64).sum();
+        if let Some(warning) = index_size_warning(index_size, inline_size) {
+            eprintln!("WARNING: {}", warning);
+        }
+    }
+
     let dir_size = header_size + index_size;
     let index_size = index_size as u32;
 
+    if !options.quiet && options.max_inline_size > DEFAULT_MAX_INLINE_SIZE && options.index_warning_ratio > 0.0 {
+        let data_size: u64 = list.iter().map(|item| item.file.size as u64).sum();
+        let package_size = dir_size as u64 + data_size;
+        let ratio = dir_size as f64 / package_size as f64;
+
+        if ratio > options.index_warning_ratio {
+            let inline_size: u64 = list.iter().map(|item| item.file.inline_size as u64).sum();
+            eprintln!(
+                "WARNING: the index is {:.1} % of the package size, because {} bytes of file data are inlined. \
+                 This makes reading the index slow, consider a --max-inline-size of {} or lower.",
+                ratio * 100.0, inline_size, DEFAULT_MAX_INLINE_SIZE);
+        }
+    }
+
     if options.verbose {
         println!("distributing files to archives...");
     }
+    let mut dedup = if options.dedup {
+        Some(Dedup::new(options.buffer_size))
+    } else {
+        None
+    };
+
+    // in data mode the offsets of data in the _dir.vpk are aligned as stored
+    // in the index, which is relative to the end of the index
+    let align_base = |archive_index: u16| -> usize {
+        if archive_index == DIR_INDEX && options.align_mode == AlignMode::Data {
+            dir_size
+        } else {
+            0
+        }
+    };
+
     let mut data_end_offset = dir_size as u64;
     match options.strategy {
-        ArchiveStrategy::MaxArchiveSize(max_size) => {
+        ArchiveStrategy::MaxArchiveSize(_) | ArchiveStrategy::SingleArchive => {
             // distribute files to archives
+            let single_archive = matches!(options.strategy, ArchiveStrategy::SingleArchive);
+            let max_size = match options.strategy {
+                ArchiveStrategy::MaxArchiveSize(max_size) => max_size,
+                _ => u32::MAX,
+            };
 
             let mut archive_index = DIR_INDEX;
             let mut archive_size = dir_size;
 
             for item in list.iter_mut() {
                 if item.file.size > 0 {
-                    let remainder = archive_size % options.alignment;
-                    if remainder != 0 {
-                        archive_size += options.alignment - remainder;
+                    let mut fs_path = None;
+                    if let Some(dedup) = &mut dedup {
+                        let path = source_path(indir.as_ref(), &options.strategy, item.file.archive_index, &item.path);
+                        if dedup.find(item, &path)? {
+                            continue;
+                        }
+                        fs_path = Some(path);
                     }
 
+                    let base = align_base(archive_index);
+                    archive_size = base + align_up(archive_size - base, options.alignment);
+
                     let new_archive_size = archive_size + item.file.size as usize;
                     if new_archive_size > max_size as usize {
-                        if archive_index == DIR_INDEX {
+                        if single_archive {
+                            return Err(Error::other(format!(
+                                "package too big for a single archive: {:?} would end at offset {}",
+                                item.path, new_archive_size)));
+                        } else if archive_index == DIR_INDEX {
                             data_end_offset = archive_size as u64;
                             archive_index = 0;
                         } else if archive_index == 999 {
@@ -570,6 +1953,10 @@ pub fn pack(dirvpk_path: impl AsRef<Path>, indir: impl AsRef<Path>, options: Pac
                         archive_size = new_archive_size;
                     }
                     item.file.archive_index = archive_index;
+
+                    if let (Some(dedup), Some(fs_path)) = (&mut dedup, fs_path) {
+                        dedup.insert(item.file, fs_path);
+                    }
                 }
             }
 
@@ -583,74 +1970,113 @@ pub fn pack(dirvpk_path: impl AsRef<Path>, indir: impl AsRef<Path>, options: Pac
 
             for item in list.iter_mut() {
                 if item.file.size > 0 {
-                    if !archmap.contains_key(&item.file.archive_index) {
-                        archmap.insert(item.file.archive_index, 0);
+                    let mut fs_path = None;
+                    if let Some(dedup) = &mut dedup {
+                        let path = source_path(indir.as_ref(), &options.strategy, item.file.archive_index, &item.path);
+                        if dedup.find(item, &path)? {
+                            continue;
+                        }
+                        fs_path = Some(path);
+                    }
+
+                    if !archmap.contains_key(&item.file.archive_index) {
+                        archmap.insert(item.file.archive_index, 0);
+                    }
+                    let archive_size = archmap.get_mut(&item.file.archive_index).unwrap();
+                    let base = align_base(item.file.archive_index);
+                    *archive_size = base + align_up(*archive_size - base, options.alignment);
+                    item.file.offset = *archive_size as u32;
+                    *archive_size += item.file.size as usize;
+
+                    if let (Some(dedup), Some(fs_path)) = (&mut dedup, fs_path) {
+                        dedup.insert(item.file, fs_path);
+                    }
+                }
+            }
+
+            data_end_offset = *archmap.get(&DIR_INDEX).unwrap() as u64;
+        },
+        ArchiveStrategy::NumArchives(count) | ArchiveStrategy::BalancedCount(count) => {
+            // files are distributed in path order, the _dir.vpk only holds the index
+            let by_count = matches!(options.strategy, ArchiveStrategy::BalancedCount(_));
+            let mut archive_sizes = vec![0usize; count as usize];
+            let mut file_count = 0usize;
+
+            for item in list.iter_mut() {
+                if item.file.size > 0 {
+                    let mut fs_path = None;
+                    if let Some(dedup) = &mut dedup {
+                        let path = source_path(indir.as_ref(), &options.strategy, item.file.archive_index, &item.path);
+                        if dedup.find(item, &path)? {
+                            continue;
+                        }
+                        fs_path = Some(path);
                     }
-                    let archive_size = archmap.get_mut(&item.file.archive_index).unwrap();
-                    let remainder = *archive_size % options.alignment;
-                    if remainder != 0 {
-                        *archive_size += options.alignment - remainder;
+
+                    let archive_index = if by_count {
+                        file_count % archive_sizes.len()
+                    } else {
+                        (0..archive_sizes.len())
+                            .min_by_key(|index| archive_sizes[*index])
+                            .unwrap()
+                    };
+                    file_count += 1;
+                    let archive_size = &mut archive_sizes[archive_index];
+                    *archive_size = align_up(*archive_size, options.alignment);
+
+                    if *archive_size + item.file.size as usize > u32::MAX as usize {
+                        return Err(Error::other(format!(
+                            "archive {:03} too big for {:?}, use more archives",
+                            archive_index, item.path)));
                     }
+
+                    item.file.archive_index = archive_index as u16;
                     item.file.offset = *archive_size as u32;
                     *archive_size += item.file.size as usize;
+
+                    if let (Some(dedup), Some(fs_path)) = (&mut dedup, fs_path) {
+                        dedup.insert(item.file, fs_path);
+                    }
                 }
             }
+        }
+    }
 
-            data_end_offset = *archmap.get(&DIR_INDEX).unwrap() as u64;
+    if let Some(dedup) = &dedup {
+        if options.verbose {
+            println!("deduplication saved {} bytes", dedup.saved_size);
         }
     }
 
-    // group all of the above also per archive, for writing the data
-    let mut archmap: HashMap<u16, Vec<(&str, &File)>> =
+    // group files per archive, for writing the data
+    let mut archmap: HashMap<u16, Vec<usize>> =
         HashMap::new();
 
-    for item in &list {
-        let extname = item.ext();
-        let dirname = item.dir();
-
-        let dirmap = extmap.get_mut(extname).unwrap();
-
-        if !dirmap.contains_key(dirname) {
-            dirmap.insert(dirname, Vec::new());
+    for (index, item) in list.iter().enumerate() {
+        // Empty and fully inlined files have no archive data. They must not
+        // create (empty) archives just because of their archive index.
+        if item.file.size == 0 {
+            continue;
         }
-
-        let filelist = dirmap.get_mut(dirname).unwrap();
-        filelist.push(item);
-
-        // group archives
         if !archmap.contains_key(&item.file.archive_index) {
             archmap.insert(item.file.archive_index, Vec::new());
         }
         let sublist = archmap.get_mut(&item.file.archive_index).unwrap();
-        sublist.push((&item.path, item.file));
+        sublist.push(index);
     }
 
-    if options.verbose {
-        println!("writing index to file: {:?}", dirvpk_path.as_ref());
+    if options.verify_preload {
+        if options.verbose {
+            println!("verifying inlined data...");
+        }
+        verify_preloads(&indir, &options.strategy, &list, &mut gather.buf)?;
     }
 
-    let mut dirwriter = match write_dir(
-            &extmap,
-            dirvpk_path.as_ref(),
-            options.version,
-            dir_size as u32,
-            index_size) {
+    let mut dirwriter = match fs::File::create(dirvpk_path.as_ref()) {
         Ok(dirwriter) => dirwriter,
         Err(error) => return Err(Error::io_with_path(error, dirvpk_path)),
     };
 
-    let actual_dir_size = match dirwriter.seek(SeekFrom::Current(0)) {
-        Ok(offset) => offset,
-        Err(error) => return Err(Error::io_with_path(error, dirvpk_path)),
-    };
-
-    if actual_dir_size != dir_size as u64 {
-        return Err(Error::other(format!(
-                "internal error: actual_dir_size {} != dir_size {}",
-                actual_dir_size, dir_size)).
-            with_path(dirvpk_path));
-    }
-
     enum SelectFile<'a> {
         Referenced(&'a mut fs::File),
         Contained(fs::File),
@@ -666,7 +2092,9 @@ pub fn pack(dirvpk_path: impl AsRef<Path>, indir: impl AsRef<Path>, options: Pac
         }
     }
 
-    for (archive_index, files) in &archmap {
+    // The data is written before the index, because the CRC32 sums of the
+    // files are only known after their data is copied.
+    for (archive_index, indices) in &archmap {
         let archive_index = *archive_index;
         let archpath = archive_path(&dirpath, &prefix, archive_index);
 
@@ -682,7 +2110,15 @@ pub fn pack(dirvpk_path: impl AsRef<Path>, indir: impl AsRef<Path>, options: Pac
         };
         let writer = writer.get();
 
-        for (vpk_path, file) in files {
+        for index in indices {
+            let item = &mut list[*index];
+            if item.duplicate {
+                continue;
+            }
+
+            let vpk_path = &item.path;
+            let file = &mut *item.file;
+
             if options.verbose {
                 if archive_index == DIR_INDEX {
                     println!("writing {:>10} bytes at offset {:>10} to {}_dir.vpk: {:?}",
@@ -694,19 +2130,7 @@ pub fn pack(dirvpk_path: impl AsRef<Path>, indir: impl AsRef<Path>, options: Pac
             }
 
             if file.size > 0 {
-                let mut fs_path = indir.as_ref().to_path_buf();
-
-                if let ArchiveStrategy::ArchiveFromDirName = options.strategy {
-                    if archive_index == DIR_INDEX {
-                        fs_path.push("dir");
-                    } else {
-                        fs_path.push(format!("{:03}", archive_index));
-                    }
-                }
-
-                for (_, item, _) in split_path(vpk_path) {
-                    fs_path.push(item);
-                }
+                let fs_path = source_path(indir.as_ref(), &options.strategy, archive_index, vpk_path);
 
                 if let Err(error) = writer.seek(SeekFrom::Start(file.offset as u64)) {
                     return Err(Error::io_with_path(error, archpath));
@@ -720,8 +2144,9 @@ pub fn pack(dirvpk_path: impl AsRef<Path>, indir: impl AsRef<Path>, options: Pac
                             }
                         }
 
-                        if let Err(error) = transfer(&mut reader, writer, file.size as usize) {
-                            return Err(Error::io_with_path(error, fs_path));
+                        match copy_with_crc32(&mut reader, writer, file.size as usize, &mut gather.buf, &mut gather.digest) {
+                            Ok(crc32) => file.crc32 = crc32,
+                            Err(error) => return Err(Error::io_with_path(error, fs_path)),
                         }
                     },
                     Err(error) => {
@@ -732,6 +2157,51 @@ pub fn pack(dirvpk_path: impl AsRef<Path>, indir: impl AsRef<Path>, options: Pac
         }
     }
 
+    if options.data_order.is_some() {
+        // the index is still written sorted by path
+        list.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+
+    // group files by extension and dir, for writing the index
+    for item in &list {
+        let extname = item.ext();
+        let dirname = item.dir();
+
+        let dirmap = extmap.get_mut(extname).unwrap();
+
+        if !dirmap.contains_key(dirname) {
+            dirmap.insert(dirname, Vec::new());
+        }
+
+        let filelist = dirmap.get_mut(dirname).unwrap();
+        filelist.push(item);
+    }
+
+    if options.verbose {
+        println!("writing index to file: {:?}", dirvpk_path.as_ref());
+    }
+
+    if let Err(error) = write_dir(
+            &extmap,
+            &mut dirwriter,
+            options.version,
+            dir_size as u32,
+            index_size) {
+        return Err(Error::io_with_path(error, dirvpk_path));
+    }
+
+    let actual_dir_size = match dirwriter.seek(SeekFrom::Current(0)) {
+        Ok(offset) => offset,
+        Err(error) => return Err(Error::io_with_path(error, dirvpk_path)),
+    };
+
+    if actual_dir_size != dir_size as u64 {
+        return Err(Error::other(format!(
+                "internal error: actual_dir_size {} != dir_size {}",
+                actual_dir_size, dir_size)).
+            with_path(dirvpk_path));
+    }
+
     let data_offset = dir_size as u32;
     let data_size   = (data_end_offset - data_offset as u64) as u32;
     let signature_size = 0;
@@ -752,8 +2222,8 @@ pub fn pack(dirvpk_path: impl AsRef<Path>, indir: impl AsRef<Path>, options: Pac
         everything_md5   = [0; 16];
     } else {
         other_md5_size   = 16 * 3;
-        let mut buf = Vec::with_capacity(options.md5_chunk_size as usize);
-        buf.resize(options.md5_chunk_size as usize, 0);
+        let md5_chunk_size = options.md5_chunk_size.unwrap_or(DEFAULT_MD5_CHUNK_SIZE);
+        let mut buf = vec![0; md5_chunk_size as usize];
 
         let mut dirreader = match fs::File::open(&dirvpk_path) {
             Ok(file) => file,
@@ -799,7 +2269,7 @@ pub fn pack(dirvpk_path: impl AsRef<Path>, indir: impl AsRef<Path>, options: Pac
                 return Err(Error::io_with_path(error, archpath));
             }
 
-            while remaining >= options.md5_chunk_size {
+            while remaining >= md5_chunk_size {
                 if let Err(error) = file.read_exact(&mut buf) {
                     return Err(Error::io_with_path(error, archpath));
                 }
@@ -808,12 +2278,12 @@ pub fn pack(dirvpk_path: impl AsRef<Path>, indir: impl AsRef<Path>, options: Pac
                 archive_md5s.push(ArchiveMd5 {
                     archive_index,
                     offset,
-                    size: options.md5_chunk_size,
+                    size: md5_chunk_size,
                     md5,
                 });
 
-                offset    += options.md5_chunk_size;
-                remaining -= options.md5_chunk_size;
+                offset    += md5_chunk_size;
+                remaining -= md5_chunk_size;
             }
 
             if remaining > 0 {
@@ -859,7 +2329,7 @@ pub fn pack(dirvpk_path: impl AsRef<Path>, indir: impl AsRef<Path>, options: Pac
         }
 
         let buf = &mut gather.buf[..];
-        index_md5 = match calculate_md5(&mut dirreader, buf, V2_HEADER_SIZE as u64, index_size as u64) {
+        index_md5 = match md5_of_range(&mut dirreader, buf, V2_HEADER_SIZE as u64..V2_HEADER_SIZE as u64 + index_size as u64) {
             Ok(md5) => md5,
             Err(error) => return Err(Error::io_with_path(error, dirvpk_path)),
         };
@@ -868,7 +2338,7 @@ pub fn pack(dirvpk_path: impl AsRef<Path>, indir: impl AsRef<Path>, options: Pac
             println!("calculating MD5 sum section MD5 sum...");
         }
 
-        archive_md5s_md5 = match calculate_md5(&mut dirreader, buf, data_end_offset, archive_md5_size as u64) {
+        archive_md5s_md5 = match md5_of_range(&mut dirreader, buf, data_end_offset..data_end_offset + archive_md5_size as u64) {
             Ok(md5) => md5,
             Err(error) => return Err(Error::io_with_path(error, dirvpk_path)),
         };
@@ -901,7 +2371,7 @@ pub fn pack(dirvpk_path: impl AsRef<Path>, indir: impl AsRef<Path>, options: Pac
             println!("calculating MD5 sum of everything above...");
         }
 
-        everything_md5 = match calculate_md5(&mut dirreader, buf, 0, data_end_offset + archive_md5_size as u64 + 16 * 2) {
+        everything_md5 = match md5_of_range(&mut dirreader, buf, 0..data_end_offset + archive_md5_size as u64 + 16 * 2) {
             Ok(md5) => md5,
             Err(error) => return Err(Error::io_with_path(error, dirvpk_path)),
         };
@@ -918,15 +2388,55 @@ pub fn pack(dirvpk_path: impl AsRef<Path>, indir: impl AsRef<Path>, options: Pac
         if let Err(error) = writer.write_all(&everything_md5) {
             return Err(Error::io_with_path(error, dirvpk_path));
         }
+
+        if let Err(error) = writer.flush() {
+            return Err(Error::io_with_path(error, dirvpk_path));
+        }
+
+        if options.verify {
+            if options.verbose {
+                println!("verifying written header and MD5 sums...");
+            }
+
+            verify_v2(dirvpk_path.as_ref(), buf, &WrittenV2 {
+                index_size,
+                data_size,
+                archive_md5_size,
+                other_md5_size,
+                signature_size,
+                index_md5,
+                archive_md5s_md5,
+                everything_md5,
+            })?;
+        }
     }
 
-    if options.verbose {
-        println!("done");
+    let summary = if options.quiet {
+        Vec::new()
+    } else {
+        archive_summary(&dirpath, &prefix, &archmap, &list)?
+    };
+
+    if !options.replace.is_empty() {
+        // so the returned package has the paths that are in the index
+        let index_paths: HashMap<String, String> = list.into_iter()
+            .map(|item| (item.path, item.index_path))
+            .collect();
+        let mut moved = HashMap::with_capacity(entries.len());
+        move_files(entries, &index_paths, &mut pathbuf, &mut moved);
+        entries = moved;
+    } else if !name_case.is_identity() {
+        // so the returned package has the paths that are in the index
+        entries = normalize_names(entries, name_case);
     }
 
-    Ok(Package {
+    let (file_count, total_size, total_data_size) = sum_files(&entries);
+
+    let package = Package {
         dirpath,
         prefix,
+        archive_dirpath: None,
+        truncated_at: None,
         version: options.version,
         data_offset,
         index_size,
@@ -935,6 +2445,10 @@ pub fn pack(dirvpk_path: impl AsRef<Path>, indir: impl AsRef<Path>, options: Pac
         other_md5_size,
         signature_size,
         entries,
+        file_count,
+        total_size,
+        total_data_size,
+        duplicates: Vec::new(),
 
         // VPK 2
         archive_md5s,
@@ -943,5 +2457,892 @@ pub fn pack(dirvpk_path: impl AsRef<Path>, indir: impl AsRef<Path>, options: Pac
         everything_md5,
         public_key: Vec::new(),
         signature:  Vec::new(),
-    })
+    };
+
+    if let Some(algorithm) = options.sidecar_hash {
+        if options.verbose {
+            println!("writing sidecar file: {:?}", sidecar_path(&package, algorithm));
+        }
+        write_sidecar(&package, algorithm, options.buffer_size)?;
+    }
+
+    if !options.quiet {
+        print_table(
+            &["Archive", "Files", "Data Size", "File Size"],
+            &[Right,     Right,   Right,       Right],
+            &summary);
+    }
+
+    if gather.skipped_count > 0 && !options.quiet {
+        eprintln!("WARNING: skipped {} unreadable file(s)", gather.skipped_count);
+    }
+
+    if options.verbose {
+        println!("done");
+    }
+
+    Ok(package)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::time::{Duration, UNIX_EPOCH};
+    use crate::util::TempDir;
+    use crate::archive_cache::ArchiveCache;
+
+    /// Names and contents of the files of `dir`, except hidden ones.
+    fn dir_contents(dir: &Path) -> Vec<(OsString, Vec<u8>)> {
+        let mut contents: Vec<_> = dir_names(dir).unwrap().into_iter()
+            .filter(|name| !name.to_str().unwrap().starts_with('.'))
+            .map(|name| {
+                let data = fs::read(dir.join(&name)).unwrap();
+                (name, data)
+            })
+            .collect();
+        contents.sort();
+        contents
+    }
+
+    /// Counts the bytes read through it.
+    struct CountingReader<R> {
+        inner: R,
+        count: usize,
+    }
+
+    impl<R: Read> Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let count = self.inner.read(buf)?;
+            self.count += count;
+            Ok(count)
+        }
+    }
+
+    #[test]
+    fn copy_with_crc32_reads_once() {
+        let data: Vec<u8> = (0..1003u32).map(|index| (index * 7) as u8).collect();
+        let mut digest = crc32::Digest::new(crc32::IEEE);
+
+        for &buffer_size in &[1, 7, 1000, 4096] {
+            let mut reader = CountingReader { inner: &data[..], count: 0 };
+            let mut writer = Vec::new();
+            let mut buf = vec![0; buffer_size];
+
+            let crc32 = copy_with_crc32(&mut reader, &mut writer, 1000, &mut buf, &mut digest).unwrap();
+            assert_eq!(crc32, crc32::checksum_ieee(&data[..1000]), "buffer size {}", buffer_size);
+            assert_eq!(writer, &data[..1000]);
+            assert_eq!(reader.count, 1000);
+        }
+
+        let mut reader = &data[..10];
+        assert!(copy_with_crc32(&mut reader, &mut std::io::sink(), 11, &mut [0; 8], &mut digest).is_err());
+    }
+
+    #[test]
+    fn packed_crc32s_match() {
+        let tmp = TempDir::new("packed-crc32s");
+        let big: Vec<u8> = (0..5000u32).map(|index| (index % 251) as u8).collect();
+        let files: &[(&str, &[u8])] = &[
+            ("in/m/empty.txt", b""),
+            ("in/m/small.txt", b"small"),
+            ("in/m/big.bin",   &big),
+            ("in/m/copy.bin",  &big),
+            ("in/s/other.bin", &big[..3333]),
+        ];
+        for (path, data) in files {
+            tmp.write(path, data);
+        }
+
+        for &dedup in &[false, true] {
+            let options = PackOptions::builder()
+                .max_inline_size(16)
+                .buffer_size(7)
+                .dedup(dedup)
+                .quiet(true)
+                .build().unwrap();
+            pack(tmp.path.join("out_dir.vpk"), tmp.path.join("in"), options).unwrap();
+            let package = Package::from_path(tmp.path.join("out_dir.vpk"), ReadOptions::new()).unwrap();
+
+            for (path, data) in files {
+                let file = package.get_file(&path["in/".len()..]).unwrap();
+                assert_eq!(file.crc32, crc32::checksum_ieee(data), "{} with dedup {}", path, dedup);
+            }
+        }
+    }
+
+    #[test]
+    fn dedup_shares_data() {
+        let tmp = TempDir::new("dedup");
+        tmp.write("in/a/one.bin",   &[1; 3000]);
+        tmp.write("in/b/two.bin",   &[1; 3000]);
+        tmp.write("in/c/three.bin", &[1; 3000]);
+        tmp.write("in/d/other.bin", &[2; 3000]);
+        tmp.write("in/d/prefix.bin", &[1; 2000]);
+
+        let options = PackOptions::builder()
+            .max_inline_size(0)
+            .strategy(ArchiveStrategy::MaxArchiveSize(100))
+            .dedup(true)
+            .quiet(true)
+            .build().unwrap();
+        pack(tmp.path.join("out_dir.vpk"), tmp.path.join("in"), options).unwrap();
+        let package = Package::from_path(tmp.path.join("out_dir.vpk"), ReadOptions::new()).unwrap();
+
+        let location = |path: &str| {
+            let file = package.get_file(path).unwrap();
+            (file.archive_index, file.offset, file.size)
+        };
+        assert_eq!(location("a/one.bin"), location("b/two.bin"));
+        assert_eq!(location("a/one.bin"), location("c/three.bin"));
+        assert_ne!(location("a/one.bin"), location("d/other.bin"));
+        assert_ne!(location("a/one.bin"), location("d/prefix.bin"));
+
+        // only one copy of the duplicated data is written
+        let written: u64 = package.archive_indices().into_iter()
+            .map(|index| fs::metadata(package.archive_path(index)).unwrap().len())
+            .sum();
+        assert_eq!(written, 3000 + 3000 + 2000);
+
+        let mut archs = ArchiveCache::for_package(&package);
+        for path in &["a/one.bin", "b/two.bin", "c/three.bin"] {
+            let file = package.get_file(path).unwrap();
+            assert_eq!(archs.read_file_bytes(file).unwrap(), vec![1; 3000]);
+        }
+    }
+
+    #[test]
+    fn data_order_from_manifest() {
+        let tmp = TempDir::new("data-order");
+        for path in &["m/a.bin", "m/b.bin", "s/y.bin", "s/z.bin"] {
+            tmp.write(&format!("in/{}", path), &[0x55; 1000]);
+        }
+        let manifest = tmp.write("order.txt", b"s/z.bin\n\n/m/b.bin/\nnot/there.bin\ns/z.bin\n");
+
+        let order = read_order_list(&manifest).unwrap();
+        assert_eq!(order, ["s/z.bin", "m/b.bin", "not/there.bin", "s/z.bin"]);
+
+        let options = PackOptions::builder()
+            .max_inline_size(0)
+            .data_order(Some(order))
+            .quiet(true)
+            .build().unwrap();
+        pack(tmp.path.join("out_dir.vpk"), tmp.path.join("in"), options).unwrap();
+        let package = Package::from_path(tmp.path.join("out_dir.vpk"), ReadOptions::new()).unwrap();
+
+        let files = package.recursive_file_list(&crate::sort::PHYSICAL_ORDER);
+        assert!(files.iter().all(|(_, file)| file.archive_index == files[0].1.archive_index));
+        let paths: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, ["s/z.bin", "m/b.bin", "m/a.bin", "s/y.bin"]);
+    }
+
+    #[test]
+    fn align_up_power_of_two() {
+        assert_eq!(align_up(0, 1), 0);
+        assert_eq!(align_up(5, 1), 5);
+        assert_eq!(align_up(0, 16), 0);
+        assert_eq!(align_up(1, 16), 16);
+        assert_eq!(align_up(16, 16), 16);
+        assert_eq!(align_up(17, 16), 32);
+        assert_eq!(align_up(4095, 4096), 4096);
+    }
+
+    #[test]
+    fn align_up_arbitrary() {
+        assert_eq!(align_up(0, 3), 0);
+        assert_eq!(align_up(1, 3), 3);
+        assert_eq!(align_up(3, 3), 3);
+        assert_eq!(align_up(4, 3), 6);
+        assert_eq!(align_up(99, 100), 100);
+        assert_eq!(align_up(101, 100), 200);
+        assert_eq!(align_up(1000, 24), 1008);
+    }
+
+    #[test]
+    fn align_mode_archive_and_data() {
+        let tmp = TempDir::new("align-mode");
+        tmp.write("in/m/a.bin", &[1; 33]);
+        tmp.write("in/m/b.bin", &[2; 71]);
+        tmp.write("in/m/c.bin", &[3; 5]);
+        tmp.write("in/big/d.bin", &[4; 600]);
+        tmp.write("in/big/e.bin", &[5; 300]);
+
+        // big enough that the index never is a multiple of it
+        let alignment = 1000;
+        let packed = |name: &str, align_mode: AlignMode| {
+            let options = PackOptions::builder()
+                .alignment(alignment)
+                .align_mode(align_mode)
+                .max_inline_size(0)
+                .quiet(true)
+                .build().unwrap();
+            pack(tmp.path.join(name), tmp.path.join("in"), options).unwrap();
+            Package::from_path(tmp.path.join(name), ReadOptions::new()).unwrap()
+        };
+
+        let package = packed("archive_dir.vpk", AlignMode::Archive);
+        assert_ne!(package.data_offset() as usize % alignment, 0);
+        for (path, file) in package.recursive_file_list(&[]) {
+            assert_eq!(file.archive_index, DIR_INDEX, "{}", path);
+            // the position in the _dir.vpk file is aligned
+            assert_eq!(file.offset as usize % alignment, 0, "{}", path);
+        }
+
+        let package = packed("data_dir.vpk", AlignMode::Data);
+        for (path, file) in package.recursive_file_list(&[]) {
+            assert_eq!(file.archive_index, DIR_INDEX, "{}", path);
+            // the offset as stored in the index is aligned
+            assert_eq!(package.stored_offset(file) as usize % alignment, 0, "{}", path);
+        }
+
+        // no difference for other archives, where the data starts at 0
+        for (name, align_mode) in [("archive1_dir.vpk", AlignMode::Archive), ("data1_dir.vpk", AlignMode::Data)] {
+            let options = PackOptions::builder()
+                .alignment(alignment)
+                .align_mode(align_mode)
+                .strategy(ArchiveStrategy::MaxArchiveSize(1500))
+                .max_inline_size(0)
+                .quiet(true)
+                .build().unwrap();
+            pack(tmp.path.join(name), tmp.path.join("in"), options).unwrap();
+            let package = Package::from_path(tmp.path.join(name), ReadOptions::new()).unwrap();
+            let mut archived = 0;
+            for (path, file) in package.recursive_file_list(&[]) {
+                if file.archive_index != DIR_INDEX {
+                    assert_eq!(file.offset as usize % alignment, 0, "{:?} {}", align_mode, path);
+                    archived += 1;
+                }
+            }
+            assert!(archived > 0, "{:?}", align_mode);
+        }
+    }
+
+    #[test]
+    fn single_archive_read_stats_check() {
+        use crate::check::{check, CheckOptions};
+        use crate::stats::Stats;
+
+        let tmp = TempDir::new("single-archive");
+        tmp.write("in/m/a.bin", &[1; 3000]);
+        tmp.write("in/m/b.bin", &[2; 2000]);
+        tmp.write("in/n/c.txt", b"inline or not");
+
+        for version in [1, 2] {
+            let name = format!("v{}_dir.vpk", version);
+            let options = PackOptions::builder()
+                .version(version)
+                .strategy(ArchiveStrategy::SingleArchive)
+                .max_inline_size(0)
+                .quiet(true)
+                .build().unwrap();
+            pack(tmp.path.join(&name), tmp.path.join("in"), options).unwrap();
+            assert!(!tmp.path.join(format!("v{}_000.vpk", version)).exists());
+
+            let package = Package::from_path(tmp.path.join(&name), ReadOptions::new()).unwrap();
+            assert_eq!(package.archive_indices(), [DIR_INDEX]);
+
+            let mut archs = ArchiveCache::for_package(&package);
+            for path in ["m/a.bin", "m/b.bin", "n/c.txt"] {
+                let file = package.get_file(path).unwrap();
+                assert_eq!(file.archive_index, DIR_INDEX, "{}", path);
+                assert_eq!(archs.read_file_bytes(file).unwrap(), fs::read(tmp.path.join("in").join(path)).unwrap(), "{}", path);
+            }
+
+            let stats = Stats::scan(&package, None);
+            assert_eq!(stats.error_count(), 0);
+            assert_eq!(stats.archives().len(), 1);
+            let dir = &stats.archives()[&DIR_INDEX];
+            assert_eq!(dir.file_with_data_count(), 3);
+            assert_eq!(dir.file_size(), Some(fs::metadata(tmp.path.join(&name)).unwrap().len()));
+
+            check(&package, CheckOptions { quiet: true, ..CheckOptions::new() }).unwrap();
+        }
+    }
+
+    /// Number of files and sum of the data sizes per archive.
+    fn archive_usage(package: &Package) -> BTreeMap<u16, (usize, u64)> {
+        let mut usage = BTreeMap::new();
+        for (_, file) in package.recursive_file_list(&[]) {
+            if file.size > 0 {
+                let (count, size) = usage.entry(file.archive_index).or_insert((0, 0));
+                *count += 1;
+                *size += file.size as u64;
+            }
+        }
+        usage
+    }
+
+    #[test]
+    fn num_archives_spread() {
+        let tmp = TempDir::new("num-archives");
+        let sizes = [900, 100, 700, 300, 500, 500, 200, 800, 400, 600, 1000, 50];
+        for (index, size) in sizes.iter().enumerate() {
+            tmp.write(&format!("in/m/{:02}.bin", index), &vec![index as u8; *size]);
+        }
+
+        let packed = |name: &str, count: u16| {
+            let options = PackOptions::builder()
+                .strategy(ArchiveStrategy::NumArchives(count))
+                .max_inline_size(0)
+                .quiet(true)
+                .build().unwrap();
+            pack(tmp.path.join(name), tmp.path.join("in"), options).unwrap();
+            Package::from_path(tmp.path.join(name), ReadOptions::new()).unwrap()
+        };
+
+        let package = packed("three_dir.vpk", 3);
+        let usage = archive_usage(&package);
+        // the _dir.vpk only holds the index
+        assert_eq!(usage.keys().copied().collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(usage.values().map(|(count, _)| count).sum::<usize>(), sizes.len());
+        // each file goes to the smallest archive, so no archive is bigger
+        // than any other by more than the biggest file
+        let min = usage.values().map(|(_, size)| *size).min().unwrap();
+        let max = usage.values().map(|(_, size)| *size).max().unwrap();
+        assert!(max - min <= 1000, "{:?}", usage);
+
+        // never more archives than files with data
+        let package = packed("many_dir.vpk", 100);
+        let usage = archive_usage(&package);
+        assert_eq!(usage.len(), sizes.len());
+        assert!(usage.values().all(|(count, _)| *count == 1));
+    }
+
+    #[test]
+    fn balanced_count_near_equal_counts() {
+        let tmp = TempDir::new("balanced-count");
+        // very different sizes, which don't matter for the distribution
+        for index in 0..11 {
+            let size = if index % 4 == 0 { 5000 } else { 10 };
+            tmp.write(&format!("in/m/{:02}.bin", index), &vec![index as u8; size]);
+        }
+        tmp.write("in/m/empty.txt", b"");
+
+        let options = PackOptions::builder()
+            .strategy(ArchiveStrategy::BalancedCount(4))
+            .max_inline_size(0)
+            .quiet(true)
+            .build().unwrap();
+        pack(tmp.path.join("out_dir.vpk"), tmp.path.join("in"), options).unwrap();
+        let package = Package::from_path(tmp.path.join("out_dir.vpk"), ReadOptions::new()).unwrap();
+
+        let usage = archive_usage(&package);
+        assert_eq!(usage.keys().copied().collect::<Vec<_>>(), [0, 1, 2, 3]);
+        // 11 files with data round-robin to 4 archives, the empty file needs none
+        let counts: Vec<usize> = usage.values().map(|(count, _)| *count).collect();
+        assert_eq!(counts, [3, 3, 3, 2]);
+        // in path order
+        for index in 0..11 {
+            let file = package.get_file(&format!("m/{:02}.bin", index)).unwrap();
+            assert_eq!(file.archive_index, index % 4);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_follow_skip_error() {
+        use std::os::unix::fs::symlink;
+
+        let tmp = TempDir::new("symlinks");
+        tmp.write("in/m/a.txt", b"a");
+        symlink("a.txt", tmp.path.join("in/m/link.txt")).unwrap();
+        symlink("m", tmp.path.join("in/l")).unwrap();
+        tmp.write("loop/m/a.txt", b"a");
+        symlink("..", tmp.path.join("loop/m/up")).unwrap();
+
+        let packed = |name: &str, indir: &str, symlinks: Symlinks| -> Result<Vec<String>> {
+            let options = PackOptions::builder()
+                .symlinks(symlinks)
+                .quiet(true)
+                .build().unwrap();
+            let package = pack(tmp.path.join(name), tmp.path.join(indir), options)?;
+            let mut paths: Vec<String> = package.recursive_file_list(&[]).into_iter().map(|(path, _)| path).collect();
+            paths.sort();
+            Ok(paths)
+        };
+
+        assert_eq!(packed("follow_dir.vpk", "in", Symlinks::Follow).unwrap(),
+            ["l/a.txt", "l/link.txt", "m/a.txt", "m/link.txt"]);
+        let package = Package::from_path(tmp.path.join("follow_dir.vpk"), ReadOptions::new()).unwrap();
+        assert_eq!(package.get_file("l/link.txt").unwrap().preload, b"a");
+
+        assert_eq!(packed("skip_dir.vpk", "in", Symlinks::Skip).unwrap(), ["m/a.txt"]);
+        assert_eq!(packed("default_dir.vpk", "in", Symlinks::default()).unwrap(), ["m/a.txt"]);
+
+        let error = packed("error_dir.vpk", "in", Symlinks::Error).unwrap_err();
+        assert!(error.to_string().contains("symbolic links are not allowed"), "{}", error);
+        // whichever link is found first
+        let path = error.path().as_deref().unwrap();
+        assert!(path == tmp.path.join("in/l") || path == tmp.path.join("in/m/link.txt"), "{:?}", path);
+        assert!(!tmp.path.join("error_dir.vpk").exists());
+
+        // a link to a parent directory is never followed
+        let error = packed("loop_dir.vpk", "loop", Symlinks::Follow).unwrap_err();
+        assert!(error.to_string().contains("symbolic link loop"), "{}", error);
+        assert_eq!(packed("loop_skip_dir.vpk", "loop", Symlinks::Skip).unwrap(), ["m/a.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fifo_skipped_or_rejected() {
+        let tmp = TempDir::new("fifo");
+        tmp.write("in/m/a.txt", b"a");
+        let fifo = tmp.path.join("in/m/pipe.txt");
+        let status = std::process::Command::new("mkfifo").arg(&fifo).status().unwrap();
+        assert!(status.success());
+
+        // opening the FIFO would block forever
+        let options = PackOptions::builder().quiet(true).build().unwrap();
+        let package = pack(tmp.path.join("skip_dir.vpk"), tmp.path.join("in"), options).unwrap();
+        let paths: Vec<String> = package.recursive_file_list(&[]).into_iter().map(|(path, _)| path).collect();
+        assert_eq!(paths, ["m/a.txt"]);
+
+        let options = PackOptions::builder().strict(true).quiet(true).build().unwrap();
+        let error = pack(tmp.path.join("strict_dir.vpk"), tmp.path.join("in"), options).err().unwrap();
+        assert!(error.to_string().contains("not a regular file"), "{}", error);
+        assert_eq!(error.path().as_deref(), Some(fifo.as_path()));
+    }
+
+    #[test]
+    fn failed_move_restores_package() {
+        let tmp = TempDir::new("failed-move");
+        for index in 0..3u8 {
+            tmp.write(&format!("old/m/{}.bin", index), &[index; 1000]);
+        }
+        tmp.write("new/m/new.bin", &[7; 1000]);
+        let options = || PackOptions::builder()
+            .max_inline_size(0)
+            .strategy(ArchiveStrategy::MaxArchiveSize(1000))
+            .quiet(true)
+            .build().unwrap();
+
+        let out = tmp.path.join("out");
+        fs::create_dir(&out).unwrap();
+        tmp.write("out/other_000.vpk", b"not part of the package");
+        pack(out.join("pak_dir.vpk"), tmp.path.join("old"), options()).unwrap();
+        let old_contents = dir_contents(&out);
+        assert_eq!(old_contents.len(), 5);
+
+        let name = OsStr::new("pak_dir.vpk");
+        for fail_at in 0..2 {
+            let tempdir = create_temp_dir(&out, "pak").unwrap();
+            pack_files(tempdir.join(name), tmp.path.join("new"), options()).unwrap();
+
+            let mut count = 0;
+            let error = move_package(&tempdir, &out, "pak", name, |source, dest| {
+                count += 1;
+                if count > fail_at {
+                    return Err(Error::other("simulated failure").with_path(dest));
+                }
+                move_file(source, dest)
+            }).err().unwrap();
+            assert!(error.to_string().contains("simulated failure"));
+
+            fs::remove_dir_all(&tempdir).unwrap();
+            assert_eq!(dir_contents(&out), old_contents, "failed at move {}", fail_at);
+            assert_eq!(dir_names(&out).unwrap().len(), old_contents.len());
+        }
+
+        pack(out.join("pak_dir.vpk"), tmp.path.join("new"), options()).unwrap();
+        let mut names = dir_names(&out).unwrap();
+        names.sort();
+        assert_eq!(names, ["other_000.vpk", "pak_000.vpk", "pak_dir.vpk"]);
+        assert_eq!(fs::read(out.join("pak_000.vpk")).unwrap(), [7; 1000]);
+    }
+
+    #[test]
+    fn package_file_names() {
+        for name in ["pak_dir.vpk", "pak_000.vpk", "pak_1234.vpk"] {
+            assert!(is_package_file(OsStr::new(name), "pak"), "{}", name);
+        }
+        for name in ["pak_dir.vpk.tmp", "pak_00.vpk", "pak_0x0.vpk", "pak_000.vpk1", "pak2_000.vpk", "other_000.vpk", ".pak.rvpk-tmp-1"] {
+            assert!(!is_package_file(OsStr::new(name), "pak"), "{}", name);
+        }
+    }
+
+    #[test]
+    fn verify_v2_rereads_package() {
+        let tmp = TempDir::new("verify-v2");
+        tmp.write("in/m/a.bin", &[1; 3000]);
+        tmp.write("in/m/b.txt", b"inline");
+
+        let path = tmp.path.join("out_dir.vpk");
+        let options = PackOptions::builder()
+            .version(2)
+            .verify(true)
+            .quiet(true)
+            .build().unwrap();
+        pack(&path, tmp.path.join("in"), options).unwrap();
+
+        let package = Package::from_path(&path, ReadOptions::new()).unwrap();
+        let written = || WrittenV2 {
+            index_size:       package.index_size(),
+            data_size:        package.data_size(),
+            archive_md5_size: package.archive_md5_size(),
+            other_md5_size:   package.other_md5_size(),
+            signature_size:   package.signature_size(),
+            index_md5:        *package.index_md5().unwrap(),
+            archive_md5s_md5: *package.archive_md5s_md5().unwrap(),
+            everything_md5:   *package.everything_md5().unwrap(),
+        };
+        let mut buf = vec![0; 7];
+        verify_v2(&path, &mut buf, &written()).unwrap();
+
+        let error = verify_v2(&path, &mut buf, &WrittenV2 { data_size: package.data_size() + 1, ..written() }).err().unwrap();
+        assert!(error.to_string().contains("data size in written header"), "{}", error);
+
+        // change the first extension name, the stored MD5 sums stay the same
+        let mut data = fs::read(&path).unwrap();
+        data[V2_HEADER_SIZE] ^= 0x20;
+        fs::write(&path, &data).unwrap();
+        let error = verify_v2(&path, &mut buf, &written()).err().unwrap();
+        assert!(error.to_string().contains("directory index MD5 sum missmatch after writing"), "{}", error);
+    }
+
+    #[test]
+    fn pack_since() {
+        let tmp = TempDir::new("pack-since");
+        let old = tmp.write("in/m/old.txt", b"old");
+        let new = tmp.write("in/m/new.txt", b"new");
+        fs::File::options().write(true).open(old).unwrap().set_modified(UNIX_EPOCH + Duration::from_secs(1_000_000)).unwrap();
+        fs::File::options().write(true).open(new).unwrap().set_modified(UNIX_EPOCH + Duration::from_secs(2_000_000)).unwrap();
+
+        let options = PackOptions::builder()
+            .since(Some(UNIX_EPOCH + Duration::from_secs(1_500_000)))
+            .quiet(true)
+            .build().unwrap();
+        let package = pack(tmp.path.join("out_dir.vpk"), tmp.path.join("in"), options).unwrap();
+
+        assert_eq!(package.file_count(), 1);
+        assert!(package.get_file("m/new.txt").is_some());
+        assert!(package.get_file("m/old.txt").is_none());
+    }
+
+    fn replaced(path: &str, replace: &[(&str, &str)]) -> Result<String> {
+        let mut file = File {
+            index: 0,
+            crc32: 0,
+            inline_size: 0,
+            archive_index: DIR_INDEX,
+            offset: 0,
+            size: 0,
+            preload: Vec::new(),
+        };
+        let mut item = Item {
+            path: path.to_owned(),
+            index_path: path.to_owned(),
+            dot_index: 0,
+            slash_index: 0,
+            file: &mut file,
+            duplicate: false,
+        };
+        let replace: Vec<(String, String)> = replace.iter()
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect();
+        replace_path(&mut item, &replace)?;
+
+        assert_eq!(item.dir(), &item.index_path[..item.index_path.rfind('/').unwrap()]);
+        Ok(format!("{} {} {}", item.dir(), item.name(), item.ext()))
+    }
+
+    #[test]
+    fn verify_corrupted_preloads() {
+        let tmp = TempDir::new("verify-preloads");
+        tmp.write("in/m/a.txt", b"hello world");
+        tmp.write("in/inline/m/a.txt", b"hello world");
+
+        let verify = |preload: &[u8], strategy: ArchiveStrategy, buf_size: usize| -> Result<()> {
+            let mut file = File {
+                index: 0,
+                crc32: 0,
+                inline_size: preload.len() as u16,
+                archive_index: DIR_INDEX,
+                offset: 0,
+                size: 0,
+                preload: preload.to_vec(),
+            };
+            let item = Item {
+                path: "m/a.txt".to_owned(),
+                index_path: "m/a.txt".to_owned(),
+                dot_index: 3,
+                slash_index: 1,
+                file: &mut file,
+                duplicate: false,
+            };
+            let mut buf = vec![0; buf_size];
+            verify_preloads(&tmp.path.join("in"), &strategy, &[item], &mut buf)
+        };
+
+        for buf_size in [1, 4, BUFFER_SIZE] {
+            assert!(verify(b"hello world", ArchiveStrategy::default(), buf_size).is_ok());
+            assert!(verify(b"hello world", ArchiveStrategy::ArchiveFromDirName, buf_size).is_ok());
+        }
+        // nothing inlined, nothing to verify
+        assert!(verify(b"", ArchiveStrategy::default(), 4).is_ok());
+
+        let error = verify(b"hello wOrld", ArchiveStrategy::default(), 4).unwrap_err();
+        assert_eq!(error.to_string(), format!(
+            "{:?}: inlined data of \"m/a.txt\" doesn't match its source file at offset 4",
+            tmp.path.join("in/m/a.txt")));
+
+        let error = verify(b"hello", ArchiveStrategy::default(), 4).unwrap_err();
+        assert!(error.to_string().ends_with("inlined data of \"m/a.txt\" has 5 bytes, but its source file has 11 bytes"), "{}", error);
+
+        let error = verify(b"hello world!", ArchiveStrategy::default(), 4).unwrap_err();
+        assert!(error.to_string().ends_with("inlined data of \"m/a.txt\" has 12 bytes, but its source file has 11 bytes"), "{}", error);
+
+        tmp.write("in/inline/m/a.txt", b"hello World");
+        let error = verify(b"hello world", ArchiveStrategy::ArchiveFromDirName, BUFFER_SIZE).unwrap_err();
+        assert_eq!(error.path().as_deref(), Some(tmp.path.join("in/inline/m/a.txt").as_path()));
+    }
+
+    #[test]
+    fn normalize_ext_mixed_case() {
+        use crate::stats::Stats;
+
+        let tmp = TempDir::new("normalize-ext");
+        tmp.write("in/m/a.VMT", b"a");
+        tmp.write("in/m/b.vmt", b"b");
+        tmp.write("in/M/c.Vtf", b"c");
+
+        let packed = |name: &str, normalize_ext: bool| {
+            let options = PackOptions::builder()
+                .normalize_ext(normalize_ext)
+                .quiet(true)
+                .build().unwrap();
+            pack(tmp.path.join(name), tmp.path.join("in"), options).unwrap();
+            Package::from_path(tmp.path.join(name), ReadOptions::new()).unwrap()
+        };
+        let sorted_paths = |package: &Package| {
+            let mut paths: Vec<String> = package.recursive_file_list(&[]).into_iter().map(|(path, _)| path).collect();
+            paths.sort();
+            paths
+        };
+        let sorted_exts = |package: &Package| {
+            let stats = Stats::scan(package, None);
+            let mut exts: Vec<String> = stats.extensions().keys().map(|ext| ext.to_string()).collect();
+            exts.sort();
+            exts
+        };
+
+        // only the extensions are lowercased, not the names or directories
+        let package = packed("normalized_dir.vpk", true);
+        assert_eq!(sorted_paths(&package), ["M/c.vtf", "m/a.vmt", "m/b.vmt"]);
+        assert_eq!(sorted_exts(&package), ["vmt", "vtf"]);
+        assert_eq!(package.get_file("m/a.vmt").unwrap().preload, b"a");
+        assert_eq!(package.get_file("M/c.vtf").unwrap().preload, b"c");
+
+        let package = packed("plain_dir.vpk", false);
+        assert_eq!(sorted_paths(&package), ["M/c.Vtf", "m/a.VMT", "m/b.vmt"]);
+        assert_eq!(sorted_exts(&package), ["VMT", "Vtf", "vmt"]);
+
+        // names that only differ in the case of the extension collide
+        tmp.write("in/m/a.vmt", b"other a");
+        let options = PackOptions::builder().normalize_ext(true).quiet(true).build().unwrap();
+        let error = pack(tmp.path.join("collision_dir.vpk"), tmp.path.join("in"), options).err().unwrap();
+        assert!(error.to_string().ends_with("would have the same path in the index: \"m/a.vmt\""), "{}", error);
+    }
+
+    #[test]
+    fn lowercase_paths_collisions() {
+        let tmp = TempDir::new("lowercase-paths");
+        tmp.write("in/Models/Foo.VMT", b"foo");
+        tmp.write("in/models/bar.vmt", b"bar");
+
+        let lowercased = |name: &str, normalize_ext: bool| -> Result<Vec<String>> {
+            let options = PackOptions::builder()
+                .lowercase_paths(true)
+                .normalize_ext(normalize_ext)
+                .quiet(true)
+                .build().unwrap();
+            let package = pack(tmp.path.join(name), tmp.path.join("in"), options)?;
+            let mut paths: Vec<String> = package.recursive_file_list(&[]).into_iter().map(|(path, _)| path).collect();
+            paths.sort();
+            Ok(paths)
+        };
+
+        // directories that only differ in case are merged, extensions are
+        // only lowercased with normalize_ext
+        assert_eq!(lowercased("out1_dir.vpk", false).unwrap(), ["models/bar.vmt", "models/foo.VMT"]);
+        assert_eq!(lowercased("out2_dir.vpk", true).unwrap(), ["models/bar.vmt", "models/foo.vmt"]);
+
+        // two files
+        tmp.write("in/MODELS/BAR.vmt", b"other bar");
+        let error = lowercased("out3_dir.vpk", false).unwrap_err();
+        assert!(error.to_string().ends_with("would have the same path in the index: \"models/bar.vmt\""), "{}", error);
+        assert!(!tmp.path.join("out3_dir.vpk").exists());
+        fs::remove_dir_all(tmp.path.join("in/MODELS")).unwrap();
+
+        // a file and a directory
+        tmp.write("in/Models/Sub.d/x.vmt", b"x");
+        tmp.write("in/models/sub.d", b"file");
+        let error = lowercased("out4_dir.vpk", false).unwrap_err();
+        assert!(error.to_string().ends_with("in the index: \"models/sub.d\""), "{}", error);
+        assert!(error.to_string().contains("would be a file and a directory"), "{}", error);
+    }
+
+    #[test]
+    fn replace_path_rules() {
+        assert_eq!(replaced("models/old/a.mdl", &[("models/old/", "models/new/")]).unwrap(), "models/new a mdl");
+        assert_eq!(replaced("models/other/a.mdl", &[("models/old/", "models/new/")]).unwrap(), "models/other a mdl");
+        // applied in order, each to the result of the previous one
+        assert_eq!(replaced("a/x.txt", &[("a/", "b/"), ("b/", "c/d/")]).unwrap(), "c/d x txt");
+        assert_eq!(replaced("a/x.txt", &[("b/", "c/"), ("a/", "b/")]).unwrap(), "b x txt");
+        assert_eq!(replaced("a.b/x.txt", &[("a.b/x.txt", "a.b/y.dat")]).unwrap(), "a.b y dat");
+
+        for invalid in ["x.txt", "/a/x.txt", "a//x.txt", "a/.txt", "a/x.", "a/x"] {
+            assert!(replaced("a/x.txt", &[("a/x.txt", invalid)]).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn pack_replace() {
+        let tmp = TempDir::new("pack-replace");
+        tmp.write("in/models/old/a.mdl", b"a");
+        tmp.write("in/models/old/sub/b.mdl", b"b");
+        tmp.write("in/models/new/c.mdl", b"c");
+
+        let options = PackOptions::builder()
+            .replace(vec![("models/old/".to_owned(), "models/new/".to_owned())])
+            .quiet(true)
+            .build().unwrap();
+        let package = pack(tmp.path.join("out_dir.vpk"), tmp.path.join("in"), options).unwrap();
+        let mut paths: Vec<String> = package.recursive_file_list(&[]).into_iter().map(|(path, _)| path).collect();
+        paths.sort();
+        assert_eq!(paths, ["models/new/a.mdl", "models/new/c.mdl", "models/new/sub/b.mdl"]);
+
+        let reread = Package::from_path(tmp.path.join("out_dir.vpk"), ReadOptions::new()).unwrap();
+        assert!(reread.get_file("models/new/sub/b.mdl").is_some());
+
+        // collision
+        let options = PackOptions::builder()
+            .replace(vec![("models/old/a.mdl".to_owned(), "models/new/c.mdl".to_owned())])
+            .quiet(true)
+            .build().unwrap();
+        let error = pack(tmp.path.join("out2_dir.vpk"), tmp.path.join("in"), options).err().unwrap();
+        assert!(error.to_string().contains("would have the same path in the index"), "{}", error);
+
+        // a file and a directory
+        let options = PackOptions::builder()
+            .replace(vec![("models/new/c.mdl".to_owned(), "models/old/sub".to_owned())])
+            .quiet(true)
+            .build().unwrap();
+        assert!(pack(tmp.path.join("out3_dir.vpk"), tmp.path.join("in"), options).is_err());
+    }
+
+    #[test]
+    fn pack_relative_to() {
+        let tmp = TempDir::new("pack-relative-to");
+        tmp.write("game/materials/a.vmt", b"material a");
+        tmp.write("game/materials/sub/b.vtf", b"texture b");
+        tmp.write("game/other/x.txt", b"not packed");
+
+        let relative_paths = |name: &str, indir: &str, base: &str| {
+            let options = PackOptions::builder()
+                .relative_to(Some(tmp.path.join(base)))
+                .quiet(true)
+                .build().unwrap();
+            let package = pack(tmp.path.join(name), tmp.path.join(indir), options).unwrap();
+            let mut paths: Vec<String> = package.recursive_file_list(&[]).into_iter().map(|(path, _)| path).collect();
+            paths.sort();
+            paths
+        };
+
+        assert_eq!(relative_paths("out1_dir.vpk", "game/materials", "game"), ["materials/a.vmt", "materials/sub/b.vtf"]);
+        assert_eq!(relative_paths("out2_dir.vpk", "game/materials/sub", "."), ["game/materials/sub/b.vtf"]);
+        // the same as without --relative-to
+        assert_eq!(relative_paths("out3_dir.vpk", "game", "game"), ["materials/a.vmt", "materials/sub/b.vtf", "other/x.txt"]);
+
+        // the data is still read from the right files
+        let package = Package::from_path(tmp.path.join("out1_dir.vpk"), ReadOptions::new()).unwrap();
+        let mut archs = ArchiveCache::for_package(&package);
+        assert_eq!(archs.read_file_bytes(package.get_file("materials/a.vmt").unwrap()).unwrap(), b"material a");
+        assert_eq!(archs.read_file_bytes(package.get_file("materials/sub/b.vtf").unwrap()).unwrap(), b"texture b");
+
+        let options = PackOptions::builder()
+            .relative_to(Some(tmp.path.join("game/other")))
+            .quiet(true)
+            .build().unwrap();
+        let error = pack(tmp.path.join("out4_dir.vpk"), tmp.path.join("game/materials"), options).err().unwrap();
+        assert!(error.to_string().contains("input directory is not inside of"), "{}", error);
+    }
+
+    #[test]
+    fn validate_rejects_invalid_combinations() {
+        let rejected = [
+            PackOptions::builder().version(3),
+            PackOptions::builder().version(1).md5_chunk_size(DEFAULT_MD5_CHUNK_SIZE),
+            PackOptions::builder().version(1).md5_chunk_size(4096),
+            PackOptions::builder().version(2).md5_chunk_size(0),
+            PackOptions::builder().version(1).verify(true),
+            PackOptions::builder().alignment(0),
+            PackOptions::builder().index_warning_ratio(1.5),
+            PackOptions::builder().strategy(ArchiveStrategy::MaxArchiveSize(0)),
+            PackOptions::builder().strategy(ArchiveStrategy::NumArchives(0)),
+            PackOptions::builder().strategy(ArchiveStrategy::BalancedCount(1001)),
+            PackOptions::builder().buffer_size(0),
+            PackOptions::builder().relative_to(Some("base".into())).files(Some(Vec::new())),
+            PackOptions::builder().relative_to(Some("base".into())).strategy(ArchiveStrategy::ArchiveFromDirName),
+        ];
+        for builder in rejected {
+            assert!(builder.build().is_err());
+        }
+
+        let error = PackOptions::builder().version(1).md5_chunk_size(DEFAULT_MD5_CHUNK_SIZE).build().err().unwrap();
+        assert_eq!(error.to_string(), "--md5-chunk-size requires --version to be 2");
+
+        let error = PackOptions::builder().version(1).verify(true).build().err().unwrap();
+        assert_eq!(error.to_string(), "--verify requires --version to be 2");
+
+        let error = PackOptions::builder().alignment(0).build().err().unwrap();
+        assert_eq!(error.to_string(), "illegal argument for --alignment: \"0\"");
+
+        assert!(PackOptions::builder().build().is_ok());
+        assert!(PackOptions::builder().version(2).md5_chunk_size(DEFAULT_MD5_CHUNK_SIZE).verify(true).build().is_ok());
+    }
+
+    #[test]
+    fn builder_sets_options() {
+        let defaults = PackOptions::builder().build().unwrap();
+        let new = PackOptions::new();
+        assert_eq!(defaults.version, new.version);
+        assert_eq!(defaults.max_inline_size, new.max_inline_size);
+        assert_eq!(defaults.alignment, new.alignment);
+        assert!(matches!((defaults.strategy, new.strategy),
+            (ArchiveStrategy::MaxArchiveSize(a), ArchiveStrategy::MaxArchiveSize(b)) if a == b));
+
+        let options = PackOptions::builder()
+            .version(2)
+            .max_inline_size(4096)
+            .alignment(16)
+            .quiet(true)
+            .build().unwrap();
+        assert_eq!(options.version, 2);
+        assert_eq!(options.max_inline_size, 4096);
+        assert_eq!(options.alignment, 16);
+        assert!(options.quiet);
+
+        let tmp = TempDir::new("pack-builder");
+        tmp.write("in/m/small.txt", &[1; 100]);
+        tmp.write("in/m/big.bin", &[2; 5000]);
+        pack(tmp.path.join("out_dir.vpk"), tmp.path.join("in"), options).unwrap();
+        let package = Package::from_path(tmp.path.join("out_dir.vpk"), ReadOptions::new()).unwrap();
+        assert_eq!(package.version(), 2);
+        assert_eq!(package.get_file("m/small.txt").unwrap().inline_size, 100);
+        let big = package.get_file("m/big.bin").unwrap();
+        assert_eq!((big.inline_size, big.size), (0, 5000));
+        assert_eq!(big.offset % 16, 0);
+    }
+
+    #[test]
+    fn index_size_warning_near_limit() {
+        let limit = i32::MAX as usize;
+        assert_eq!(index_size_warning(0, 0), None);
+        assert_eq!(index_size_warning(limit / 2, 0), None);
+        assert_eq!(index_size_warning((limit as f64 * INDEX_SIZE_WARNING_RATIO) as usize, 0), None);
+
+        let warning = index_size_warning(limit / 10 * 9, 1234).unwrap();
+        assert!(warning.starts_with(&format!("the index is {} bytes, 90.0 % of the maximum index size of {} bytes", limit / 10 * 9, limit)), "{}", warning);
+        assert!(warning.contains("(1234 bytes of it are inlined file data)"), "{}", warning);
+    }
 }
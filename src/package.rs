@@ -18,24 +18,75 @@ use std::fs;
 use std::io::{Read, Seek, SeekFrom};
 use std::collections::HashMap;
 
+use crc::Hasher32;
+
 use crate::entry;
-use crate::entry::{Entry, File};
-use crate::result::{Result, Error};
+use crate::entry::{Entry, File, Dir};
+use crate::file_reader::VpkFileReader;
+use crate::archive_cache::ArchiveCache;
+use crate::hash::CrcAlgorithm;
+use crate::validate::{validate, ValidateOptions, Problem, ProblemKind};
+use crate::result::{Result, Error, ErrorType};
 use crate::sort::{Order, sort};
-use crate::consts::{VPK_MAGIC, V1_HEADER_SIZE, V2_HEADER_SIZE, DIR_INDEX, ARCHIVE_MD5_SIZE};
+use crate::consts::{VPK_MAGIC, VPK_MAGIC_BIG_ENDIAN, V1_HEADER_SIZE, V2_HEADER_SIZE, DIR_INDEX, ARCHIVE_MD5_SIZE};
 use crate::io::*;
 use crate::util::*;
 
 pub type Magic = [u8; 4];
 pub type Md5 = [u8; 16];
 
+/// Encoding of the names in the index.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    /// Strict UTF-8, anything else is an error.
+    Utf8,
+    /// Names that aren't valid UTF-8 are decoded as Latin-1 (ISO 8859-1),
+    /// as used by some legacy packages. Valid UTF-8 is kept as it is.
+    Latin1,
+}
+
+impl Default for Encoding {
+    #[inline]
+    fn default() -> Self {
+        Encoding::Utf8
+    }
+}
+
+/// How to read the index of a package.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadOptions {
+    /// Read packages without a header (version 0).
+    pub allow_v0: bool,
+    /// Don't fail on an entry with an illegal terminator, instead use all
+    /// entries read up to that point. See [`Package::truncated_at`].
+    pub lenient: bool,
+    /// Encoding of the names in the index.
+    pub encoding: Encoding,
+}
+
+impl ReadOptions {
+    #[inline]
+    pub fn new() -> Self {
+        ReadOptions::default()
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct ArchiveMd5 {
     pub(crate) archive_index: u16,
     pub(crate) offset:        u32,
     pub(crate) size:          u32,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_md5"))]
     pub(crate) md5:           Md5,
 }
 
+#[cfg(feature = "serde")]
+fn serialize_md5<S>(md5: &Md5, serializer: S) -> std::result::Result<S::Ok, S::Error>
+where S: serde::Serializer {
+    serializer.serialize_str(&format_md5(md5))
+}
+
 impl ArchiveMd5 {
     #[inline]
     pub fn archive_index(&self) -> u16 {
@@ -58,9 +109,14 @@ impl ArchiveMd5 {
     }
 }
 
+/// A parsed VPK package. Cloning it copies the whole tree of entries, so
+/// a clone can be changed without affecting the original.
+#[derive(Clone)]
 pub struct Package {
     pub(crate) dirpath: PathBuf,
     pub(crate) prefix: String,
+    /// Directory of the _NNN.vpk archives if it differs from `dirpath`.
+    pub(crate) archive_dirpath: Option<PathBuf>,
 
     pub(crate) version:          u32,
     pub(crate) data_offset:      u32,
@@ -70,6 +126,11 @@ pub struct Package {
     pub(crate) other_md5_size:   u32,
     pub(crate) signature_size:   u32,
     pub(crate) entries: HashMap<String, Entry>,
+    pub(crate) file_count: usize,
+    pub(crate) total_size: u64,
+    pub(crate) total_data_size: u64,
+    pub(crate) duplicates: Vec<String>,
+    pub(crate) truncated_at: Option<u64>,
 
     // VPK2
     pub(crate) archive_md5s: Vec<ArchiveMd5>,
@@ -81,6 +142,30 @@ pub struct Package {
     pub(crate) signature:  Vec<u8>,
 }
 
+#[cfg(feature = "serde")]
+impl serde::Serialize for Package {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where S: serde::Serializer {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("Package", 13)?;
+        state.serialize_field("version",          &self.version)?;
+        state.serialize_field("header_size",      &self.header_size())?;
+        state.serialize_field("data_offset",      &self.data_offset)?;
+        state.serialize_field("index_size",       &self.index_size)?;
+        state.serialize_field("data_size",        &self.data_size)?;
+        state.serialize_field("archive_md5_size", &self.archive_md5_size)?;
+        state.serialize_field("other_md5_size",   &self.other_md5_size)?;
+        state.serialize_field("signature_size",   &self.signature_size)?;
+        state.serialize_field("archive_md5s",     &self.archive_md5s)?;
+        state.serialize_field("index_md5",        &self.index_md5().map(format_md5))?;
+        state.serialize_field("archive_md5s_md5", &self.archive_md5s_md5().map(format_md5))?;
+        state.serialize_field("everything_md5",   &self.everything_md5().map(format_md5))?;
+        state.serialize_field("root",             &self.entries)?;
+        state.end()
+    }
+}
+
 fn mkpath<'a>(mut entries: &'a mut HashMap<String, Entry>, dirpath: &str) -> Result<&'a mut HashMap<String, Entry>> {
     for (path, item, _) in split_path(dirpath) {
         if !entries.contains_key(item) {
@@ -151,23 +236,66 @@ pub(crate) fn parse_path(path: impl AsRef<Path>) -> Result<(PathBuf, String)> {
 }
 
 impl Package {
-    pub fn from_path(path: impl AsRef<Path>, allow_v0: bool) -> Result<Package> {
+    /// Read the index of a package.
+    pub fn from_path(path: impl AsRef<Path>, options: ReadOptions) -> Result<Package> {
         match fs::File::open(&path) {
-            Ok(mut file) => match Self::from_file(&mut file, &path, allow_v0) {
-                Ok(package) => Ok(package),
-                Err(error) => if error.path.is_none() {
-                    Err(error.with_path(path))
-                } else {
-                    Err(error)
-                },
-            }
+            Ok(mut file) => {
+                // otherwise this only fails with a cryptic error when reading the magic
+                let meta = match file.metadata() {
+                    Ok(meta) => meta,
+                    Err(error) => return Err(Error::io_with_path(error, path)),
+                };
+                if !meta.is_file() {
+                    return Err(Error::other("not a regular file").with_path(path));
+                }
+                if meta.len() == 0 {
+                    return Err(Error::other("file is empty").with_path(path));
+                }
+
+                match Self::from_file(&mut file, &path, options) {
+                    Ok(package) => Ok(package),
+                    Err(error) => if error.path.is_none() {
+                        Err(error.with_path(path))
+                    } else {
+                        Err(error)
+                    },
+                }
+            },
             Err(error) => Err(Error::io_with_path(error, path.as_ref().to_path_buf())),
         }
     }
 
-    fn from_file(file: &mut fs::File, path: impl AsRef<Path>, allow_v0: bool) -> Result<Package> {
+    /// Like [`Package::from_path`], but the _NNN.vpk archives are read from
+    /// `archive_dir` instead of the directory of the _dir.vpk file, e.g. for
+    /// a patched _dir.vpk file that lives somewhere else. File data stored
+    /// in the _dir.vpk file itself is still read from `path`.
+    ///
+    /// ```ignore
+    /// let package = Package::from_path_with_archive_dir(
+    ///     "patch/pak01_dir.vpk", "game/vpks", ReadOptions::new())?;
+    /// assert_eq!(package.archive_path(0), Path::new("game/vpks/pak01_000.vpk"));
+    /// ```
+    pub fn from_path_with_archive_dir(path: impl AsRef<Path>, archive_dir: impl Into<PathBuf>, options: ReadOptions) -> Result<Package> {
+        let mut package = Self::from_path(path, options)?;
+        package.archive_dirpath = Some(archive_dir.into());
+        Ok(package)
+    }
+
+    fn from_file(file: &mut fs::File, path: impl AsRef<Path>, options: ReadOptions) -> Result<Package> {
         let (dirpath, prefix) = parse_path(&path)?;
 
+        Self::from_reader(file, dirpath, prefix, options)
+    }
+
+    /// Read the index of a package from any reader, e.g. a package embedded
+    /// in another file. `dirpath` and `prefix` are only used to find the
+    /// archives (`{dirpath}/{prefix}_{NNN}.vpk`) when reading file data.
+    pub fn from_reader<R>(reader: R, dirpath: impl Into<PathBuf>, prefix: impl Into<String>, options: ReadOptions) -> Result<Package>
+    where R: Read, R: Seek {
+        let dirpath = dirpath.into();
+        let prefix  = prefix.into();
+        let encoding = options.encoding;
+
         let mut archive_md5s = Vec::new();
         let mut index_md5:        Md5 = [0; 16];
         let mut archive_md5s_md5: Md5 = [0; 16];
@@ -175,19 +303,21 @@ impl Package {
         let mut public_key = Vec::new();
         let mut signature  = Vec::new();
 
-        let mut file = std::io::BufReader::new(file);
+        let mut file = std::io::BufReader::new(reader);
         let mut magic = [0; 4];
         file.read_exact(&mut magic)?;
 
         let version;
         let mut index_size;
 
-        if magic != VPK_MAGIC {
-            if allow_v0 {
+        if magic == VPK_MAGIC_BIG_ENDIAN {
+            return Err(Error::big_endian());
+        } else if magic != VPK_MAGIC {
+            if options.allow_v0 {
                 version = 0;
                 file.seek(SeekFrom::Start(0))?;
             } else {
-                return Err(Error::illegal_magic(magic).with_path(path));
+                return Err(Error::illegal_magic(magic));
             }
             // offsets of file data in _dir.vpk needs to be fixed later
             index_size = 0;
@@ -195,7 +325,7 @@ impl Package {
             version = read_u32(&mut file)?;
 
             if version == 0 || version > 2 {
-                return Err(Error::unsupported_version(version).with_path(path));
+                return Err(Error::unsupported_version(version));
             }
             index_size = read_u32(&mut file)?;
         }
@@ -228,21 +358,27 @@ impl Package {
 
         let mut entries = HashMap::new();
         let mut index   = 0usize;
+        let mut file_count = 0usize;
+        let mut total_size = 0u64;
+        let mut total_data_size = 0u64;
+        let mut duplicates = Vec::new();
 
         // buffer reuse over loops:
         let mut extbuf  = Vec::new();
         let mut dirbuf  = Vec::new();
         let mut namebuf = Vec::new();
 
-        loop {
-            let ext = read_str(&mut file, &mut extbuf)?;
+        let mut truncated_at = None;
+
+        'index: loop {
+            let ext = read_str(&mut file, &mut extbuf, encoding)?;
 
             if ext.is_empty() {
                 break;
             }
 
             loop {
-                let dirname = read_str(&mut file, &mut dirbuf)?;
+                let dirname = read_str(&mut file, &mut dirbuf, encoding)?;
 
                 if dirname.is_empty() {
                     break;
@@ -251,25 +387,45 @@ impl Package {
                 let children = mkpath(&mut entries, &dirname)?;
 
                 loop {
-                    let name = read_str(&mut file, &mut namebuf)?;
+                    let name = read_str(&mut file, &mut namebuf, encoding)?;
 
                     if name.is_empty() {
                         break;
                     }
 
-                    let mut name = name.to_owned();
+                    let mut name = name.into_owned();
                     name.push('.');
                     name.push_str(&ext);
 
-                    let entry = read_file(&mut file, index, data_offset)?;
+                    let entry = match read_file(&mut file, index, data_offset) {
+                        Ok(entry) => entry,
+                        Err(Error { error_type: ErrorType::IllegalTerminator { offset, .. }, .. }) if options.lenient && version > 0 => {
+                            // The size of the preload data of this entry can't
+                            // be trusted, so there is no way to find the next one.
+                            truncated_at = Some(offset);
+                            break 'index;
+                        },
+                        Err(error) => return Err(error),
+                    };
                     index += 1;
 
                     if children.contains_key(&name) {
-                        eprintln!("WARNING: file occured more than once: {:?}",
-                            format!("{}/{}.{}", dirname, name, ext));
+                        duplicates.push(format!("{}/{}", dirname, name));
                     }
 
-                    children.insert(name, Entry::File(entry));
+                    file_count += 1;
+                    total_size += entry.inline_size as u64 + entry.size as u64;
+                    total_data_size += entry.size as u64;
+
+                    if let Some(old) = children.insert(name, Entry::File(entry)) {
+                        let (old_count, old_size, old_data_size) = match &old {
+                            Entry::File(file) => (1, file.inline_size as u64 + file.size as u64, file.size as u64),
+                            Entry::Dir(dir) => sum_files(&dir.children),
+                        };
+                        file_count -= old_count;
+                        total_size -= old_size;
+                        total_data_size -= old_data_size;
+                    }
                 }
             }
         }
@@ -282,14 +438,16 @@ impl Package {
         } else {
             let actual_data_offset = file.seek(SeekFrom::Current(0))?;
 
-            if actual_data_offset < data_offset as u64 {
+            if truncated_at.is_some() {
+                file.seek(SeekFrom::Start(data_offset as u64))?;
+            } else if actual_data_offset > data_offset as u64 {
                 return Err(Error::sanity_check_failed(format!(
                     "index overlaps with data section: {} > {}",
-                    data_offset, actual_data_offset)));
-            } else if actual_data_offset > data_offset as u64 {
-                let remaining = actual_data_offset - data_offset as u64;
+                    actual_data_offset, data_offset)));
+            } else if actual_data_offset < data_offset as u64 {
+                let remaining = data_offset as u64 - actual_data_offset;
                 eprintln!("WARNING: {} bytes left after index section", remaining);
-                file.seek(SeekFrom::Current(remaining as i64))?;
+                file.seek(SeekFrom::Start(data_offset as u64))?;
             }
         }
 
@@ -356,6 +514,11 @@ impl Package {
             if remaining >= 4 {
                 let pubkey_size = read_u32(&mut file)?;
                 remaining -= 4;
+                if pubkey_size > remaining {
+                    return Err(Error::sanity_check_failed(format!(
+                        "public key size {} exceeds the signature section size {}",
+                        pubkey_size, signature_size)));
+                }
                 public_key.resize(pubkey_size as usize, 0);
                 file.read_exact(&mut public_key)?;
                 remaining -= pubkey_size;
@@ -363,21 +526,28 @@ impl Package {
                 if remaining >= 4 {
                     let sig_size = read_u32(&mut file)?;
                     remaining -= 4;
+                    if sig_size > remaining {
+                        return Err(Error::sanity_check_failed(format!(
+                            "signature size {} exceeds the signature section size {}",
+                            sig_size, signature_size)));
+                    }
                     signature.resize(sig_size as usize, 0);
                     file.read_exact(&mut signature)?;
                     remaining -= sig_size;
                 }
             }
 
+            // Anything after the signature section (e.g. padding or metadata
+            // appended by other tools) is never read, so it isn't an error.
             if remaining > 0 {
-                eprintln!("WARNING: {} bytes left after the signature section", remaining);
-                file.seek(SeekFrom::Current(remaining as i64))?;
+                eprintln!("WARNING: {} bytes left in the signature section", remaining);
             }
         }
 
         Ok(Package {
             dirpath,
             prefix,
+            archive_dirpath: None,
             version,
             data_offset,
             index_size,
@@ -386,6 +556,11 @@ impl Package {
             other_md5_size,
             signature_size,
             entries,
+            file_count,
+            total_size,
+            total_data_size,
+            duplicates,
+            truncated_at,
             archive_md5s,
             index_md5,
             archive_md5s_md5,
@@ -395,11 +570,70 @@ impl Package {
         })
     }
 
+    /// Directory containing the _dir.vpk file of the package.
+    #[inline]
+    pub fn dirpath(&self) -> &Path {
+        &self.dirpath
+    }
+
+    /// Directory containing the _NNN.vpk archives of the package. This is
+    /// the same as [`Package::dirpath`] unless the package was read with
+    /// [`Package::from_path_with_archive_dir`].
+    #[inline]
+    pub fn archive_dirpath(&self) -> &Path {
+        self.archive_dirpath.as_deref().unwrap_or(&self.dirpath)
+    }
+
+    /// File name prefix of the archives, e.g. `pak01` for `pak01_dir.vpk`.
+    #[inline]
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
     #[inline]
     pub fn version(&self) -> u32 {
         self.version
     }
 
+    /// Number of files in the package, counted while reading the index.
+    #[inline]
+    pub fn file_count(&self) -> usize {
+        self.file_count
+    }
+
+    /// Sum of the full sizes (inline size + archive size) of all files,
+    /// calculated while reading the index.
+    #[inline]
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    /// Sum of the archive sizes of all files, i.e. the file data that isn't
+    /// inlined in the index. Calculated while reading the index, so unlike
+    /// [`Stats::scan`](crate::stats::Stats::scan) this doesn't touch the
+    /// file system.
+    #[inline]
+    pub fn total_data_size(&self) -> u64 {
+        self.total_data_size
+    }
+
+    /// Paths of the index entries that occured more than once. Only the
+    /// last entry of such a path is used. Parsing doesn't print anything,
+    /// commands report these with [`crate::warnings::Warnings::warn_duplicates`].
+    #[inline]
+    pub fn duplicate_warnings(&self) -> &[String] {
+        &self.duplicates
+    }
+
+    /// Offset of the illegal terminator at which reading the index stopped
+    /// in lenient mode (see [`ReadOptions::lenient`]). All entries after it
+    /// are missing from the package. Commands report this with
+    /// [`crate::warnings::Warnings::warn_truncated`].
+    #[inline]
+    pub fn truncated_at(&self) -> Option<u64> {
+        self.truncated_at
+    }
+
     #[inline]
     pub fn header_size(&self) -> u32 {
         match self.version {
@@ -476,6 +710,21 @@ impl Package {
         }
     }
 
+    /// The sections of the _dir.vpk file that are covered by the MD5 sums
+    /// stored in a version 2 package, only those whose sum is present.
+    pub fn md5_sections(&self) -> Vec<Md5Section<'_>> {
+        let md5_offset = self.data_offset as u64 + self.data_size as u64;
+        let sections = [
+            (self.index_md5(),        V2_HEADER_SIZE as u64..V2_HEADER_SIZE as u64 + self.index_size as u64, "directory index"),
+            (self.archive_md5s_md5(), md5_offset..md5_offset + self.archive_md5_size as u64,                 "MD5 sum list"),
+            (self.everything_md5(),   0..md5_offset + self.archive_md5_size as u64 + 16 * 2,                 "everything"),
+        ];
+
+        sections.iter().cloned()
+            .filter_map(|(md5, range, what)| md5.map(|md5| Md5Section { md5, range, what }))
+            .collect()
+    }
+
     #[inline]
     pub fn public_key(&self) -> Option<&Vec<u8>> {
         if self.signature_size >= 4 {
@@ -494,6 +743,37 @@ impl Package {
         }
     }
 
+    /// Offset of the data of `file` as stored in the index. For data in the
+    /// _dir.vpk file this is relative to the start of the data section,
+    /// whereas [`File::offset`] is always the position in the archive file.
+    #[inline]
+    pub fn stored_offset(&self, file: &File) -> u32 {
+        if file.archive_index == DIR_INDEX {
+            file.offset - self.data_offset
+        } else {
+            file.offset
+        }
+    }
+
+    /// Whether the package contains a (non-empty) signature. The signature
+    /// itself is not verified.
+    #[inline]
+    pub fn is_signed(&self) -> bool {
+        self.signature_len() > 0
+    }
+
+    /// Length of the public key in bytes, 0 if there is none.
+    #[inline]
+    pub fn public_key_len(&self) -> usize {
+        self.public_key().map_or(0, Vec::len)
+    }
+
+    /// Length of the signature in bytes, 0 if there is none.
+    #[inline]
+    pub fn signature_len(&self) -> usize {
+        self.signature().map_or(0, Vec::len)
+    }
+
     pub fn get<'a>(&'a self, path: &str) -> Option<&'a Entry> {
         let mut entries = &self.entries;
         for (_, item, is_last) in split_path(path) {
@@ -536,6 +816,110 @@ impl Package {
         return None;
     }
 
+    /// Like [`Package::get`], but only returns files.
+    ///
+    /// ```ignore
+    /// if let Some(file) = package.get_file("materials/foo.vmt") {
+    ///     println!("{} bytes", file.inline_size as u32 + file.size);
+    /// }
+    /// assert!(package.get_file("materials").is_none());
+    /// ```
+    pub fn get_file<'a>(&'a self, path: &str) -> Option<&'a File> {
+        match self.get(path) {
+            Some(Entry::File(file)) => Some(file),
+            _ => None,
+        }
+    }
+
+    /// Like [`Package::get`], but only returns directories.
+    ///
+    /// ```ignore
+    /// if let Some(dir) = package.get_dir("materials") {
+    ///     println!("{} entries", dir.children.len());
+    /// }
+    /// assert!(package.get_dir("materials/foo.vmt").is_none());
+    /// ```
+    pub fn get_dir<'a>(&'a self, path: &str) -> Option<&'a Dir> {
+        match self.get(path) {
+            Some(Entry::Dir(dir)) => Some(dir),
+            _ => None,
+        }
+    }
+
+    /// Call `f` with the path and entry of every file and directory of the
+    /// package, in no particular order. See [`Entry::walk`].
+    ///
+    /// ```ignore
+    /// let mut count = 0;
+    /// package.walk(|_path, entry| {
+    ///     if entry.is_file() {
+    ///         count += 1;
+    ///     }
+    /// });
+    /// assert_eq!(count, package.file_count());
+    /// ```
+    pub fn walk(&self, mut f: impl FnMut(&str, &Entry)) {
+        let mut path = String::new();
+        for (name, entry) in &self.entries {
+            path.clear();
+            path.push_str(name);
+            entry.walk(&mut path, &mut f);
+        }
+    }
+
+    /// The sorted distinct archive indices of all files that have data in
+    /// an archive, i.e. the archives that are needed to read every file.
+    /// Files that are fully stored in the index don't need any archive.
+    /// [`DIR_INDEX`](crate::consts::DIR_INDEX) stands for the _dir.vpk file.
+    ///
+    /// ```ignore
+    /// for archive_index in package.archive_indices() {
+    ///     println!("{:?}", package.archive_path(archive_index));
+    /// }
+    /// ```
+    pub fn archive_indices(&self) -> Vec<u16> {
+        let mut indices = Vec::new();
+        self.walk(|_, entry| {
+            if let Entry::File(file) = entry {
+                if file.size > 0 {
+                    indices.push(file.archive_index);
+                }
+            }
+        });
+        indices.sort_unstable();
+        indices.dedup();
+
+        indices
+    }
+
+    /// The longest directory path (without trailing `/`) that contains all
+    /// files of the package, e.g. `"root"` if everything is below `root/`.
+    /// Empty if there is no such directory.
+    ///
+    /// ```ignore
+    /// let prefix = package.common_prefix();
+    /// if !prefix.is_empty() {
+    ///     println!("use --strip-components {}", prefix.split('/').count());
+    /// }
+    /// ```
+    pub fn common_prefix(&self) -> String {
+        let mut prefix = String::new();
+        let mut entries = &self.entries;
+        while entries.len() == 1 {
+            match entries.iter().next() {
+                Some((name, Entry::Dir(dir))) => {
+                    if !prefix.is_empty() {
+                        prefix.push('/');
+                    }
+                    prefix.push_str(name);
+                    entries = &dir.children;
+                },
+                _ => break,
+            }
+        }
+        prefix
+    }
+
     pub fn recursive_file_list(&self, order: &Order) -> Vec<(String, &File)> {
         let mut list = Vec::new();
         let mut pathbuf = String::new();
@@ -574,8 +958,141 @@ impl Package {
     }
 
     pub fn archive_path(&self, archive_index: u16) -> PathBuf {
-        archive_path(&self.dirpath, &self.prefix, archive_index)
+        if archive_index == DIR_INDEX {
+            archive_path(&self.dirpath, &self.prefix, archive_index)
+        } else {
+            archive_path(self.archive_dirpath(), &self.prefix, archive_index)
+        }
+    }
+
+    /// Make sure every referenced archive exists and the data of every file
+    /// (and every MD5 chunk) lies within its archive. This fails on the first
+    /// [`ProblemKind::MissingArchive`] or [`ProblemKind::OutOfBounds`] problem
+    /// found by [`Package::validate`], so it agrees with `doctor`. It is not
+    /// done while parsing so that reading an index doesn't touch the archives.
+    pub fn validate_offsets(&self) -> Result<()> {
+        let problems = self.validate(&ValidateOptions::new())?;
+
+        for problem in problems {
+            let archive_index = problem.archive_index.unwrap_or(DIR_INDEX);
+            match problem.kind {
+                ProblemKind::MissingArchive => {
+                    return Err(Error::missing_archive(archive_index)
+                        .with_path(self.archive_path(archive_index)));
+                },
+                ProblemKind::OutOfBounds => {
+                    return Err(Error::sanity_check_failed(problem.message)
+                        .with_path(self.archive_path(archive_index)));
+                },
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Find structural problems of the package, like missing archives or
+    /// file data beyond the end of an archive, without printing anything.
+    /// An empty list means the package is fine. Only reading the MD5
+    /// sections (see [`ValidateOptions::check_md5s`]) can fail.
+    ///
+    /// ```ignore
+    /// let package = Package::from_path("pak01_dir.vpk", ReadOptions::new())?;
+    /// for problem in package.validate(&ValidateOptions::new())? {
+    ///     eprintln!("{:?}: {}", problem.kind, problem);
+    /// }
+    /// ```
+    #[inline]
+    pub fn validate(&self, options: &ValidateOptions) -> Result<Vec<Problem>> {
+        validate(self, options)
+    }
+
+    /// Open a file inside of the package for reading. The archive containing
+    /// the file data is only opened if the file isn't fully inlined.
+    pub fn open_file<'a>(&'a self, path: &str) -> Result<VpkFileReader<'a>> {
+        let path = path.trim_matches('/');
+        match self.get(path) {
+            None => Err(Error::no_such_entry(path)),
+            Some(Entry::Dir(_)) => Err(Error::other(format!("entry is a directory: {:?}", path))),
+            Some(Entry::File(file)) => {
+                let archive = if file.size > 0 {
+                    let archpath = self.archive_path(file.archive_index);
+                    match fs::File::open(&archpath) {
+                        Ok(archive) => Some(archive),
+                        Err(error) => return Err(Error::io_with_path(error, archpath)),
+                    }
+                } else {
+                    None
+                };
+
+                Ok(VpkFileReader::new(file, archive))
+            }
+        }
+    }
+
+    /// Calculate the CRC32 sum of the data of `file` with the polynomial
+    /// `crc`, which has to be a file of this package. `archs` has to be
+    /// created for this package, see [`ArchiveCache::for_reading`].
+    pub fn file_crc32(&self, file: &File, archs: &mut ArchiveCache, crc: CrcAlgorithm) -> Result<u32> {
+        let mut digest = crc.digest();
+        archs.read_file_data(file, |data| {
+            digest.write(data);
+            Ok(())
+        })?;
+
+        Ok(digest.sum32())
+    }
+
+    /// Whether the CRC32 sum of the data of `file` matches the sum stored in
+    /// the index. `check` doesn't use this, because it also calculates the
+    /// sidecar checksum while reading the data of a file.
+    ///
+    /// ```ignore
+    /// let package = Package::from_path("pak01_dir.vpk", ReadOptions::new())?;
+    /// let mut archs = ArchiveCache::for_package(&package);
+    ///
+    /// let file = package.get_file("materials/foo.vmt").unwrap();
+    /// assert!(package.verify_file_crc(file, &mut archs, CrcAlgorithm::IEEE)?);
+    /// ```
+    #[inline]
+    pub fn verify_file_crc(&self, file: &File, archs: &mut ArchiveCache, crc: CrcAlgorithm) -> Result<bool> {
+        Ok(self.file_crc32(file, archs, crc)? == file.crc32)
+    }
+}
+
+/// A part of the _dir.vpk file and its MD5 sum, see [`Package::md5_sections`].
+#[derive(Debug, Clone)]
+pub struct Md5Section<'a> {
+    pub md5: &'a Md5,
+    /// Byte range in the _dir.vpk file.
+    pub range: std::ops::Range<u64>,
+    /// Human readable name of the section.
+    pub what: &'static str,
+}
+
+/// Number of files, sum of their full sizes and sum of their archive sizes.
+pub(crate) fn sum_files(entries: &HashMap<String, Entry>) -> (usize, u64, u64) {
+    let mut file_count = 0usize;
+    let mut total_size = 0u64;
+    let mut total_data_size = 0u64;
+
+    for entry in entries.values() {
+        match entry {
+            Entry::Dir(dir) => {
+                let (count, size, data_size) = sum_files(&dir.children);
+                file_count += count;
+                total_size += size;
+                total_data_size += data_size;
+            },
+            Entry::File(file) => {
+                file_count += 1;
+                total_size += file.inline_size as u64 + file.size as u64;
+                total_data_size += file.size as u64;
+            }
+        }
     }
+
+    (file_count, total_size, total_data_size)
 }
 
 fn recursive_file_list<'a>(entries: &'a HashMap<String, Entry>, pathbuf: &mut String, list: &mut Vec<(String, &'a File)>) {
@@ -594,3 +1111,450 @@ fn recursive_file_list<'a>(entries: &'a HashMap<String, Entry>, pathbuf: &mut St
         pathbuf.truncate(len);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pack::{pack, PackOptions};
+    use crate::util::{TempDir, TestFile, test_dir_vpk};
+    use crate::warnings::Warnings;
+
+    #[test]
+    fn verify_file_crc_with_polynomial() {
+        let tmp = TempDir::new("verify-file-crc");
+        tmp.write("in/m/a.bin", &[0x17; 3000]);
+
+        let options = PackOptions::builder()
+            .max_inline_size(0)
+            .crc(CrcAlgorithm::Castagnoli)
+            .quiet(true)
+            .build().unwrap();
+        let package = pack(tmp.path.join("out_dir.vpk"), tmp.path.join("in"), options).unwrap();
+        let file = package.get_file("m/a.bin").unwrap();
+        let mut archs = ArchiveCache::for_package(&package);
+
+        assert!(package.verify_file_crc(file, &mut archs, CrcAlgorithm::Castagnoli).unwrap());
+        assert!(!package.verify_file_crc(file, &mut archs, CrcAlgorithm::IEEE).unwrap());
+    }
+
+    #[test]
+    fn lenient_illegal_terminator() {
+        let mut data = test_dir_vpk(&[
+            ("m/a.txt", DIR_INDEX, 0, 0, b""),
+            ("m/b.txt", DIR_INDEX, 0, 0, b""),
+            ("m/c.txt", DIR_INDEX, 0, 0, b""),
+        ], b"");
+        let terminators: Vec<usize> = data.windows(2)
+            .enumerate()
+            .filter(|(_, bytes)| bytes == &[0xFF, 0xFF])
+            .map(|(pos, _)| pos)
+            .collect();
+        assert_eq!(terminators.len(), 3);
+        data[terminators[1]] = 0x12;
+
+        let tmp = TempDir::new("lenient-terminator");
+        let path = tmp.write("pak_dir.vpk", &data);
+
+        let offset = match Package::from_path(&path, ReadOptions::new()) {
+            Err(Error { error_type: ErrorType::IllegalTerminator { terminator: 0xFF12, offset }, .. }) => offset,
+            Err(error) => panic!("unexpected error: {}", error),
+            Ok(_) => panic!("illegal terminator not detected"),
+        };
+
+        let package = Package::from_path(&path, ReadOptions { lenient: true, ..ReadOptions::new() }).unwrap();
+        assert_eq!(package.truncated_at(), Some(offset));
+        assert_eq!(package.file_count(), 1);
+        assert!(package.get_file("m/a.txt").is_some());
+        assert!(package.get_file("m/b.txt").is_none());
+
+        let mut warnings = Warnings::new(false, true);
+        warnings.warn_truncated(&package);
+        assert_eq!(warnings.messages(), [
+            format!("illegal terminator at offset {}, ignored the rest of the index", offset)]);
+
+        let package = Package::from_path(tmp.write("ok_dir.vpk", &test_dir_vpk(&[("m/a.txt", DIR_INDEX, 0, 0, b"")], b"")), ReadOptions::new()).unwrap();
+        assert_eq!(package.truncated_at(), None);
+    }
+
+    #[test]
+    fn counters_match_stats() {
+        use crate::stats::Stats;
+
+        let tmp = TempDir::new("package-counters");
+        tmp.write("pak_000.vpk", &[0; 300]);
+        let path = tmp.write("pak_dir.vpk", &test_dir_vpk(&[
+            ("m/a.bin",     0,         0,   100, b"head"),
+            ("m/b.bin",     0,         100, 200, b""),
+            ("m/b.bin",     0,         100, 150, b""),
+            ("m/sub/c.txt", DIR_INDEX, 0,   0,   b"inline"),
+            ("n/d.bin",     DIR_INDEX, 0,   3,   b""),
+        ], b"abc"));
+        let package = Package::from_path(&path, ReadOptions::new()).unwrap();
+
+        assert_eq!(package.file_count(), 4);
+        assert_eq!(package.total_data_size(), 100 + 150 + 3);
+        assert_eq!(package.total_size(), 4 + 100 + 150 + 6 + 3);
+
+        let stats = Stats::scan(&package, None);
+        assert_eq!(package.file_count(), stats.file_count());
+        assert_eq!(package.total_data_size(), stats.sum_data_size());
+        assert_eq!(package.total_size(), stats.sum_inline_size() + stats.sum_data_size());
+
+        tmp.write("in/m/a.bin", &[1; 2000]);
+        tmp.write("in/m/b.txt", b"inline");
+        let options = PackOptions::builder().quiet(true).build().unwrap();
+        let package = pack(tmp.path.join("out_dir.vpk"), tmp.path.join("in"), options).unwrap();
+        let stats = Stats::scan(&package, None);
+        assert_eq!(package.file_count(), stats.file_count());
+        assert_eq!(package.total_data_size(), stats.sum_data_size());
+    }
+
+    #[test]
+    fn from_reader_in_memory() {
+        use std::io::Cursor;
+        use crate::sort::DEFAULT_ORDER;
+
+        let index = test_dir_vpk(&[
+            ("m/a.txt", DIR_INDEX, 0, 3, b"ab"),
+            ("m/b.bin", 2,         8, 100, b""),
+        ], b"cde");
+        let package = Package::from_reader(Cursor::new(index), "games/hl2", "pak01", ReadOptions::new()).unwrap();
+
+        assert_eq!(package.version(), 1);
+        assert_eq!(package.file_count(), 2);
+        assert_eq!(package.dirpath(), Path::new("games/hl2"));
+        assert_eq!(package.prefix(), "pak01");
+        assert_eq!(package.archive_path(2), Path::new("games/hl2/pak01_002.vpk"));
+        assert_eq!(package.archive_path(DIR_INDEX), Path::new("games/hl2/pak01_dir.vpk"));
+        assert_eq!(package.get_file("m/a.txt").unwrap().preload, b"ab");
+        assert_eq!(package.get_file("m/b.bin").unwrap().offset, 8);
+
+        // a packed v2 package read into memory is the same as when read from disk
+        let tmp = TempDir::new("from-reader");
+        tmp.write("in/m/a.bin", &[1; 3000]);
+        tmp.write("in/s/b.txt", b"hello");
+        let options = PackOptions::builder().version(2).quiet(true).build().unwrap();
+        let dirpath = tmp.path.join("out_dir.vpk");
+        pack(&dirpath, tmp.path.join("in"), options).unwrap();
+
+        let from_path = Package::from_path(&dirpath, ReadOptions::new()).unwrap();
+        let data = fs::read(&dirpath).unwrap();
+        let from_reader = Package::from_reader(Cursor::new(data), &tmp.path, "out", ReadOptions::new()).unwrap();
+
+        let files = |package: &Package| -> Vec<(String, u32, u16, u32, u32, Vec<u8>)> {
+            package.recursive_file_list(&DEFAULT_ORDER).into_iter()
+                .map(|(path, file)| (path, file.crc32, file.archive_index, file.offset, file.size, file.preload.clone()))
+                .collect()
+        };
+        assert_eq!(from_reader.version(), 2);
+        assert_eq!(files(&from_reader), files(&from_path));
+        assert_eq!(from_reader.archive_path(DIR_INDEX), dirpath);
+    }
+
+
+    #[test]
+    fn duplicate_entries() {
+        use std::io::Cursor;
+
+        let index = test_dir_vpk(&[
+            ("m/a.bin", DIR_INDEX, 0, 1, b""),
+            ("m/b.bin", DIR_INDEX, 1, 1, b""),
+            ("x/a.bin", DIR_INDEX, 2, 1, b""),
+            ("m/a.bin", DIR_INDEX, 3, 1, b""),
+            ("m/a.bin", DIR_INDEX, 4, 1, b""),
+        ], b"abcde");
+        let package = Package::from_reader(Cursor::new(index), ".", "pak", ReadOptions::new()).unwrap();
+
+        assert_eq!(package.duplicate_warnings(), ["m/a.bin", "m/a.bin"]);
+        assert_eq!(package.file_count(), 3);
+        assert_eq!(package.total_data_size(), 3);
+        // the last entry wins
+        assert_eq!(package.stored_offset(package.get_file("m/a.bin").unwrap()), 4);
+
+        let index = test_dir_vpk(&[
+            ("m/a.bin", DIR_INDEX, 0, 1, b""),
+            ("x/a.bin", DIR_INDEX, 1, 1, b""),
+        ], b"ab");
+        let package = Package::from_reader(Cursor::new(index), ".", "pak", ReadOptions::new()).unwrap();
+        assert!(package.duplicate_warnings().is_empty());
+    }
+
+
+    #[test]
+    fn validate_offsets_out_of_bounds() {
+        let tmp = TempDir::new("validate-offsets");
+        tmp.write("pak_000.vpk", &[0; 100]);
+        let files: &[TestFile] = &[
+            ("m/a.bin", 0,         0,  100, b""),
+            ("m/b.bin", DIR_INDEX, 0,  10,  b""),
+        ];
+        let dirpath = tmp.write("pak_dir.vpk", &test_dir_vpk(files, &[0; 10]));
+        let package = Package::from_path(&dirpath, ReadOptions::new()).unwrap();
+        assert!(package.validate_offsets().is_ok());
+
+        // one byte past the end of the archive
+        tmp.write("pak_000.vpk", &[0; 99]);
+        let error = package.validate_offsets().unwrap_err();
+        assert!(matches!(error.error_type(), ErrorType::SanityCheckFaild(message) if message.contains("m/a.bin")), "{:?}", error);
+        assert_eq!(error.path().as_deref(), Some(tmp.path.join("pak_000.vpk").as_path()));
+
+        // data past the end of the _dir.vpk file
+        tmp.write("pak_000.vpk", &[0; 100]);
+        let dirpath = tmp.write("pak_dir.vpk", &test_dir_vpk(files, &[0; 9]));
+        let package = Package::from_path(&dirpath, ReadOptions::new()).unwrap();
+        let error = package.validate_offsets().unwrap_err();
+        assert!(matches!(error.error_type(), ErrorType::SanityCheckFaild(message) if message.contains("m/b.bin")), "{:?}", error);
+        assert_eq!(error.path().as_deref(), Some(dirpath.as_path()));
+
+        fs::remove_file(tmp.path.join("pak_000.vpk")).unwrap();
+        let error = package.validate_offsets().unwrap_err();
+        assert!(error.is_missing_archive(), "{:?}", error);
+    }
+
+    #[test]
+    fn walk_visits_every_entry() {
+        let files: Vec<TestFile> = vec![
+            ("a/b/c.txt", DIR_INDEX, 0, 0, b"c"),
+            ("a/d.txt",   DIR_INDEX, 0, 0, b"d"),
+            ("e/f.txt",   DIR_INDEX, 0, 0, b"f"),
+        ];
+        let index = test_dir_vpk(&files, &[]);
+        let package = Package::from_reader(std::io::Cursor::new(&index), ".", "pak", ReadOptions::new()).unwrap();
+
+        let mut visited = Vec::new();
+        package.walk(|path, entry| visited.push((path.to_owned(), entry.is_dir())));
+        visited.sort();
+        assert_eq!(visited, [
+            ("a".to_owned(),         true),
+            ("a/b".to_owned(),       true),
+            ("a/b/c.txt".to_owned(), false),
+            ("a/d.txt".to_owned(),   false),
+            ("e".to_owned(),         true),
+            ("e/f.txt".to_owned(),   false),
+        ]);
+
+        // starting below the root, with the path of the entry as buffer
+        let mut path = String::from("a");
+        let mut visited = Vec::new();
+        package.get("a").unwrap().walk(&mut path, &mut |path, _| visited.push(path.to_owned()));
+        visited.sort();
+        assert_eq!(visited, ["a", "a/b", "a/b/c.txt", "a/d.txt"]);
+        assert_eq!(path, "a");
+
+        // a file is visited on its own
+        let mut path = String::from("e/f.txt");
+        let mut visited = Vec::new();
+        package.get("e/f.txt").unwrap().walk(&mut path, &mut |path, _| visited.push(path.to_owned()));
+        assert_eq!(visited, ["e/f.txt"]);
+
+        let mut count = 0;
+        package.walk(|_, entry| if entry.is_file() { count += 1 });
+        assert_eq!(count, package.file_count());
+    }
+
+    #[test]
+    fn common_prefix() {
+        let package = |paths: &[&str]| {
+            let files: Vec<TestFile> = paths.iter()
+                .map(|path| (*path, DIR_INDEX, 0, 0, &b""[..]))
+                .collect();
+            let index = test_dir_vpk(&files, &[]);
+            Package::from_reader(std::io::Cursor::new(&index), ".", "pak", ReadOptions::new()).unwrap()
+        };
+
+        assert_eq!(package(&["root/a/b.txt", "root/a/c/d.txt"]).common_prefix(), "root/a");
+        assert_eq!(package(&["root/a.txt", "root/b/c.txt"]).common_prefix(), "root");
+        // a lone file is not a directory that could be stripped
+        assert_eq!(package(&["root/sub/a.txt"]).common_prefix(), "root/sub");
+
+        // no shared prefix
+        assert_eq!(package(&["a/b.txt", "c/d.txt"]).common_prefix(), "");
+        assert_eq!(package(&[]).common_prefix(), "");
+    }
+
+    #[test]
+    fn get_file_and_dir() {
+        let files: Vec<TestFile> = vec![
+            ("a/b/c.txt", DIR_INDEX, 0, 0, b"c"),
+            ("a/d.txt",   DIR_INDEX, 0, 0, b"d"),
+        ];
+        let index = test_dir_vpk(&files, &[]);
+        let package = Package::from_reader(std::io::Cursor::new(&index), ".", "pak", ReadOptions::new()).unwrap();
+
+        assert_eq!(package.get_file("a/b/c.txt").unwrap().preload, b"c");
+        assert_eq!(package.get_file("a/d.txt").unwrap().preload, b"d");
+        assert!(package.get_file("a/b").is_none());
+        assert!(package.get_file("a").is_none());
+
+        let mut names: Vec<&str> = package.get_dir("a").unwrap().children.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        assert_eq!(names, ["b", "d.txt"]);
+        assert_eq!(package.get_dir("a/b").unwrap().children.len(), 1);
+        assert!(package.get_dir("a/d.txt").is_none());
+
+        // missing entries and paths through a file
+        for path in ["", "x", "a/x.txt", "a/b/c.txt/x", "a/d.txt/x", "b/c.txt"] {
+            assert!(package.get(path).is_none(), "{:?}", path);
+            assert!(package.get_file(path).is_none(), "{:?}", path);
+            assert!(package.get_dir(path).is_none(), "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn archive_indices_of_files_with_data() {
+        let files: Vec<TestFile> = vec![
+            ("m/a.bin",   2,         0,   10, b""),
+            ("m/b.bin",   0,         0,   10, b""),
+            ("m/c.bin",   2,         10,  10, b"pre"),
+            ("m/d.bin",   DIR_INDEX, 0,   10, b""),
+            // only inline data, the archive index doesn't matter
+            ("m/e.txt",   5,         0,   0,  b"inline"),
+        ];
+        let index = test_dir_vpk(&files, &[0; 10]);
+        let package = Package::from_reader(std::io::Cursor::new(&index), ".", "pak", ReadOptions::new()).unwrap();
+        assert_eq!(package.archive_indices(), [0, 2, DIR_INDEX]);
+
+        let index = test_dir_vpk(&[("m/e.txt", 5, 0, 0, b"inline")], &[]);
+        let package = Package::from_reader(std::io::Cursor::new(&index), ".", "pak", ReadOptions::new()).unwrap();
+        assert!(package.archive_indices().is_empty());
+    }
+
+    #[test]
+    fn clone_is_independent() {
+        let files: Vec<TestFile> = vec![
+            ("a/b.txt", DIR_INDEX, 0, 0, b"b"),
+            ("a/c.txt", DIR_INDEX, 0, 0, b"c"),
+        ];
+        let index = test_dir_vpk(&files, &[]);
+        let original = Package::from_reader(std::io::Cursor::new(&index), ".", "pak", ReadOptions::new()).unwrap();
+
+        let mut clone = original.clone();
+        match clone.get_mut("a/b.txt") {
+            Some(Entry::File(file)) => {
+                file.preload = b"changed".to_vec();
+                file.crc32 = 1;
+            },
+            _ => panic!("a/b.txt is not a file"),
+        }
+        match clone.get_mut("a") {
+            Some(Entry::Dir(dir)) => { dir.children.remove("c.txt"); },
+            _ => panic!("a is not a directory"),
+        }
+
+        assert_eq!(clone.get_file("a/b.txt").unwrap().preload, b"changed");
+        assert!(clone.get_file("a/c.txt").is_none());
+
+        let file = original.get_file("a/b.txt").unwrap();
+        assert_eq!(file.preload, b"b");
+        assert_eq!(file.crc32, crc::crc32::checksum_ieee(b"b"));
+        assert_eq!(original.get_file("a/c.txt").unwrap().preload, b"c");
+        assert_eq!(original.get_dir("a").unwrap().children.len(), 2);
+    }
+
+    #[test]
+    fn archives_in_separate_directory() {
+        use crate::archive_cache::ArchiveCache;
+
+        let tmp = TempDir::new("archive-dir");
+        tmp.write("vpks/pak_000.vpk", b"archived data");
+        // a stale archive next to the _dir.vpk file is ignored
+        tmp.write("patch/pak_000.vpk", b"stale archive");
+        let dirpath = tmp.write("patch/pak_dir.vpk", &test_dir_vpk(&[
+            ("m/a.txt", 0,         0, 13, b""),
+            ("m/b.txt", DIR_INDEX, 0, 8,  b""),
+        ], b"dir data"));
+
+        let package = Package::from_path_with_archive_dir(&dirpath, tmp.path.join("vpks"), ReadOptions::new()).unwrap();
+        assert_eq!(package.archive_path(0), tmp.path.join("vpks/pak_000.vpk"));
+        // data stored in the _dir.vpk file is still read from there
+        assert_eq!(package.archive_path(DIR_INDEX), dirpath);
+        assert!(package.validate_offsets().is_ok());
+
+        let mut archs = ArchiveCache::for_package(&package);
+        assert_eq!(archs.read_file_bytes(package.get_file("m/a.txt").unwrap()).unwrap(), b"archived data");
+        assert_eq!(archs.read_file_bytes(package.get_file("m/b.txt").unwrap()).unwrap(), b"dir data");
+
+        let package = Package::from_path(&dirpath, ReadOptions::new()).unwrap();
+        assert_eq!(package.archive_path(0), tmp.path.join("patch/pak_000.vpk"));
+        let mut archs = ArchiveCache::for_package(&package);
+        assert_eq!(archs.read_file_bytes(package.get_file("m/a.txt").unwrap()).unwrap(), b"stale archive");
+
+        fs::remove_file(tmp.path.join("vpks/pak_000.vpk")).unwrap();
+        let package = Package::from_path_with_archive_dir(&dirpath, tmp.path.join("vpks"), ReadOptions::new()).unwrap();
+        let error = package.validate_offsets().unwrap_err();
+        assert!(error.is_missing_archive(), "{:?}", error);
+    }
+
+    #[test]
+    fn signed_and_unsigned() {
+        let tmp = TempDir::new("is-signed");
+        tmp.write("in/m/a.txt", b"a");
+        let options = PackOptions::builder().version(2).quiet(true).build().unwrap();
+        pack(tmp.path.join("pak_dir.vpk"), tmp.path.join("in"), options).unwrap();
+        let unsigned = fs::read(tmp.path.join("pak_dir.vpk")).unwrap();
+
+        let package = Package::from_path(tmp.path.join("pak_dir.vpk"), ReadOptions::new()).unwrap();
+        assert!(!package.is_signed());
+        assert_eq!(package.signature_len(), 0);
+        assert_eq!(package.public_key_len(), 0);
+
+        let index = test_dir_vpk(&[("m/a.txt", DIR_INDEX, 0, 0, b"a")], &[]);
+        let package = Package::from_reader(std::io::Cursor::new(&index), ".", "pak", ReadOptions::new()).unwrap();
+        assert!(!package.is_signed());
+
+        // append a signature section and enter its size in the header
+        let with_signature = |section: &[u8]| {
+            let mut data = unsigned.clone();
+            data[V2_HEADER_SIZE - 4..V2_HEADER_SIZE].copy_from_slice(&(section.len() as u32).to_le_bytes());
+            data.extend_from_slice(section);
+            let path = tmp.write("signed_dir.vpk", &data);
+            Package::from_path(path, ReadOptions::new()).unwrap()
+        };
+        let section = |public_key: &[u8], signature: Option<&[u8]>| {
+            let mut section = (public_key.len() as u32).to_le_bytes().to_vec();
+            section.extend_from_slice(public_key);
+            if let Some(signature) = signature {
+                section.extend_from_slice(&(signature.len() as u32).to_le_bytes());
+                section.extend_from_slice(signature);
+            }
+            section
+        };
+
+        let package = with_signature(&section(&[1; 8], Some(&[2; 16])));
+        assert!(package.is_signed());
+        assert_eq!(package.public_key_len(), 8);
+        assert_eq!(package.signature_len(), 16);
+        assert_eq!(package.signature().unwrap(), &vec![2; 16]);
+
+        // a public key alone or an empty signature is no signature
+        let package = with_signature(&section(&[1; 8], None));
+        assert!(!package.is_signed());
+        assert_eq!(package.public_key_len(), 8);
+
+        let package = with_signature(&section(&[1; 8], Some(&[])));
+        assert!(!package.is_signed());
+    }
+
+    #[test]
+    fn from_path_empty_file_and_directory() {
+        let tmp = TempDir::new("from-path-errors");
+        let empty = tmp.write("empty_dir.vpk", b"");
+        let folder = tmp.path.join("folder_dir.vpk");
+        fs::create_dir(&folder).unwrap();
+
+        let error = Package::from_path(&empty, ReadOptions::new()).err().unwrap();
+        assert_eq!(error.to_string(), format!("{:?}: file is empty", empty));
+        assert_eq!(error.path().as_deref(), Some(empty.as_path()));
+
+        let error = Package::from_path(&folder, ReadOptions::new()).err().unwrap();
+        assert_eq!(error.to_string(), format!("{:?}: not a regular file", folder));
+        assert_eq!(error.path().as_deref(), Some(folder.as_path()));
+
+        // a real I/O error for a missing file
+        let missing = tmp.path.join("missing_dir.vpk");
+        let error = Package::from_path(&missing, ReadOptions::new()).err().unwrap();
+        assert!(matches!(error.error_type(), ErrorType::IO(_)), "{:?}", error);
+        assert_eq!(error.path().as_deref(), Some(missing.as_path()));
+    }
+
+}
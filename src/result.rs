@@ -21,6 +21,7 @@ pub enum ErrorType {
     StringFromUTF8(std::string::FromUtf8Error),
     StrFromUTF8(std::str::Utf8Error),
     IllegalMagic(crate::package::Magic),
+    BigEndian,
     UnsupportedVersion(u32),
     IllegalTerminator { terminator: u16, offset: u64 },
     EntryNotADir(String),
@@ -28,6 +29,9 @@ pub enum ErrorType {
     IllegalArgument { name: &'static str, value: String },
     UnexpectedEOF,
     SanityCheckFaild(String),
+    /// The archive with the given index doesn't exist, e.g. because a
+    /// package was only partially downloaded.
+    MissingArchive(u16),
     Other(String),
 }
 
@@ -64,6 +68,13 @@ impl Error {
         }
     }
 
+    /// Whether this error is about an archive that doesn't exist, see
+    /// [`ErrorType::MissingArchive`].
+    #[inline]
+    pub fn is_missing_archive(&self) -> bool {
+        matches!(self.error_type, ErrorType::MissingArchive(_))
+    }
+
     #[inline]
     pub fn io_with_path(error: std::io::Error, path: impl AsRef<Path>) -> Self {
         Error {
@@ -112,6 +123,14 @@ impl Error {
         }
     }
 
+    #[inline]
+    pub fn big_endian() -> Self {
+        Error {
+            path:       None,
+            error_type: ErrorType::BigEndian,
+        }
+    }
+
     #[inline]
     pub fn unsupported_version(version: u32) -> Self {
         Error {
@@ -150,6 +169,14 @@ impl Error {
         }
     }
 
+    #[inline]
+    pub fn missing_archive(archive_index: u16) -> Self {
+        Error {
+            path:       None,
+            error_type: ErrorType::MissingArchive(archive_index),
+        }
+    }
+
     #[inline]
     pub fn sanity_check_failed(message: impl AsRef<str>) -> Self {
         Error {
@@ -168,6 +195,7 @@ impl std::fmt::Display for ErrorType {
             ErrorType::StringFromUTF8(err)             => err.fmt(f),
             ErrorType::StrFromUTF8(err)                => err.fmt(f),
             ErrorType::IllegalMagic(magic)             => write!(f, "illegal file magic: {:02X} {:02X} {:02X} {:02X}", magic[0], magic[1], magic[2], magic[3]),
+            ErrorType::BigEndian                       => write!(f, "big-endian VPK packages (e.g. from game consoles) are not supported"),
             ErrorType::UnsupportedVersion(version)     => write!(f, "version {} is not supported", version),
             ErrorType::IllegalTerminator { terminator, offset } => write!(f, "illegal terminator 0x{:02x} at offset {}", terminator, offset),
             ErrorType::EntryNotADir(path)              => write!(f, "entry is not a directory: {:?}", path),
@@ -175,6 +203,7 @@ impl std::fmt::Display for ErrorType {
             ErrorType::IllegalArgument { name, value } => write!(f, "illegal argument for {}: {:?}", name, value),
             ErrorType::UnexpectedEOF                   => write!(f, "unexpected end of file"),
             ErrorType::SanityCheckFaild(msg)           => msg.fmt(f),
+            ErrorType::MissingArchive(index)           => write!(f, "archive {} is missing", crate::util::format_archive(*index)),
             ErrorType::Other(msg)                      => msg.fmt(f),
         }
     }
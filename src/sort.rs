@@ -130,9 +130,24 @@ fn make_chain(cmp1: Box<dyn Fn(&Item, &Item) -> Ordering>, mut iter: std::slice:
     }
 }
 
+/// Sort files by the given keys. Files that are equal in respect to all
+/// keys are sorted by name, so the result doesn't depend on the order in
+/// which the entries were collected. An empty order doesn't sort at all.
 pub fn sort(list: &mut Vec<(String, &File)>, order: &Order) {
     let mut iter = order.iter();
 
+    if let Some(first_key) = iter.next() {
+        let cmp = make_chain(Box::new(first_key.to_cmp()), iter);
+        let cmp = chain(cmp, Box::new(SortKey::Name.to_cmp()));
+        list.sort_by(cmp);
+    }
+}
+
+/// Like [`sort`], but without sorting by name as the last key. The order of
+/// files that are equal in respect to all keys is unspecified.
+pub fn sort_raw(list: &mut Vec<(String, &File)>, order: &Order) {
+    let mut iter = order.iter();
+
     if let Some(first_key) = iter.next() {
         let cmp = make_chain(Box::new(first_key.to_cmp()), iter);
         list.sort_by(cmp);
@@ -146,3 +161,52 @@ pub fn parse_order(value: &str) -> Result<Vec<SortKey>> {
     }
     Ok(order)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::DIR_INDEX;
+
+    fn file(index: usize, size: u32) -> File {
+        File {
+            index,
+            crc32: 0,
+            inline_size: 0,
+            archive_index: DIR_INDEX,
+            offset: 0,
+            size,
+            preload: Vec::new(),
+        }
+    }
+
+    fn names(list: &[Item]) -> Vec<String> {
+        list.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    #[test]
+    fn ties_sorted_by_name() {
+        let files: Vec<File> = (0..20).map(|index| file(index, if index % 3 == 0 { 10 } else { 20 })).collect();
+        let mut forward: Vec<Item> = files.iter().enumerate().map(|(index, file)| (format!("f{:02}.bin", index), file)).collect();
+        let mut backward: Vec<Item> = forward.iter().rev().cloned().collect();
+        let mut shuffled: Vec<Item> = (0..20).map(|index| forward[index * 7 % 20].clone()).collect();
+
+        let order = [SortKey::FullSize];
+        sort(&mut forward,  &order);
+        sort(&mut backward, &order);
+        sort(&mut shuffled, &order);
+        assert_eq!(names(&forward), names(&backward));
+        assert_eq!(names(&forward), names(&shuffled));
+
+        let expected: Vec<String> = (0..20).filter(|index| index % 3 == 0)
+            .chain((0..20).filter(|index| index % 3 != 0))
+            .map(|index| format!("f{:02}.bin", index))
+            .collect();
+        assert_eq!(names(&forward), expected);
+
+        // without the name as tie breaker only the given keys are sorted
+        sort_raw(&mut shuffled, &[SortKey::RevFullSize]);
+        let sizes: Vec<u32> = shuffled.iter().map(|(_, file)| file.size).collect();
+        assert!(sizes.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert_eq!(sizes.iter().filter(|&&size| size == 20).count(), 13);
+    }
+}
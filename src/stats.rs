@@ -20,7 +20,9 @@ use crate::package::{Package, Md5};
 use crate::result::Result;
 use crate::consts::DIR_INDEX;
 use crate::entry::Entry;
+use crate::util;
 use crate::util::{format_size, print_headless_table, print_table, Align::*};
+use crate::color::{paint, Color};
 
 pub struct ArchStats {
     file_count: usize,
@@ -87,6 +89,40 @@ pub struct Stats<'a> {
     sum_archive_size: u64,
     min_md5_chunk_size: u32,
     max_md5_chunk_size: u32,
+    alignment:         Option<u32>,
+    unaligned_count:   usize,
+    alignment_padding: u64,
+    inline_file_count: usize,
+    sum_inline_size:   u64,
+    sum_data_size:     u64,
+    index_size:        u64,
+}
+
+pub struct StatsOptions {
+    pub human_readable: bool,
+    pub alignment: Option<u32>,
+    pub color: bool,
+    /// Only print the per-archive table, see [`print_archive_stats`].
+    pub archives_only: bool,
+}
+
+impl StatsOptions {
+    #[inline]
+    pub fn new() -> Self {
+        StatsOptions::default()
+    }
+}
+
+impl Default for StatsOptions {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            human_readable: false,
+            alignment: None,
+            color: false,
+            archives_only: false,
+        }
+    }
 }
 
 impl<'a> Stats<'a> {
@@ -104,6 +140,13 @@ impl<'a> Stats<'a> {
             sum_archive_size: 0,
             min_md5_chunk_size: 0,
             max_md5_chunk_size: 0,
+            alignment:         None,
+            unaligned_count:   0,
+            alignment_padding: 0,
+            inline_file_count: 0,
+            sum_inline_size:   0,
+            sum_data_size:     0,
+            index_size:        0,
         }
     }
 
@@ -155,9 +198,57 @@ impl<'a> Stats<'a> {
         self.max_md5_chunk_size
     }
 
+    pub fn alignment(&self) -> Option<u32> {
+        self.alignment
+    }
+
+    /// Number of files with archive data that don't start at a multiple of
+    /// the alignment. Always 0 if no alignment was given.
+    pub fn unaligned_count(&self) -> usize {
+        self.unaligned_count
+    }
+
+    /// Sum of the bytes that would need to be inserted before unaligned
+    /// files so that they start at a multiple of the alignment.
+    pub fn alignment_padding(&self) -> u64 {
+        self.alignment_padding
+    }
+
+    /// Number of files that are fully stored in the index.
+    pub fn inline_file_count(&self) -> usize {
+        self.inline_file_count
+    }
+
+    /// Sum of the data of all files that is stored in the index.
+    pub fn sum_inline_size(&self) -> u64 {
+        self.sum_inline_size
+    }
+
+    /// Sum of the data of all files that is stored in archives.
+    pub fn sum_data_size(&self) -> u64 {
+        self.sum_data_size
+    }
+
+    /// Fraction of files that are fully stored in the index.
+    pub fn inline_count_ratio(&self) -> f64 {
+        ratio(self.inline_file_count as u64, self.file_count as u64)
+    }
+
+    /// Fraction of the bytes of all files that is stored in the index.
+    pub fn inline_size_ratio(&self) -> f64 {
+        ratio(self.sum_inline_size, self.sum_inline_size + self.sum_data_size)
+    }
 
-    pub fn scan(package: &'a Package) -> Self {
+    /// Fraction of the package size (header, index and file data) used by
+    /// the header and the index without the inlined file data.
+    pub fn index_overhead_ratio(&self) -> f64 {
+        ratio(self.index_size.saturating_sub(self.sum_inline_size), self.index_size + self.sum_data_size)
+    }
+
+    pub fn scan(package: &'a Package, alignment: Option<u32>) -> Self {
         let mut stats = Self::new();
+        stats.alignment = alignment;
+        stats.index_size = package.data_offset as u64;
         stats.scan_entries(&package.entries);
 
         for (archive_index, archstat) in stats.archmap.iter_mut() {
@@ -212,6 +303,11 @@ impl<'a> Stats<'a> {
                 },
                 Entry::File(file) => {
                     self.file_count += 1;
+                    self.sum_inline_size += file.inline_size as u64;
+                    self.sum_data_size   += file.size as u64;
+                    if file.size == 0 {
+                        self.inline_file_count += 1;
+                    }
                     let dot_index = name.rfind('.').unwrap();
                     let ext = &name[dot_index + 1..];
 
@@ -256,27 +352,58 @@ impl<'a> Stats<'a> {
                     if full_size > self.max_full_size {
                         self.max_full_size = full_size;
                     }
+
+                    if let Some(alignment) = self.alignment {
+                        // inline data has no place in any archive
+                        if file.size > 0 {
+                            let remainder = file.offset % alignment;
+                            if remainder != 0 {
+                                self.unaligned_count   += 1;
+                                self.alignment_padding += (alignment - remainder) as u64;
+                            }
+                        }
+                    }
                 }
             }
         }
     }
 }
 
+#[inline]
+fn ratio(part: u64, total: u64) -> f64 {
+    if total == 0 { 0.0 } else { part as f64 / total as f64 }
+}
+
+#[inline]
+fn format_percent(ratio: f64) -> String {
+    format!("{:.1} %", ratio * 100.0)
+}
+
 fn format_md5(md5: Option<&Md5>) -> String {
     if let Some(md5) = md5 {
-        format!(
-            "{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
-            md5[0], md5[1], md5[2],  md5[ 3], md5[ 4], md5[ 5], md5[ 6], md5[ 7],
-            md5[8], md5[9], md5[10], md5[11], md5[12], md5[13], md5[14], md5[15],
-        )
+        util::format_md5(md5)
     } else {
         "".to_owned()
     }
 }
 
-pub fn stats(package: &Package, human_readable: bool) -> Result<()> {
-    let stats = Stats::scan(package);
+pub fn stats(package: &Package, options: StatsOptions) -> Result<()> {
+    let stats = Stats::scan(package, options.alignment);
+    if options.archives_only {
+        print_archive_stats(&stats, options.human_readable, options.color);
+        return Ok(());
+    }
+    print_stats(package, &stats, options.human_readable, options.color)
+}
 
+/// Print statistics as returned by [`Stats::scan`].
+///
+/// ```ignore
+/// let package = Package::from_path("pak01_dir.vpk", ReadOptions::new())?;
+/// let stats = Stats::scan(&package, None);
+/// println!("{} files in {} archives", stats.file_count(), stats.archives().len());
+/// ```
+pub fn print_stats(package: &Package, stats: &Stats, human_readable: bool, color: bool) -> Result<()> {
     let fmt_size = if human_readable {
         |size: u64| format_size(size)
     } else {
@@ -284,7 +411,7 @@ pub fn stats(package: &Package, human_readable: bool) -> Result<()> {
     };
 
     let wasted = if stats.sum_used_size > stats.sum_archive_size {
-        "Error: Used size bigger than file size!".to_owned()
+        paint("Error: Used size bigger than file size!", Color::Red, color)
     } else {
         fmt_size(stats.sum_archive_size - stats.sum_used_size)
     };
@@ -297,7 +424,11 @@ pub fn stats(package: &Package, human_readable: bool) -> Result<()> {
         vec!["Directory Count:", &format!("{}", stats.dir_count)],
         vec!["Extension Count:", &format!("{}", stats.extmap.len())],
         vec!["Archive Count:",   &format!("{}", stats.archmap.len())],
-        vec!["IO Error Count:",  &format!("{}", stats.error_count)],
+        vec!["IO Error Count:",  &if stats.error_count > 0 {
+            paint(format!("{}", stats.error_count), Color::Red, color)
+        } else {
+            "0".to_owned()
+        }],
         vec![],
         vec!["Max Inline-Size:",       &fmt_size(stats.max_inline_size as u64)],
         vec!["Max Non-Inline-Size:",   &fmt_size(stats.max_size as u64)],
@@ -307,6 +438,25 @@ pub fn stats(package: &Package, human_readable: bool) -> Result<()> {
         vec!["Wasted Size:",           &wasted],
     ], &[Left, Right]);
 
+    println!();
+
+    print_headless_table(&[
+        vec!["Inline Files:",   &format!("{}", stats.inline_file_count), &format_percent(stats.inline_count_ratio())],
+        vec!["Inline Size:",    &fmt_size(stats.sum_inline_size),       &format_percent(stats.inline_size_ratio())],
+        vec!["Archive Size:",   &fmt_size(stats.sum_data_size),         &format_percent(1.0 - stats.inline_size_ratio())],
+        vec!["Index Overhead:", "",                                      &format_percent(stats.index_overhead_ratio())],
+    ], &[Left, Right, Right]);
+
+    if let Some(alignment) = stats.alignment {
+        println!();
+
+        print_headless_table(&[
+            vec!["Alignment:",         &fmt_size(alignment as u64)],
+            vec!["Unaligned Files:",   &format!("{}", stats.unaligned_count)],
+            vec!["Alignment Padding:", &fmt_size(stats.alignment_padding)],
+        ], &[Left, Right]);
+    }
+
     let header_size = package.header_size();
     if package.version > 1 {
         println!();
@@ -318,8 +468,9 @@ pub fn stats(package: &Package, human_readable: bool) -> Result<()> {
             vec!["Index MD5:",         if package.index_md5().is_some()        { "Yes" } else { "No" }, &format_md5(package.index_md5())],
             vec!["Archive MD5s MD5:",  if package.archive_md5s_md5().is_some() { "Yes" } else { "No" }, &format_md5(package.archive_md5s_md5())],
             vec!["Everything MD5:",    if package.everything_md5().is_some()   { "Yes" } else { "No" }, &format_md5(package.everything_md5())],
-            vec!["Public Key:",        if package.public_key().is_some()       { "Yes" } else { "No" }],
-            vec!["Signature:",         if package.signature().is_some()        { "Yes" } else { "No" }],
+            vec!["Signed:",            if package.is_signed()                  { "Yes" } else { "No" }],
+            vec!["Public Key:",        if package.public_key().is_some()       { "Yes" } else { "No" }, &fmt_size(package.public_key_len() as u64)],
+            vec!["Signature:",         if package.signature().is_some()        { "Yes" } else { "No" }, &fmt_size(package.signature_len() as u64)],
         ], &[Left, Right, Left]);
 
         let archive_md5s_offset = package.data_offset + package.data_size;
@@ -374,6 +525,20 @@ pub fn stats(package: &Package, human_readable: bool) -> Result<()> {
 
     println!();
 
+    print_archive_stats(stats, human_readable, color);
+
+    Ok(())
+}
+
+/// Print the per-archive part of [`print_stats`], i.e. the size of each
+/// archive file on disk compared to the size used by the index.
+pub fn print_archive_stats(stats: &Stats, human_readable: bool, color: bool) {
+    let fmt_size = if human_readable {
+        |size: u64| format_size(size)
+    } else {
+        |size: u64| format!("{}", size)
+    };
+
     let mut arch_indices: Vec<u16> = stats.archmap.keys().map(|index| *index).collect();
     arch_indices.sort();
 
@@ -401,7 +566,7 @@ pub fn stats(package: &Package, human_readable: bool) -> Result<()> {
         ];
 
         if let Some(io_error) = &archstats.io_error {
-            row.push(format!("{}", io_error));
+            row.push(paint(format!("{}", io_error), Color::Red, color));
         }
 
         row
@@ -420,6 +585,70 @@ pub fn stats(package: &Package, human_readable: bool) -> Result<()> {
             &body
         );
     }
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::ReadOptions;
+    use crate::util::{TempDir, test_dir_vpk};
+
+    #[test]
+    fn inline_and_archive_ratios() {
+        let tmp = TempDir::new("stats-ratios");
+        tmp.write("pak_000.vpk", &[0x42; 150]);
+        let dirpath = tmp.write("pak_dir.vpk", &test_dir_vpk(&[
+            ("m/inline.txt", DIR_INDEX, 0,   0,   b"0123456789"),
+            ("m/arch.bin",   0,         0,   100, b""),
+            ("m/split.bin",  0,         100, 50,  b"abcd"),
+        ], &[]));
+
+        let package = Package::from_path(&dirpath, ReadOptions::new()).unwrap();
+        let stats = Stats::scan(&package, None);
+        let index_size = package.data_offset as u64;
+
+        assert_eq!(stats.file_count(), 3);
+        assert_eq!(stats.inline_file_count(), 1);
+        assert_eq!(stats.sum_inline_size(), 14);
+        assert_eq!(stats.sum_data_size(), 150);
+        assert_eq!(stats.error_count(), 0);
+        assert_eq!(stats.inline_count_ratio(), 1.0 / 3.0);
+        assert_eq!(stats.inline_size_ratio(), 14.0 / 164.0);
+        assert_eq!(stats.index_overhead_ratio(), (index_size - 14) as f64 / (index_size + 150) as f64);
+    }
+
+    #[test]
+    fn scan_without_printing() {
+        let tmp = TempDir::new("stats-scan");
+        tmp.write("pak_000.vpk", &[0; 100]);
+        tmp.write("pak_001.vpk", &[0; 50]);
+        let dirpath = tmp.write("pak_dir.vpk", &test_dir_vpk(&[
+            ("m/a.bin", 0, 0,  60, b""),
+            ("m/b.bin", 0, 60, 40, b""),
+            ("s/c.bin", 1, 0,  30, b""),
+        ], &[]));
+
+        let package = Package::from_path(&dirpath, ReadOptions::new()).unwrap();
+        let stats = Stats::scan(&package, None);
+
+        assert_eq!(stats.file_count(), 3);
+        assert_eq!(stats.dir_count(), 2);
+        assert_eq!(stats.archives().len(), 2);
+        assert_eq!(stats.archives()[&0].file_size(), Some(100));
+        assert_eq!(stats.archives()[&1].file_size(), Some(50));
+        assert_eq!(stats.sum_archive_size(), 150);
+        assert_eq!(stats.extensions()["bin"].file_count(), 3);
+    }
+
+    #[test]
+    fn index_overhead_ratio_saturates() {
+        let mut stats = Stats::new();
+        assert_eq!(stats.index_overhead_ratio(), 0.0);
+
+        // a damaged index can claim more inline data than the index holds
+        stats.index_size      = 10;
+        stats.sum_inline_size = 20;
+        stats.sum_data_size   = 90;
+        assert_eq!(stats.index_overhead_ratio(), 0.0);
+    }
 }
@@ -0,0 +1,118 @@
+// This file is part of rust-vpk.
+//
+// rust-vpk is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rust-vpk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rust-vpk.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use crate::package::Package;
+use crate::entry::Entry;
+use crate::result::{Result, Error};
+use crate::util::format_size;
+
+pub struct TreeOptions<'a> {
+    pub human_readable: bool,
+    pub depth: Option<usize>,
+    pub path:  Option<&'a str>,
+}
+
+impl TreeOptions<'_> {
+    #[inline]
+    pub fn new() -> Self {
+        TreeOptions::default()
+    }
+}
+
+impl Default for TreeOptions<'_> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            human_readable: false,
+            depth: None,
+            path:  None,
+        }
+    }
+}
+
+fn tree_lines(entries: &HashMap<String, Entry>, prefix: &mut String, depth: Option<usize>, lines: &mut Vec<(String, Option<u64>)>) {
+    let mut names: Vec<&String> = entries.keys().collect();
+    names.sort();
+
+    let count = names.len();
+    for (index, name) in names.into_iter().enumerate() {
+        let is_last = index + 1 == count;
+        let connector = if is_last { "└── " } else { "├── " };
+        let line = format!("{}{}{}", prefix, connector, name);
+
+        match &entries[name] {
+            Entry::Dir(dir) => {
+                lines.push((line, None));
+
+                if depth != Some(1) {
+                    let len = prefix.len();
+                    prefix.push_str(if is_last { "    " } else { "│   " });
+                    tree_lines(&dir.children, prefix, depth.map(|depth| depth - 1), lines);
+                    prefix.truncate(len);
+                }
+            },
+            Entry::File(file) => {
+                lines.push((line, Some(file.inline_size as u64 + file.size as u64)));
+            }
+        }
+    }
+}
+
+/// Print the directory hierarchy of a package like the `tree` command does.
+pub fn tree(package: &Package, options: TreeOptions) -> Result<()> {
+    let (root_name, entries) = match options.path {
+        None => ("/", package.root()),
+        Some(path) => {
+            let path = path.trim_matches('/');
+            if path.is_empty() {
+                ("/", package.root())
+            } else {
+                match package.get(path) {
+                    Some(Entry::Dir(dir)) => (path, &dir.children),
+                    Some(Entry::File(_))  => return Err(Error::entry_not_a_dir(path)),
+                    None => return Err(Error::no_such_entry(path)),
+                }
+            }
+        }
+    };
+
+    let mut lines = Vec::new();
+    if options.depth != Some(0) {
+        let mut prefix = String::new();
+        tree_lines(entries, &mut prefix, options.depth, &mut lines);
+    }
+
+    println!("{}", root_name);
+
+    if options.human_readable {
+        let width = lines.iter().map(|(line, _)| line.chars().count()).max().unwrap_or(0);
+        for (line, size) in &lines {
+            if let Some(size) = size {
+                let padding = width - line.chars().count();
+                println!("{}{:padding$}  {:>8}", line, "", format_size(*size), padding = padding);
+            } else {
+                println!("{}", line);
+            }
+        }
+    } else {
+        for (line, _) in &lines {
+            println!("{}", line);
+        }
+    }
+
+    Ok(())
+}
@@ -16,21 +16,71 @@
 use std::path::{Path};
 use std::io::{Write};
 use std::fs;
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
 
-use crc::{crc32, Hasher32};
+use crc::Hasher32;
 
 use crate::sort::PHYSICAL_ORDER;
 use crate::archive_cache::ArchiveCache;
 use crate::package::Package;
+use crate::entry::{Entry, File};
+use crate::check::check_range;
 use crate::result::{Result, Error};
-use crate::util::split_path;
-use crate::consts::DIR_INDEX;
+use crate::util::{split_path, format_archive, retain_extensions};
+use crate::consts::{DIR_INDEX, BUFFER_SIZE};
+use crate::warnings::Warnings;
+use crate::hash::CrcAlgorithm;
+use crate::filter_file::FilterFile;
+
+/// What to do when two files have the same name when unpacking with
+/// flattened directory structure.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flatten {
+    /// Fail before anything is written.
+    Error,
+    /// Append `-N` to the name of every further file, e.g. `name-1.ext`.
+    Rename,
+}
 
 pub struct UnpackOptions<'a> {
     pub filter:               Option<&'a [&'a str]>,
+    pub extensions:           Option<&'a [&'a str]>,
+    /// Exclude files matching these patterns, additionally to `filter`.
+    /// Paths are relative to `root`, if given.
+    pub filter_file:          Option<&'a FilterFile>,
     pub verbose:              bool,
+    /// Don't print warnings. Errors are still reported.
+    pub quiet:                bool,
     pub check:                bool,
+    pub verify:               bool,
     pub dirname_from_archive: bool,
+    /// Only extract the files below this directory inside of the package,
+    /// with the directory stripped from their paths. `filter` paths are
+    /// then relative to this directory.
+    pub root:                 Option<&'a str>,
+    pub strip_components:     usize,
+    /// Directory inside of the package that is prepended to the paths of
+    /// all extracted files, after stripping `strip_components`.
+    pub prefix:               Option<&'a str>,
+    /// Write all files directly into the output directory (or the
+    /// directory given by `prefix`) using only their file names.
+    pub flatten:              Option<Flatten>,
+    /// Must not be 0.
+    pub buffer_size:          usize,
+    pub retries:              u32,
+    pub max_open_files:       usize,
+    pub color:                bool,
+    /// Skip files whose archive doesn't exist instead of failing, e.g. for
+    /// partially downloaded packages. The number of skipped files is
+    /// reported as a warning at the end.
+    pub ignore_missing:       bool,
+    /// Set the modification time of every extracted file to the one of the
+    /// archive its data is read from (the _dir.vpk file for inlined files).
+    /// The VPK format has no time information for the files themselves.
+    pub preserve_times:       bool,
+    /// Polynomial of the CRC32 sums in the index, for `check` and `verify`.
+    pub crc:                  CrcAlgorithm,
 }
 
 impl UnpackOptions<'_> {
@@ -45,24 +95,152 @@ impl Default for UnpackOptions<'_> {
     fn default() -> Self {
         Self {
             filter:               None,
+            extensions:           None,
+            filter_file:          None,
             verbose:              false,
+            quiet:                false,
             check:                false,
+            verify:               false,
             dirname_from_archive: false,
+            root:                 None,
+            strip_components:     0,
+            prefix:               None,
+            flatten:              None,
+            buffer_size:          BUFFER_SIZE,
+            retries:              0,
+            max_open_files:       0,
+            color:                false,
+            ignore_missing:       false,
+            preserve_times:       false,
+            crc:                  CrcAlgorithm::default(),
+        }
+    }
+}
+
+/// Create `outdir` if needed and make sure files can be created in it, so
+/// a bad destination is reported before any work is done.
+fn check_outdir(outdir: &Path) -> Result<()> {
+    if let Err(error) = fs::create_dir_all(outdir) {
+        return Err(Error::io_with_path(error, outdir));
+    }
+
+    let probe = outdir.join(format!(".rvpk-write-test-{}", std::process::id()));
+    match fs::OpenOptions::new().write(true).create_new(true).open(&probe) {
+        Ok(_) => {
+            if let Err(error) = fs::remove_file(&probe) {
+                return Err(Error::io_with_path(error, probe));
+            }
+        },
+        Err(error) => {
+            return Err(Error::other(format!("output directory is not writable: {}", error)).with_path(outdir));
         }
     }
+
+    Ok(())
 }
 
+/// Warnings are printed in one block at the end, or as they occur and
+/// only counted at the end with `verbose`.
 pub fn unpack(package: &Package, outdir: impl AsRef<Path>, options: UnpackOptions) -> Result<()> {
-    let mut digest = crc32::Digest::new(crc32::IEEE);
-    let mut archs = ArchiveCache::for_reading(package.dirpath.to_path_buf(), package.prefix.to_string());
+    let mut warnings = Warnings::new(options.verbose, options.quiet);
+    warnings.warn_duplicates(package);
+    warnings.warn_truncated(package);
+
+    let result = unpack_files(package, outdir.as_ref(), &options, &mut warnings);
+    warnings.print_summary();
+
+    result
+}
+
+fn unpack_files(package: &Package, outdir: &Path, options: &UnpackOptions, warnings: &mut Warnings) -> Result<()> {
+    check_outdir(outdir)?;
 
-    let files = match options.filter {
-        None => package.recursive_file_list(&PHYSICAL_ORDER),
-        Some(paths) => package.recursive_file_list_from(paths, &PHYSICAL_ORDER)?,
+    if options.verbose && options.root.is_none() && options.strip_components == 0 && options.flatten.is_none() {
+        let prefix = package.common_prefix();
+        if !prefix.is_empty() {
+            println!("all files are below {:?}, use --strip-components {} or --root {:?} to unpack without it",
+                prefix, prefix.split('/').count(), prefix);
+        }
+    }
+
+    let mut digest = options.crc.digest();
+    let mut archs = ArchiveCache::for_package(package);
+    archs.set_buffer_size(options.buffer_size)?;
+    archs.set_retries(options.retries);
+    archs.set_max_open_files(options.max_open_files);
+
+    let mut files = if let Some(root) = options.root {
+        let root = root.trim_matches('/');
+        match package.get(root) {
+            None => return Err(Error::no_such_entry(root)),
+            Some(Entry::File(_)) => return Err(Error::entry_not_a_dir(root)),
+            Some(Entry::Dir(_)) => {},
+        }
+
+        let paths = match options.filter {
+            None => vec![root.to_owned()],
+            Some(paths) => paths.iter().map(|path| format!("{}/{}", root, path.trim_matches('/'))).collect(),
+        };
+
+        let mut files = package.recursive_file_list_from(&paths, &PHYSICAL_ORDER)?;
+        for (path, _) in &mut files {
+            path.drain(..root.len() + 1);
+        }
+        files
+    } else {
+        match options.filter {
+            None => package.recursive_file_list(&PHYSICAL_ORDER),
+            Some(paths) => package.recursive_file_list_from(paths, &PHYSICAL_ORDER)?,
+        }
     };
 
-    for (path, file) in files {
-        let mut outpath = outdir.as_ref().to_path_buf();
+    if let Some(extensions) = options.extensions {
+        retain_extensions(&mut files, extensions);
+    }
+
+    if let Some(filter_file) = options.filter_file {
+        filter_file.retain(&mut files);
+    }
+
+    if options.strip_components > 0 {
+        files.retain(|(path, _)| {
+            if split_path(path).count() > options.strip_components {
+                true
+            } else {
+                warnings.warn(format!("skipping file with not more than {} path components: {:?}",
+                    options.strip_components, path));
+                false
+            }
+        });
+    }
+
+    let flat_names = if let Some(flatten) = options.flatten {
+        Some(flatten_names(&files, flatten)?)
+    } else {
+        None
+    };
+
+    let mut failed_files_count = 0usize;
+    let mut skipped_files_count = 0usize;
+    let mut written_size = 0u64;
+    let mut mtimes = HashMap::new();
+
+    for (file_index, (path, file)) in files.iter().enumerate() {
+        if options.ignore_missing && file.size > 0 {
+            // check before the output file is created, so no empty file is left behind
+            if let Err(error) = archs.get(file.archive_index) {
+                if !error.is_missing_archive() {
+                    return Err(error);
+                }
+                if options.verbose {
+                    println!("skipping {:?}: {}", path, error);
+                }
+                skipped_files_count += 1;
+                continue;
+            }
+        }
+
+        let mut outpath = outdir.to_path_buf();
 
         if options.dirname_from_archive {
             if file.size == 0 {
@@ -74,8 +252,18 @@ pub fn unpack(package: &Package, outdir: impl AsRef<Path>, options: UnpackOption
             }
         }
 
-        for (_, item, _) in split_path(&path) {
-            outpath.push(item);
+        if let Some(prefix) = options.prefix {
+            for (_, item, _) in split_path(prefix) {
+                outpath.push(item);
+            }
+        }
+
+        if let Some(flat_names) = &flat_names {
+            outpath.push(&flat_names[file_index]);
+        } else {
+            for (_, item, _) in split_path(path).skip(options.strip_components) {
+                outpath.push(item);
+            }
         }
 
         if options.verbose {
@@ -88,24 +276,30 @@ pub fn unpack(package: &Package, outdir: impl AsRef<Path>, options: UnpackOption
 
         match fs::File::create(&outpath) {
             Ok(mut writer) => {
-                if options.check {
+                if options.check || options.verify {
                     digest.reset();
                     archs.read_file_data(file, |data| {
                         if let Err(error) = writer.write_all(data) {
                             return Err(Error::io_with_path(error, outpath.to_path_buf()));
                         }
                         digest.write(data);
+                        written_size += data.len() as u64;
                         Ok(())
                     })?;
 
                     let sum = digest.sum32();
                     if sum != file.crc32 {
-                        return Err(Error::other(format!(
-                            "{}: CRC32 sum missmatch, expected: 0x{:08x}, actual: 0x{:08x}",
-                            path, file.crc32, sum)));
+                        if !options.verify {
+                            return Err(Error::other(format!(
+                                "{}: CRC32 sum missmatch, expected: 0x{:08x}, actual: 0x{:08x}",
+                                path, file.crc32, sum)));
+                        }
+                        eprintln!("{}: CRC32 sum missmatch, expected: 0x{:08x}, actual: 0x{:08x}",
+                            path, file.crc32, sum);
+                        failed_files_count += 1;
                     }
                 } else {
-                    match archs.transfer(file, &mut writer) {
+                    match archs.transfer_with_progress(file, &mut writer, &mut |count| written_size += count as u64) {
                         Err(error) => return if error.path.is_none() {
                             Err(error.with_path(outpath))
                         } else {
@@ -114,11 +308,390 @@ pub fn unpack(package: &Package, outdir: impl AsRef<Path>, options: UnpackOption
                         Ok(()) => {}
                     }
                 }
+
+                if options.preserve_times {
+                    let archive_index = if file.size > 0 { file.archive_index } else { DIR_INDEX };
+                    let mtime = archive_mtime(&archs, &mut mtimes, archive_index)?;
+                    if let Err(error) = writer.set_modified(mtime) {
+                        return Err(Error::io_with_path(error, outpath));
+                    }
+                }
             },
             Err(error) => {
                 return Err(Error::io_with_path(error, outpath));
             }
         }
     }
+
+    if skipped_files_count > 0 {
+        warnings.warn(format!("skipped {} file(s) in missing archives", skipped_files_count));
+    }
+
+    if options.verbose {
+        println!("unpacked {} file(s) with {} bytes", files.len() - skipped_files_count, written_size);
+    }
+
+    if options.verify {
+        let failed_md5_count = verify_archive_md5s(package, &files, &mut archs, options, warnings)?;
+
+        if failed_files_count > 0 || failed_md5_count > 0 {
+            return Err(Error::other(format!(
+                "CRC32 check failed for {} file(s) and MD5 check failed for {} archive chunk(s)",
+                failed_files_count, failed_md5_count)));
+        }
+    }
+
     Ok(())
 }
+
+/// Modification time of an archive, cached in `mtimes`.
+fn archive_mtime(archs: &ArchiveCache, mtimes: &mut HashMap<u16, SystemTime>, archive_index: u16) -> Result<SystemTime> {
+    if let Some(mtime) = mtimes.get(&archive_index) {
+        return Ok(*mtime);
+    }
+
+    let path = archs.archive_path(archive_index);
+    let mtime = match fs::metadata(&path).and_then(|meta| meta.modified()) {
+        Ok(mtime) => mtime,
+        Err(error) => return Err(Error::io_with_path(error, path)),
+    };
+    mtimes.insert(archive_index, mtime);
+
+    Ok(mtime)
+}
+
+/// File names of `files` for unpacking into a single directory. Names are
+/// unique, files that would collide are either an error or are renamed.
+fn flatten_names(files: &[(String, &File)], flatten: Flatten) -> Result<Vec<String>> {
+    fn basename(path: &str) -> &str {
+        match path.rfind('/') {
+            Some(index) => &path[index + 1..],
+            None => path,
+        }
+    }
+
+    // original names are reserved, so a renamed file never takes the name
+    // of a file that comes later in the list
+    let mut used: HashSet<String> = files.iter().map(|(path, _)| basename(path).to_owned()).collect();
+    let mut taken: HashMap<&str, &str> = HashMap::new();
+    let mut names = Vec::with_capacity(files.len());
+
+    for (path, _) in files {
+        let name = basename(path);
+        if let Some(other) = taken.get(name) {
+            match flatten {
+                Flatten::Error => {
+                    return Err(Error::other(format!(
+                        "{} and {} would both be unpacked as {:?}",
+                        other, path, name)));
+                },
+                Flatten::Rename => {
+                    let (stem, ext) = match name.rfind('.') {
+                        Some(index) => name.split_at(index),
+                        None => (name, ""),
+                    };
+                    let mut counter = 1usize;
+                    let mut new_name = format!("{}-{}{}", stem, counter, ext);
+                    while used.contains(&new_name) {
+                        counter += 1;
+                        new_name = format!("{}-{}{}", stem, counter, ext);
+                    }
+                    used.insert(new_name.clone());
+                    names.push(new_name);
+                }
+            }
+        } else {
+            taken.insert(name, path);
+            names.push(name.to_owned());
+        }
+    }
+
+    Ok(names)
+}
+
+/// Check the MD5 sums of all archive chunks that contain data of the given files.
+/// Returns the number of chunks with a wrong MD5 sum.
+fn verify_archive_md5s(package: &Package, files: &[(String, &File)], archs: &mut ArchiveCache, options: &UnpackOptions, warnings: &mut Warnings) -> Result<usize> {
+    if package.version < 2 {
+        warnings.warn(format!("VPK v{} packages have no archive MD5 sums, skipping MD5 verification",
+            package.version));
+        return Ok(0);
+    }
+
+    // chunks sorted by offset per archive
+    let mut chunkmap: HashMap<u16, Vec<usize>> = HashMap::new();
+    for (index, item) in package.archive_md5s.iter().enumerate() {
+        chunkmap.entry(item.archive_index).or_default().push(index);
+    }
+
+    for chunks in chunkmap.values_mut() {
+        chunks.sort_by_key(|index| package.archive_md5s[*index].offset);
+    }
+
+    let mut selected = vec![false; package.archive_md5s.len()];
+    for (_, file) in files {
+        if file.size == 0 {
+            continue;
+        }
+
+        if let Some(chunks) = chunkmap.get(&file.archive_index) {
+            let start = file.offset as u64;
+            let end   = start + file.size as u64;
+
+            let first = chunks.partition_point(|index| {
+                let item = &package.archive_md5s[*index];
+                item.offset as u64 + item.size as u64 <= start
+            });
+
+            for index in &chunks[first..] {
+                if package.archive_md5s[*index].offset as u64 >= end {
+                    break;
+                }
+                selected[*index] = true;
+            }
+        }
+    }
+
+    let mut buf = vec![0; options.buffer_size];
+    let mut failed_md5_count = 0usize;
+    for (item, selected) in package.archive_md5s.iter().zip(selected) {
+        if !selected {
+            continue;
+        }
+
+        let what = format!("archive {} at offset {} with size {}",
+            format_archive(item.archive_index), item.offset, item.size);
+
+        let arch = match archs.get(item.archive_index) {
+            // the files in this archive were skipped
+            Err(error) if options.ignore_missing && error.is_missing_archive() => continue,
+            result => result?,
+        };
+        let offset = item.offset as u64;
+        match check_range(arch, &mut buf, offset..offset + item.size as u64, &item.md5, &what, options.verbose, options.color) {
+            Ok(true)  => {},
+            Ok(false) => failed_md5_count += 1,
+            Err(error) => return Err(Error::io_with_path(error, archs.archive_path(item.archive_index))),
+        }
+    }
+
+    Ok(failed_md5_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::ReadOptions;
+    use crate::result::ErrorType;
+    use crate::util::{TempDir, TestFile, test_dir_vpk};
+
+    fn flatten(paths: &[&str], flatten: Flatten) -> Result<Vec<String>> {
+        let file = File {
+            index: 0,
+            crc32: 0,
+            inline_size: 0,
+            archive_index: DIR_INDEX,
+            offset: 0,
+            size: 0,
+            preload: Vec::new(),
+        };
+        let files: Vec<(String, &File)> = paths.iter().map(|path| (path.to_string(), &file)).collect();
+        flatten_names(&files, flatten)
+    }
+
+    #[test]
+    fn flatten_names_rename() {
+        assert_eq!(
+            flatten(&["a/foo.txt", "b/foo.txt", "c/foo-1.txt", "d/foo.txt", "e/bar"], Flatten::Rename).unwrap(),
+            ["foo.txt", "foo-2.txt", "foo-1.txt", "foo-3.txt", "bar"]);
+
+        assert_eq!(
+            flatten(&["a/bar", "b/bar"], Flatten::Rename).unwrap(),
+            ["bar", "bar-1"]);
+    }
+
+    #[test]
+    fn flatten_names_error() {
+        assert_eq!(
+            flatten(&["a/foo.txt", "b/bar.txt"], Flatten::Error).unwrap(),
+            ["foo.txt", "bar.txt"]);
+
+        let error = flatten(&["a/foo.txt", "b/bar.txt", "c/foo.txt"], Flatten::Error).err().unwrap();
+        assert_eq!(error.to_string(), "a/foo.txt and c/foo.txt would both be unpacked as \"foo.txt\"");
+    }
+
+    /// A package with fully inlined files, each containing its own path.
+    fn nested_package() -> Package {
+        let paths = ["a/b/c.txt", "a/d.txt", "e/f/g/h.txt"];
+        let files: Vec<TestFile> = paths.iter()
+            .map(|path| (*path, DIR_INDEX, 0, 0, path.as_bytes()))
+            .collect();
+        let index = std::io::Cursor::new(test_dir_vpk(&files, b""));
+        Package::from_reader(index, ".", "pak", ReadOptions::new()).unwrap()
+    }
+
+    /// Unpack `package` into a new directory `name` inside of `tmp` and
+    /// return the paths (relative to that directory) and contents of all
+    /// written files.
+    fn unpacked(package: &Package, tmp: &TempDir, name: &str, options: UnpackOptions) -> Vec<(String, Vec<u8>)> {
+        fn collect(dir: &Path, rel: &str, files: &mut Vec<(String, Vec<u8>)>) {
+            for entry in fs::read_dir(dir).unwrap() {
+                let entry = entry.unwrap();
+                let name = entry.file_name().into_string().unwrap();
+                let rel = if rel.is_empty() { name } else { format!("{}/{}", rel, name) };
+                if entry.file_type().unwrap().is_dir() {
+                    collect(&entry.path(), &rel, files);
+                } else {
+                    files.push((rel, fs::read(entry.path()).unwrap()));
+                }
+            }
+        }
+
+        let outdir = tmp.path.join(name);
+        unpack(package, &outdir, options).unwrap();
+
+        let mut files = Vec::new();
+        collect(&outdir, "", &mut files);
+        files.sort();
+        files
+    }
+
+    fn file(path: &str, data: &str) -> (String, Vec<u8>) {
+        (path.to_owned(), data.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn strip_components() {
+        let tmp = TempDir::new("strip-components");
+        let package = nested_package();
+
+        assert_eq!(unpacked(&package, &tmp, "one", UnpackOptions { strip_components: 1, quiet: true, ..UnpackOptions::new() }), [
+            file("b/c.txt",   "a/b/c.txt"),
+            file("d.txt",     "a/d.txt"),
+            file("f/g/h.txt", "e/f/g/h.txt"),
+        ]);
+
+        // files with not more than N components are skipped
+        assert_eq!(unpacked(&package, &tmp, "two", UnpackOptions { strip_components: 2, quiet: true, ..UnpackOptions::new() }), [
+            file("c.txt",   "a/b/c.txt"),
+            file("g/h.txt", "e/f/g/h.txt"),
+        ]);
+
+        assert!(unpacked(&package, &tmp, "many", UnpackOptions { strip_components: 10, quiet: true, ..UnpackOptions::new() }).is_empty());
+    }
+
+
+    #[test]
+    fn prefix_after_strip_components() {
+        let tmp = TempDir::new("unpack-prefix");
+        let package = nested_package();
+
+        assert_eq!(unpacked(&package, &tmp, "prefix", UnpackOptions { prefix: Some("mods/x"), ..UnpackOptions::new() }), [
+            file("mods/x/a/b/c.txt",   "a/b/c.txt"),
+            file("mods/x/a/d.txt",     "a/d.txt"),
+            file("mods/x/e/f/g/h.txt", "e/f/g/h.txt"),
+        ]);
+
+        // the prefix isn't stripped, and redundant slashes are ignored
+        assert_eq!(unpacked(&package, &tmp, "strip", UnpackOptions {
+            prefix: Some("/mods//x/"),
+            strip_components: 2,
+            quiet: true,
+            ..UnpackOptions::new()
+        }), [
+            file("mods/x/c.txt",   "a/b/c.txt"),
+            file("mods/x/g/h.txt", "e/f/g/h.txt"),
+        ]);
+    }
+
+
+    #[test]
+    fn unpack_root() {
+        let tmp = TempDir::new("unpack-root");
+        let package = nested_package();
+
+        assert_eq!(unpacked(&package, &tmp, "a", UnpackOptions { root: Some("a"), ..UnpackOptions::new() }), [
+            file("b/c.txt", "a/b/c.txt"),
+            file("d.txt",   "a/d.txt"),
+        ]);
+
+        // filter paths are relative to the root, slashes around it are ignored
+        let filter = ["g"];
+        assert_eq!(unpacked(&package, &tmp, "g", UnpackOptions { root: Some("/e/f/"), filter: Some(&filter), ..UnpackOptions::new() }), [
+            file("g/h.txt", "e/f/g/h.txt"),
+        ]);
+
+        let outdir = tmp.path.join("errors");
+        let error = unpack(&package, &outdir, UnpackOptions { root: Some("a/d.txt"), ..UnpackOptions::new() }).unwrap_err();
+        assert!(matches!(error.error_type(), ErrorType::EntryNotADir(path) if path == "a/d.txt"), "{:?}", error);
+
+        let error = unpack(&package, &outdir, UnpackOptions { root: Some("x"), ..UnpackOptions::new() }).unwrap_err();
+        assert!(matches!(error.error_type(), ErrorType::NoSuchEntry(path) if path == "x"), "{:?}", error);
+    }
+
+    #[test]
+    fn max_open_files() {
+        let tmp = TempDir::new("unpack-max-open-files");
+        let mut files = Vec::new();
+        let mut datas = Vec::new();
+        for archive_index in 0..4u16 {
+            let data = vec![archive_index as u8 + 1; 1000 + archive_index as usize];
+            tmp.write(&format!("pak_{:03}.vpk", archive_index), &data);
+            datas.push((format!("m/{}.bin", archive_index), data));
+        }
+        for (archive_index, (path, data)) in datas.iter().enumerate() {
+            files.push((path.as_str(), archive_index as u16, 0, data.len() as u32, &b""[..]));
+        }
+        let dirpath = tmp.write("pak_dir.vpk", &test_dir_vpk(&files, b""));
+        let package = Package::from_path(&dirpath, ReadOptions::new()).unwrap();
+
+        let mut expected = datas.clone();
+        expected.sort();
+        assert_eq!(unpacked(&package, &tmp, "out", UnpackOptions { max_open_files: 1, ..UnpackOptions::new() }), expected);
+
+        // reading the archives in turns keeps re-opening them
+        let mut archs = ArchiveCache::for_package(&package);
+        archs.set_max_open_files(1);
+        for _ in 0..2 {
+            for (path, data) in &datas {
+                let file = package.get_file(path).unwrap();
+                assert_eq!(&archs.read_file_bytes(file).unwrap(), data);
+                assert_eq!(archs.archives().len(), 1);
+                assert!(archs.archives().contains_key(&file.archive_index));
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn preserve_times() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let tmp = TempDir::new("unpack-preserve-times");
+        let archpath = tmp.write("pak_000.vpk", &[0x42; 100]);
+        let dirpath = tmp.write("pak_dir.vpk", &test_dir_vpk(&[
+            ("m/a.bin", 0, 0, 100, b""),
+            ("m/b.txt", DIR_INDEX, 0, 0, b"inline"),
+        ], b""));
+
+        let arch_mtime = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let dir_mtime  = UNIX_EPOCH + Duration::from_secs(1_200_000_000);
+        fs::File::options().write(true).open(&archpath).unwrap().set_modified(arch_mtime).unwrap();
+        fs::File::options().write(true).open(&dirpath).unwrap().set_modified(dir_mtime).unwrap();
+
+        let package = Package::from_path(&dirpath, ReadOptions::new()).unwrap();
+        let mtime = |path: &Path| fs::metadata(path).unwrap().modified().unwrap();
+
+        let outdir = tmp.path.join("preserved");
+        unpack(&package, &outdir, UnpackOptions { preserve_times: true, quiet: true, ..UnpackOptions::new() }).unwrap();
+        assert_eq!(mtime(&outdir.join("m/a.bin")), arch_mtime);
+        // inlined files get the time of the _dir.vpk file
+        assert_eq!(mtime(&outdir.join("m/b.txt")), dir_mtime);
+
+        let outdir = tmp.path.join("default");
+        unpack(&package, &outdir, UnpackOptions { quiet: true, ..UnpackOptions::new() }).unwrap();
+        assert!(mtime(&outdir.join("m/a.bin")) > dir_mtime);
+        assert!(mtime(&outdir.join("m/b.txt")) > dir_mtime);
+    }
+
+}
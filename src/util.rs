@@ -15,8 +15,16 @@
 
 use std::str::FromStr;
 use std::path::{Path, PathBuf};
+use std::ffi::OsString;
+use std::io::Read;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH, Duration};
 
-use crate::consts::DIR_INDEX;
+use crate::consts::{DIR_INDEX, JSON_VERSION};
+use crate::result::{Error, Result};
+use crate::package::Md5;
+use crate::entry::File;
+use crate::color::display_len;
 
 pub struct PathSplitter<'a> {
     path: &'a str,
@@ -85,6 +93,14 @@ pub fn format_size(size: u64) -> String {
     }
 }
 
+pub fn format_md5(md5: &Md5) -> String {
+    format!(
+        "{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+        md5[0], md5[1], md5[2],  md5[ 3], md5[ 4], md5[ 5], md5[ 6], md5[ 7],
+        md5[8], md5[9], md5[10], md5[11], md5[12], md5[13], md5[14], md5[15],
+    )
+}
+
 pub fn archive_path(dirpath: impl AsRef<Path>, prefix: &str, archive_index: u16) -> PathBuf {
     let mut path = dirpath.as_ref().to_path_buf();
     
@@ -97,6 +113,14 @@ pub fn archive_path(dirpath: impl AsRef<Path>, prefix: &str, archive_index: u16)
     path
 }
 
+pub fn format_archive(archive_index: u16) -> String {
+    if archive_index == DIR_INDEX {
+        "dir".to_owned()
+    } else {
+        format!("{:03}", archive_index)
+    }
+}
+
 pub enum Align {
     Left,
     Right
@@ -128,10 +152,13 @@ pub fn print_row(row: &[impl AsRef<str>], lens: &[usize], align: &[Align]) {
             print!("  "); // cell spacing
         }
 
+        // padding is done by hand, because cells might contain color escape sequences
+        let cell = cell.as_ref();
+        let padding = len.saturating_sub(display_len(cell));
         if align.is_right() {
-            print!("{:>1$}", cell.as_ref(), *len);
+            print!("{:padding$}{}", "", cell, padding = padding);
         } else {
-            print!("{:<1$}", cell.as_ref(), *len);
+            print!("{}{:padding$}", cell, "", padding = padding);
         }
     }
 
@@ -143,7 +170,7 @@ pub fn print_table(header: &[impl AsRef<str>], align: &[Align], body: &[Vec<impl
     let mut lens: Vec<usize> = align.iter().map(|_| 0).collect();
 
     for (cell, max_len) in header.iter().zip(lens.iter_mut()) {
-        let len = cell.as_ref().chars().count();
+        let len = display_len(cell.as_ref());
         if len > *max_len {
             *max_len = len;
         }
@@ -151,7 +178,7 @@ pub fn print_table(header: &[impl AsRef<str>], align: &[Align], body: &[Vec<impl
 
     for row in body {
         for (cell, max_len) in row.iter().zip(lens.iter_mut()) {
-            let len = cell.as_ref().chars().count();
+            let len = display_len(cell.as_ref());
             if len > *max_len {
                 *max_len = len;
             }
@@ -188,7 +215,7 @@ pub fn print_headless_table(body: &[Vec<impl AsRef<str>>], align: &[Align]) {
             lens.push(0);
         }
         for (cell, max_len) in row.iter().zip(lens.iter_mut()) {
-            let len = cell.as_ref().chars().count();
+            let len = display_len(cell.as_ref());
             if len > *max_len {
                 *max_len = len;
             }
@@ -235,3 +262,366 @@ pub fn parse_size(value: &str) -> std::result::Result<usize, <usize as FromStr>:
         value.parse()
     }
 }
+
+/// Parse a point in time given either as `@SECONDS` since the UNIX epoch or
+/// as RFC 3339 date-time, e.g. `2021-02-03T04:05:06Z` or
+/// `2021-02-03 04:05:06.5+01:00`.
+pub fn parse_time(value: &str) -> Option<SystemTime> {
+    let value = value.trim();
+
+    if let Some(seconds) = value.strip_prefix('@') {
+        let seconds = seconds.parse::<i64>().ok()?;
+        return Some(from_unix_time(seconds, 0));
+    }
+
+    let bytes = value.as_bytes();
+    if bytes.len() < 20 || bytes[4] != b'-' || bytes[7] != b'-' ||
+       !matches!(bytes[10], b'T' | b't' | b' ') || bytes[13] != b':' || bytes[16] != b':' {
+        return None;
+    }
+
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = value.get(range)?;
+        if digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            digits.parse().ok()
+        } else {
+            None
+        }
+    };
+
+    let year   = number(0..4)?;
+    let month  = number(5..7)?;
+    let day    = number(8..10)?;
+    let hour   = number(11..13)?;
+    let minute = number(14..16)?;
+    let second = number(17..19)?;
+
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) ||
+       hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let mut index = 19;
+    let mut nanos = 0i64;
+    if bytes[index] == b'.' {
+        index += 1;
+        let start = index;
+        while index < bytes.len() && bytes[index].is_ascii_digit() {
+            if index - start < 9 {
+                nanos = nanos * 10 + (bytes[index] - b'0') as i64;
+            }
+            index += 1;
+        }
+        if index == start {
+            return None;
+        }
+        for _ in (index - start)..9 {
+            nanos *= 10;
+        }
+    }
+
+    let offset = match &value[index..] {
+        "Z" | "z" => 0,
+        zone if zone.len() == 6 && zone.as_bytes()[3] == b':' => {
+            let sign = match zone.as_bytes()[0] {
+                b'+' =>  1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let hours   = number(index + 1..index + 3)?;
+            let minutes = number(index + 4..index + 6)?;
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            sign * (hours * 60 + minutes) * 60
+        },
+        _ => return None,
+    };
+
+    let seconds = days_from_civil(year, month, day) * 86400 +
+        hour * 3600 + minute * 60 + second - offset;
+
+    Some(from_unix_time(seconds, nanos as u32))
+}
+
+fn from_unix_time(seconds: i64, nanos: u32) -> SystemTime {
+    if seconds >= 0 {
+        UNIX_EPOCH + Duration::new(seconds as u64, nanos)
+    } else {
+        UNIX_EPOCH - Duration::new(seconds.unsigned_abs(), 0) + Duration::new(0, nanos)
+    }
+}
+
+fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 => if is_leap_year(year) { 29 } else { 28 },
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of the given date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Replace every `@FILE` argument by the whitespace separated arguments read
+/// from FILE (a response file), for lists of paths that are too long for the
+/// command line. Like with GCC, if FILE doesn't exist the argument is kept
+/// as it is, so e.g. `--since @SECONDS` still works. A leading `@@` is
+/// replaced by a literal `@`. Arguments after `--` are kept as they are.
+pub(crate) fn expand_response_files(args: impl IntoIterator<Item=OsString>) -> Result<Vec<OsString>> {
+    let mut expanded = Vec::new();
+    let mut args = args.into_iter();
+
+    // the program name is never expanded
+    expanded.extend(args.next());
+
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--") => {
+                expanded.push(arg);
+                expanded.extend(args);
+                break;
+            },
+            Some(value) if value.starts_with("@@") => {
+                expanded.push(OsString::from(&value[1..]));
+            },
+            Some(value) if value.len() > 1 && value.starts_with('@') && Path::new(&value[1..]).is_file() => {
+                let path = Path::new(&value[1..]);
+                let data = match fs::read_to_string(path) {
+                    Ok(data) => data,
+                    Err(error) => return Err(Error::io_with_path(error, path)),
+                };
+                expanded.extend(data.split_whitespace().map(OsString::from));
+            },
+            _ => expanded.push(arg),
+        }
+    }
+
+    Ok(expanded)
+}
+
+/// Read the non-empty lines of a text file, or its NULL separated entries
+/// if `null_separated`. `-` reads from stdin.
+pub(crate) fn read_lines(path: &Path, null_separated: bool) -> Result<Vec<String>> {
+    let mut data = Vec::new();
+    let result = if path == Path::new("-") {
+        std::io::stdin().read_to_end(&mut data)
+    } else {
+        fs::File::open(path).and_then(|mut file| file.read_to_end(&mut data))
+    };
+
+    if let Err(error) = result {
+        return Err(Error::io_with_path(error, path));
+    }
+
+    let data = match String::from_utf8(data) {
+        Ok(data) => data,
+        Err(_) => return Err(Error::other("file list is not valid UTF-8").with_path(path)),
+    };
+
+    let sep = if null_separated { '\0' } else { '\n' };
+    Ok(data.split(sep)
+        .map(|line| if null_separated { line } else { line.trim_end_matches('\r') })
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_owned())
+        .collect())
+}
+
+/// Quote `value` as JSON string.
+pub fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"'  => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Build a JSON object from already encoded `"key":value` pairs. Every JSON
+/// document printed by rvpk is built with this, so it starts with the
+/// version of the JSON format and of rvpk itself.
+pub fn json_document(fields: &str) -> String {
+    format!("{{\"vpk_json_version\":{},\"tool_version\":{},{}}}",
+        JSON_VERSION, json_string(env!("CARGO_PKG_VERSION")), fields)
+}
+
+/// Only keep files whose extension (the part after the last `.`) is one of
+/// `extensions`, ignoring ASCII case.
+pub fn retain_extensions(files: &mut Vec<(String, &File)>, extensions: &[&str]) {
+    files.retain(|(path, _)| {
+        let ext = match path.rfind('.') {
+            Some(index) => &path[index + 1..],
+            None => "",
+        };
+        extensions.iter().any(|extension| extension.eq_ignore_ascii_case(ext))
+    });
+}
+
+/// Directory for the files of a test that is removed again when dropped.
+#[cfg(test)]
+pub(crate) struct TempDir {
+    pub path: PathBuf,
+}
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("rvpk-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    /// Write a file relative to the directory, creating parent directories.
+    pub fn write(&self, rel_path: &str, data: &[u8]) -> PathBuf {
+        let path = self.path.join(rel_path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, data).unwrap();
+        path
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A file of a hand written index for tests: path inside of the package,
+/// archive index, offset, size in the archive and the inlined data.
+#[cfg(test)]
+pub(crate) type TestFile<'a> = (&'a str, u16, u32, u32, &'a [u8]);
+
+/// A version 1 _dir.vpk file with an index of `files`, each in its own
+/// extension and directory group, followed by `data`. The CRC32 sums are
+/// those of the inlined data, so only fully inlined files pass `check`.
+#[cfg(test)]
+pub(crate) fn test_dir_vpk(files: &[TestFile], data: &[u8]) -> Vec<u8> {
+    use crate::consts::{VPK_MAGIC, TERMINATOR};
+
+    let mut index = Vec::new();
+    for (path, archive_index, offset, size, preload) in files {
+        let slash = path.rfind('/').unwrap();
+        let dot = path.rfind('.').unwrap();
+        for name in [&path[dot + 1..], &path[..slash], &path[slash + 1..dot]] {
+            index.extend_from_slice(name.as_bytes());
+            index.push(0);
+        }
+        index.extend_from_slice(&crc::crc32::checksum_ieee(preload).to_le_bytes());
+        index.extend_from_slice(&(preload.len() as u16).to_le_bytes());
+        index.extend_from_slice(&archive_index.to_le_bytes());
+        index.extend_from_slice(&offset.to_le_bytes());
+        index.extend_from_slice(&size.to_le_bytes());
+        index.extend_from_slice(&TERMINATOR.to_le_bytes());
+        index.extend_from_slice(preload);
+        index.extend_from_slice(b"\0\0");
+    }
+    index.push(0);
+
+    let mut vpk = Vec::new();
+    vpk.extend_from_slice(&VPK_MAGIC);
+    vpk.extend_from_slice(&1u32.to_le_bytes());
+    vpk.extend_from_slice(&(index.len() as u32).to_le_bytes());
+    vpk.extend_from_slice(&index);
+    vpk.extend_from_slice(data);
+    vpk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn parse_times() {
+        let at = |seconds: u64| Some(UNIX_EPOCH + Duration::from_secs(seconds));
+
+        assert_eq!(parse_time("@1600000000"), at(1600000000));
+        assert_eq!(parse_time("@-1"), Some(UNIX_EPOCH - Duration::from_secs(1)));
+        assert_eq!(parse_time("1970-01-01T00:00:00Z"), at(0));
+        assert_eq!(parse_time("2021-02-03T04:05:06Z"), at(1612325106));
+        assert_eq!(parse_time("2021-02-03 05:05:06+01:00"), at(1612325106));
+        assert_eq!(parse_time("2021-02-03t04:05:06.5z"), Some(UNIX_EPOCH + Duration::from_millis(1612325106500)));
+        assert_eq!(parse_time("2020-02-29T00:00:00Z"), at(1582934400));
+
+        for invalid in ["", "@", "@x", "2021-02-03", "2021-02-03T04:05:06", "2021-13-03T04:05:06Z",
+                        "2021-02-29T04:05:06Z", "2021-02-03T24:05:06Z", "2021-02-03T04:05:06+1:00"] {
+            assert_eq!(parse_time(invalid), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn response_files() {
+        let tmp = TempDir::new("response-files");
+        let file = tmp.write("args.txt", b"a/b.vmt\n  c/d.vtf\te/f.txt\n");
+        let at_file = format!("@{}", file.to_str().unwrap());
+
+        assert_eq!(
+            expand_response_files(args(&["rvpk", "list", "pak01_dir.vpk", &at_file])).unwrap(),
+            args(&["rvpk", "list", "pak01_dir.vpk", "a/b.vmt", "c/d.vtf", "e/f.txt"]));
+
+        // not a file, escaped, and after --
+        assert_eq!(
+            expand_response_files(args(&["rvpk", "pack", "--since", "@1600000000", &format!("@{}", at_file), "--", &at_file])).unwrap(),
+            args(&["rvpk", "pack", "--since", "@1600000000", &at_file, "--", &at_file]));
+    }
+
+    #[test]
+    fn extensions_and_paths() {
+        use crate::package::{Package, ReadOptions};
+        use crate::sort::DEFAULT_ORDER;
+
+        let index = std::io::Cursor::new(test_dir_vpk(&[
+            ("materials/a.vmt",     DIR_INDEX, 0, 0, b""),
+            ("materials/b.VTF",     DIR_INDEX, 0, 0, b""),
+            ("materials/sub/c.vmt", DIR_INDEX, 0, 0, b""),
+            ("sound/d.vmt",         DIR_INDEX, 0, 0, b""),
+            ("sound/e.wav",         DIR_INDEX, 0, 0, b""),
+        ], b""));
+        let package = Package::from_reader(index, ".", "pak", ReadOptions::new()).unwrap();
+        let paths = |files: &[(String, &File)]| -> Vec<String> {
+            files.iter().map(|(path, _)| path.clone()).collect()
+        };
+
+        let mut files = package.recursive_file_list_from(&["materials", "sound/e.wav"], &DEFAULT_ORDER).unwrap();
+        retain_extensions(&mut files, &["vmt"]);
+        assert_eq!(paths(&files), ["materials/a.vmt", "materials/sub/c.vmt"]);
+
+        let mut files = package.recursive_file_list_from(&["materials", "sound/e.wav"], &DEFAULT_ORDER).unwrap();
+        retain_extensions(&mut files, &["vtf", "WAV"]);
+        assert_eq!(paths(&files), ["materials/b.VTF", "sound/e.wav"]);
+
+        let mut files = package.recursive_file_list_from(&["materials/sub"], &DEFAULT_ORDER).unwrap();
+        retain_extensions(&mut files, &["wav"]);
+        assert!(files.is_empty());
+
+        let mut files = package.recursive_file_list(&DEFAULT_ORDER);
+        retain_extensions(&mut files, &[]);
+        assert!(files.is_empty());
+    }
+
+}
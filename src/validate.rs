@@ -0,0 +1,345 @@
+// This file is part of rust-vpk.
+//
+// rust-vpk is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rust-vpk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rust-vpk.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry as MapEntry;
+
+use crate::sort::PHYSICAL_ORDER;
+use crate::package::Package;
+use crate::result::{Result, Error};
+use crate::consts::{DIR_INDEX, BUFFER_SIZE};
+use crate::util::format_archive;
+use crate::io::md5_of_range;
+
+pub struct ValidateOptions {
+    /// Also report files whose archive data isn't aligned at this number of bytes.
+    pub alignment: Option<u32>,
+    /// Also check the MD5 sums of a version 2 package. This reads the
+    /// _dir.vpk file and all archive chunks listed in the MD5 section, the
+    /// other checks only look at the index and the archive file sizes.
+    pub check_md5s: bool,
+    /// Must not be 0.
+    pub buffer_size: usize,
+}
+
+impl ValidateOptions {
+    #[inline]
+    pub fn new() -> Self {
+        ValidateOptions::default()
+    }
+}
+
+impl Default for ValidateOptions {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            alignment: None,
+            check_md5s: false,
+            buffer_size: BUFFER_SIZE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProblemKind {
+    /// An archive that is referenced by the index doesn't exist or isn't a file.
+    MissingArchive,
+    /// File data or an MD5 chunk reaches beyond the end of its archive.
+    OutOfBounds,
+    /// A path occurs more than once in the index.
+    DuplicatePath,
+    /// The archive data of a file isn't aligned as requested.
+    Unaligned,
+    /// An MD5 sum of a version 2 package doesn't match.
+    Md5Mismatch,
+}
+
+/// A single issue found by [`Package::validate`].
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub kind: ProblemKind,
+    /// Path of the affected file inside of the package, if any.
+    pub path: Option<String>,
+    /// Index of the affected archive, if any.
+    pub archive_index: Option<u16>,
+    /// Human readable description, including the path or archive.
+    pub message: String,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.message.fmt(f)
+    }
+}
+
+#[inline]
+fn md5_mismatch(archive_index: u16, message: String) -> Problem {
+    Problem {
+        kind: ProblemKind::Md5Mismatch,
+        path: None,
+        archive_index: Some(archive_index),
+        message,
+    }
+}
+
+pub(crate) fn validate(package: &Package, options: &ValidateOptions) -> Result<Vec<Problem>> {
+    if options.buffer_size == 0 {
+        return Err(Error::illegal_argument("buffer_size", "0"));
+    }
+
+    let files = package.recursive_file_list(&PHYSICAL_ORDER);
+    let mut problems = Vec::new();
+
+    for path in package.duplicate_warnings() {
+        problems.push(Problem {
+            kind: ProblemKind::DuplicatePath,
+            path: Some(path.to_owned()),
+            archive_index: None,
+            message: format!("{}: occurs more than once in the index", path),
+        });
+    }
+
+    // archive sizes, None if the archive is missing
+    let mut archive_sizes: HashMap<u16, Option<u64>> = HashMap::new();
+    archive_sizes.insert(DIR_INDEX, None);
+    for (_, file) in &files {
+        if file.size > 0 {
+            archive_sizes.insert(file.archive_index, None);
+        }
+    }
+    for item in &package.archive_md5s {
+        archive_sizes.insert(item.archive_index, None);
+    }
+
+    let mut archive_indices: Vec<u16> = archive_sizes.keys().copied().collect();
+    archive_indices.sort();
+
+    for archive_index in archive_indices {
+        let path = package.archive_path(archive_index);
+        let message = match fs::metadata(&path) {
+            Ok(meta) if meta.is_file() => {
+                archive_sizes.insert(archive_index, Some(meta.len()));
+                continue;
+            },
+            Ok(_) => format!("{:?}: not a file", path),
+            Err(error) => format!("{:?}: {}", path, error),
+        };
+        problems.push(Problem {
+            kind: ProblemKind::MissingArchive,
+            path: None,
+            archive_index: Some(archive_index),
+            message,
+        });
+    }
+
+    for (path, file) in &files {
+        if file.size == 0 {
+            continue;
+        }
+
+        let end = file.offset as u64 + file.size as u64;
+        let limit = if file.archive_index == DIR_INDEX && package.version > 1 {
+            Some(package.data_offset as u64 + package.data_size as u64)
+        } else {
+            archive_sizes[&file.archive_index]
+        };
+
+        if let Some(limit) = limit {
+            if end > limit {
+                problems.push(Problem {
+                    kind: ProblemKind::OutOfBounds,
+                    path: Some(path.to_owned()),
+                    archive_index: Some(file.archive_index),
+                    message: format!(
+                        "{}: data ends at {} in archive {}, which is only {} bytes long",
+                        path, end, format_archive(file.archive_index), limit),
+                });
+            }
+        }
+
+        if let Some(alignment) = options.alignment {
+            let reminder = if alignment > 0 { file.offset % alignment } else { 0 };
+            if reminder != 0 {
+                problems.push(Problem {
+                    kind: ProblemKind::Unaligned,
+                    path: Some(path.to_owned()),
+                    archive_index: Some(file.archive_index),
+                    message: format!(
+                        "{}: offset {} in archive {} is not aligned at {} bytes, remainder: {}",
+                        path, file.offset, format_archive(file.archive_index), alignment, reminder),
+                });
+            }
+        }
+    }
+
+    // MD5 chunks that can be read
+    let mut md5_chunks = Vec::new();
+    for item in &package.archive_md5s {
+        if let Some(size) = archive_sizes[&item.archive_index] {
+            let end = item.offset as u64 + item.size as u64;
+            if end > size {
+                problems.push(Problem {
+                    kind: ProblemKind::OutOfBounds,
+                    path: None,
+                    archive_index: Some(item.archive_index),
+                    message: format!(
+                        "MD5 chunk at offset {} with size {} exceeds archive {}",
+                        item.offset, item.size, format_archive(item.archive_index)),
+                });
+            } else {
+                md5_chunks.push(item);
+            }
+        }
+    }
+
+    if !options.check_md5s || package.version < 2 {
+        return Ok(problems);
+    }
+
+    let mut buf = vec![0; options.buffer_size];
+
+    if archive_sizes[&DIR_INDEX].is_some() {
+        let path = package.archive_path(DIR_INDEX);
+        let mut reader = match fs::File::open(&path) {
+            Ok(reader) => reader,
+            Err(error) => return Err(Error::io_with_path(error, path)),
+        };
+
+        for section in package.md5_sections() {
+            match md5_of_range(&mut reader, &mut buf, section.range) {
+                Ok(sum) if &sum == section.md5 => {},
+                Ok(_) => problems.push(md5_mismatch(DIR_INDEX, format!("MD5 sum of {} missmatch", section.what))),
+                Err(error) => return Err(Error::io_with_path(error, path)),
+            }
+        }
+    }
+
+    let mut readers: HashMap<u16, fs::File> = HashMap::new();
+    for item in md5_chunks {
+        let path = package.archive_path(item.archive_index);
+        let reader = match readers.entry(item.archive_index) {
+            MapEntry::Occupied(entry) => entry.into_mut(),
+            MapEntry::Vacant(entry) => match fs::File::open(&path) {
+                Ok(reader) => entry.insert(reader),
+                Err(error) => return Err(Error::io_with_path(error, path)),
+            },
+        };
+
+        let range = item.offset as u64..item.offset as u64 + item.size as u64;
+        match md5_of_range(reader, &mut buf, range) {
+            Ok(sum) if sum == item.md5 => {},
+            Ok(_) => problems.push(md5_mismatch(item.archive_index, format!(
+                "archive {} at offset {} with size {}: MD5 sum missmatch",
+                format_archive(item.archive_index), item.offset, item.size))),
+            Err(error) => return Err(Error::io_with_path(error, path)),
+        }
+    }
+
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::ReadOptions;
+    use crate::pack::{pack, PackOptions, ArchiveStrategy};
+    use crate::util::{TempDir, test_dir_vpk};
+
+    fn problems_of(problems: &[Problem], kind: ProblemKind) -> Vec<(Option<&str>, Option<u16>)> {
+        let mut found: Vec<_> = problems.iter()
+            .filter(|problem| problem.kind == kind)
+            .map(|problem| (problem.path.as_deref(), problem.archive_index))
+            .collect();
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn structural_problems() {
+        let tmp = TempDir::new("validate-structure");
+        tmp.write("pak_000.vpk", &[0; 100]);
+        let path = tmp.write("pak_dir.vpk", &test_dir_vpk(&[
+            ("m/ok.bin",      0,         0,  100, b""),
+            ("m/out.bin",     0,         50, 51,  b""),
+            ("m/missing.bin", 1,         0,  10,  b""),
+            ("m/dup.txt",     DIR_INDEX, 0,  0,   b"first"),
+            ("m/dup.txt",     DIR_INDEX, 0,  0,   b"second"),
+            ("m/dir.bin",     DIR_INDEX, 0,  4,   b""),
+            ("m/dirout.bin",  DIR_INDEX, 2,  4,   b""),
+        ], &[1, 2, 3, 4]));
+        let package = Package::from_path(path, ReadOptions::new()).unwrap();
+
+        let problems = package.validate(&ValidateOptions::new()).unwrap();
+        assert_eq!(problems_of(&problems, ProblemKind::MissingArchive), [(None, Some(1))]);
+        assert_eq!(problems_of(&problems, ProblemKind::OutOfBounds), [
+            (Some("m/dirout.bin"), Some(DIR_INDEX)),
+            (Some("m/out.bin"), Some(0)),
+        ]);
+        assert_eq!(problems_of(&problems, ProblemKind::DuplicatePath), [(Some("m/dup.txt"), None)]);
+        assert!(problems_of(&problems, ProblemKind::Unaligned).is_empty());
+        assert_eq!(problems.len(), 4);
+
+        let options = ValidateOptions { alignment: Some(4), ..ValidateOptions::new() };
+        let problems = package.validate(&options).unwrap();
+        let unaligned = problems_of(&problems, ProblemKind::Unaligned);
+        assert!(unaligned.contains(&(Some("m/out.bin"), Some(0))));
+        assert!(!unaligned.contains(&(Some("m/ok.bin"), Some(0))));
+        assert!(!unaligned.contains(&(Some("m/missing.bin"), Some(1))));
+    }
+
+    #[test]
+    fn md5_mismatch_problems() {
+        let tmp = TempDir::new("validate-md5");
+        tmp.write("in/m/a.bin", &[0x33; 900]);
+        let options = PackOptions::builder()
+            .version(2)
+            .max_inline_size(0)
+            .strategy(ArchiveStrategy::MaxArchiveSize(100))
+            .quiet(true)
+            .build().unwrap();
+        pack(tmp.path.join("pak_dir.vpk"), tmp.path.join("in"), options).unwrap();
+
+        let path = tmp.path.join("pak_dir.vpk");
+        let check_md5s = ValidateOptions { check_md5s: true, buffer_size: 100, ..ValidateOptions::new() };
+        let package = Package::from_path(&path, ReadOptions::new()).unwrap();
+        assert!(package.validate(&check_md5s).unwrap().is_empty());
+
+        let archive = tmp.path.join("pak_000.vpk");
+        let mut data = fs::read(&archive).unwrap();
+        data[899] ^= 1;
+        fs::write(&archive, &data).unwrap();
+
+        let problems = package.validate(&check_md5s).unwrap();
+        assert_eq!(problems_of(&problems, ProblemKind::Md5Mismatch), [(None, Some(0))]);
+        assert_eq!(problems.len(), 1);
+        // without check_md5s nothing is read
+        assert!(package.validate(&ValidateOptions::new()).unwrap().is_empty());
+
+        let mut data = fs::read(&path).unwrap();
+        data[crate::consts::V2_HEADER_SIZE] ^= 0x20;
+        fs::write(&path, &data).unwrap();
+        let problems = package.validate(&check_md5s).unwrap();
+        assert_eq!(problems_of(&problems, ProblemKind::Md5Mismatch), [
+            (None, Some(0)),
+            (None, Some(DIR_INDEX)),
+            (None, Some(DIR_INDEX)),
+        ]);
+        assert!(problems.iter().any(|problem| problem.message.contains("directory index")));
+        assert!(problems.iter().any(|problem| problem.message.contains("everything")));
+
+        assert!(package.validate(&ValidateOptions { buffer_size: 0, ..ValidateOptions::new() }).is_err());
+    }
+}
@@ -0,0 +1,132 @@
+// This file is part of rust-vpk.
+//
+// rust-vpk is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rust-vpk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rust-vpk.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::package::Package;
+
+/// Collects the warnings of a command, so they can be printed in one block
+/// at the end instead of getting lost between other output.
+pub struct Warnings {
+    messages:  Vec<String>,
+    immediate: bool,
+    quiet:     bool,
+}
+
+impl Warnings {
+    /// If `immediate` every warning is also printed as soon as it occurs
+    /// and the summary only contains the count. If `quiet` nothing is printed.
+    pub fn new(immediate: bool, quiet: bool) -> Self {
+        Self {
+            messages: Vec::new(),
+            immediate,
+            quiet,
+        }
+    }
+
+    pub fn warn(&mut self, message: impl Into<String>) {
+        let message = message.into();
+        if self.immediate && !self.quiet {
+            eprintln!("WARNING: {}", message);
+        }
+        self.messages.push(message);
+    }
+
+    /// Add a warning for every path that occurs more than once in the index.
+    pub fn warn_duplicates(&mut self, package: &Package) {
+        for path in package.duplicate_warnings() {
+            self.warn(format!("file occured more than once: {:?}", path));
+        }
+    }
+
+    /// Add a warning if reading the index stopped early in lenient mode.
+    pub fn warn_truncated(&mut self, package: &Package) {
+        if let Some(offset) = package.truncated_at() {
+            self.warn(format!("illegal terminator at offset {}, ignored the rest of the index", offset));
+        }
+    }
+
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.messages.len()
+    }
+
+    #[inline]
+    pub fn messages(&self) -> &[String] {
+        &self.messages
+    }
+
+    pub fn print_summary(&self) {
+        if self.quiet || self.messages.is_empty() {
+            return;
+        }
+
+        if self.immediate {
+            eprintln!("{} warning(s)", self.messages.len());
+        } else {
+            eprintln!("{} warning(s):", self.messages.len());
+            for message in &self.messages {
+                eprintln!("  {}", message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::{VPK_MAGIC, DIR_INDEX, TERMINATOR};
+    use crate::package::ReadOptions;
+    use crate::util::TempDir;
+
+    /// A version 1 index with "m/a.bin" twice and "m/b.bin" once.
+    fn index_with_duplicates() -> Vec<u8> {
+        let mut tree = Vec::new();
+        tree.extend_from_slice(b"bin\0m\0");
+        for name in [&b"a\0"[..], b"a\0", b"b\0"] {
+            tree.extend_from_slice(name);
+            tree.extend_from_slice(&0u32.to_le_bytes()); // CRC32
+            tree.extend_from_slice(&0u16.to_le_bytes()); // inline size
+            tree.extend_from_slice(&DIR_INDEX.to_le_bytes());
+            tree.extend_from_slice(&0u32.to_le_bytes()); // offset
+            tree.extend_from_slice(&0u32.to_le_bytes()); // size
+            tree.extend_from_slice(&TERMINATOR.to_le_bytes());
+        }
+        tree.extend_from_slice(b"\0\0\0");
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&VPK_MAGIC);
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&(tree.len() as u32).to_le_bytes());
+        data.extend_from_slice(&tree);
+        data
+    }
+
+    #[test]
+    fn summary_counts_duplicates() {
+        let tmp = TempDir::new("warnings-duplicates");
+        let path = tmp.write("dup_dir.vpk", &index_with_duplicates());
+        let package = Package::from_path(path, ReadOptions::new()).unwrap();
+        assert_eq!(package.file_count(), 2);
+
+        let mut warnings = Warnings::new(false, true);
+        warnings.warn_duplicates(&package);
+        warnings.warn("something else");
+
+        assert_eq!(warnings.count(), 2);
+        assert_eq!(warnings.messages(), [
+            "file occured more than once: \"m/a.bin\"".to_owned(),
+            "something else".to_owned(),
+        ]);
+    }
+}
@@ -0,0 +1,953 @@
+// This file is part of rust-vpk.
+//
+// rust-vpk is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// rust-vpk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with rust-vpk.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rvpk-cli-test-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+    path
+}
+
+fn write(path: &Path, data: &[u8]) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, data).unwrap();
+}
+
+fn rvpk(args: &[&str], dir: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rvpk"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+/// Run rvpk with `input` written to its stdin.
+fn rvpk_stdin(args: &[&str], dir: &Path, input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rvpk"))
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Just enough of JSON to check the output of `--json`.
+#[derive(Debug, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(text: &str) -> Result<Json, String> {
+        let mut chars = text.chars().peekable();
+        let value = parse_json_value(&mut chars)?;
+        skip_json_whitespace(&mut chars);
+        match chars.next() {
+            None => Ok(value),
+            Some(ch) => Err(format!("trailing character {:?}", ch)),
+        }
+    }
+
+    fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(fields) => fields.iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value)
+                .unwrap_or_else(|| panic!("missing field {:?} in {:?}", key, self)),
+            _ => panic!("not an object: {:?}", self),
+        }
+    }
+}
+
+type JsonChars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+fn skip_json_whitespace(chars: &mut JsonChars) {
+    while matches!(chars.peek(), Some(ch) if ch.is_ascii_whitespace()) {
+        chars.next();
+    }
+}
+
+fn expect_json(chars: &mut JsonChars, expected: &str) -> Result<(), String> {
+    for ch in expected.chars() {
+        if chars.next() != Some(ch) {
+            return Err(format!("expected {:?}", expected));
+        }
+    }
+    Ok(())
+}
+
+fn parse_json_string(chars: &mut JsonChars) -> Result<String, String> {
+    expect_json(chars, "\"")?;
+    let mut value = String::new();
+    loop {
+        match chars.next() {
+            None => return Err("unterminated string".to_owned()),
+            Some('"') => return Ok(value),
+            Some('\\') => match chars.next() {
+                Some('"')  => value.push('"'),
+                Some('\\') => value.push('\\'),
+                Some('/')  => value.push('/'),
+                Some('b')  => value.push('\u{8}'),
+                Some('f')  => value.push('\u{c}'),
+                Some('n')  => value.push('\n'),
+                Some('r')  => value.push('\r'),
+                Some('t')  => value.push('\t'),
+                Some('u')  => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let code = u32::from_str_radix(&hex, 16).map_err(|error| error.to_string())?;
+                    value.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                },
+                ch => return Err(format!("illegal escape {:?}", ch)),
+            },
+            Some(ch) if (ch as u32) < 0x20 => return Err(format!("unescaped control character {:?}", ch)),
+            Some(ch) => value.push(ch),
+        }
+    }
+}
+
+fn parse_json_value(chars: &mut JsonChars) -> Result<Json, String> {
+    skip_json_whitespace(chars);
+    match chars.peek().copied() {
+        Some('n') => expect_json(chars, "null").map(|_| Json::Null),
+        Some('t') => expect_json(chars, "true").map(|_| Json::Bool(true)),
+        Some('f') => expect_json(chars, "false").map(|_| Json::Bool(false)),
+        Some('"') => parse_json_string(chars).map(Json::String),
+        Some('[') => {
+            chars.next();
+            let mut items = Vec::new();
+            skip_json_whitespace(chars);
+            if chars.peek() == Some(&']') {
+                chars.next();
+                return Ok(Json::Array(items));
+            }
+            loop {
+                items.push(parse_json_value(chars)?);
+                skip_json_whitespace(chars);
+                match chars.next() {
+                    Some(',') => {},
+                    Some(']') => return Ok(Json::Array(items)),
+                    ch => return Err(format!("expected , or ] but got {:?}", ch)),
+                }
+            }
+        },
+        Some('{') => {
+            chars.next();
+            let mut fields = Vec::new();
+            skip_json_whitespace(chars);
+            if chars.peek() == Some(&'}') {
+                chars.next();
+                return Ok(Json::Object(fields));
+            }
+            loop {
+                skip_json_whitespace(chars);
+                let name = parse_json_string(chars)?;
+                skip_json_whitespace(chars);
+                expect_json(chars, ":")?;
+                fields.push((name, parse_json_value(chars)?));
+                skip_json_whitespace(chars);
+                match chars.next() {
+                    Some(',') => {},
+                    Some('}') => return Ok(Json::Object(fields)),
+                    ch => return Err(format!("expected , or }} but got {:?}", ch)),
+                }
+            }
+        },
+        Some(ch) if ch == '-' || ch.is_ascii_digit() => {
+            let mut number = String::new();
+            while let Some(&ch) = chars.peek() {
+                if ch == '-' || ch == '+' || ch == '.' || ch == 'e' || ch == 'E' || ch.is_ascii_digit() {
+                    number.push(ch);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            number.parse().map(Json::Number).map_err(|_| format!("illegal number {:?}", number))
+        },
+        ch => Err(format!("unexpected {:?}", ch)),
+    }
+}
+
+/// Parse every line of `stdout` as a JSON value.
+fn json_lines(stdout: &[u8]) -> Vec<Json> {
+    String::from_utf8_lossy(stdout).lines()
+        .map(|line| Json::parse(line).unwrap_or_else(|error| panic!("{}: {}", error, line)))
+        .collect()
+}
+
+/// A version 1 index with "m/a.bin" twice.
+fn index_with_duplicates() -> Vec<u8> {
+    let mut tree = Vec::new();
+    tree.extend_from_slice(b"bin\0m\0");
+    for _ in 0..2 {
+        tree.extend_from_slice(b"a\0");
+        tree.extend_from_slice(&[0; 4 + 2]); // CRC32 and inline size
+        tree.extend_from_slice(&0x7FFFu16.to_le_bytes()); // archive index
+        tree.extend_from_slice(&[0; 4 + 4]); // offset and size
+        tree.extend_from_slice(&0xFFFFu16.to_le_bytes()); // terminator
+    }
+    tree.extend_from_slice(b"\0\0\0");
+
+    let mut data = vec![0x34, 0x12, 0xAA, 0x55];
+    data.extend_from_slice(&1u32.to_le_bytes());
+    data.extend_from_slice(&(tree.len() as u32).to_le_bytes());
+    data.extend_from_slice(&tree);
+    data
+}
+
+#[test]
+fn check_quiet_prints_nothing() {
+    let dir = temp_dir("check-quiet");
+    write(&dir.join("in/m/a.bin"), &[0x42; 10000]);
+    write(&dir.join("in/m/b.txt"), b"hello");
+
+    let output = rvpk(&["pack", "-q", "--max-inline-size", "0", "pak_dir.vpk", "in"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = rvpk(&["check", "-q", "pak_dir.vpk"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn quiet_silences_duplicate_warning() {
+    let dir = temp_dir("extract-quiet");
+    write(&dir.join("dup_dir.vpk"), &index_with_duplicates());
+
+    let output = rvpk(&["extract", "dup_dir.vpk", "m/a.bin"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("more than once"));
+
+    for args in [&["extract", "-q", "dup_dir.vpk", "m/a.bin"][..], &["check", "-q", "dup_dir.vpk"]] {
+        let output = rvpk(args, &dir);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+    }
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn files_from_with_cur_dir() {
+    let dir = temp_dir("files-from-cur-dir");
+    write(&dir.join("in/m/a.txt"), b"a");
+    write(&dir.join("in/m/sub/b.txt"), b"b");
+
+    let lists = [
+        ("in/m/a.txt\nin/m/sub/b.txt\n", "./in"),
+        ("./in/m/a.txt\n./in/m/sub/b.txt\n", "in"),
+        ("./in/m/a.txt\nin/m/sub/b.txt\n", "./in"),
+        ("m/a.txt\n./m/sub/b.txt\n", "./in"),
+    ];
+    for (list, indir) in &lists {
+        write(&dir.join("list.txt"), list.as_bytes());
+        let output = rvpk(&["pack", "-q", "-T", "list.txt", "out_dir.vpk", indir], &dir);
+        assert!(output.status.success(), "{:?} {}: {}", list, indir, String::from_utf8_lossy(&output.stderr));
+
+        let output = rvpk(&["list", "--only-names", "out_dir.vpk"], &dir);
+        let mut names: Vec<String> = String::from_utf8_lossy(&output.stdout).lines().map(str::to_owned).collect();
+        names.sort();
+        assert_eq!(names, ["m/a.txt", "m/sub/b.txt"], "{:?} {}", list, indir);
+    }
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn json_outputs_are_versioned() {
+    let dir = temp_dir("json-version");
+    write(&dir.join("in/m/a.bin"), &[0x42; 1000]);
+    write(&dir.join("in/m/b.bin"), &[0x43; 1000]);
+
+    let output = rvpk(&["pack", "-q", "--max-inline-size", "0", "--max-archive-size", "1500", "pak_dir.vpk", "in"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let prefix = format!("{{\"vpk_json_version\":1,\"tool_version\":\"{}\",", env!("CARGO_PKG_VERSION"));
+    let assert_versioned = |stdout: &[u8]| -> Vec<String> {
+        let lines: Vec<String> = String::from_utf8_lossy(stdout).lines().map(str::to_owned).collect();
+        assert!(!lines.is_empty());
+        for line in &lines {
+            assert!(line.starts_with(&prefix), "{}", line);
+            assert!(line.ends_with('}'), "{}", line);
+        }
+        lines
+    };
+
+    let output = rvpk(&["check", "--json", "pak_dir.vpk"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let lines = assert_versioned(&output.stdout);
+    assert_eq!(lines.len(), 3);
+    assert!(lines[..2].iter().all(|line| line.contains("\"type\":\"file\"")));
+    assert!(lines[2].contains("\"type\":\"summary\""));
+
+    // the summary is still printed when stopping at the first error
+    fs::write(dir.join("pak_000.vpk"), &[0u8; 2000][..]).unwrap();
+    let output = rvpk(&["check", "--json", "--stop-on-error", "pak_dir.vpk"], &dir);
+    assert!(!output.status.success());
+    let lines = assert_versioned(&output.stdout);
+    assert!(lines.last().unwrap().contains("\"type\":\"summary\""));
+
+    let output = rvpk(&["needed-archives", "--json", "pak_dir.vpk"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let lines = assert_versioned(&output.stdout);
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].contains("\"archives\":["), "{}", lines[0]);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn dirname_from_archive_round_trip() {
+    let dir = temp_dir("archive-dirname");
+    let data: Vec<u8> = (0..3000u32).map(|index| (index % 251) as u8).collect();
+    write(&dir.join("in/000/m/a.bin"), &data[..2000]);
+    write(&dir.join("in/001/m/b.bin"), &data);
+    write(&dir.join("in/dir/s/c.bin"), &data[..1500]);
+    write(&dir.join("in/inline/t/d.txt"), b"hello");
+    write(&dir.join("in/inline/t/empty.txt"), b"");
+
+    let output = rvpk(&["pack", "-q", "--archive-from-dirname", "one_dir.vpk", "in"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = rvpk(&["unpack", "-q", "--dirname-from-archive", "-o", "out", "one_dir.vpk"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    for path in &["000/m/a.bin", "001/m/b.bin", "dir/s/c.bin", "inline/t/d.txt", "inline/t/empty.txt"] {
+        assert_eq!(fs::read(dir.join("out").join(path)).unwrap(), fs::read(dir.join("in").join(path)).unwrap(), "{}", path);
+    }
+
+    let output = rvpk(&["pack", "-q", "--archive-from-dirname", "two_dir.vpk", "out"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let one = rvpk(&["list", "one_dir.vpk"], &dir);
+    let two = rvpk(&["list", "two_dir.vpk"], &dir);
+    assert!(one.status.success() && two.status.success());
+    assert_eq!(String::from_utf8_lossy(&one.stdout), String::from_utf8_lossy(&two.stdout));
+
+    for (one, two) in &[("one_dir.vpk", "two_dir.vpk"), ("one_000.vpk", "two_000.vpk"), ("one_001.vpk", "two_001.vpk")] {
+        assert_eq!(fs::read(dir.join(one)).unwrap(), fs::read(dir.join(two)).unwrap(), "{}", two);
+    }
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn list_and_stats_with_missing_archive() {
+    let dir = temp_dir("missing-archive");
+    write(&dir.join("in/000/m/a.bin"), &[1; 2000]);
+    write(&dir.join("in/001/m/b.bin"), &[2; 2000]);
+
+    let output = rvpk(&["pack", "-q", "--archive-from-dirname", "pak_dir.vpk", "in"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    fs::remove_file(dir.join("pak_001.vpk")).unwrap();
+
+    let output = rvpk(&["list", "--check-archives", "pak_dir.vpk"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(lines[0].ends_with("Archive-Status"), "{}", lines[0]);
+    assert!(!lines[2].contains("MISSING"), "{}", lines[2]);
+    assert!(lines[3].contains("m/b.bin") && lines[3].contains("MISSING"), "{}", lines[3]);
+
+    let output = rvpk(&["stats", "pak_dir.vpk"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.lines().any(|line| line.starts_with("IO Error Count:") && line.ends_with(" 1")), "{}", stdout);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn check_failure_reports_location() {
+    let dir = temp_dir("check-location");
+    for name in &["a", "b", "c", "d"] {
+        write(&dir.join(format!("in/m/{}.bin", name)), &[0x33; 1000]);
+    }
+
+    let output = rvpk(&["pack", "-q", "--max-inline-size", "0", "--max-archive-size", "2500", "pak_dir.vpk", "in"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // find a file in an archive that doesn't start at offset 0
+    let output = rvpk(&["list", "pak_dir.vpk"], &dir);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (archive, offset, path) = stdout.lines().skip(2)
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .find(|cols| cols[1] != "dir" && cols[2] != "0")
+        .map(|cols| (cols[1].parse::<u16>().unwrap(), cols[2].parse::<u64>().unwrap(), cols[7].to_owned()))
+        .unwrap();
+
+    let archive_path = dir.join(format!("pak_{:03}.vpk", archive));
+    let mut data = fs::read(&archive_path).unwrap();
+    data[offset as usize + 10] ^= 0xFF;
+    fs::write(&archive_path, &data).unwrap();
+
+    let location = format!("{}: CRC32 sum missmatch in archive {:03} at offset {} with size 1000", path, archive, offset);
+
+    let output = rvpk(&["check", "pak_dir.vpk"], &dir);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&location), "{}", stderr);
+
+    let output = rvpk(&["check", "--verbose", "pak_dir.vpk"], &dir);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&format!("FAILED, CRC32 sum missmatch in archive {:03} at offset {} with size 1000", archive, offset)), "{}", stdout);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn check_json_lines() {
+    let dir = temp_dir("check-json");
+    write(&dir.join("in/m/a.bin"), &[1; 1000]);
+    write(&dir.join("in/m/b.bin"), &[2; 1000]);
+    write(&dir.join("in/m/q\"u\\ote.bin"), &[3; 1000]);
+
+    let output = rvpk(&["pack", "-q", "--max-inline-size", "0", "--max-archive-size", "1500", "pak_dir.vpk", "in"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = rvpk(&["check", "--json", "pak_dir.vpk"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let lines = json_lines(&output.stdout);
+    assert_eq!(lines.len(), 4);
+    let mut paths: Vec<&Json> = lines[..3].iter().map(|line| line.get("path")).collect();
+    paths.sort_by_key(|path| format!("{:?}", path));
+    assert_eq!(paths, [
+        &Json::String("m/a.bin".to_owned()),
+        &Json::String("m/b.bin".to_owned()),
+        &Json::String("m/q\"u\\ote.bin".to_owned()),
+    ]);
+    assert!(lines[..3].iter().all(|line| line.get("ok") == &Json::Bool(true) && line.get("error") == &Json::Null));
+    assert_eq!(lines[3].get("type"), &Json::String("summary".to_owned()));
+    assert_eq!(lines[3].get("files"), &Json::Number(3.0));
+    assert_eq!(lines[3].get("ok"), &Json::Bool(true));
+
+    // corrupt every archive
+    for index in 0..3 {
+        let path = dir.join(format!("pak_{:03}.vpk", index));
+        if path.exists() {
+            fs::write(&path, &[0xFF; 1000][..]).unwrap();
+        }
+    }
+
+    let output = rvpk(&["check", "--json", "pak_dir.vpk"], &dir);
+    assert!(!output.status.success());
+    let lines = json_lines(&output.stdout);
+    let summary = lines.last().unwrap();
+    assert_eq!(summary.get("type"), &Json::String("summary".to_owned()));
+    assert_eq!(summary.get("ok"), &Json::Bool(false));
+    let failed = lines.iter().filter(|line| line.get("type") == &Json::String("file".to_owned()) && line.get("ok") == &Json::Bool(false)).count();
+    assert!(failed >= 1);
+    assert_eq!(summary.get("failed_files"), &Json::Number(failed as f64));
+
+    let output = rvpk(&["check", "--json", "--stop-on-error", "pak_dir.vpk"], &dir);
+    assert!(!output.status.success());
+    let lines = json_lines(&output.stdout);
+    let summary = lines.last().unwrap();
+    assert_eq!(summary.get("type"), &Json::String("summary".to_owned()));
+    assert_eq!(summary.get("ok"), &Json::Bool(false));
+    assert_eq!(summary.get("failed_files"), &Json::Number(1.0));
+    assert_eq!(lines[lines.len() - 2].get("ok"), &Json::Bool(false));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn empty_file_round_trip() {
+    let dir = temp_dir("empty-files");
+    write(&dir.join("in/m/empty.txt"), b"");
+    write(&dir.join("in/only/empty.bin"), b"");
+    write(&dir.join("in/m/data.bin"), &[7; 3000]);
+
+    for version in &["1", "2"] {
+        let package = format!("v{}_dir.vpk", version);
+        let output = rvpk(&["pack", "-q", "--version", version, "--max-inline-size", "0", &package, "in"], &dir);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+        let output = rvpk(&["list", &package], &dir);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        for path in &["m/empty.txt", "only/empty.bin"] {
+            let cols: Vec<&str> = stdout.lines()
+                .find(|line| line.ends_with(path) || line.trim_end().ends_with(path))
+                .unwrap_or_else(|| panic!("{} not listed:\n{}", path, stdout))
+                .split_whitespace().collect();
+            // inline size, archive size and full size
+            assert_eq!(&cols[3..6], ["0", "0", "0"], "{}", stdout);
+            // CRC32 of no data
+            assert_eq!(cols[6], "0x00000000", "{}", stdout);
+        }
+
+        let output = rvpk(&["check", &package], &dir);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+        let outdir = format!("out{}", version);
+        let output = rvpk(&["unpack", "-q", "-o", &outdir, &package], &dir);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        for path in &["m/empty.txt", "only/empty.bin", "m/data.bin"] {
+            assert_eq!(fs::read(dir.join(&outdir).join(path)).unwrap(), fs::read(dir.join("in").join(path)).unwrap(), "{}", path);
+        }
+    }
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn paths0_from_stdin() {
+    let dir = temp_dir("paths0");
+    write(&dir.join("in/m/a.txt"), b"a");
+    write(&dir.join("in/m/new\nline.txt"), b"new line");
+    write(&dir.join("in/m/b c.txt"), b"b c");
+    write(&dir.join("in/n/d.txt"), b"d");
+
+    let output = rvpk(&["pack", "-q", "out_dir.vpk", "in"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = rvpk_stdin(&["list", "-n", "-z", "--paths0", "out_dir.vpk"], &dir, b"m/new\nline.txt\0m/b c.txt\0n");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mut names: Vec<&[u8]> = output.stdout.split(|&byte| byte == 0).filter(|name| !name.is_empty()).collect();
+    names.sort();
+    assert_eq!(names, [&b"m/b c.txt"[..], b"m/new\nline.txt", b"n/d.txt"]);
+
+    let output = rvpk_stdin(&["check", "-q", "--paths0", "out_dir.vpk"], &dir, b"m/new\nline.txt\0");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // split at NUL bytes only, so a path with a new line isn't two paths
+    let output = rvpk_stdin(&["list", "-n", "--paths0", "out_dir.vpk"], &dir, b"m/a.txt\nm/b c.txt\0");
+    assert!(!output.status.success());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn list_verbose_summary_matches_stats() {
+    let dir = temp_dir("list-verbose");
+    write(&dir.join("in/m/a.bin"), &[1; 3000]);
+    write(&dir.join("in/m/b.bin"), &[2; 2000]);
+    write(&dir.join("in/m/c.txt"), b"hi\n");
+
+    let output = rvpk(&["pack", "-q", "--max-inline-size", "0", "--max-archive-size", "2500", "out_dir.vpk", "in"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = rvpk(&["list", "--verbose", "out_dir.vpk"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let list = String::from_utf8(output.stdout).unwrap();
+    let (files, summary) = list.split_at(list.find("\n\n").expect("no summary after the file list"));
+    let summary = summary.trim_start_matches('\n');
+    assert!(summary.starts_with("Archive "), "{}", list);
+
+    let output = rvpk(&["stats", "out_dir.vpk"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stats = String::from_utf8(output.stdout).unwrap();
+    let archives = &stats[stats.rfind("\nArchive ").unwrap() + 1..];
+    assert_eq!(summary, archives);
+
+    // the used size of each archive is the sum of the archive sizes listed
+    // for it
+    let mut used = std::collections::BTreeMap::new();
+    for line in files.lines().skip(2) {
+        let cols: Vec<&str> = line.split_whitespace().collect();
+        let (count, size) = used.entry(cols[1].parse::<u16>().unwrap()).or_insert((0, 0));
+        *count += 1;
+        *size += cols[4].parse::<u64>().unwrap();
+    }
+    // columns: archive, file count, files with data, file size, used size
+    let rows: Vec<(u16, usize, u64)> = summary.lines().skip(2)
+        .map(|line| {
+            let cols: Vec<&str> = line.split_whitespace().collect();
+            (cols[0].parse().unwrap(), cols[1].parse().unwrap(), cols[4].parse().unwrap())
+        })
+        .collect();
+    let expected: Vec<(u16, usize, u64)> = used.into_iter().map(|(index, (count, size))| (index, count, size)).collect();
+    assert_eq!(rows, expected);
+    assert_eq!(rows, [(0, 1, 3000), (1, 2, 2003)]);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn index_warning_ratio() {
+    let dir = temp_dir("index-warning-ratio");
+    write(&dir.join("in/m/a.bin"), &[1; 2000]);
+    write(&dir.join("in/m/b.bin"), &[2; 3000]);
+    write(&dir.join("in/m/big.bin"), &[3; 60000]);
+
+    let warned = |args: &[&str]| -> bool {
+        let mut pack_args = vec!["pack"];
+        pack_args.extend_from_slice(args);
+        pack_args.extend_from_slice(&["out_dir.vpk", "in"]);
+        let output = rvpk(&pack_args, &dir);
+        assert!(output.status.success(), "{:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stderr).contains("WARNING: the index is ")
+    };
+
+    // a.bin and b.bin are inlined, but big.bin isn't
+    assert!(!warned(&["--max-inline-size", "4000"]));
+    // everything is inlined
+    assert!(warned(&["--max-inline-size", "65535"]));
+    assert!(warned(&["--max-inline-size", "65535", "--index-warning-ratio", "0.9"]));
+    assert!(!warned(&["--max-inline-size", "65535", "--index-warning-ratio", "0"]));
+    assert!(!warned(&["--max-inline-size", "65535", "-q"]));
+    // only for a --max-inline-size bigger than the default
+    assert!(!warned(&["--index-warning-ratio", "0.001"]));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn needed_archives_of_selected_files() {
+    let dir = temp_dir("needed-archives");
+    write(&dir.join("in/000/m/a.bin"), &[0; 100]);
+    write(&dir.join("in/001/m/b.bin"), &[1; 100]);
+    write(&dir.join("in/002/n/c.bin"), &[2; 100]);
+    write(&dir.join("in/002/n/d.bin"), &[3; 100]);
+    write(&dir.join("in/003/o/e.bin"), &[4; 100]);
+    write(&dir.join("in/dir/p/f.bin"), &[5; 100]);
+    write(&dir.join("in/inline/q/g.txt"), b"inline");
+
+    let output = rvpk(&["pack", "-q", "--archive-from-dirname", "pak_dir.vpk", "in"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let needed = |paths: &[&str]| -> Vec<String> {
+        let mut args = vec!["needed-archives", "pak_dir.vpk"];
+        args.extend_from_slice(paths);
+        let output = rvpk(&args, &dir);
+        assert!(output.status.success(), "{:?}: {}", paths, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap().lines()
+            .map(|line| Path::new(line).file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    };
+
+    // the files are confined to two of the four archives
+    assert_eq!(needed(&["m/b.bin", "n"]), ["pak_001.vpk", "pak_002.vpk"]);
+    assert_eq!(needed(&["n/c.bin", "n/d.bin"]), ["pak_002.vpk"]);
+    // inline files need no archive, data in the _dir.vpk needs it
+    assert!(needed(&["q"]).is_empty());
+    assert_eq!(needed(&["p", "q"]), ["pak_dir.vpk"]);
+    assert_eq!(needed(&[]), ["pak_000.vpk", "pak_001.vpk", "pak_002.vpk", "pak_003.vpk", "pak_dir.vpk"]);
+
+    let output = rvpk(&["needed-archives", "--json", "pak_dir.vpk", "m/b.bin", "n"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let json = Json::parse(String::from_utf8(output.stdout).unwrap().trim()).unwrap();
+    let archives: Vec<&Json> = match json.get("archives") {
+        Json::Array(archives) => archives.iter().map(|archive| archive.get("archive")).collect(),
+        other => panic!("not an array: {:?}", other),
+    };
+    assert_eq!(archives, [&Json::String("001".to_owned()), &Json::String("002".to_owned())]);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn check_group_by_archive_headers() {
+    let dir = temp_dir("group-by-archive");
+    write(&dir.join("in/000/m/a.bin"), &[1; 300]);
+    write(&dir.join("in/001/m/b.bin"), &[2; 200]);
+    write(&dir.join("in/001/n/c.bin"), &[3; 100]);
+    write(&dir.join("in/dir/o/d.bin"), &[4; 50]);
+
+    let output = rvpk(&["pack", "-q", "--archive-from-dirname", "pak_dir.vpk", "in"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = rvpk(&["check", "--verbose", "--group-by-archive", "pak_dir.vpk"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    // one header per archive, followed by the indented files of that archive
+    let mut groups: Vec<(String, Vec<String>)> = Vec::new();
+    for line in stdout.lines() {
+        if let Some(archive) = line.strip_prefix("archive ").and_then(|rest| rest.strip_suffix(':')) {
+            groups.push((archive.to_owned(), Vec::new()));
+        } else if line.ends_with("... OK") {
+            let (archive, files) = groups.last_mut().unwrap_or_else(|| panic!("file before any header:\n{}", stdout));
+            assert!(line.starts_with("      "), "not indented: {:?}", line);
+            let cols: Vec<&str> = line.split_whitespace().collect();
+            assert_eq!(cols[0], archive, "{}", stdout);
+            files.push(cols[cols.len() - 2].trim_end_matches("...").to_owned());
+        }
+    }
+    groups.sort();
+    assert_eq!(groups, [
+        ("000".to_owned(), vec!["m/a.bin".to_owned()]),
+        ("001".to_owned(), vec!["m/b.bin".to_owned(), "n/c.bin".to_owned()]),
+        ("dir".to_owned(), vec!["o/d.bin".to_owned()]),
+    ]);
+
+    let output = rvpk(&["check", "--verbose", "pak_dir.vpk"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!String::from_utf8(output.stdout).unwrap().lines().any(|line| line.starts_with("archive ")));
+
+    // requires --verbose
+    let output = rvpk(&["check", "--group-by-archive", "pak_dir.vpk"], &dir);
+    assert!(!output.status.success());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn pack_check_after() {
+    let dir = temp_dir("check-after");
+    write(&dir.join("in/m/a.bin"), &[1; 3000]);
+    write(&dir.join("in/m/b.bin"), &[2; 2000]);
+    write(&dir.join("in/n/c.txt"), b"inline");
+
+    for version in &["1", "2"] {
+        let package = format!("v{}_dir.vpk", version);
+        let args = ["pack", "--verbose", "--check-after", "--version", version, "--max-inline-size", "0", "--max-archive-size", "2500", &package, "in"];
+        let output = rvpk(&args, &dir);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let stdout = String::from_utf8(output.stdout).unwrap();
+
+        // every file of the written package is checked
+        for path in &["m/a.bin", "m/b.bin", "n/c.txt"] {
+            assert!(stdout.lines().any(|line| line.ends_with(&format!("{}... OK", path))), "{}:\n{}", path, stdout);
+        }
+        if *version == "2" {
+            assert!(stdout.contains("MD5"), "{}", stdout);
+        }
+
+        // and re-reading it finds the same
+        let output = rvpk(&["check", &package], &dir);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    let output = rvpk(&["pack", "--verbose", "--max-inline-size", "0", "--max-archive-size", "2500", "plain_dir.vpk", "in"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!String::from_utf8(output.stdout).unwrap().contains("... OK"));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn ignore_missing_archive() {
+    let dir = temp_dir("ignore-missing");
+    write(&dir.join("in/000/m/a.bin"), &[1; 300]);
+    write(&dir.join("in/001/m/b.bin"), &[2; 200]);
+    write(&dir.join("in/001/n/c.bin"), &[3; 100]);
+    write(&dir.join("in/inline/o/d.txt"), b"inline");
+
+    let output = rvpk(&["pack", "-q", "--version", "2", "--archive-from-dirname", "pak_dir.vpk", "in"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    fs::remove_file(dir.join("pak_001.vpk")).unwrap();
+
+    let output = rvpk(&["check", "pak_dir.vpk"], &dir);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("pak_001.vpk"), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = rvpk(&["check", "--ignore-missing", "pak_dir.vpk"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // b.bin, c.bin and the MD5 section of pak_001.vpk
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("skipped 2 file(s) and 1 MD5 section(s) in missing archives"), "{}", stderr);
+
+    let output = rvpk(&["unpack", "-q", "-o", "out", "pak_dir.vpk"], &dir);
+    assert!(!output.status.success());
+
+    let output = rvpk(&["unpack", "-o", "out2", "--ignore-missing", "pak_dir.vpk"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("skipped 2 file(s) in missing archives"), "{}", stderr);
+    assert_eq!(fs::read(dir.join("out2/m/a.bin")).unwrap(), [1; 300]);
+    assert_eq!(fs::read(dir.join("out2/o/d.txt")).unwrap(), b"inline");
+    // no empty files are left behind for the skipped ones
+    assert!(!dir.join("out2/m/b.bin").exists());
+    assert!(!dir.join("out2/n/c.bin").exists());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn keep_going_skips_unreadable_files() {
+    // running as root would make permission based tests pointless, so these
+    // files are unreadable because they don't exist or are directories
+    let dir = temp_dir("keep-going");
+    write(&dir.join("in/m/a.txt"), b"a");
+    write(&dir.join("in/m/sub/b.txt"), b"b");
+    write(&dir.join("in/m/dir.txt/c.txt"), b"c");
+    write(&dir.join("list.txt"), b"m/a.txt\nm/gone.txt\nm/dir.txt\n");
+
+    let output = rvpk(&["pack", "-q", "-T", "list.txt", "fail_dir.vpk", "in"], &dir);
+    assert!(!output.status.success());
+    assert!(!dir.join("fail_dir.vpk").exists());
+
+    let output = rvpk(&["pack", "--keep-going", "-T", "list.txt", "out_dir.vpk", "in"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("skipping unreadable file") && stderr.contains("gone.txt"), "{}", stderr);
+    assert!(stderr.contains("skipped 2 unreadable file(s)"), "{}", stderr);
+
+    let output = rvpk(&["list", "--only-names", "out_dir.vpk"], &dir);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "m/a.txt\n");
+
+    // quiet drops the warnings, but still skips
+    let output = rvpk(&["pack", "-q", "--keep-going", "-T", "list.txt", "quiet_dir.vpk", "in"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink("gone.txt", dir.join("in/m/dangling.txt")).unwrap();
+
+        let output = rvpk(&["pack", "-q", "--follow-symlinks", "fail2_dir.vpk", "in"], &dir);
+        assert!(!output.status.success());
+
+        let output = rvpk(&["pack", "-q", "--follow-symlinks", "--keep-going", "links_dir.vpk", "in"], &dir);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let output = rvpk(&["list", "--only-names", "links_dir.vpk"], &dir);
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "m/a.txt\nm/dir.txt/c.txt\nm/sub/b.txt\n");
+    }
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn pack_layout_summary() {
+    let dir = temp_dir("layout-summary");
+    write(&dir.join("in/m/a.bin"), &[1; 3000]);
+    write(&dir.join("in/m/b.bin"), &[2; 2000]);
+    write(&dir.join("in/m/c.txt"), b"hi\n");
+
+    let output = rvpk(&["pack", "--version", "2", "--max-inline-size", "0", "--max-archive-size", "2500", "pak_dir.vpk", "in"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0].split_whitespace().collect::<Vec<_>>(), ["Archive", "Files", "Data", "Size", "File", "Size"], "{}", stdout);
+
+    let rows: Vec<Vec<&str>> = lines[2..].iter().map(|line| line.split_whitespace().collect()).collect();
+    let file_size = |name: &str| fs::metadata(dir.join(name)).unwrap().len().to_string();
+    // the _dir.vpk only holds the index, which in a version 2 package is
+    // followed by the MD5 sums of the archives
+    assert_eq!(rows, [
+        vec!["000", "1", "3000", &file_size("pak_000.vpk")[..]],
+        vec!["001", "2", "2003", &file_size("pak_001.vpk")[..]],
+        vec!["dir", "0", "0",    &file_size("pak_dir.vpk")[..]],
+    ]);
+    assert_eq!(file_size("pak_000.vpk"), "3000");
+    assert_eq!(file_size("pak_001.vpk"), "2003");
+
+    let output = rvpk(&["pack", "-q", "--max-inline-size", "0", "--max-archive-size", "2500", "quiet_dir.vpk", "in"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.stdout.is_empty());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn trailing_junk_after_v2_package() {
+    let dir = temp_dir("trailing-junk");
+    write(&dir.join("in/m/a.bin"), &[1; 3000]);
+    write(&dir.join("in/m/b.txt"), b"inline");
+
+    let output = rvpk(&["pack", "-q", "--version", "2", "pak_dir.vpk", "in"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mut data = fs::read(dir.join("pak_dir.vpk")).unwrap();
+    data.extend_from_slice(&[0xAB; 100]);
+    fs::write(dir.join("pak_dir.vpk"), &data).unwrap();
+
+    for command in &["list", "stats", "check"] {
+        let output = rvpk(&[command, "pak_dir.vpk"], &dir);
+        assert!(output.status.success(), "{}: {}", command, String::from_utf8_lossy(&output.stderr));
+    }
+
+    let output = rvpk(&["unpack", "-q", "-o", "out", "pak_dir.vpk"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(fs::read(dir.join("out/m/a.bin")).unwrap(), [1; 3000]);
+    assert_eq!(fs::read(dir.join("out/m/b.txt")).unwrap(), b"inline");
+
+    // not a problem, but noted
+    let output = rvpk(&["doctor", "pak_dir.vpk"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let line = stdout.lines().find(|line| line.starts_with("VPK v2 sections")).unwrap();
+    assert_eq!(line.split_whitespace().skip(3).collect::<Vec<_>>().join(" "), "OK 100 trailing bytes after the signature section");
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn stats_archives_only() {
+    let dir = temp_dir("stats-archives-only");
+    write(&dir.join("in/m/a.bin"), &[1; 3000]);
+    write(&dir.join("in/m/b.bin"), &[2; 2000]);
+
+    let output = rvpk(&["pack", "-q", "--max-inline-size", "0", "--max-archive-size", "2500", "pak_dir.vpk", "in"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = rvpk(&["stats", "--archives-only", "pak_dir.vpk"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0].split_whitespace().collect::<Vec<_>>(),
+        ["Archive", "File", "Count", "File", "With", "Data", "Count", "File", "Size", "Used", "Size", "Wasted", "Size"], "{}", stdout);
+    assert!(lines[1].chars().all(|ch| ch == '-'), "{}", stdout);
+
+    // nothing but the archive table, no header block or extension table
+    let rows: Vec<Vec<&str>> = lines[2..].iter().map(|line| line.split_whitespace().collect()).collect();
+    assert_eq!(rows, [
+        vec!["000", "1", "1", "3000", "3000", "0"],
+        vec!["001", "1", "1", "2000", "2000", "0"],
+    ], "{}", stdout);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn timing_line() {
+    let dir = temp_dir("timing");
+    write(&dir.join("in/m/a.bin"), &[1; 3000]);
+    write(&dir.join("in/m/b.bin"), &[2; 2000]);
+
+    // processed SIZE in SECS s (SIZE/s)
+    let check_timing = |output: &Output| {
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let line = stderr.lines().last().unwrap_or("");
+        let words: Vec<&str> = line.split(' ').collect();
+        assert_eq!(words.len(), 8, "{}", line);
+        assert_eq!(&words[..4], ["processed", "4.9", "K", "in"], "{}", line);
+        let (int, frac) = words[4].split_once('.').unwrap_or_else(|| panic!("{}", line));
+        assert!(int.parse::<u64>().is_ok() && frac.len() == 3 && frac.parse::<u64>().is_ok(), "{}", line);
+        assert_eq!(words[5], "s", "{}", line);
+        assert!(words[6].starts_with('(') && words[7].ends_with("/s)"), "{}", line);
+    };
+
+    check_timing(&rvpk(&["pack", "-q", "--timing", "--max-inline-size", "0", "pak_dir.vpk", "in"], &dir));
+    check_timing(&rvpk(&["check", "--timing", "pak_dir.vpk"], &dir));
+    check_timing(&rvpk(&["unpack", "-q", "--timing", "-o", "out", "pak_dir.vpk"], &dir));
+
+    // without the option nothing is printed
+    let output = rvpk(&["check", "pak_dir.vpk"], &dir);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("processed"));
+
+    let _ = fs::remove_dir_all(&dir);
+}